@@ -0,0 +1,69 @@
+-- <const> : 初始值是常量时是编译期常量 , 引用处直接替换成值
+local N <const> = 10
+local NAME <const> = "cfg"
+local HALF <const> = N / 4
+local t = {}
+t[NAME] = N * 2
+print(t.cfg, HALF, N // 3)
+local function get() return N + 1, NAME end
+print(get())
+
+-- 初始值不是常量的<const>变量也不能赋值
+local start <const> = os.time()
+print(type(start))
+print(load("local x <const> = 1; x = 2"))
+print(load("local y <const> = {}; y = nil", "=const"))
+print(load("local z <const> = 1; local function f() z = 2 end", "=upvalue"))
+print(load("local w <close> = nil; w = 1", "=close"))
+print(load("local u <const> = 1; function u() end", "=function"))
+print(load("local v <other> = 1", "=attrib"))
+local shadow = load("local v <const> = 1; local v = 2; v = 3; return v", "=shadow")
+print(shadow())
+
+-- <close> : 离开作用域时调用__close(value, err)
+local function closer(name)
+  return setmetatable({}, {__close = function (_, err) print("close", name, err) end})
+end
+
+do
+  local a <close> = closer("a")
+  local b <close> = closer("b")
+  local c <close> = nil -- nil和false不需要关闭
+  print("in block")
+end
+
+-- break和goto跳出语句块
+for i = 1, 3 do
+  local x <close> = closer("loop " .. i)
+  if i == 2 then break end
+end
+do
+  local g <close> = closer("goto")
+  goto out
+end
+::out::
+
+-- return : 返回值先算好 , 再关闭
+local function f()
+  local r <close> = closer("return")
+  return "result"
+end
+print(f())
+
+-- 出错时关闭 , __close收到错误
+print(pcall(function ()
+  local e <close> = closer("error")
+  error("boom")
+end))
+
+-- 没有__close元方法的值
+print(pcall(function ()
+  local bad <close> = {}
+end))
+
+-- __close中的错误代替原来的错误
+print(pcall(function ()
+  local x <close> = setmetatable({}, {__close = function () error("in close") end})
+  local y <close> = closer("y")
+  error("original")
+end))
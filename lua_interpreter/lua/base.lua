@@ -0,0 +1,45 @@
+print(type(1), type("a"), type(nil), type(print), type({}))
+print(tostring(12), tostring(1.5), tostring(nil), tostring(true))
+print(tonumber("  0x10  "), tonumber("1e2"), tonumber(" 12 "), tonumber("z", 36), tonumber("ff", 16), tonumber("8", 8), tonumber("abc"), tonumber("0x1p4"), tonumber(".5"), tonumber("5."))
+print(pcall(error, "boom"))
+print(pcall(error, {code = 1}))
+print(pcall(function() error("lvl1") end))
+local function f() error("lvl2", 2) end
+print(pcall(function() f() end))
+print(pcall(function() error("nolvl", 0) end))
+print(xpcall(function() error("x") end, function(m) return "handled: " .. m end))
+print(xpcall(function(a, b) return a + b end, print, 3, 4))
+print(select("#", 1, nil, 3, nil))
+print(select(2, "a", "b", "c"))
+print(select(-1, "a", "b", "c"))
+print(pcall(select, 0, 1))
+local t = {}
+rawset(t, "k", 1)
+print(rawget(t, "k"), rawequal(t, t), rawequal(t, {}), rawlen({1, 2, 3}), rawlen("abcd"))
+local mt = {__index = function(t, k) return k .. "!" end, __tostring = function() return "T" end}
+setmetatable(t, mt)
+print(t.k, t.zz, tostring(t), getmetatable(t) == mt)
+mt.__metatable = "locked"
+print(getmetatable(t), pcall(setmetatable, t, {}))
+print(pcall(assert, false))
+print(pcall(assert, nil, "custom"))
+print(assert(1, 2, 3))
+print(pcall(setmetatable, 1, {}))
+print(pcall(rawlen, 5))
+local add = setmetatable({}, {__add = function(a, b) return 42 end, __call = function(self, x) return x * 2 end})
+print(add + 1, 1 + add, add(21))
+local function counter()
+  local n = 0
+  return function() n = n + 1 return n end
+end
+local c = counter()
+c() c()
+print(c())
+local function va(...) return select("#", ...), ... end
+print(va(1, 2, 3))
+print(pcall(function() local x = nil; return x.y end))
+print(pcall(error))
+-- 不是UTF-8的字节串 : tostring、format和error都原样保留
+local bin = "\xff\xfe\xfd\xfc"
+print(#tostring(bin), tostring(bin) == bin, string.format("%s|%s", bin, 1) == bin .. "|1")
+print(select(2, pcall(error, bin)) == bin, select(2, pcall(error, bin, 2)):sub(-4) == bin)
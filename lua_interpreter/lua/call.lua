@@ -0,0 +1,37 @@
+-- 多个局部变量 : 值不够时补nil , 多余的丢弃 , 最后的函数调用展开成多个值
+local function three() return 1, 2, 3 end
+local a, b, c = 1, 2
+print(a, b, c)
+local x, y = three()
+local p, q, r, s = 0, three()
+local u, v = three(), 10
+print(x, y, p, q, r, s, u, v)
+local m, n
+print(m, n)
+local k1 <const>, k2 = 5, "k"
+print(k1 * 2, k2)
+print(load("local a <close>, b <close> = nil", "=close"))
+
+-- 任意前缀表达式上的调用
+local function mk(tag)
+  return function (...) return tag, select('#', ...), ... end
+end
+print(mk("a")())
+print(mk("b")(3, 4))
+print((mk("c"))(5))
+print(tostring(nil):sub(1, 2), ("x"):rep(3), ("%d-%d"):format(1, 2))
+local t = {f = function (v) return v * 2 end, g = {h = function (self, v) return self.base + v end, base = 10}}
+print(t.f(5), t.g:h(1), t.g.h({base = 0}, 9), t["f"](6))
+local chain = {}
+function chain:add(v) self[#self + 1] = v return self end
+print(#chain:add(1):add(2):add(3), chain[3])
+
+-- 字符串和table作为唯一的参数
+print(type{1, 2}, #{1, 2, 3}, select("#", "only"))
+local function keys(opts) return opts.name, opts[1] end
+print(keys{name = "n", "first"})
+print(chain:add{}, #chain)
+
+-- 表达式中的调用只取第一个值
+print(three() + 10, -three(), (three()), three() == 1)
+print(({three(), three()})[4], ({three(), (three())})[3])
@@ -0,0 +1,175 @@
+-- 生成器 : yield传出值 , resume的参数作为yield的返回值
+local gen = coroutine.create(function (a, b)
+  print("start", a, b)
+  local c = coroutine.yield(a + b)
+  print("got", c)
+  local r = {coroutine.yield(c * 2)}
+  print("got", r[1], r[2])
+  return "done", r[1] + r[2]
+end)
+print(coroutine.status(gen), type(gen))
+print(coroutine.resume(gen, 1, 2))
+print(coroutine.status(gen))
+print(coroutine.resume(gen, 10))
+print(coroutine.resume(gen, 3, 4))
+print(coroutine.status(gen))
+print(coroutine.resume(gen))
+
+-- 状态 : 正在运行的是running , resume了别人的是normal
+local outer
+local inner = coroutine.create(function ()
+  print("inner", coroutine.status(outer), coroutine.isyieldable())
+  coroutine.yield()
+end)
+outer = coroutine.create(function ()
+  print("outer", coroutine.status(outer), coroutine.running() == outer)
+  coroutine.resume(inner)
+  print(coroutine.status(inner))
+  print(coroutine.resume(outer))
+end)
+coroutine.resume(outer)
+print(coroutine.status(outer), coroutine.isyieldable(), select(2, coroutine.running()))
+
+-- wrap : 每次调用resume一次
+local function range(n)
+  return coroutine.wrap(function ()
+    for i = 1, n do
+      coroutine.yield(i)
+    end
+  end)
+end
+for i in range(3) do
+  print("range", i)
+end
+
+-- 跨过pcall的yield , 恢复之后的错误依然被pcall捕获
+local co = coroutine.create(function ()
+  print("caught", pcall(function ()
+    local v = coroutine.yield("in pcall")
+    error("after " .. v)
+  end))
+  print("xpcall", xpcall(function ()
+    return coroutine.yield("in xpcall") + 1
+  end, function (m) return "handled: " .. m end))
+  return "end"
+end)
+print(coroutine.resume(co))
+print(coroutine.resume(co, "resume"))
+print(coroutine.resume(co, 41))
+print(coroutine.status(co))
+
+-- 协程中的错误通过resume返回
+local bad = coroutine.create(function () local x = nil; return x.y end)
+print(coroutine.resume(bad))
+print(coroutine.status(bad), coroutine.resume(bad))
+print(coroutine.resume(coroutine.create(function () error({code = 1}) end)))
+print(pcall(coroutine.resume, 1))
+
+-- wrap抛出的错误加上调用的位置
+local w = coroutine.wrap(function () error("boom") end)
+print(pcall(w))
+print(pcall(w))
+local w2 = coroutine.wrap(function () error({}) end)
+print(pcall(w2))
+
+-- 不能yield的地方
+print(pcall(coroutine.yield, 1))
+print(coroutine.resume(coroutine.create(function ()
+  return tostring(setmetatable({}, {__tostring = function () return coroutine.yield() end}))
+end)))
+print(coroutine.resume(coroutine.create(function ()
+  table.sort({3, 2, 1}, function (a, b) coroutine.yield() return a < b end)
+end)))
+print(coroutine.resume(coroutine.running()))
+print(coroutine.resume(coroutine.create(function () return coroutine.resume(coroutine.running()) end)))
+
+-- Upvalue在协程和主线程之间共享
+local count = 0
+local inc = coroutine.wrap(function ()
+  local mine = 100
+  local function bump() mine = mine + 1; count = count + 1; return mine end
+  while true do
+    coroutine.yield(bump)
+  end
+end)
+local bump = inc()
+print(bump(), bump(), count)
+inc()
+print(bump(), count)
+
+-- close : 关闭挂起的协程时执行还没关闭的to-be-closed变量
+local closer = setmetatable({}, {__close = function (_, e) print("closed", e) end})
+local function iter(_, i)
+  if i < 3 then return i + 1 end
+end
+local cl = coroutine.create(function ()
+  for i in iter, nil, 0, closer do
+    coroutine.yield(i)
+  end
+end)
+print(coroutine.resume(cl))
+print(coroutine.close(cl), coroutine.status(cl))
+print(coroutine.close(cl))
+local dead = coroutine.create(function () error("dead") end)
+coroutine.resume(dead)
+print(coroutine.close(dead))
+print(coroutine.close(coroutine.create(print)))
+print(pcall(coroutine.close, coroutine.running()))
+print(coroutine.resume(coroutine.create(function ()
+  return pcall(coroutine.close, outer)
+end)))
+
+-- Rust函数作为协程的主体
+local p = coroutine.wrap(print)
+p("from", "print")
+local y = coroutine.create(coroutine.yield)
+print(coroutine.resume(y, 1, 2))
+print(coroutine.resume(y, 3))
+print(coroutine.status(y))
+print(pcall(coroutine.create, 1))
+local w3 = coroutine.wrap(function () error("deep") end)
+print(pcall(function () w3() end))
+
+-- 元方法中yield : 恢复之后完成原来的取值、赋值、运算和比较
+local Y = coroutine.yield
+local mt = {
+  __index = function (_, k) return Y("index " .. k) end,
+  __newindex = function (t, k, v) rawset(t, k, Y("newindex " .. k) .. v) end,
+  __add = function () return Y("add") end,
+  __lt = function () return Y("lt") end,
+  __le = function () return Y("le") end,
+  __eq = function () return Y("eq") end,
+  __concat = function () return Y("concat") end,
+  __len = function () return Y("len") end,
+}
+local mco = coroutine.wrap(function ()
+  local a, b = setmetatable({}, mt), setmetatable({}, mt)
+  print("index", a.x)
+  a.y = "v"
+  print("newindex", rawget(a, "y"))
+  print("add", a + 1, "len", #a)
+  print("lt", a < b, "le", a <= b)
+  if a == b then print("eq", true) else print("eq", false) end
+  print("concat", a .. "s")
+  local o = setmetatable({}, {__index = function (_, k) return Y(k) end})
+  print("method", o:m())
+  return "done"
+end)
+local answers = {["index x"] = 10, ["newindex y"] = "new", add = 3, len = 4, lt = 1, le = false, eq = true, concat = "ab",
+  m = function (self) return getmetatable(self) ~= nil end}
+local r = mco()
+while r ~= "done" do
+  r = mco(answers[r])
+end
+
+-- __pairs中yield : pairs通过后续完成
+local pco = coroutine.wrap(function ()
+  local t = setmetatable({}, {__pairs = function (t)
+    local v = coroutine.yield("in __pairs")
+    return function (_, k) if not k then return 1, v end end, t, nil
+  end})
+  for k, v in pairs(t) do print("pair", k, v) end
+  return "done"
+end)
+print(pco())
+print(pco("resumed"))
@@ -0,0 +1,112 @@
+-- 调用链 : 局部函数、全局函数、字段、方法和尾调用
+local function lf() print(debug.traceback("traceback", 1)) end
+function gf() lf() end
+local t = {}
+function t.field() gf() end
+function t:meth() t.field() end
+local function tail() return t:meth() end
+tail()
+print(debug.traceback(nil), type(debug.traceback({})))
+
+-- pcall捕获的错误不带调用链
+local function boom() error("boom") end
+print(pcall(boom))
+
+-- 函数信息
+local function f(a, b, ...)
+  local x = 10
+  local info = debug.getinfo(1, "nSlu")
+  print(info.name, info.namewhat, info.short_src, info.what, info.currentline,
+    info.linedefined, info.lastlinedefined, info.nparams, info.isvararg)
+  return info
+end
+f(1, 2)
+local pi = debug.getinfo(print)
+print(pi.what, pi.short_src, pi.currentline, pi.func == print)
+print(debug.getinfo(1, "S").what, debug.getinfo(100), pcall(debug.getinfo, 1, "X"))
+
+-- 局部变量 : 负数是可变参数 , 函数作为参数时只有参数名
+local function locals(a, b, ...)
+  local x = "x"
+  for i = 1, 3 do
+    print("local", i, debug.getlocal(1, i))
+  end
+  print(debug.getlocal(1, -1), debug.getlocal(1, -2), debug.getlocal(1, -3))
+  print(debug.setlocal(1, 3, "y"), x)
+end
+locals(1, 2, "va1", "va2")
+print(debug.getlocal(locals, 1), debug.getlocal(locals, 2), debug.getlocal(locals, 3))
+
+-- 上值
+local up1, up2 = 1, 2
+local function g() return up1 + up2 end
+local function h() return up2 end
+print(debug.getupvalue(g, 1), debug.getupvalue(g, 2), debug.getupvalue(g, 3))
+print(debug.setupvalue(g, 1, 10), up1, g())
+print(debug.upvalueid(g, 2) == debug.upvalueid(h, 1), debug.upvalueid(g, 1) == debug.upvalueid(h, 1))
+debug.upvaluejoin(g, 1, h, 1)
+print(g(), pcall(debug.upvaluejoin, g, 5, h, 1))
+
+-- 元表 : 绕过__metatable , 可以给任何类型设置
+local obj = setmetatable({}, { __metatable = "locked" })
+print(getmetatable(obj), type(debug.getmetatable(obj)))
+debug.setmetatable(10, { __index = math })
+print((16):sqrt(), (2.5):floor())
+debug.setmetatable(10, nil)
+print(pcall(function () return (1):abs() end))
+print(debug.getmetatable("").__index == string)
+
+-- 名字推断
+local mt = { m = function () return debug.getinfo(1, "n") end }
+local function show(i) print(i.namewhat, i.name) end
+show(mt.m())
+show(mt:m())
+for _ in function () show(debug.getinfo(1, "n")) end do break end
+local meta = setmetatable({}, { __index = function () show(debug.getinfo(1, "n")) end })
+local _ = meta.x
+
+-- 钩子 : call/return/line事件
+local function add(a, b)
+  return a + b
+end
+local events = {}
+debug.sethook(function (ev, line)
+  local info = debug.getinfo(2, "n")
+  events[#events + 1] = ev .. ":" .. (line or info.name or "?")
+end, "crl")
+local s = add(1, 2)
+s = s + add(3, 4)
+debug.sethook()
+print(table.concat(events, " "))
+print(debug.gethook())
+
+-- 计数钩子
+local n = 0
+debug.sethook(function () n = n + 1 end, "", 10)
+for i = 1, 100 do local _ = i end
+debug.sethook()
+print(n > 0)
+
+-- 循环中的line事件 : 跳回循环开头算作for所在的行
+local lines = {}
+debug.sethook(function (_, l) lines[#lines + 1] = l end, "l")
+for i = 1, 2 do
+  local x = i
+end
+debug.sethook()
+print(table.concat(lines, ","))
+
+-- 钩子中出错 : 和普通错误一样传播
+print(pcall(function ()
+  debug.sethook(function () debug.sethook() error("in hook") end, "l")
+  local x = 1
+end))
+
+-- 改坏for循环的内部状态 : 普通的错误 , 可以被pcall捕获
+print(pcall(function ()
+  for i = 1, 3 do print(debug.getlocal(1, 1)) debug.setlocal(1, 1, "x") end
+end))
+
+-- 没有捕获的错误 : 解释器打印调用链
+local function deep() error("uncaught") end
+deep()
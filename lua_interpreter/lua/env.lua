@@ -0,0 +1,70 @@
+-- 全局变量就是_ENV的成员 , _G是全局变量表本身
+x = 10
+print(_G.x, _ENV.x, _G == _ENV, _G._G == _G, package.loaded._G == _G)
+_G.y = 20
+print(y, rawget(_G, "y"))
+
+-- 遍历全局变量
+local names = {}
+for k, v in pairs(_G) do
+  if type(v) == "table" and k ~= "_G" and k ~= "package" then
+    names[#names + 1] = k
+  end
+end
+table.sort(names)
+print(table.concat(names, " "))
+
+-- local _ENV : 之后的全局变量都访问这个table
+local function sandbox()
+  local print = print
+  local _ENV = {z = 1}
+  w = 2
+  print(z, w, x, _ENV.w)
+  local function inner() return z + w end
+  print(inner())
+end
+sandbox()
+print(w, z)
+
+-- 函数参数也可以是_ENV
+local function with_env(_ENV)
+  return a + b
+end
+print(with_env({a = 1, b = 2}))
+
+-- 全局变量表的元表 : 访问未定义的变量时报错
+setmetatable(_G, {
+  __index = function (_, k) error("undefined variable '" .. k .. "'", 2) end,
+  __newindex = function (t, k, v) print("new global", k); rawset(t, k, v) end,
+})
+print(pcall(function () return undefined_name end))
+fresh = 1
+fresh = 2
+print(fresh)
+setmetatable(_G, nil)
+
+-- load : 字符串或者读取函数 , 可以指定_ENV
+local f = load("return x + ...")
+print(f(5))
+local env = {x = 100}
+local g = load("x = x + 1; return x", "chunk", "t", env)
+print(g(), g(), env.x, x)
+local parts = {"return ", "1 ", "+ 2"}
+local i = 0
+local r = load(function () i = i + 1; return parts[i] end)
+print(r())
+local n = load("return _ENV", "nil env", "t", nil)
+print(n())
+print(load("return 1", "binary", "b"))
+print(load("\27Lua", "binary", "t"))
+print(load(function () return {} end))
+print(load(function () error("reader failed") end))
+print(pcall(load, 1))
+print(pcall(load("error('in chunk')", "=mychunk")))
+print(pcall(load("error('in chunk')")))
+
+-- 常量很多时全局变量名的常量索引超出字节码参数
+local big = {}
+for n = 1, 300 do big[n] = "v" .. n .. " = " .. n end
+local src = table.concat(big, "; ") .. "; return v1 + v300"
+print(load(src)(), v299)
@@ -0,0 +1,48 @@
+-- 常量折叠测试 : 比较的行都应该输出true
+-- 左边在编译期折叠,右边通过局部变量在运行时计算,两边结果必须完全一致(包括整数/浮点数的区别)
+local one = 1
+local two = 2
+local three = 3
+local half = 0.5
+local zero = 0
+
+print(1 + 2 == one + two, 1 - 2 == one - two, 2 * 3 == two * three)
+print(3 / 2 == three / two, 3 // 2 == three // two, 3 % 2 == three % two)
+print(-3 // 2 == -three // two, -3 % 2 == -three % two, 3 % -2 == three % -two)
+print(2 ^ 3 == two ^ three, 0.5 + 1 == half + one, 1 // 0.5 == one // half)
+print(1 & 3 == one & three, 1 | 2 == one | two, 1 ~ 3 == one ~ three)
+print(1 << 2 == one << two, 3 >> 1 == three >> one, ~1 == ~one)
+print(1 << 64 == one << 64, 1 << -1 == one << -1, -1 >> 1 == -one >> one)
+print(- -1 == one, -(1 + 2) == -(one + two), 1.0 + 2 == 3.0)
+print(1 < 2 == (one < two), 2 <= 2 == (two <= two), 3 > 2 == (three > two))
+print("a" < "b", "a" .. "b" == "ab", not nil == true, not 0 == false)
+print(1 == 1.0, (1 == 2) == false, 1 ~= 2)
+print((nil or 3) == 3, (false and 1) == false, (1 and 2) == 2, (1 or error) == 1)
+
+-- 折叠结果的类型 : ==不区分1和1.0 , 每两行中上面折叠、下面运行时计算 , 输出必须一样
+print(math.type(1 + 2), math.type(3 / 1), math.type(3 // 2), math.type(3.0 // 2), math.type(2 ^ 2), math.type(7 % 3), math.type(7.0 % 3))
+print(math.type(one + two), math.type(three / one), math.type(three // two), math.type(3.0 // two), math.type(two ^ two), math.type(7 % three), math.type(7.0 % three))
+print(math.type(1 << 2), math.type(~1), math.type(- -1), math.type(1.0 + 2), math.type(0.5 + 1), math.type(-(1 + 2)), math.type(1 // 0.5))
+print(math.type(one << two), math.type(~one), math.type(- -one), math.type(1.0 + two), math.type(half + one), math.type(-(one + two)), math.type(one // half))
+
+-- 不折叠的情况 : 运行时会报错或者结果是NaN/-0.0的运算保持原样
+print(1 // 0.0 == one // 0.0, -1 // 0.0 == -one // 0.0)
+print(0 / 0 ~= 0 / 0, zero / zero ~= zero / zero)
+print(-0.0 == 0.0, 1 / -0.0 == one / -(zero + 0.0))
+print(pcall(function () return 1 % 0 end))
+print(pcall(function () return one // zero end))
+
+-- 整数和浮点数在输出上要区分开
+print(3 // 2, 3.0 // 2, 3 / 1, 2 ^ 2, 7 % 3, 7.0 % 3)
+print(three // two, 3.0 // two, three / one, two ^ two, 7 % three, 7.0 % three)
+
+-- 死分支消除
+if false then print(false) end
+if nil then print(false) elseif 1 then print(true) else print(false) end
+while false do print(false) end
+local n = 0
+while true do
+    n = n + 1
+    if n == 3 then break end
+end
+print(n == 3)
@@ -0,0 +1,14 @@
+print(string.format("%5d|%-5d|%05d|%+d|% d|%.3d|%.0d|", 42, 42, -42, 42, 42, 7, 0))
+print(string.format("%x|%X|%#x|%#o|%5.2x|%u|%x", 255, 255, 255, 8, 10, -1, -1))
+print(string.format("%.3f|%10.2f|%05.0f|%e|%+.3E", 3.14159, 2.5, 100.4, 12345.678, 0.000123))
+print(string.format("%g|%g|%G|%#g|%-8.3g|%g", 0.0001, 2^63, 0.00001234, 1.0, 3.14159, 100000))
+print(string.format("%a|%A|%a|%.3a|%.0a|%#a", 1.5, 255.5, 0.1, 1/3, 1.9999, 2.0))
+print(string.format("%s|%10.3s|%-3c|%%", 1.5, "abcdef", 65))
+print(string.format("%q", "a\"b\\c\nd\re\0f\0001\1"))
+print(string.format("%q|%q|%q|%q|%q|%q", 42, -9223372036854775807 - 1, 0.1, 3.0, 1/0, -1/0))
+print(string.format("%q|%q", nil, true))
+print(1.5, 2^63, 1/3, -0.0, 100.0, 1/0, 2^53, 0.1)
+print(pcall(string.format, "%10q", "x"))
+print(pcall(string.format, "%q", {}))
+print(pcall(string.format, "%#d", 1))
+print(pcall(string.format, "%123d", 1))
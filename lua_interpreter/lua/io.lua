@@ -0,0 +1,54 @@
+local name = os and os.tmpname and os.tmpname() or "/tmp/lua_io_test.txt"
+local f = io.open(name, "w")
+local s = tostring(f)
+print(io.type(f), io.type(io.stdout), io.type(42), s:sub(1, 6))
+print(f:write("hello\n", 42, " ", 1.5, "\n", "0x1F -3.25e1 .5 abc\n", "last") == f)
+f:close()
+print(io.type(f), tostring(f), pcall(f.write, f, "x"))
+
+f = io.open(name)
+print(f:read())
+print(f:read("L"))
+print(f:read("n", "n", "n", "n"))
+print(f:read("l"))
+print(f:read(2), f:read("a"), f:read("a"), f:read(0), f:read("l"))
+print(f:seek("set", 2), f:read(3), f:seek(), f:seek("end"))
+print(pcall(f.seek, f, "bad"))
+print(pcall(f.read, f, "x"))
+f:close()
+
+for l in io.lines(name) do io.write("[", l, "]") end
+print()
+for a, b in io.lines(name, 1, "l") do print(a, b) end
+f = io.open(name)
+for l in f:lines("L") do io.write(l) end
+print()
+print(io.type(f))
+f:close()
+
+print(io.open("/nonexistent/dir/file"))
+print(pcall(io.open, name, "rw"))
+print(pcall(io.lines, "/nonexistent/file"))
+print(io.stdout:close())
+
+local t = io.tmpfile()
+t:write("abc", "def")
+t:seek("set")
+print(t:read("a"))
+t:close()
+
+f = io.open(name, "a+")
+f:write("\nappended")
+f:seek("set")
+print(f:read("a"))
+f:close()
+
+print(io.input() == io.stdin, io.output() == io.stdout)
+io.input(name)
+print(io.read("l"), io.read(1))
+io.input():close()
+print(pcall(io.read))
+io.input(io.stdin)
+print(string.char(228, 189, 160), "\xff\xfe" == string.char(255, 254))
+io.stdout:write("no newline")
+io.write(" then newline\n")
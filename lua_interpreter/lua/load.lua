@@ -0,0 +1,106 @@
+-- 运行时拼出代码再执行 , ...是调用时的参数
+local add = load("local a = ... return a * select(2, ...)")
+print(add(1, 2))
+local f = load("return 'chunk', ...", "=gen")
+print(f(1, nil, 3))
+
+-- 语法错误返回nil和错误信息 , 不会中断脚本
+print(load("x ="))
+print(load("x = = 1", "=cfg"))
+print(load("for i = 1 do end", "@settings.lua"))
+print(load("local t = {1, 2\nprint(t)"))
+print(load("return 1 +"))
+print(load("if x then", "=if"))
+print(load("f() = 1", "=call"))
+print(load("local a (a) = 2", "=paren"))
+print(load("(t.x) = 3", "=paren"))
+print(load("local t = {} (t).x = 3 (t)[1] = 4 return t.x + t[1]", "=paren")())
+print(load("x y", "=stat"))
+print(load("return ...", "=vararg"))
+print(load("function f() return ... end", "=vararg"))
+print(load("goto nowhere", "=goto"))
+print(load("do goto l1 local a ::l1:: print(a) end", "=goto"))
+print(load("::a:: ::a::", "=label"))
+print(load("break", "=break"))
+print(load("x = 'unfinished\nstring'", "=str"))
+print(load("x = 'bad \\q escape'", "=str"))
+print(load("x = '\\300'", "=str"))
+print(load("x = '\\xZZ'", "=str"))
+print(load("x = 3x", "=num"))
+print(load("x = @", "=sym"))
+print(load("local function (a) end", "=name"))
+print(load("return (1", "=paren"))
+-- nil和NaN作为key在运行时才报错
+print(pcall(load("t = {[nil] = 1}", "=key")))
+print(pcall(load("t = {1, x = 2, [0/0] = 3}", "=key")))
+print(load("return 1 end", "=end"))
+
+-- 栈位置用完 : 嵌套太深的table构造 , 或者已经有很多局部变量
+print(load("return " .. ("{"):rep(300), "=regs"))
+local names = {}
+for i = 1, 199 do names[i] = "a" .. i end
+print(load("local " .. table.concat(names, ",") .. " = 1 local t = " .. ("{"):rep(60), "=regs"))
+print(load("return " .. ("{"):rep(100) .. ("}"):rep(100), "=regs") ~= nil)
+print(load("x = " .. ("x and "):rep(300) .. "x or " .. ("x or "):rep(300) .. "x", "=regs") ~= nil)
+
+-- 嵌套层数太多 : 报语法错误 , 不会耗尽栈
+print(load("return " .. ("not "):rep(100000) .. "x", "=levels"))
+print(load("return " .. ("("):rep(100000) .. "1", "=levels"))
+print(load(("do "):rep(100000), "=levels"))
+print(load("return " .. ("("):rep(150) .. "1" .. (")"):rep(150), "=levels")())
+
+-- 读取函数 : 一段一段地返回源代码 , 返回nil或空串结束
+local parts = {"return ", "'pie", "ces', ", "#{...}"}
+local i = 0
+local pieces = load(function ()
+  i = i + 1
+  return parts[i]
+end)
+print(pieces(1, 2, 3))
+local n = 0
+print(load(function ()
+  n = n + 1
+  if n == 1 then return "return 1 +" end
+end))
+
+-- mode和env
+print(load("return 1", "=m", "b"))
+print(load("\27Lua", "=m", "t"))
+local sandbox = {tostring = tostring}
+local g = load("v = 7 return tostring(v)", "=env", "t", sandbox)
+print(g(), sandbox.v, v)
+
+-- loadfile : 和load一样返回函数 , 不存在的文件返回nil和错误信息
+local cfg = loadfile("lua/modules/config.lua")
+print(cfg("custom"))
+print(width, height)
+local env = {}
+local cfg2 = loadfile("lua/modules/config.lua", "t", env)
+print(cfg2(), env.width, rawget(env, "height"))
+print(loadfile("lua/modules/missing.lua"))
+print(loadfile("lua/modules/broken.lua"))
+print(loadfile("lua/modules/config.lua", "b"))
+
+-- dofile : 直接执行文件 , 返回全部返回值 , 错误直接抛出
+width = nil
+print(dofile("lua/modules/config.lua"))
+print(width)
+print(pcall(dofile, "lua/modules/missing.lua"))
+print(pcall(dofile, "lua/modules/broken.lua"))
+
+-- require加载语法错误的模块
+package.path = "lua/modules/?.lua"
+print(pcall(require, "broken"))
+
+-- dofile执行的代码块中可以yield
+local co = coroutine.wrap(function ()
+  local tmp = os.tmpname()
+  local fh = io.open(tmp, "w")
+  fh:write("coroutine.yield('inside') return 'after'")
+  fh:close()
+  local r = dofile(tmp)
+  os.remove(tmp)
+  return r
+end)
+print(co())
+print(co())
@@ -0,0 +1,33 @@
+-- 数值for循环 : 一次都不执行时从循环之后的第一条语句继续
+local x = 7
+for i = 3, 1 do end
+x = 9
+print(x)
+for i = 3, 1 do end
+print("after empty loop")
+
+-- 浮点数的上限和步长
+local y = 1
+for i = 1, 0.5 do y = 0 end
+y = y + 1
+print(y)
+for i = 1.0, 0 do end
+print("float start")
+for i = 1, 2, 0.5 do io.write(i, " ") end
+print()
+for i = 10, 1, -3 do io.write(i, " ") end
+print()
+
+-- 循环变量被闭包捕获 : 循环结束后的Close也不能被跳过
+local fs = {}
+for i = 3, 1 do fs[#fs + 1] = function () return i end end
+print(#fs)
+for i = 1, 2 do fs[#fs + 1] = function () return i end end
+print(#fs, fs[1](), fs[2]())
+
+-- break跳到循环之后
+for i = 1, 3 do
+  if i == 2 then break end
+  print("loop", i)
+end
+print("after break")
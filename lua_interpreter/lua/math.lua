@@ -0,0 +1,24 @@
+print(math.abs(-3), math.abs(-3.5), math.abs(math.mininteger), math.ceil(3.2), math.floor(-3.2), math.ceil(2^70))
+print(math.fmod(7, 3), math.fmod(-7, 3), math.fmod(7, -1), math.fmod(7.5, 2), pcall(math.fmod, 1, 0))
+print(math.modf(3.7), math.modf(-3.7), math.modf(5), math.modf(1/0))
+-- 和Lua 5.4一致 : 整数参数原样返回 , 小数部分是浮点数 ; -0.0的整数部分保留符号 , 小数部分是0.0
+print(math.modf(-0.0))
+print(math.type((math.modf(5))), math.type((math.modf(5.0))), math.modf(-2.5))
+print(math.sqrt(16), math.exp(0), math.log(8, 2), math.log(100, 10), math.log(27, 3), math.log(1))
+print(math.sin(0), math.cos(0), math.atan(1, 1) * 4 == math.pi, math.atan(1))
+print(math.tointeger(3.0), math.tointeger(3.5), math.tointeger("8"), math.tointeger({}))
+print(math.type(1), math.type(1.0), math.type("1"), pcall(math.type))
+print(math.ult(1, -1), math.ult(-1, 1), math.max(1, 2.5, 2), math.min(3, 1.0, 1), math.max(4))
+print(pcall(math.max))
+print(math.huge, -math.huge, math.pi, math.maxinteger, math.mininteger)
+print(math.randomseed(42))
+local t = {}
+for i = 1, 5 do t[#t + 1] = math.random(1, 100) end
+print(t[1], t[2], t[3], t[4], t[5])
+math.randomseed(42)
+print(math.random(1, 100) == t[1], math.random(0), math.random())
+print(math.random(3), math.random(-10, -5), math.random(math.mininteger, math.maxinteger) ~= nil)
+print(pcall(math.random, 2, 1))
+print(pcall(math.random, 1, 2, 3))
+local r = math.random()
+print(r >= 0 and r < 1)
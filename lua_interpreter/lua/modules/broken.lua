@@ -0,0 +1,5 @@
+local t = {
+  answer = 42
+  question = "?"
+}
+return t
@@ -0,0 +1,5 @@
+-- 被dofile/loadfile执行的配置 , 通过...接收参数
+local name = ...
+width = 640
+height = 480
+return name or "default", width * height
@@ -0,0 +1,10 @@
+-- require的两个参数 : 模块名和找到的文件名
+local name = ...
+print("loading", name, select(2, ...))
+local M = {}
+M.count = 0
+function M.hello(who)
+  M.count = M.count + 1
+  return "hello, " .. who
+end
+return M
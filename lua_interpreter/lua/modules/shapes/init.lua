@@ -0,0 +1,2 @@
+-- 没有返回值的模块 : package.loaded中是true
+shapes_loaded = (shapes_loaded or 0) + 1
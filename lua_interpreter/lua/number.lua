@@ -0,0 +1,44 @@
+-- 整数运算溢出时回绕
+print(math.maxinteger + 1 == math.mininteger, math.maxinteger * 2, math.mininteger // -1)
+
+-- 整数和浮点数精确比较 , 不经过转换成浮点数
+print(2^53 == 2^53 + 1, math.maxinteger + 0.0 == math.maxinteger, math.maxinteger < math.maxinteger + 0.0)
+print(9007199254740993 < 9007199254740992.0, 9007199254740993 == 9007199254740992.0)
+print(math.mininteger <= -2^63, math.mininteger < -2^63, 1 < 0/0, 1 == 1.0)
+
+-- 浮点数转整数 : 没有小数部分并且在整数范围内才可以
+print(3 | 0, 3.0 | 0, pcall(function () return 3.5 | 0 end))
+print(string.format("%d", 3.0), pcall(string.format, "%d", 3.5))
+print(("x"):rep(2.0), pcall(string.rep, "x", 2.5))
+print(math.tointeger(3.0), math.tointeger(3.5), math.tointeger(2^63))
+local t = {}
+t[1.0] = "a"
+t[2^53] = "b"
+print(t[1], t[2^53 | 0], math.type(next(t)))
+
+-- 字符串转数字和数字字面量的语法一样
+print("10" + 1, "3.0" * 2, "0x10" | 1, " 7 " // 2, -"2")
+print(tonumber("  0x1p4  "), tonumber("1e2"), tonumber(".5"), tonumber("5."), tonumber("0x.8"))
+print(tonumber("1e"), tonumber("inf"), tonumber("nan"), tonumber("1 2"), tonumber(""))
+print(tonumber("9223372036854775808"), tonumber("-9223372036854775808"), tonumber("0xffffffffffffffff"))
+print(pcall(function () return "abc" + 1 end))
+print(pcall(function () return {} .. "" end))
+
+-- 浮点数用%.14g输出 , 整数值的浮点数带".0"
+print(1e15, 1e100, 2^63, -0.0, 1/0, -1/0, 100 / 2, 0.1 + 0.2)
+print(100000000000000, 1e14, 123456789012345678, 3.0 .. "", 1.5 .. "|")
+
+-- 长字符串和长注释
+local s = [[
+first
+second]]
+print(s, #s)
+print([==[a]]b]=]c]==], [[]], [=[]]]=])
+--[[ 长注释
+print("skipped") ]] print("after comment")
+--[==[
+]]
+]==]
+print(load("x = [==[ abc"))
+print(load("x = [= abc"))
+print(load("--[[ abc"))
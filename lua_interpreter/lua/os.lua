@@ -0,0 +1,30 @@
+local t = 1700000000
+print(os.date("!%Y-%m-%d %H:%M:%S", t), os.date("!%c", t), os.date("!%x %X %p %Z %z", t))
+print(os.date("!%a %A %b %B %C %d %D %e %F %g %G %h %I %j %m %M %n %r %R %S %t %T %u %U %V %w %W %y %%", t))
+print(os.date("!%Ec|%EY|%Od|%OH", 0))
+local d = os.date("!*t", t)
+print(d.year, d.month, d.day, d.hour, d.min, d.sec, d.wday, d.yday, d.isdst)
+print(os.date("!%G-W%V-%u", 1104537600), os.date("!%G-W%V-%u", 1230681600), os.date("!%U %W", 1104537600))
+print(pcall(os.date, "%Ez"), pcall(os.date, "%"))
+
+local lt = os.date("*t", t)
+print(os.time(lt) == t, os.date("%H:%M %Z", t))
+local n = {year = 2024, month = 14, day = 31, hour = 25, min = -5, sec = 70}
+local r = os.time(n)
+print(r == os.time({year = 2025, month = 3, day = 4, hour = 0, min = 56, sec = 10}), n.year, n.month, n.day, n.hour, n.min, n.sec, n.wday, n.yday)
+print(os.date("%Y-%m-%d %H:%M", os.time({year = 2000, month = 1, day = 1})))
+print(pcall(os.time, {year = 2000}), pcall(os.time, {year = 2000, month = "x", day = 1}))
+print(pcall(os.time, {year = 2000, month = 1, day = 1.5}), pcall(os.time, {year = 2^40, month = 1, day = 1}))
+print(os.time({year = "2000", month = 1.0, day = 1}) == os.time({year = 2000, month = 1, day = 1}))
+
+print(math.type(os.time()), os.time() > 1700000000, math.type(os.clock()), os.clock() >= 0)
+print(os.difftime(t, t - 90), os.difftime(5))
+print(os.getenv("HOME") ~= nil, os.getenv("NO_SUCH_VARIABLE_HOPEFULLY"))
+
+local name = os.tmpname()
+print(io.type(io.open(name)), name:sub(1, 9))
+local new = name .. ".renamed"
+print(os.rename(name, new))
+print(os.rename(name, new))
+print(os.remove(new))
+print(os.remove(new))
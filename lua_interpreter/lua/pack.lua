@@ -0,0 +1,29 @@
+local function hex(s) return (s:gsub(".", function(c) return string.format("%02x", c:byte()) end)) end
+print(hex(string.pack("i4", 100)), hex(string.pack(">I2", 0x1234)), hex(string.pack("<h>h", -2, -2)))
+print(string.packsize("i4i8"), string.packsize("!i4i8"), string.packsize("!4 i1 i8"), string.packsize("c10 x b Xi4"))
+print(pcall(string.packsize, "!8 i3 i8"))
+print(pcall(string.pack, "b", 200))
+print(pcall(string.pack, "B", -1))
+print(string.unpack("<i16", string.pack("<i16", -2)))
+print(string.unpack(">i16", string.pack(">i16", 9007199254740993)))
+print(string.unpack("z", "abc" .. string.char(0) .. "def"))
+print(pcall(string.pack, "s1", string.rep("x", 256)))
+print(pcall(string.unpack, "z", "abc"))
+print(pcall(string.unpack, "i4", "abc"))
+print(pcall(string.unpack, "i4", "abcd", 10))
+local bin = string.pack(">s2 d f z B", "hello", 3.25, 1.5, "zs", 255)
+print(#bin, hex(bin))
+print(string.unpack(">s2 d f z B", bin))
+print(string.unpack("<I3", string.char(1, 2, 3)), string.unpack("<i3", string.char(255, 255, 255)))
+print(pcall(string.unpack, "<i9", string.char(0,0,0,0,0,0,0,0,1)))
+print(string.unpack("<i9", string.char(255,255,255,255,255,255,255,255,255)))
+print(pcall(string.pack, "i17", 1))
+print(pcall(string.pack, "y", 1))
+print(pcall(string.pack, "c", 1))
+print(pcall(string.pack, "Xc1"))
+print(hex(string.pack("!4 b Xi4 i4", 1, 2)), string.unpack("c3", "abcdef", 2))
+print(hex(string.pack("j", -1)), string.unpack("j", string.pack("j", -9223372036854775807 - 1)))
+print(pcall(string.pack, "z", "a" .. string.char(0)))
+print(pcall(string.pack, "c2", "abc"), hex(string.pack("c5", "ab")))
+local all = string.char(0, 1, 127, 128, 200, 255)
+print(string.unpack("s", string.pack("s", all)) == all)
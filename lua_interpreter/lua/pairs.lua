@@ -0,0 +1,44 @@
+local t = {10, 20, 30, x = 1, y = 2, z = 3}
+for k, v in pairs(t) do print(k, v) end
+for i, v in ipairs(t) do print(i, v) end
+-- assigning existing fields (including nil) during traversal
+local n = 0
+for k, v in pairs(t) do
+  t[k] = nil
+  n = n + 1
+end
+print(n, next(t))
+local u = {a = 1, b = 2, c = 3, d = 4}
+for k in pairs(u) do u[k] = k .. "!" end
+for k, v in pairs(u) do print(k, v) end
+print(next({}), pcall(next, {}, "nokey"))
+local p = setmetatable({}, {__pairs = function(t) return function(_, i) if i < 3 then return i + 1 end end, t, 0 end})
+for i in pairs(p) do print("pairs meta", i) end
+local ip = setmetatable({}, {__index = function(_, i) if i <= 3 then return i * 10 end end})
+for i, v in ipairs(ip) do print("ipairs index", i, v) end
+-- closures capture a fresh loop variable per iteration
+local fs = {}
+for i, v in ipairs({"a", "b", "c"}) do fs[i] = function() return v end end
+print(fs[1](), fs[2](), fs[3]())
+-- closing value
+local function closer(name)
+  return setmetatable({}, {__close = function(_, err) print("closed", name, err) end})
+end
+local function iter(s, i) if i < 2 then return i + 1 end end
+for i in iter, nil, 0, closer("normal") do print("it", i) end
+for i in iter, nil, 0, closer("break") do break end
+print(pcall(function()
+  for i in iter, nil, 0, closer("error") do error("oops") end
+end))
+local function early()
+  for i in iter, nil, 0, closer("return") do return i end
+end
+print(early())
+do
+  for i in iter, nil, 0, closer("goto") do goto out end
+  ::out::
+end
+print(pcall(function() for i in iter, nil, 0, 42 do end end))
+for a, b, c in function(_, i) if i < 2 then return i + 1, "b", "c" end end, nil, 0 do print(a, b, c) end
+-- nested
+for k1 in pairs({1, 2}) do for k2 in pairs({1, 2}) do cnt = (cnt or 0) + 1 end print(k1) end
@@ -0,0 +1,45 @@
+-- package.path中的'?'替换成模块名 , 模块名中的'.'替换成目录分隔符
+package.path = "lua/modules/?.lua;lua/modules/?/init.lua"
+local greet = require("greet")
+print(greet.hello("lua"), greet.count)
+print(require("greet") == greet, package.loaded.greet == greet)
+print(require("shapes"), shapes_loaded, require("shapes"), shapes_loaded, package.loaded.shapes)
+print(package.loaded.string == string, package.loaded.package == package, package.config)
+
+-- searchpath报告尝试过的每个文件
+print(package.searchpath("greet", package.path))
+print(package.searchpath("a.b.c", "x/?.lua;;y/?/z.lua"))
+print(package.searchpath("a.b", "?.x", "", ""))
+print(package.searchpath("a_b", "lua/modules/?.lua", "_", "/"))
+
+-- 找不到模块时列出每个查找函数的说明
+print(pcall(require, "nope.mod"))
+
+-- package.preload
+package.preload.virtual = function (name, extra)
+  print("preload", name, extra)
+  return {name = name}
+end
+print(require("virtual").name, select(2, require("virtual")))
+
+-- 扩展查找函数 : 从table中的"源代码"加载
+local bundle = {embedded = function (name) return {from = "bundle", name = name} end}
+table.insert(package.searchers, function (name)
+  local f = bundle[name]
+  if f then return f, "bundle:" .. name end
+  return "no bundle entry '" .. name .. "'"
+end)
+local e = require("embedded")
+print(e.from, e.name)
+print(pcall(require, "missing"))
+
+-- 加载函数中的错误直接传出
+package.preload.broken = function () error("broken module") end
+print(pcall(require, "broken"))
+print(package.loaded.broken)
+local searchers = package.searchers
+package.searchers = nil
+print(pcall(require, "other"))
+package.searchers = searchers
+package.path = nil
+print(pcall(require, "other"))
@@ -0,0 +1,21 @@
+local s = "hello world"
+print(#s, s:len(), s:upper(), ("ABC"):lower(), s:reverse())
+print(s:sub(1, 5), s:sub(-5), s:sub(3, -3), s:byte(1, 3))
+print(("ab"):rep(3, ","), string.char(72, 105))
+print(s:find("wor"))
+print(s:find("o", 6), s:find(".", 1, true))
+print(("key = value"):match("^(%w+)%s*=%s*(%w+)$"))
+print(("  trim  "):match("^%s*(.-)%s*$") .. "|")
+print(("f(a(b)c)d"):match("%b()"), ("THE (quick) fox"):find("%f[%a]%a+"))
+print(("hello"):match("()ll()"))
+for k, v in ("a=1, b=2, c=3"):gmatch("(%w+)=(%w+)") do print(k, v) end
+print(("hello world"):gsub("(%w+)", "<%1>"))
+print(("$name is $age"):gsub("%$(%w+)", {name = "bob", age = 42}))
+print(("abc"):gsub("%w", function(c) return c:upper() .. "." end))
+print(("abc"):gsub("", "-"))
+print(string.format("%5d|%-5d|%05d|%+d|%x|%#X|%o", 42, 42, 42, 42, 255, 255, 8))
+print(string.format("%.3f|%10.2f|%e|%g|%s|%.2s|%c", 3.14159, 2.5, 12345.678, 0.0001, "hi", "hello", 65))
+print(pcall(string.format, "%y", 1))
+print(pcall(string.find, "abc", "[a"))
+print(pcall(string.gsub, "abc", "a", "%2"))
+print(pcall(string.match, string.rep("a", 300), string.rep("a?", 300) .. string.rep("a", 300)))
@@ -0,0 +1,24 @@
+-- table构造 : 全部是常量的table在编译期构造好,每次执行都拷贝出一个新的table
+local t = {1, 2, "x", k = 3; [10] = 4, [1.0] = 9, [2^53] = 5,}
+print(t[1], t[2], t[3], t.k, t[10], #t, t[2^53])
+local a = {}
+local b = {}
+print(a == b)
+local i = 0
+local last = nil
+while i < 2 do
+  local c = {1, 2}
+  c[1] = c[1] + i
+  print(c[1], last == c)
+  last = c
+  i = i + 1
+end
+local x = 7
+local m = {x, x + 1, y = x, [x] = "seven"}
+print(m[1], m[2], m.y, m[7])
+local big = {1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31,32,33,34,35,36,37,38,39,40,41,42,43,44,45,46,47,48,49,50,51,52,53}
+print(#big, big[50], big[53])
+local big2 = {x,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31,32,33,34,35,36,37,38,39,40,41,42,43,44,45,46,47,48,49,50,51,52,53}
+print(#big2, big2[1], big2[50], big2[53])
+local n = {{1}, {2}}
+print(n[1][1], n[2][1])
@@ -0,0 +1,49 @@
+local t = {1, 2, 3}
+table.insert(t, 4)
+table.insert(t, 1, 0)
+print(table.concat(t, ","), #t)
+print(table.remove(t), table.remove(t, 1), table.concat(t, ","))
+print(pcall(table.insert, t, 10, 1))
+print(pcall(table.insert, t, 1, 2, 3))
+print(table.remove({}), #t, table.remove(t, #t + 1))
+print(table.concat({1, 2.5, "x"}, "-", 2, 3), table.concat({}), table.concat({"a", "b"}, ", ", 3))
+print(pcall(table.concat, {1, {}, 3}))
+local p = table.pack(1, nil, 3)
+print(p.n, p[1], p[2], p[3])
+print(table.unpack({1, 2, 3}), table.unpack({1, 2, 3}, 2), table.unpack({1, 2}, 1, 4))
+print(table.concat(table.move({1, 2, 3, 4, 5}, 2, 4, 1), ","), table.concat(table.move({1, 2, 3}, 1, 3, 3), ","))
+print(table.concat(table.move({1, 2, 3}, 1, 3, 2, {}), ",", 2, 4))
+local s = {5, 2, 8, 1, 9, 3, 7, 4, 6, 0}
+table.sort(s)
+print(table.concat(s, " "))
+table.sort(s, function(a, b) return a > b end)
+print(table.concat(s, " "))
+local words = {"banana", "apple", "Cherry", "date"}
+table.sort(words)
+print(table.concat(words, " "))
+print(pcall(table.sort, {1, "x", 2}))
+print(pcall(table.sort, {3, 1, 2, 5, 4}, function(a, b) return true end))
+print(pcall(table.sort, {1, 2}, 3))
+-- large sort with many duplicates, checked for order
+local big = {}
+math.randomseed(7)
+for i = 1, 20000 do big[i] = math.random(1, 100) end
+table.sort(big)
+local ok = true
+for i = 2, #big do if big[i - 1] > big[i] then ok = false end end
+print("sorted", ok, #big)
+local desc = {}
+for i = 1, 20000 do desc[i] = 20001 - i end
+table.sort(desc)
+print(desc[1], desc[20000])
+-- proxies through metamethods
+local store = {10, 20, 30}
+local proxy = setmetatable({}, {
+  __index = function(_, k) return store[k] end,
+  __newindex = function(_, k, v) store[k] = v end,
+  __len = function() return #store end,
+})
+table.insert(proxy, 40)
+table.sort(proxy, function(a, b) return a > b end)
+print(table.concat(proxy, ","), table.unpack(proxy))
+print(pcall(table.insert, nil, 1))
@@ -0,0 +1,58 @@
+-- 尾调用不占用新的栈空间 : 远超过调用层数上限的递归
+local function count(n, acc)
+  if n == 0 then return acc end
+  return count(n - 1, acc + 1)
+end
+print(count(1000000, 0))
+
+-- 状态机 : 状态之间互相尾调用
+local even, odd
+function even(n) if n == 0 then return "even" end return odd(n - 1) end
+function odd(n) if n == 0 then return "odd" end return even(n - 1) end
+print(even(300001), odd(300001))
+
+-- 普通递归依然有上限
+local function depth(n) if n == 0 then return 0 end return 1 + depth(n - 1) end
+print(pcall(depth, 1000000))
+
+-- 尾调用的返回值全部交给调用方 , 由调用方截断
+local function multi(...) return ... end
+local function pass(...) return multi(...) end
+print(pass(1, 2, 3), (pass(4, 5)))
+local t = {pass(6, 7, 8)}
+print(#t, select("#", pass()))
+
+-- Rust函数、方法和__call
+local function len(s) return s:len() end
+local function sel(...) return select("#", ...) end
+local callable = {}
+setmetatable(callable, {__call = function (self, a, b) return self == callable, a + b end})
+local function via_call(a) return callable(a, 1) end
+print(len("hello"), sel(nil, nil), via_call(41))
+local function fail() return error("tail error") end
+print(pcall(fail))
+local function bad() return (nil)() end
+print(pcall(bad))
+
+-- 尾调用的Rust函数中yield
+local co = coroutine.wrap(function (a)
+  local function step(x) return coroutine.yield(x * 2) end
+  local b = step(a)
+  return step(b)
+end)
+print(co(1), co(10), co(100))
+
+-- 括号、to-be-closed变量和泛型for中的return都不是尾调用 , 结果依然正确
+local closer = setmetatable({}, {__close = function () print("closed") end})
+local function in_tbc()
+  local c <close> = closer
+  return multi("after", "close")
+end
+print(in_tbc())
+local function in_for()
+  for i in function (_, i) if not i then return 1 end end, nil, nil, closer do
+    return multi("loop", i)
+  end
+end
+print(in_for())
+print((multi(1, 2)))
@@ -0,0 +1,17 @@
+local s = "你好, Lua"
+print(#s, utf8.len(s), utf8.len(s, 4), utf8.len(s, -3))
+print(utf8.codepoint(s, 1, -1))
+print(utf8.char(20320, 22909, 44, 32, 76) == "你好, L", utf8.char(72, 0x7FFFFFFF):byte(1, -1))
+for p, c in utf8.codes(s) do print(p, c, utf8.char(c)) end
+print(utf8.offset(s, 2), utf8.offset(s, -1), utf8.offset(s, 0, 5), utf8.offset(s, 20), utf8.offset(s, 3, 4))
+print(pcall(utf8.offset, s, 1, 2))
+local bad = "a" .. string.char(0xFF) .. "b"
+print(utf8.len(bad), utf8.len(string.char(0xE4, 0xBD)), utf8.len(string.char(0xED, 0xA0, 0x80)))
+print(utf8.len(string.char(0xED, 0xA0, 0x80), 1, -1, true), utf8.codepoint(string.char(0xF4, 0x90, 0x80, 0x80), 1, 1, true))
+print(pcall(utf8.codepoint, bad, 1, -1))
+print(pcall(function() for p, c in utf8.codes(bad) do end end))
+print(pcall(utf8.char, -1), pcall(utf8.char, 0x80000000))
+print(pcall(utf8.len, s, 20))
+local n = 0
+for ch in s:gmatch(utf8.charpattern) do n = n + 1 end
+print(n, #utf8.charpattern)
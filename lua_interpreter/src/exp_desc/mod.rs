@@ -1,3 +1,5 @@
+use crate::interface::OpCode;
+
 /** ### ExpDesc : temp ast  */
 #[derive(Debug, PartialEq, Clone)]
 pub enum ExpDesc {
@@ -7,8 +9,16 @@ pub enum ExpDesc {
     Float(f64),
     String(Vec<u8>) /* 字符串 */,
     Local(usize) /* 临时变量 */,
-    Global(usize) /* 全局变量 */,
-    Index(usize, usize),
-    IndexField(usize, usize),
-    IndexInt(usize, u8),
+    Upvalue(usize) /* Upvalue : 在Upvalue列表中的索引 */,
+    Index(usize, usize) /* table栈位置|key栈位置 */,
+    IndexField(usize, usize) /* table栈位置|key常量位置 */,
+    IndexInt(usize, u8) /* table栈位置|整数key */,
+    IndexUpField(usize, usize) /* table所在的Upvalue索引|key常量位置 , 全局变量name就是_ENV.name */,
+    UnaryOp(OpCode, usize) /* 一元运算 : 操作|操作数栈位置 */,
+    BinaryOp(OpCode, usize, usize, bool) /* 二元运算 : 操作|左操作数栈位置|右操作数位置|右操作数是否在常量表 */,
+    Jump(usize) /* 已经生成的比较+Jmp,条件为真时跳转 : Jmp的位置 */,
+    Closure(usize) /* 子函数 : 在子函数原型列表中的索引 */,
+    Call(usize) /* 函数调用 : Call的位置 , 返回值从函数所在的栈位置开始 */,
+    VarArg(usize) /* 可变参数... : VarArg的位置 */,
+    Test(Box<ExpDesc>, Vec<usize>, Vec<usize>) /* 逻辑运算 : 最后一个操作数|为真时的跳转列表|为假时的跳转列表 */,
 }
@@ -0,0 +1,154 @@
+use std::{ rc::Rc, cell::RefCell };
+
+use crate::{ vm::{ ExeState, LuaThread, ThreadStatus }, interface::{ Value, LuaError, RustFunction } };
+
+use super::new_lib;
+
+/** coroutine库 : 注册到全局变量coroutine */
+pub fn open_coroutine(state: &mut ExeState) {
+    let funcs: [(&str, RustFunction); 8] = [
+        ("create", lib_create),
+        ("resume", lib_resume),
+        ("yield", lib_yield),
+        ("status", lib_status),
+        ("wrap", lib_wrap),
+        ("isyieldable", lib_isyieldable),
+        ("running", lib_running),
+        ("close", lib_close),
+    ];
+    state.set_global("coroutine", new_lib(&funcs));
+}
+
+fn check_thread(state: &ExeState, i: usize) -> Result<Rc<RefCell<LuaThread>>, LuaError> {
+    return match state.get(i) {
+        Value::Thread(co) => Ok(co),
+        _ => Err(state.type_error(i, "coroutine")),
+    };
+}
+
+fn check_function(state: &ExeState, i: usize) -> Result<Value, LuaError> {
+    let f = state.get(i);
+    if !f.is_function() {
+        return Err(state.type_error(i, "function"));
+    }
+    return Ok(f);
+}
+
+/** create(f) : 用函数f创建挂起的协程 */
+fn lib_create(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = check_function(state, 1)?;
+    let co = state.new_thread(f);
+    state.push(Value::Thread(co));
+    return Ok(1);
+}
+
+/** resume(co, ...) : 成功时返回true和yield(或者函数返回)的值 , 出错时返回false和错误 */
+fn lib_resume(state: &mut ExeState) -> Result<i32, LuaError> {
+    let co = check_thread(state, 1)?;
+    let nargs = state.get_top() - 1;
+    match state.resume(&co, nargs) {
+        Ok(n) => {
+            let i = state.stack.len() - n;
+            state.stack.insert(i, Value::Boolean(true));
+            return Ok(n as i32 + 1);
+        }
+        Err(e) if e.is_interrupt() => {
+            return Err(e);
+        }
+        Err(e) => {
+            state.push(false);
+            state.push(e.value);
+            return Ok(2);
+        }
+    }
+}
+
+/** yield(...) : 挂起当前协程 , 参数作为resume的返回值 ; 恢复时resume的参数作为yield的返回值 */
+fn lib_yield(state: &mut ExeState) -> Result<i32, LuaError> {
+    let n = state.get_top();
+    return state.yield_values(n);
+}
+
+/** status(co) : "running" , "suspended" , "normal" 或者 "dead" */
+fn lib_status(state: &mut ExeState) -> Result<i32, LuaError> {
+    let co = check_thread(state, 1)?;
+    let status = co.borrow().status();
+    state.push(status.name());
+    return Ok(1);
+}
+
+/** wrap(f) : 创建协程 , 返回每次调用就resume它一次的函数 ; 出错时关闭协程,把错误继续抛出 */
+fn lib_wrap(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = check_function(state, 1)?;
+    let co = state.new_thread(f);
+    state.push(Value::closure(move |state| {
+        let started = co.borrow().status() == ThreadStatus::Suspended;
+        let nargs = state.get_top();
+        match state.resume(&co, nargs) {
+            Ok(n) => {
+                return Ok(n as i32);
+            }
+            Err(e) if e.is_interrupt() => {
+                return Err(e);
+            }
+            Err(e) => {
+                /* 协程中出错 : 关闭它的to-be-closed变量 , __close出错时抛出新的错误 */
+                let e = if started { state.close_thread(&co).err().unwrap_or(e) } else { e };
+                if e.value.is_str() && !e.is_interrupt() {
+                    let mut msg = state.location(1).into_bytes();
+                    msg.extend_from_slice(<&[u8]>::from(&e.value));
+                    return Err(LuaError::new(msg));
+                }
+                return Err(e);
+            }
+        }
+    }));
+    return Ok(1);
+}
+
+/** isyieldable([co]) : 协程(默认是正在运行的)能否yield */
+fn lib_isyieldable(state: &mut ExeState) -> Result<i32, LuaError> {
+    let co = match state.get(1) {
+        Value::Nil => state.running().0,
+        _ => check_thread(state, 1)?,
+    };
+    let yieldable = state.is_yieldable(&co);
+    state.push(yieldable);
+    return Ok(1);
+}
+
+/** running() : 正在运行的协程 , 以及它是不是主线程 */
+fn lib_running(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (co, is_main) = state.running();
+    state.push(Value::Thread(co));
+    state.push(is_main);
+    return Ok(2);
+}
+
+/** close(co) : 关闭挂起或者结束的协程 , 关闭它所有还没关闭的to-be-closed变量 ;
+    成功时返回true , 协程出错结束过或者__close出错时返回false和错误
+ */
+fn lib_close(state: &mut ExeState) -> Result<i32, LuaError> {
+    let co = check_thread(state, 1)?;
+    let status = co.borrow().status();
+    match status {
+        ThreadStatus::Suspended | ThreadStatus::Dead => (),
+        _ => {
+            return Err(state.error(format!("cannot close a {} coroutine", status.name())));
+        }
+    }
+    match state.close_thread(&co) {
+        Ok(()) => {
+            state.push(true);
+            return Ok(1);
+        }
+        Err(e) if e.is_interrupt() => {
+            return Err(e);
+        }
+        Err(e) => {
+            state.push(false);
+            state.push(e.value);
+            return Ok(2);
+        }
+    }
+}
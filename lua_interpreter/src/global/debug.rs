@@ -0,0 +1,337 @@
+use std::{ rc::Rc, cell::RefCell };
+
+use crate::{
+    vm::{ ExeState, LuaThread, DebugInfo, MASK_CALL, MASK_RET, MASK_LINE, MASK_COUNT },
+    interface::{ Value, LuaError, RustFunction, table::Table },
+};
+
+use super::new_lib;
+
+/* ### debug库
+    可以传入线程的函数 , 第一个参数是线程时查看那个协程 , 其余参数依次后移 ;
+    钩子属于整个虚拟机 , sethook/gethook的线程参数只是为了和Lua的调用方式兼容
+ */
+
+/** debug库 : 注册到全局变量debug */
+pub fn open_debug(state: &mut ExeState) {
+    let funcs: [(&str, RustFunction); 12] = [
+        ("traceback", lib_traceback),
+        ("getinfo", lib_getinfo),
+        ("getlocal", lib_getlocal),
+        ("setlocal", lib_setlocal),
+        ("getupvalue", lib_getupvalue),
+        ("setupvalue", lib_setupvalue),
+        ("upvalueid", lib_upvalueid),
+        ("upvaluejoin", lib_upvaluejoin),
+        ("getmetatable", lib_getmetatable),
+        ("setmetatable", lib_setmetatable),
+        ("sethook", lib_sethook),
+        ("gethook", lib_gethook),
+    ];
+    state.set_global("debug", new_lib(&funcs));
+}
+
+/** 第一个参数是线程时返回它和1 , 否则返回正在运行的线程和0 : 其余参数从这个位置之后开始 */
+fn get_thread(state: &ExeState) -> (Rc<RefCell<LuaThread>>, usize) {
+    return match state.get(1) {
+        Value::Thread(co) => (co, 1),
+        _ => (state.running().0, 0),
+    };
+}
+
+/** 检查第i个参数是函数 */
+fn check_function(state: &ExeState, i: usize) -> Result<Value, LuaError> {
+    let f = state.get(i);
+    if !f.is_function() {
+        return Err(state.type_error(i, "function"));
+    }
+    return Ok(f);
+}
+
+/** 层数参数 : 负数的层数不存在 */
+fn check_level(state: &ExeState, i: usize) -> Result<Option<usize>, LuaError> {
+    return Ok(usize::try_from(state.check_integer(i)?).ok());
+}
+
+/** traceback([thread,] [message [, level]]) : message加上调用链 ;
+    message不是字符串(也不是nil)时原样返回 , 正在运行的线程默认从调用者(第1层)开始
+ */
+fn lib_traceback(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (co, arg) = get_thread(state);
+    let msg = state.get(arg + 1);
+    if !msg.is_str() && !matches!(msg, Value::Nil | Value::Integer(_) | Value::Float(_)) {
+        state.push(msg);
+        return Ok(1);
+    }
+    let default = if Rc::ptr_eq(&co, &state.running().0) { 1 } else { 0 };
+    let level = state.opt_integer(arg + 2, default)?;
+    let tb = match usize::try_from(level) {
+        Ok(level) => state.traceback(&co, level),
+        Err(_) => String::from("stack traceback:"),
+    };
+    match msg {
+        Value::Nil => state.push(tb),
+        _ => {
+            let msg = state.check_string(arg + 1)?;
+            state.push([<&[u8]>::from(&msg), b"\n", tb.as_bytes()].concat());
+        }
+    }
+    return Ok(1);
+}
+
+/** getinfo([thread,] f [, what]) : 函数或者第f层调用的信息 , 层数不存在时返回nil ;
+    what选择返回的字段 : S(代码位置) l(当前行) u(Upvalue和参数) n(函数名) r(传递的值) t(尾调用) f(函数) L(有代码的行)
+ */
+fn lib_getinfo(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (co, arg) = get_thread(state);
+    let what = match state.get(arg + 2) {
+        Value::Nil => String::from("flnSrtu"),
+        _ => String::from(&state.check_string(arg + 2)?),
+    };
+    let f = state.get(arg + 1);
+    let info = if f.is_function() {
+        DebugInfo::of_function(&f)
+    } else {
+        let Some(level) = check_level(state, arg + 1)? else {
+            state.push(Value::Nil);
+            return Ok(1);
+        };
+        match state.get_info(&co, level) {
+            Some(info) => info,
+            None => {
+                state.push(Value::Nil);
+                return Ok(1);
+            }
+        }
+    };
+
+    let mut t = Table::new(0, 16);
+    let mut set = |k: &str, v: Value| t.set(Value::from(k), v);
+    for c in what.chars() {
+        match c {
+            'S' => {
+                set("source", Value::from(info.source.as_str()));
+                set("short_src", Value::from(info.short_src.as_str()));
+                set("linedefined", Value::Integer(info.line_defined));
+                set("lastlinedefined", Value::Integer(info.last_line_defined));
+                set("what", Value::from(info.what));
+            }
+            'l' => set("currentline", Value::Integer(info.current_line)),
+            'u' => {
+                set("nups", Value::Integer(info.nups as i64));
+                set("nparams", Value::Integer(info.nparams as i64));
+                set("isvararg", Value::Boolean(info.is_vararg));
+            }
+            'n' => {
+                set("name", info.name.as_deref().map_or(Value::Nil, Value::from));
+                set("namewhat", Value::from(info.namewhat));
+            }
+            'r' => {
+                set("ftransfer", Value::Integer(0));
+                set("ntransfer", Value::Integer(0));
+            }
+            't' => set("istailcall", Value::Boolean(info.is_tail_call)),
+            'f' => set("func", info.func.clone()),
+            'L' => {
+                if let Value::LuaFunction(_) = info.func {
+                    let mut lines = Table::new(0, info.active_lines.len());
+                    for line in info.active_lines.iter() {
+                        lines.set(Value::Integer(*line as i64), Value::Boolean(true));
+                    }
+                    set("activelines", Value::Table(Rc::new(RefCell::new(lines))));
+                }
+            }
+            _ => {
+                return Err(state.arg_error(arg + 2, "invalid option"));
+            }
+        }
+    }
+    state.push(Value::Table(Rc::new(RefCell::new(t))));
+    return Ok(1);
+}
+
+/** getlocal([thread,] f, n) : 第f层函数的第n个局部变量的名字和值 , 不存在时返回nil ;
+    n为负数时是可变参数 ; f是函数时只返回第n个参数的名字
+ */
+fn lib_getlocal(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (co, arg) = get_thread(state);
+    let n = state.check_integer(arg + 2)?;
+    let f = state.get(arg + 1);
+    if f.is_function() {
+        let name = state.param_name(&f, n);
+        state.push(name.map_or(Value::Nil, Value::from));
+        return Ok(1);
+    }
+    let level = check_level(state, arg + 1)?;
+    match level.and_then(|level| state.get_local(&co, level, n)) {
+        Some(Some((name, value))) => {
+            state.push(name);
+            state.push(value);
+            return Ok(2);
+        }
+        Some(None) => {
+            state.push(Value::Nil);
+            return Ok(1);
+        }
+        None => {
+            return Err(state.arg_error(arg + 1, "level out of range"));
+        }
+    }
+}
+
+/** setlocal([thread,] level, n, value) : 修改局部变量 , 返回它的名字 , 不存在时返回nil */
+fn lib_setlocal(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (co, arg) = get_thread(state);
+    let level = check_level(state, arg + 1)?;
+    let n = state.check_integer(arg + 2)?;
+    let value = state.check_any(arg + 3)?;
+    match level.and_then(|level| state.set_local(&co, level, n, value)) {
+        Some(name) => {
+            state.push(name.map_or(Value::Nil, Value::from));
+            return Ok(1);
+        }
+        None => {
+            return Err(state.arg_error(arg + 1, "level out of range"));
+        }
+    }
+}
+
+/** Upvalue的序号参数 , 不能转换成usize的都不存在 */
+fn check_upvalue_index(state: &ExeState, i: usize) -> Result<usize, LuaError> {
+    return Ok(usize::try_from(state.check_integer(i)?).unwrap_or(0));
+}
+
+/** getupvalue(f, n) : Lua函数的第n个Upvalue的名字和值 , 不存在时没有返回值 */
+fn lib_getupvalue(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = check_function(state, 1)?;
+    let n = check_upvalue_index(state, 2)?;
+    let Some((name, value)) = state.get_upvalue_of(&f, n) else {
+        return Ok(0);
+    };
+    state.push(name);
+    state.push(value);
+    return Ok(2);
+}
+
+/** setupvalue(f, n, value) : 修改Upvalue , 返回它的名字 , 不存在时没有返回值 */
+fn lib_setupvalue(state: &mut ExeState) -> Result<i32, LuaError> {
+    let value = state.check_any(3)?;
+    let f = check_function(state, 1)?;
+    let n = check_upvalue_index(state, 2)?;
+    let Some(name) = state.set_upvalue_of(&f, n, value) else {
+        return Ok(0);
+    };
+    state.push(name);
+    return Ok(1);
+}
+
+/** upvalueid(f, n) : Upvalue的唯一标识 , 共享同一个Upvalue的闭包得到相同的值 ;
+    没有light userdata , 用Upvalue的地址(整数)代替 ; 不存在时返回nil
+ */
+fn lib_upvalueid(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = check_function(state, 1)?;
+    let n = check_upvalue_index(state, 2)?;
+    let id = match &f {
+        Value::LuaFunction(closure) => n
+            .checked_sub(1)
+            .and_then(|i| closure.upvalues.borrow().get(i).map(|up| Value::Integer(Rc::as_ptr(up) as i64))),
+        _ => None,
+    };
+    state.push(id.unwrap_or(Value::Nil));
+    return Ok(1);
+}
+
+/** upvaluejoin(f1, n1, f2, n2) : 让f1的第n1个Upvalue引用f2的第n2个Upvalue */
+fn lib_upvaluejoin(state: &mut ExeState) -> Result<i32, LuaError> {
+    let mut ups = Vec::new();
+    for (argf, argn) in [(1, 2), (3, 4)] {
+        let f = check_function(state, argf)?;
+        let n = check_upvalue_index(state, argn)?;
+        if state.get_upvalue_of(&f, n).is_none() && matches!(f, Value::LuaFunction(_)) {
+            return Err(state.arg_error(argn, "invalid upvalue index"));
+        }
+        let Value::LuaFunction(closure) = f else {
+            return Err(state.arg_error(argf, "Lua function expected"));
+        };
+        ups.push((closure, n - 1));
+    }
+    let up = ups[1].0.upvalues.borrow()[ups[1].1].clone();
+    ups[0].0.upvalues.borrow_mut()[ups[0].1] = up;
+    return Ok(0);
+}
+
+/** getmetatable(v) : v的元表 , 不受__metatable的保护 */
+fn lib_getmetatable(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.check_any(1)?;
+    let mt = state.get_metatable(&v);
+    state.push(mt.map_or(Value::Nil, Value::Table));
+    return Ok(1);
+}
+
+/** setmetatable(v, mt) : 设置任意类型的值的元表 , 返回v ;
+    table和userdata有自己的元表 , 其他类型的所有值共享一个元表
+ */
+fn lib_setmetatable(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.get(1);
+    let mt = match state.get(2) {
+        Value::Nil => None,
+        Value::Table(mt) => Some(mt),
+        _ => {
+            return Err(state.type_error(2, "nil or table"));
+        }
+    };
+    match &v {
+        Value::Table(t) => t.borrow_mut().metatable = mt,
+        Value::UserData(u) => u.borrow_mut().metatable = mt,
+        v if v.is_str() => state.string_meta = mt,
+        v => {
+            match mt {
+                Some(mt) => state.type_metas.insert(v.ty(), mt),
+                None => state.type_metas.remove(v.ty()),
+            };
+        }
+    }
+    state.push(v);
+    return Ok(1);
+}
+
+/** sethook([thread,] hook, mask [, count]) : mask中的'c' 'r' 'l'分别是call , return和line事件 ,
+    count大于0时每执行count条字节码触发一次count事件 ; 没有参数时取消钩子
+ */
+fn lib_sethook(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (_, arg) = get_thread(state);
+    if let Value::Nil = state.get(arg + 1) {
+        state.set_hook(Value::Nil, 0, 0);
+        return Ok(0);
+    }
+    let smask = String::from(&state.check_string(arg + 2)?);
+    let f = check_function(state, arg + 1)?;
+    let count = usize::try_from(state.opt_integer(arg + 3, 0)?).unwrap_or(0);
+    let mut mask = 0;
+    for (c, m) in [('c', MASK_CALL), ('r', MASK_RET), ('l', MASK_LINE)] {
+        if smask.contains(c) {
+            mask |= m;
+        }
+    }
+    state.set_hook(f, mask, count);
+    return Ok(0);
+}
+
+/** gethook([thread]) : 钩子函数 , mask字符串和count , 没有钩子时返回nil */
+fn lib_gethook(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (f, mask, count) = state.get_hook();
+    if let Value::Nil = f {
+        state.push(Value::Nil);
+        return Ok(1);
+    }
+    let mut smask = String::new();
+    for (c, m) in [('c', MASK_CALL), ('r', MASK_RET), ('l', MASK_LINE)] {
+        if mask & m != 0 {
+            smask.push(c);
+        }
+    }
+    state.push(f);
+    state.push(smask);
+    state.push(Value::Integer(if mask & MASK_COUNT != 0 { count as i64 } else { 0 }));
+    return Ok(3);
+}
@@ -0,0 +1,257 @@
+use crate::{ vm::ExeState, interface::{ Value, LuaError, number::{ fmt_a, fmt_e, fmt_f, fmt_g } } };
+
+/* ### string.format
+    和C语言的printf一致 : %[flags][width][.precision]conversion ,
+    width和precision最多2位数字 , 每种转换允许的flags不同
+ */
+
+/** 一个转换说明 */
+struct Spec {
+    left: bool /* - : 左对齐 */,
+    plus: bool /* + : 正数也显示符号 */,
+    space: bool /* 空格 : 正数前面加空格 */,
+    alt: bool /* # : 替代形式 */,
+    zero: bool /* 0 : 用0填充 */,
+    width: usize,
+    precision: Option<usize>,
+}
+
+/** string.format的实现 : 第1个参数是格式 , 后面依次是转换的参数 */
+pub fn format(state: &mut ExeState) -> Result<Vec<u8>, LuaError> {
+    let fmt = state.check_string(1)?;
+    let fmt = <&[u8]>::from(&fmt);
+    let mut buf = Vec::with_capacity(fmt.len());
+    let mut arg = 1;
+    let mut i = 0;
+    while i < fmt.len() {
+        let c = fmt[i];
+        i += 1;
+        if c != b'%' {
+            buf.push(c);
+            continue;
+        }
+        if fmt.get(i) == Some(&b'%') {
+            buf.push(b'%');
+            i += 1;
+            continue;
+        }
+        /* 解析转换说明 */
+        let start = i;
+        let mut spec = Spec { left: false, plus: false, space: false, alt: false, zero: false, width: 0, precision: None };
+        while let Some(&f) = fmt.get(i) {
+            match f {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alt = true,
+                b'0' => spec.zero = true,
+                _ => {
+                    break;
+                }
+            }
+            i += 1;
+        }
+        let flags = &fmt[start..i];
+        let (width, n) = read_digits(&fmt[i..]);
+        spec.width = width;
+        i += n;
+        let mut valid = n <= 2;
+        if fmt.get(i) == Some(&b'.') {
+            i += 1;
+            let (precision, n) = read_digits(&fmt[i..]);
+            spec.precision = Some(precision);
+            i += n;
+            valid &= n <= 2;
+        }
+        let conv = fmt.get(i).copied().unwrap_or(0);
+        i += 1;
+        let text = String::from_utf8_lossy(&fmt[start - 1..i.min(fmt.len())]).into_owned();
+        let invalid = || state.error(format!("invalid conversion '{text}' to 'format'"));
+        if conv == b'q' {
+            if i - start > 1 {
+                return Err(state.error("specifier '%q' cannot have modifiers"));
+            }
+            arg += 1;
+            if arg > state.get_top() {
+                return Err(state.arg_error(arg, "no value"));
+            }
+            let v = state.get(arg);
+            add_literal(state, &mut buf, &v)?;
+            continue;
+        }
+        /* 每种转换允许的flags , 以及是否允许precision */
+        let (allowed, precision): (&[u8], bool) = match conv {
+            b'c' => (b"-", false),
+            b'd' | b'i' | b'u' => (b"-+0 ", true),
+            b'o' | b'x' | b'X' => (b"-#0", true),
+            b'a' | b'A' | b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => (b"-+#0 ", true),
+            b's' => (b"-", true),
+            _ => {
+                return Err(invalid());
+            }
+        };
+        if !valid || !flags.iter().all(|f| allowed.contains(f)) || (!precision && spec.precision.is_some()) {
+            return Err(invalid());
+        }
+
+        arg += 1;
+        if arg > state.get_top() {
+            return Err(state.arg_error(arg, "no value"));
+        }
+        match conv {
+            b'c' => {
+                let c = state.check_integer(arg)?;
+                pad(&mut buf, &spec, b"", &[c as u8], false);
+            }
+            b'd' | b'i' => {
+                let n = state.check_integer(arg)?;
+                let sign: &[u8] = if n < 0 { b"-" } else if spec.plus { b"+" } else if spec.space { b" " } else { b"" };
+                let digits = int_digits(n.unsigned_abs().to_string(), &spec);
+                pad(&mut buf, &spec, sign, digits.as_bytes(), spec.precision.is_none());
+            }
+            b'u' => {
+                let n = state.check_integer(arg)? as u64;
+                let digits = int_digits(n.to_string(), &spec);
+                pad(&mut buf, &spec, b"", digits.as_bytes(), spec.precision.is_none());
+            }
+            b'o' => {
+                let n = state.check_integer(arg)? as u64;
+                let mut digits = int_digits(format!("{n:o}"), &spec);
+                if spec.alt && !digits.starts_with('0') {
+                    digits.insert(0, '0');
+                }
+                pad(&mut buf, &spec, b"", digits.as_bytes(), spec.precision.is_none());
+            }
+            b'x' | b'X' => {
+                let n = state.check_integer(arg)? as u64;
+                let digits = int_digits(format!("{n:x}"), &spec);
+                let prefix: &[u8] = if spec.alt && n != 0 { b"0x" } else { b"" };
+                let (prefix, digits) = if conv == b'X' {
+                    (prefix.to_ascii_uppercase(), digits.to_ascii_uppercase())
+                } else {
+                    (prefix.to_vec(), digits)
+                };
+                pad(&mut buf, &spec, &prefix, digits.as_bytes(), spec.precision.is_none());
+            }
+            b'a' | b'A' | b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                let x = state.check_number(arg)?;
+                let sign: &[u8] = if x.is_sign_negative() {
+                    b"-"
+                } else if spec.plus {
+                    b"+"
+                } else if spec.space {
+                    b" "
+                } else {
+                    b""
+                };
+                let x = x.abs();
+                let precision = spec.precision.unwrap_or(6);
+                let mut digits = match conv.to_ascii_lowercase() {
+                    _ if !x.is_finite() => (if x.is_nan() { "nan" } else { "inf" }).to_string(),
+                    b'a' => fmt_a(x, spec.precision, spec.alt),
+                    b'e' => fmt_e(x, precision, spec.alt),
+                    b'f' => fmt_f(x, precision, spec.alt),
+                    _ => fmt_g(x, precision, spec.alt),
+                };
+                if conv.is_ascii_uppercase() {
+                    digits = digits.to_ascii_uppercase();
+                }
+                pad(&mut buf, &spec, sign, digits.as_bytes(), x.is_finite());
+            }
+            b's' => {
+                let v = state.get(arg);
+                let s = state.tostring(&v)?;
+                let mut s = <&[u8]>::from(&s);
+                if let Some(p) = spec.precision {
+                    s = &s[..p.min(s.len())];
+                }
+                pad(&mut buf, &spec, b"", s, false);
+            }
+            _ => unreachable!(),
+        }
+    }
+    return Ok(buf);
+}
+
+/** 读取十进制数字 , 返回(数值, 数字的个数) */
+fn read_digits(s: &[u8]) -> (usize, usize) {
+    let n = s.iter().take_while(|c| c.is_ascii_digit()).count();
+    let v = s[..n].iter().fold(0usize, |v, c| v.saturating_mul(10).saturating_add((c - b'0') as usize));
+    return (v, n);
+}
+
+/** 整数的precision是最少的数字个数 , 为0时数值0不输出任何数字 */
+fn int_digits(digits: String, spec: &Spec) -> String {
+    return match spec.precision {
+        Some(0) if digits == "0" => String::new(),
+        Some(p) if digits.len() < p => format!("{}{}", "0".repeat(p - digits.len()), digits),
+        _ => digits,
+    };
+}
+
+/** 按width填充 : 0填充放在符号(前缀)和数字之间 , zero表示是否允许0填充(整数指定了precision时不允许) */
+fn pad(buf: &mut Vec<u8>, spec: &Spec, prefix: &[u8], body: &[u8], zero: bool) {
+    let len = prefix.len() + body.len();
+    let fill = spec.width.saturating_sub(len);
+    if spec.left {
+        buf.extend_from_slice(prefix);
+        buf.extend_from_slice(body);
+        buf.resize(buf.len() + fill, b' ');
+    } else if spec.zero && zero {
+        buf.extend_from_slice(prefix);
+        buf.resize(buf.len() + fill, b'0');
+        buf.extend_from_slice(body);
+    } else {
+        buf.resize(buf.len() + fill, b' ');
+        buf.extend_from_slice(prefix);
+        buf.extend_from_slice(body);
+    }
+}
+
+/** %q : 输出可以被Lua重新读取的字面量 */
+fn add_literal(state: &mut ExeState, buf: &mut Vec<u8>, v: &Value) -> Result<(), LuaError> {
+    match v {
+        Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_) => {
+            let s = <&[u8]>::from(v);
+            buf.push(b'"');
+            for (i, &c) in s.iter().enumerate() {
+                match c {
+                    b'"' | b'\\' | b'\n' => buf.extend_from_slice(&[b'\\', c]),
+                    b'\r' => buf.extend_from_slice(b"\\r"),
+                    b'\0' if !s.get(i + 1).is_some_and(u8::is_ascii_digit) => buf.extend_from_slice(b"\\0"),
+                    _ if c.is_ascii_control() => {
+                        /* 后面跟着数字时需要补足3位 , 避免和后面的数字连在一起 */
+                        let esc = if s.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                            format!("\\{c:03}")
+                        } else {
+                            format!("\\{c}")
+                        };
+                        buf.extend_from_slice(esc.as_bytes());
+                    }
+                    _ => buf.push(c),
+                }
+            }
+            buf.push(b'"');
+        }
+        /* math.mininteger写成十进制会被读成float , 所以用十六进制 */
+        Value::Integer(i64::MIN) => buf.extend_from_slice(b"0x8000000000000000"),
+        Value::Integer(n) => buf.extend_from_slice(n.to_string().as_bytes()),
+        Value::Float(x) => {
+            let s = if x.is_nan() {
+                String::from("(0/0)")
+            } else if x.is_infinite() {
+                String::from(if *x > 0.0 { "1e9999" } else { "-1e9999" })
+            } else {
+                /* 用十六进制浮点数保证精确 */
+                let sign = if x.is_sign_negative() { "-" } else { "" };
+                format!("{sign}{}", fmt_a(x.abs(), None, false))
+            };
+            buf.extend_from_slice(s.as_bytes());
+        }
+        Value::Nil | Value::Boolean(_) => buf.extend_from_slice(v.to_string().as_bytes()),
+        _ => {
+            return Err(state.error("value has no literal form"));
+        }
+    }
+    return Ok(());
+}
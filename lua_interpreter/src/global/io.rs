@@ -0,0 +1,810 @@
+use std::{
+    rc::Rc,
+    cell::RefCell,
+    fs::{ self, OpenOptions },
+    io::{ self, Read, Seek, SeekFrom, Write },
+};
+
+use crate::{
+    vm::ExeState,
+    interface::{ Value, LuaError, RustFunction, arith, table::Table, userdata::UserData },
+};
+
+use super::new_lib;
+
+/* ### io库
+    文件对象是userdata , 元表的__name是"FILE*" , 方法放在__index中 ;
+    没有垃圾回收 , 文件对象的最后一个引用消失时由Drop刷新缓冲区并关闭文件(相当于__gc) ;
+    print也通过io.stdout输出 , 宿主可以用set_stdout重定向
+ */
+
+/** 读写缓冲区的大小 */
+const BUFFER_SIZE: usize = 8192;
+/** io.read("n")读取的数字的最大长度 */
+const MAX_NUMBER_LEN: usize = 200;
+/** lines最多接受的格式个数 */
+const MAX_ARGS_LINE: usize = 250;
+
+/** 底层的流 */
+enum Stream {
+    File(fs::File),
+    Stdin,
+    Stdout,
+    Stderr,
+    Writer(Box<dyn Write>) /* 宿主提供的输出 */,
+}
+
+/** 缓冲模式 , 对应setvbuf */
+#[derive(PartialEq)]
+enum BufMode {
+    No,
+    Full,
+    Line,
+}
+
+/** ## 文件对象
+    读取时预读到rbuf , 写入时先放到wbuf ; 读写切换时处理好文件位置
+ */
+pub struct LuaFile {
+    stream: Option<Stream> /* None表示已经关闭 */,
+    rbuf: Vec<u8>,
+    rpos: usize,
+    wbuf: Vec<u8>,
+    mode: BufMode,
+}
+
+impl LuaFile {
+    fn new(stream: Stream) -> Self {
+        let mode = if matches!(stream, Stream::File(_)) { BufMode::Full } else { BufMode::Line };
+        return LuaFile { stream: Some(stream), rbuf: Vec::new(), rpos: 0, wbuf: Vec::new(), mode };
+    }
+
+    fn is_closed(&self) -> bool {
+        return self.stream.is_none();
+    }
+
+    /** 标准输入输出不能被关闭 */
+    fn is_std(&self) -> bool {
+        return matches!(self.stream, Some(Stream::Stdin | Stream::Stdout | Stream::Stderr | Stream::Writer(_)));
+    }
+
+    fn stream(&mut self) -> io::Result<&mut Stream> {
+        return self.stream.as_mut().ok_or_else(|| io::Error::from_raw_os_error(9));
+    }
+
+    /** 预读的数据还没有用完时 , 文件的真实位置在前面 , 写入或者定位之前要退回去 */
+    fn drop_read_buffer(&mut self) -> io::Result<()> {
+        let unread = (self.rbuf.len() - self.rpos) as i64;
+        self.rbuf.clear();
+        self.rpos = 0;
+        if unread > 0 {
+            if let Stream::File(f) = self.stream()? {
+                f.seek(SeekFrom::Current(-unread))?;
+            }
+        }
+        return Ok(());
+    }
+
+    /** 保证预读缓冲区中有数据 , 到达文件末尾时返回false */
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.rpos < self.rbuf.len() {
+            return Ok(true);
+        }
+        self.flush()?;
+        let mut buf = vec![0; BUFFER_SIZE];
+        let n = match self.stream()? {
+            Stream::File(f) => f.read(&mut buf)?,
+            Stream::Stdin => io::stdin().read(&mut buf)?,
+            _ => {
+                return Err(io::Error::from_raw_os_error(9));
+            }
+        };
+        buf.truncate(n);
+        self.rbuf = buf;
+        self.rpos = 0;
+        return Ok(n > 0);
+    }
+
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        return Ok(if self.fill()? { Some(self.rbuf[self.rpos]) } else { None });
+    }
+
+    fn getc(&mut self) -> io::Result<Option<u8>> {
+        let c = self.peek()?;
+        if c.is_some() {
+            self.rpos += 1;
+        }
+        return Ok(c);
+    }
+
+    /** 读取一行 , 返回(内容, 是否读到了换行符) */
+    fn read_line(&mut self, keep_nl: bool) -> io::Result<(Vec<u8>, bool)> {
+        let mut line = Vec::new();
+        while self.fill()? {
+            let rest = &self.rbuf[self.rpos..];
+            if let Some(i) = rest.iter().position(|&c| c == b'\n') {
+                line.extend_from_slice(&rest[..if keep_nl { i + 1 } else { i }]);
+                self.rpos += i + 1;
+                return Ok((line, true));
+            }
+            line.extend_from_slice(rest);
+            self.rpos = self.rbuf.len();
+        }
+        return Ok((line, false));
+    }
+
+    /** 最多读取n个字节 */
+    fn read_chars(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        while buf.len() < n && self.fill()? {
+            let take = (n - buf.len()).min(self.rbuf.len() - self.rpos);
+            buf.extend_from_slice(&self.rbuf[self.rpos..self.rpos + take]);
+            self.rpos += take;
+        }
+        return Ok(buf);
+    }
+
+    fn read_all(&mut self) -> io::Result<Vec<u8>> {
+        return self.read_chars(usize::MAX);
+    }
+
+    fn write(&mut self, s: &[u8]) -> io::Result<()> {
+        self.drop_read_buffer()?;
+        match self.stream()? {
+            Stream::File(_) => {
+                self.wbuf.extend_from_slice(s);
+                let full = self.wbuf.len() >= BUFFER_SIZE;
+                if full || self.mode == BufMode::No || (self.mode == BufMode::Line && s.contains(&b'\n')) {
+                    self.flush()?;
+                }
+            }
+            Stream::Stdin => {
+                return Err(io::Error::from_raw_os_error(9));
+            }
+            Stream::Stdout => {
+                io::stdout().write_all(s)?;
+                if self.mode == BufMode::No {
+                    io::stdout().flush()?;
+                }
+            }
+            Stream::Stderr => io::stderr().write_all(s)?,
+            Stream::Writer(w) => w.write_all(s)?,
+        }
+        return Ok(());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let wbuf = std::mem::take(&mut self.wbuf);
+        match self.stream()? {
+            Stream::File(f) => f.write_all(&wbuf)?,
+            Stream::Stdin => (),
+            Stream::Stdout => io::stdout().flush()?,
+            Stream::Stderr => io::stderr().flush()?,
+            Stream::Writer(w) => w.flush()?,
+        }
+        return Ok(());
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.flush()?;
+        self.drop_read_buffer()?;
+        return match self.stream()? {
+            Stream::File(f) => f.seek(pos),
+            _ => Err(io::Error::from_raw_os_error(29)) /* ESPIPE */,
+        };
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let r = self.flush();
+        self.stream = None;
+        return r;
+    }
+}
+
+impl Drop for LuaFile {
+    fn drop(&mut self) {
+        if !self.is_closed() {
+            let _ = self.flush();
+        }
+    }
+}
+
+/** 需要访问io库状态的函数 */
+type IoFunction = fn(&mut ExeState, &IoLib) -> Result<i32, LuaError>;
+
+/** io库共享的状态 : 文件对象的元表 , 默认的输入和输出 */
+struct IoLib {
+    meta: Rc<RefCell<Table>>,
+    input: RefCell<Value>,
+    output: RefCell<Value>,
+}
+
+impl IoLib {
+    fn new_file(&self, file: LuaFile) -> Value {
+        return Value::UserData(Rc::new(RefCell::new(UserData::new(file, Some(self.meta.clone())))));
+    }
+}
+
+/** io库 : 注册到全局变量io , 同时设置print使用的标准输出 */
+pub fn open_io(state: &mut ExeState) {
+    let methods: [(&str, RustFunction); 7] = [
+        ("close", f_close),
+        ("flush", f_flush),
+        ("lines", f_lines),
+        ("read", f_read),
+        ("seek", f_seek),
+        ("setvbuf", f_setvbuf),
+        ("write", f_write),
+    ];
+    let mut meta = Table::new(0, 5);
+    meta.set(Value::from("__index"), new_lib(&methods));
+    meta.set(Value::from("__name"), Value::from("FILE*"));
+    meta.set(Value::from("__tostring"), Value::RustFunction(f_tostring));
+    meta.set(Value::from("__gc"), Value::RustFunction(f_gc));
+    meta.set(Value::from("__close"), Value::RustFunction(f_gc));
+    let meta = Rc::new(RefCell::new(meta));
+
+    let io = Rc::new(IoLib { meta, input: RefCell::new(Value::Nil), output: RefCell::new(Value::Nil) });
+    let stdin = io.new_file(LuaFile::new(Stream::Stdin));
+    let stdout = io.new_file(LuaFile::new(Stream::Stdout));
+    let stderr = io.new_file(LuaFile::new(Stream::Stderr));
+    *io.input.borrow_mut() = stdin.clone();
+    *io.output.borrow_mut() = stdout.clone();
+    state.stdout = stdout.clone();
+
+    let funcs: [(&str, IoFunction); 10] = [
+        ("close", io_close),
+        ("flush", io_flush),
+        ("input", io_input),
+        ("lines", io_lines),
+        ("open", io_open),
+        ("output", io_output),
+        ("read", io_read),
+        ("tmpfile", io_tmpfile),
+        ("type", io_type),
+        ("write", io_write),
+    ];
+    let mut lib = Table::new(0, funcs.len() + 3);
+    for (name, f) in funcs {
+        let io = io.clone();
+        lib.set(Value::from(name), Value::closure(move |state| f(state, &io)));
+    }
+    lib.set(Value::from("stdin"), stdin);
+    lib.set(Value::from("stdout"), stdout);
+    lib.set(Value::from("stderr"), stderr);
+    state.set_global("io", Value::Table(Rc::new(RefCell::new(lib))));
+}
+
+/** 宿主重定向标准输出 : print和io.stdout都会写到w */
+pub fn set_stdout(state: &ExeState, w: impl Write + 'static) {
+    if let Value::UserData(u) = &state.stdout {
+        if let Some(f) = u.borrow_mut().downcast_mut::<LuaFile>() {
+            let _ = f.flush();
+            f.stream = Some(Stream::Writer(Box::new(w)));
+        }
+    }
+}
+
+/** 写到标准输出 , 没有打开io库时直接使用进程的标准输出 */
+pub fn write_stdout(state: &mut ExeState, s: &[u8]) -> Result<(), LuaError> {
+    let r = match &state.stdout {
+        Value::UserData(u) =>
+            match u.borrow_mut().downcast_mut::<LuaFile>() {
+                Some(f) => f.write(s).and_then(|_| f.flush()),
+                None => Ok(()),
+            }
+        _ => io::stdout().write_all(s),
+    };
+    return r.map_err(|e| state.error(os_message(&e)));
+}
+
+/** 错误信息 : 去掉Rust附加的" (os error N)" */
+pub fn os_message(e: &io::Error) -> String {
+    let msg = e.to_string();
+    return match msg.find(" (os error") {
+        Some(i) => msg[..i].to_string(),
+        None => msg,
+    };
+}
+
+/** 成功时返回true , 失败时返回nil, 错误信息, 错误码 */
+fn file_result(state: &mut ExeState, r: io::Result<()>, fname: Option<&str>) -> Result<i32, LuaError> {
+    match r {
+        Ok(()) => {
+            state.push(true);
+            return Ok(1);
+        }
+        Err(e) => {
+            let msg = os_message(&e);
+            state.push(Value::Nil);
+            state.push(match fname {
+                Some(name) => format!("{name}: {msg}"),
+                None => msg,
+            });
+            state.push(e.raw_os_error().unwrap_or(0) as i64);
+            return Ok(3);
+        }
+    }
+}
+
+/** 第i个参数是文件对象 */
+fn to_file(state: &ExeState, i: usize) -> Result<Rc<RefCell<UserData>>, LuaError> {
+    if let Value::UserData(u) = state.get(i) {
+        if u.borrow().data.is::<LuaFile>() {
+            return Ok(u);
+        }
+    }
+    return Err(state.type_error(i, "FILE*"));
+}
+
+/** 第i个参数是没有关闭的文件对象 */
+fn check_file(state: &ExeState, i: usize) -> Result<Rc<RefCell<UserData>>, LuaError> {
+    let u = to_file(state, i)?;
+    if file_of(&u).is_closed() {
+        return Err(state.error("attempt to use a closed file"));
+    }
+    return Ok(u);
+}
+
+fn file_of(u: &Rc<RefCell<UserData>>) -> std::cell::RefMut<'_, LuaFile> {
+    return std::cell::RefMut::map(u.borrow_mut(), |u| u.downcast_mut::<LuaFile>().unwrap());
+}
+
+/** 检查打开模式 : [rwa]%+?b* */
+fn check_mode(mode: &[u8]) -> bool {
+    return match mode {
+        [b'r' | b'w' | b'a', rest @ ..] => {
+            let rest = rest.strip_prefix(b"+").unwrap_or(rest);
+            rest.iter().all(|&c| c == b'b')
+        }
+        _ => false,
+    };
+}
+
+/** 按照C语言fopen的模式打开文件 */
+fn open_file(name: &str, mode: &[u8]) -> io::Result<LuaFile> {
+    let plus = mode.contains(&b'+');
+    let mut opts = OpenOptions::new();
+    match mode[0] {
+        b'r' => opts.read(true).write(plus),
+        b'w' => opts.write(true).create(true).truncate(true).read(plus),
+        _ => opts.append(true).create(true).read(plus),
+    };
+    return Ok(LuaFile::new(Stream::File(opts.open(name)?)));
+}
+
+/** 打开文件 , 失败时报错 */
+fn open_check_file(state: &ExeState, io: &IoLib, name: &str, mode: &[u8]) -> Result<Value, LuaError> {
+    return match open_file(name, mode) {
+        Ok(f) => Ok(io.new_file(f)),
+        Err(e) => Err(state.error(format!("cannot open file '{name}' ({})", os_message(&e)))),
+    };
+}
+
+/** 默认的输入或者输出 , 已经关闭时报错 */
+fn get_io_file(state: &ExeState, io: &IoLib, output: bool) -> Result<Rc<RefCell<UserData>>, LuaError> {
+    let v = if output { io.output.borrow().clone() } else { io.input.borrow().clone() };
+    if let Value::UserData(u) = v {
+        if !file_of(&u).is_closed() {
+            return Ok(u);
+        }
+    }
+    return Err(state.error(format!("default {} file is closed", if output { "output" } else { "input" })));
+}
+
+/** 关闭文件 : 标准文件不能关闭 */
+fn aux_close(state: &mut ExeState, u: &Rc<RefCell<UserData>>) -> Result<i32, LuaError> {
+    if file_of(u).is_std() {
+        state.push(Value::Nil);
+        state.push("cannot close standard file");
+        return Ok(2);
+    }
+    let r = file_of(u).close();
+    return file_result(state, r, None);
+}
+
+/** read的一种格式 */
+enum ReadFormat {
+    Chars(i64) /* 最多n个字节 , 0表示检查文件末尾 */,
+    Number,
+    Line(bool) /* 是否保留换行符 */,
+    All,
+}
+
+/** 解析第arg个参数的格式 , '*'前缀可以省略 */
+fn read_format(state: &ExeState, arg: usize, fmt: &Value) -> Result<ReadFormat, LuaError> {
+    match fmt {
+        Value::Integer(n) => {
+            return Ok(ReadFormat::Chars(*n));
+        }
+        Value::Float(x) => {
+            return match arith::float_to_int(*x) {
+                Some(n) => Ok(ReadFormat::Chars(n)),
+                None => Err(state.arg_error(arg, "number has no integer representation")),
+            };
+        }
+        _ => (),
+    }
+    if !fmt.is_str() {
+        return Err(state.type_error(arg, "string"));
+    }
+    let p = <&[u8]>::from(fmt);
+    let p = p.strip_prefix(b"*").unwrap_or(p);
+    return match p.first() {
+        Some(b'n') => Ok(ReadFormat::Number),
+        Some(b'l') => Ok(ReadFormat::Line(false)),
+        Some(b'L') => Ok(ReadFormat::Line(true)),
+        Some(b'a') => Ok(ReadFormat::All),
+        _ => Err(state.arg_error(arg, "invalid format")),
+    };
+}
+
+/** 按照格式读取一项 , 失败时为nil */
+fn read_item(f: &mut LuaFile, fmt: ReadFormat) -> io::Result<Value> {
+    return Ok(match fmt {
+        ReadFormat::Chars(n) if n <= 0 => {
+            if f.peek()?.is_some() { Value::from("") } else { Value::Nil }
+        }
+        ReadFormat::Chars(n) => {
+            let s = f.read_chars(n as usize)?;
+            if s.is_empty() { Value::Nil } else { Value::from(s) }
+        }
+        ReadFormat::Number => read_number(f)?,
+        ReadFormat::Line(keep_nl) => {
+            let (line, nl) = f.read_line(keep_nl)?;
+            if nl || !line.is_empty() { Value::from(line) } else { Value::Nil }
+        }
+        ReadFormat::All => Value::from(f.read_all()?),
+    });
+}
+
+/** read的格式 , 依次读取 , 某一项失败时放入nil并且停止 */
+fn g_read(state: &mut ExeState, u: &Rc<RefCell<UserData>>, formats: &[(usize, Value)]) -> Result<i32, LuaError> {
+    let mut results = Vec::new();
+    let mut r = Ok(());
+    if formats.is_empty() {
+        r = read_item(&mut file_of(u), ReadFormat::Line(false)).map(|v| results.push(v));
+    }
+    for (arg, fmt) in formats {
+        let fmt = read_format(state, *arg, fmt)?;
+        r = read_item(&mut file_of(u), fmt).map(|v| results.push(v));
+        if r.is_err() || matches!(results.last(), Some(Value::Nil)) {
+            break;
+        }
+    }
+    if let Err(e) = r {
+        return file_result(state, Err(e), None);
+    }
+    let n = results.len();
+    for v in results {
+        state.push(v);
+    }
+    return Ok(n as i32);
+}
+
+/** 读取一个数字 : 按照Lua数字的语法读取最长的前缀 , 然后转换 */
+fn read_number(f: &mut LuaFile) -> io::Result<Value> {
+    let mut buf = Vec::new();
+    let mut c = f.peek()?;
+    while c.is_some_and(|c| c.is_ascii_whitespace()) {
+        f.getc()?;
+        c = f.peek()?;
+    }
+    /* 当前字符属于set时读入 */
+    let test2 = |f: &mut LuaFile, buf: &mut Vec<u8>, set: &[u8; 2]| -> io::Result<bool> {
+        match f.peek()? {
+            Some(c) if (c == set[0] || c == set[1]) && buf.len() < MAX_NUMBER_LEN => {
+                buf.push(c);
+                f.getc()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    };
+    let read_digits = |f: &mut LuaFile, buf: &mut Vec<u8>, hex: bool| -> io::Result<usize> {
+        let mut count = 0;
+        while let Some(c) = f.peek()? {
+            let ok = if hex { c.is_ascii_hexdigit() } else { c.is_ascii_digit() };
+            if !ok || buf.len() >= MAX_NUMBER_LEN {
+                break;
+            }
+            buf.push(c);
+            f.getc()?;
+            count += 1;
+        }
+        Ok(count)
+    };
+    test2(f, &mut buf, b"-+")?;
+    let mut count = 0;
+    let mut hex = false;
+    if test2(f, &mut buf, b"00")? {
+        if test2(f, &mut buf, b"xX")? {
+            hex = true;
+        } else {
+            count = 1;
+        }
+    }
+    count += read_digits(f, &mut buf, hex)?;
+    if test2(f, &mut buf, b"..")? {
+        count += read_digits(f, &mut buf, hex)?;
+    }
+    if count > 0 && test2(f, &mut buf, if hex { b"pP" } else { b"eE" })? {
+        test2(f, &mut buf, b"-+")?;
+        read_digits(f, &mut buf, false)?;
+    }
+    return Ok(arith::str_to_number(&buf).unwrap_or(Value::Nil));
+}
+
+/** 依次写入参数 , 成功时返回文件对象 */
+fn g_write(state: &mut ExeState, u: &Rc<RefCell<UserData>>, first: usize) -> Result<i32, LuaError> {
+    for i in first..=state.get_top() {
+        let s = state.check_string(i)?;
+        if let Err(e) = file_of(u).write(<&[u8]>::from(&s)) {
+            return file_result(state, Err(e), None);
+        }
+    }
+    state.push(Value::UserData(u.clone()));
+    return Ok(1);
+}
+
+/** 读取格式参数 , 用于lines */
+fn line_formats(state: &ExeState, first: usize) -> Result<Vec<(usize, Value)>, LuaError> {
+    let formats: Vec<(usize, Value)> = (first..=state.get_top()).map(|i| (i, state.get(i))).collect();
+    if formats.len() > MAX_ARGS_LINE {
+        return Err(state.arg_error(MAX_ARGS_LINE + first, "too many arguments"));
+    }
+    return Ok(formats);
+}
+
+/** lines的迭代函数 : 到达文件末尾时返回nil , toclose为true时关闭文件 */
+fn aux_lines(u: Rc<RefCell<UserData>>, formats: Vec<(usize, Value)>, toclose: bool) -> Value {
+    return Value::closure(move |state| {
+        if file_of(&u).is_closed() {
+            return Err(state.error("file is already closed"));
+        }
+        let n = g_read(state, &u, &formats)?;
+        let first = state.get(state.get_top() + 1 - n as usize);
+        if first.truthy() {
+            return Ok(n);
+        }
+        if n > 1 {
+            /* 读取出错 , 第2个返回值是错误信息 */
+            let msg = state.get(state.get_top() + 2 - n as usize);
+            return Err(state.error(msg.to_string()));
+        }
+        if toclose {
+            let _ = file_of(&u).close();
+        }
+        return Ok(0);
+    });
+}
+
+/** io.open(filename [, mode]) */
+fn io_open(state: &mut ExeState, io: &IoLib) -> Result<i32, LuaError> {
+    let name = state.check_string(1)?.to_string();
+    let mode = match state.get(2) {
+        Value::Nil => Value::from("r"),
+        _ => state.check_string(2)?,
+    };
+    let mode = <&[u8]>::from(&mode);
+    if !check_mode(mode) {
+        return Err(state.arg_error(2, "invalid mode"));
+    }
+    match open_file(&name, mode) {
+        Ok(f) => {
+            state.push(io.new_file(f));
+            return Ok(1);
+        }
+        Err(e) => {
+            return file_result(state, Err(e), Some(&name));
+        }
+    }
+}
+
+/** io.close([file]) : 默认关闭默认输出 */
+fn io_close(state: &mut ExeState, io: &IoLib) -> Result<i32, LuaError> {
+    if let Value::Nil = state.get(1) {
+        let u = get_io_file(state, io, true)?;
+        return aux_close(state, &u);
+    }
+    return f_close(state);
+}
+
+/** io.flush() : 刷新默认输出 */
+fn io_flush(state: &mut ExeState, io: &IoLib) -> Result<i32, LuaError> {
+    let u = get_io_file(state, io, true)?;
+    let r = file_of(&u).flush();
+    return file_result(state, r, None);
+}
+
+/** io.input和io.output : 参数是文件名时打开文件 , 返回当前的默认文件 */
+fn g_iofile(state: &mut ExeState, io: &IoLib, output: bool) -> Result<i32, LuaError> {
+    let v = state.get(1);
+    if !matches!(v, Value::Nil) {
+        let file = match v {
+            Value::Integer(_) | Value::Float(_) => {
+                let name = v.to_string();
+                open_check_file(state, io, &name, if output { b"w" } else { b"r" })?
+            }
+            v if v.is_str() => {
+                let name = state.check_string(1)?.to_string();
+                open_check_file(state, io, &name, if output { b"w" } else { b"r" })?
+            }
+            _ => Value::UserData(to_file(state, 1)?),
+        };
+        *(if output { &io.output } else { &io.input }).borrow_mut() = file;
+    }
+    let current = if output { io.output.borrow().clone() } else { io.input.borrow().clone() };
+    state.push(current);
+    return Ok(1);
+}
+
+fn io_input(state: &mut ExeState, io: &IoLib) -> Result<i32, LuaError> {
+    return g_iofile(state, io, false);
+}
+
+fn io_output(state: &mut ExeState, io: &IoLib) -> Result<i32, LuaError> {
+    return g_iofile(state, io, true);
+}
+
+/** io.lines([filename, ...]) : 指定文件名时 , 读完之后关闭文件 */
+fn io_lines(state: &mut ExeState, io: &IoLib) -> Result<i32, LuaError> {
+    let formats = line_formats(state, 2)?;
+    if let Value::Nil = state.get(1) {
+        let u = get_io_file(state, io, false)?;
+        state.push(aux_lines(u, formats, false));
+        return Ok(1);
+    }
+    let name = state.check_string(1)?.to_string();
+    let file = open_check_file(state, io, &name, b"r")?;
+    let Value::UserData(u) = file.clone() else { unreachable!() };
+    state.push(aux_lines(u, formats, true));
+    state.push(Value::Nil);
+    state.push(Value::Nil);
+    state.push(file);
+    return Ok(4);
+}
+
+/** io.read(...) : 从默认输入读取 */
+fn io_read(state: &mut ExeState, io: &IoLib) -> Result<i32, LuaError> {
+    let u = get_io_file(state, io, false)?;
+    let formats: Vec<(usize, Value)> = (1..=state.get_top()).map(|i| (i, state.get(i))).collect();
+    return g_read(state, &u, &formats);
+}
+
+/** io.write(...) : 写到默认输出 */
+fn io_write(state: &mut ExeState, io: &IoLib) -> Result<i32, LuaError> {
+    let u = get_io_file(state, io, true)?;
+    return g_write(state, &u, 1);
+}
+
+/** io.tmpfile() : 可读写的临时文件 , 创建之后立即删除文件名 , 关闭之后就不存在了 */
+fn io_tmpfile(state: &mut ExeState, io: &IoLib) -> Result<i32, LuaError> {
+    match super::os::create_temp() {
+        Ok((path, f)) => {
+            let _ = fs::remove_file(path);
+            state.push(io.new_file(LuaFile::new(Stream::File(f))));
+            return Ok(1);
+        }
+        Err(e) => {
+            return file_result(state, Err(e), None);
+        }
+    }
+}
+
+/** io.type(obj) : "file" , "closed file" , 不是文件时返回nil */
+fn io_type(state: &mut ExeState, _io: &IoLib) -> Result<i32, LuaError> {
+    state.check_any(1)?;
+    let v = match to_file(state, 1) {
+        Ok(u) if file_of(&u).is_closed() => Value::from("closed file"),
+        Ok(_) => Value::from("file"),
+        Err(_) => Value::Nil,
+    };
+    state.push(v);
+    return Ok(1);
+}
+
+/** file:close() */
+fn f_close(state: &mut ExeState) -> Result<i32, LuaError> {
+    let u = check_file(state, 1)?;
+    return aux_close(state, &u);
+}
+
+/** file:flush() */
+fn f_flush(state: &mut ExeState) -> Result<i32, LuaError> {
+    let u = check_file(state, 1)?;
+    let r = file_of(&u).flush();
+    return file_result(state, r, None);
+}
+
+/** file:lines(...) : 读完之后不关闭文件 */
+fn f_lines(state: &mut ExeState) -> Result<i32, LuaError> {
+    let u = check_file(state, 1)?;
+    let formats = line_formats(state, 2)?;
+    state.push(aux_lines(u, formats, false));
+    return Ok(1);
+}
+
+/** file:read(...) */
+fn f_read(state: &mut ExeState) -> Result<i32, LuaError> {
+    let u = check_file(state, 1)?;
+    let formats: Vec<(usize, Value)> = (2..=state.get_top()).map(|i| (i, state.get(i))).collect();
+    return g_read(state, &u, &formats);
+}
+
+/** file:write(...) */
+fn f_write(state: &mut ExeState) -> Result<i32, LuaError> {
+    let u = check_file(state, 1)?;
+    return g_write(state, &u, 2);
+}
+
+/** file:seek([whence [, offset]]) : 返回相对文件开头的位置 */
+fn f_seek(state: &mut ExeState) -> Result<i32, LuaError> {
+    let u = check_file(state, 1)?;
+    let whence = match state.get(2) {
+        Value::Nil => Value::from("cur"),
+        _ => state.check_string(2)?,
+    };
+    let offset = state.opt_integer(3, 0)?;
+    let pos = match <&[u8]>::from(&whence) {
+        b"set" => SeekFrom::Start(offset as u64),
+        b"cur" => SeekFrom::Current(offset),
+        b"end" => SeekFrom::End(offset),
+        w => {
+            let msg = format!("invalid option '{}'", String::from_utf8_lossy(w));
+            return Err(state.arg_error(2, msg));
+        }
+    };
+    let r = file_of(&u).seek(pos);
+    match r {
+        Ok(p) => {
+            state.push(p as i64);
+            return Ok(1);
+        }
+        Err(e) => {
+            return file_result(state, Err(e), None);
+        }
+    }
+}
+
+/** file:setvbuf(mode [, size]) */
+fn f_setvbuf(state: &mut ExeState) -> Result<i32, LuaError> {
+    let u = check_file(state, 1)?;
+    let mode = state.check_string(2)?;
+    let mode = match <&[u8]>::from(&mode) {
+        b"no" => BufMode::No,
+        b"full" => BufMode::Full,
+        b"line" => BufMode::Line,
+        m => {
+            let msg = format!("invalid option '{}'", String::from_utf8_lossy(m));
+            return Err(state.arg_error(2, msg));
+        }
+    };
+    let mut f = file_of(&u);
+    f.mode = mode;
+    let r = f.flush();
+    drop(f);
+    return file_result(state, r, None);
+}
+
+/** __tostring : "file (closed)" 或者 "file (地址)" */
+fn f_tostring(state: &mut ExeState) -> Result<i32, LuaError> {
+    let u = to_file(state, 1)?;
+    let s = if file_of(&u).is_closed() {
+        String::from("file (closed)")
+    } else {
+        format!("file ({:?})", Rc::as_ptr(&u))
+    };
+    state.push(s);
+    return Ok(1);
+}
+
+/** __gc和__close : 关闭还没有关闭的文件 , 标准文件除外 */
+fn f_gc(state: &mut ExeState) -> Result<i32, LuaError> {
+    let u = to_file(state, 1)?;
+    let mut f = file_of(&u);
+    if !f.is_closed() && !f.is_std() {
+        let _ = f.close();
+    }
+    return Ok(0);
+}
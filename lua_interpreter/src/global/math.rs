@@ -0,0 +1,334 @@
+use std::{ rc::Rc, cell::RefCell, time::{ SystemTime, UNIX_EPOCH } };
+
+use crate::{ vm::ExeState, interface::{ Value, LuaError, RustFunction, OpCode, arith } };
+
+use super::new_lib;
+
+/** math库 : random和randomseed是共享同一个随机数状态的Rust闭包 */
+pub fn open_math(state: &mut ExeState) {
+    let funcs: [(&str, RustFunction); 21] = [
+        ("abs", lib_abs),
+        ("ceil", lib_ceil),
+        ("floor", lib_floor),
+        ("fmod", lib_fmod),
+        ("modf", lib_modf),
+        ("sqrt", lib_sqrt),
+        ("exp", lib_exp),
+        ("log", lib_log),
+        ("sin", lib_sin),
+        ("cos", lib_cos),
+        ("tan", lib_tan),
+        ("asin", lib_asin),
+        ("acos", lib_acos),
+        ("atan", lib_atan),
+        ("tointeger", lib_tointeger),
+        ("type", lib_type),
+        ("ult", lib_ult),
+        ("max", lib_max),
+        ("min", lib_min),
+        ("deg", lib_deg),
+        ("rad", lib_rad),
+    ];
+    let lib = new_lib(&funcs);
+    if let Value::Table(t) = &lib {
+        let mut t = t.borrow_mut();
+        t.set(Value::from("pi"), Value::Float(std::f64::consts::PI));
+        t.set(Value::from("huge"), Value::Float(f64::INFINITY));
+        t.set(Value::from("maxinteger"), Value::Integer(i64::MAX));
+        t.set(Value::from("mininteger"), Value::Integer(i64::MIN));
+
+        let rng = Rc::new(RefCell::new(Xoshiro256::new(0, 0)));
+        rng.borrow_mut().randomize();
+        let r = rng.clone();
+        t.set(Value::from("random"), Value::closure(move |state| lib_random(state, &mut r.borrow_mut())));
+        t.set(Value::from("randomseed"), Value::closure(move |state| lib_randomseed(state, &mut rng.borrow_mut())));
+    }
+    state.set_global("math", lib);
+}
+
+/** 检查第i个参数是数字 , 保留整数或者浮点数的类型 */
+fn check_numeric(state: &ExeState, i: usize) -> Result<Value, LuaError> {
+    return arith::to_number(&state.get(i)).ok_or_else(|| state.type_error(i, "number"));
+}
+
+/** 取整之后的浮点数 : 在整数范围内时转换成整数 */
+fn push_num_int(state: &mut ExeState, f: f64) {
+    match arith::float_to_int(f) {
+        Some(i) => state.push(i),
+        None => state.push(f),
+    }
+}
+
+/** math.abs(x) : 整数的最小值取绝对值时回绕 */
+fn lib_abs(state: &mut ExeState) -> Result<i32, LuaError> {
+    match check_numeric(state, 1)? {
+        Value::Integer(i) => state.push(i.wrapping_abs()),
+        _ => state.push(state.check_number(1)?.abs()),
+    }
+    return Ok(1);
+}
+
+/** math.ceil(x) */
+fn lib_ceil(state: &mut ExeState) -> Result<i32, LuaError> {
+    match check_numeric(state, 1)? {
+        Value::Integer(i) => state.push(i),
+        _ => push_num_int(state, state.check_number(1)?.ceil()),
+    }
+    return Ok(1);
+}
+
+/** math.floor(x) */
+fn lib_floor(state: &mut ExeState) -> Result<i32, LuaError> {
+    match check_numeric(state, 1)? {
+        Value::Integer(i) => state.push(i),
+        _ => push_num_int(state, state.check_number(1)?.floor()),
+    }
+    return Ok(1);
+}
+
+/** math.fmod(a, b) : 结果的符号和a相同 */
+fn lib_fmod(state: &mut ExeState) -> Result<i32, LuaError> {
+    match (check_numeric(state, 1)?, check_numeric(state, 2)?) {
+        (Value::Integer(a), Value::Integer(b)) => {
+            if b == 0 {
+                return Err(state.arg_error(2, "zero"));
+            }
+            /* b为-1时避免溢出 */
+            state.push(if b == -1 { 0 } else { a % b });
+        }
+        _ => {
+            let (a, b) = (state.check_number(1)?, state.check_number(2)?);
+            state.push(a % b);
+        }
+    }
+    return Ok(1);
+}
+
+/** math.modf(x) : 返回整数部分和小数部分 ; 整数参数的整数部分就是它自己 , 其他情况都是浮点数 */
+fn lib_modf(state: &mut ExeState) -> Result<i32, LuaError> {
+    if let Value::Integer(i) = check_numeric(state, 1)? {
+        state.push(i);
+        state.push(0.0);
+        return Ok(2);
+    }
+    let n = state.check_number(1)?;
+    let ip = if n < 0.0 { n.ceil() } else { n.floor() };
+    state.push(ip);
+    state.push(if n == ip { 0.0 } else { n - ip });
+    return Ok(2);
+}
+
+/** 只有一个浮点数参数的函数 */
+fn math_float(state: &mut ExeState, f: fn(f64) -> f64) -> Result<i32, LuaError> {
+    let x = state.check_number(1)?;
+    state.push(f(x));
+    return Ok(1);
+}
+
+fn lib_sqrt(state: &mut ExeState) -> Result<i32, LuaError> {
+    return math_float(state, f64::sqrt);
+}
+
+fn lib_exp(state: &mut ExeState) -> Result<i32, LuaError> {
+    return math_float(state, f64::exp);
+}
+
+fn lib_sin(state: &mut ExeState) -> Result<i32, LuaError> {
+    return math_float(state, f64::sin);
+}
+
+fn lib_cos(state: &mut ExeState) -> Result<i32, LuaError> {
+    return math_float(state, f64::cos);
+}
+
+fn lib_tan(state: &mut ExeState) -> Result<i32, LuaError> {
+    return math_float(state, f64::tan);
+}
+
+fn lib_asin(state: &mut ExeState) -> Result<i32, LuaError> {
+    return math_float(state, f64::asin);
+}
+
+fn lib_acos(state: &mut ExeState) -> Result<i32, LuaError> {
+    return math_float(state, f64::acos);
+}
+
+fn lib_deg(state: &mut ExeState) -> Result<i32, LuaError> {
+    return math_float(state, f64::to_degrees);
+}
+
+fn lib_rad(state: &mut ExeState) -> Result<i32, LuaError> {
+    return math_float(state, f64::to_radians);
+}
+
+/** math.atan(y [, x]) */
+fn lib_atan(state: &mut ExeState) -> Result<i32, LuaError> {
+    let y = state.check_number(1)?;
+    let x = if matches!(state.get(2), Value::Nil) { 1.0 } else { state.check_number(2)? };
+    state.push(y.atan2(x));
+    return Ok(1);
+}
+
+/** math.log(x [, base]) : 默认是自然对数 */
+fn lib_log(state: &mut ExeState) -> Result<i32, LuaError> {
+    let x = state.check_number(1)?;
+    let res = if matches!(state.get(2), Value::Nil) {
+        x.ln()
+    } else {
+        match state.check_number(2)? {
+            2.0 => x.log2(),
+            10.0 => x.log10(),
+            base => x.ln() / base.ln(),
+        }
+    };
+    state.push(res);
+    return Ok(1);
+}
+
+/** math.tointeger(x) : 不能转换成整数时返回nil */
+fn lib_tointeger(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.check_any(1)?;
+    state.push(arith::to_integer(&v).map_or(Value::Nil, Value::Integer));
+    return Ok(1);
+}
+
+/** math.type(x) : "integer" , "float" , 不是数字时返回nil */
+fn lib_type(state: &mut ExeState) -> Result<i32, LuaError> {
+    state.push(match state.check_any(1)? {
+        Value::Integer(_) => Value::from("integer"),
+        Value::Float(_) => Value::from("float"),
+        _ => Value::Nil,
+    });
+    return Ok(1);
+}
+
+/** math.ult(m, n) : 按无符号整数比较 */
+fn lib_ult(state: &mut ExeState) -> Result<i32, LuaError> {
+    let m = state.check_integer(1)?;
+    let n = state.check_integer(2)?;
+    state.push((m as u64) < (n as u64));
+    return Ok(1);
+}
+
+/** math.max和math.min : 返回原来的值 , 保留整数或者浮点数的类型 */
+fn min_max(state: &mut ExeState, is_max: bool) -> Result<i32, LuaError> {
+    let mut best = check_numeric(state, 1)?;
+    for i in 2..=state.get_top() {
+        let v = check_numeric(state, i)?;
+        let (a, b) = if is_max { (&best, &v) } else { (&v, &best) };
+        if arith::compare(OpCode::Lt, a, b).unwrap_or(false) {
+            best = v;
+        }
+    }
+    state.push(best);
+    return Ok(1);
+}
+
+fn lib_max(state: &mut ExeState) -> Result<i32, LuaError> {
+    return min_max(state, true);
+}
+
+fn lib_min(state: &mut ExeState) -> Result<i32, LuaError> {
+    return min_max(state, false);
+}
+
+/* ### 伪随机数
+    和Lua 5.4一样使用xoshiro256** 算法 , 相同的种子产生和Lua相同的序列
+ */
+
+/** xoshiro256** 的状态 */
+struct Xoshiro256 {
+    s: [u64; 4],
+}
+
+impl Xoshiro256 {
+    /** 和Lua的setseed一致 : 用两个整数初始化 , 并且丢弃前16个值 */
+    fn new(n1: u64, n2: u64) -> Self {
+        let mut rng = Xoshiro256 { s: [n1, 0xff, n2, 0] };
+        for _ in 0..16 {
+            rng.next();
+        }
+        return rng;
+    }
+
+    /** 没有指定种子时 , 使用当前时间和状态的地址作为种子 */
+    fn randomize(&mut self) -> (u64, u64) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        let addr = self as *const Self as u64;
+        *self = Xoshiro256::new(time, addr);
+        return (time, addr);
+    }
+
+    fn next(&mut self) -> u64 {
+        let s = &mut self.s;
+        let res = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        return res;
+    }
+
+    /** 把随机数投影到[0, n] : n+1是2的幂时直接取低位 , 否则用掩码拒绝采样 */
+    fn project(&mut self, mut ran: u64, n: u64) -> u64 {
+        if n & n.wrapping_add(1) == 0 {
+            return ran & n;
+        }
+        let lim = u64::MAX >> n.leading_zeros();
+        loop {
+            ran &= lim;
+            if ran <= n {
+                return ran;
+            }
+            ran = self.next();
+        }
+    }
+}
+
+/** math.random([m [, n]]) : 没有参数时返回[0,1)之间的浮点数 , 否则返回[m,n]之间的整数 */
+fn lib_random(state: &mut ExeState, rng: &mut Xoshiro256) -> Result<i32, LuaError> {
+    let rv = rng.next();
+    let (low, up) = match state.get_top() {
+        0 => {
+            /* 取高53位作为浮点数的尾数 */
+            state.push((rv >> 11) as f64 * (0.5f64).powi(53));
+            return Ok(1);
+        }
+        1 => {
+            let up = state.check_integer(1)?;
+            if up == 0 {
+                state.push(rv as i64);
+                return Ok(1);
+            }
+            (1, up)
+        }
+        2 => (state.check_integer(1)?, state.check_integer(2)?),
+        _ => {
+            return Err(state.error("wrong number of arguments"));
+        }
+    };
+    if low > up {
+        return Err(state.arg_error(1, "interval is empty"));
+    }
+    let p = rng.project(rv, (up as u64).wrapping_sub(low as u64));
+    state.push(p.wrapping_add(low as u64) as i64);
+    return Ok(1);
+}
+
+/** math.randomseed([x [, y]]) : 返回实际使用的两个种子 */
+fn lib_randomseed(state: &mut ExeState, rng: &mut Xoshiro256) -> Result<i32, LuaError> {
+    let (n1, n2) = if state.get_top() == 0 {
+        rng.randomize()
+    } else {
+        let n1 = state.check_integer(1)? as u64;
+        let n2 = state.opt_integer(2, 0)? as u64;
+        *rng = Xoshiro256::new(n1, n2);
+        (n1, n2)
+    };
+    state.push(n1 as i64);
+    state.push(n2 as i64);
+    return Ok(2);
+}
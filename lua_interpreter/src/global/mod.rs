@@ -1,7 +1,506 @@
-use crate::vm::ExeState;
+use std::{ rc::Rc, cell::RefCell, fs, io::Read };
 
-pub fn lib_print(state: &mut ExeState) -> i32 {
-    /* 只接受一个参数,所以说的 func_index +1  */
-    println!("{:?}", state.stack[state.func_index + 1]);
-    return 0; /* 返回0表示不返回任何数据 */
+use crate::{ vm::ExeState, parse::ParseProto, interface::{ Value, LuaError, RustFunction, arith, table::Table } };
+
+mod pattern;
+mod format;
+mod pack;
+pub mod string;
+pub mod math;
+pub mod table;
+pub mod utf8;
+pub mod io;
+pub mod os;
+pub mod coroutine;
+pub mod package;
+pub mod debug;
+
+/** 打开所有的标准库 */
+pub fn open_libs(state: &mut ExeState) {
+    open_base(state);
+    string::open_string(state);
+    math::open_math(state);
+    table::open_table(state);
+    coroutine::open_coroutine(state);
+    utf8::open_utf8(state);
+    io::open_io(state);
+    os::open_os(state);
+    debug::open_debug(state);
+    package::open_package(state);
+}
+
+/** 用一组Rust函数创建库table */
+pub fn new_lib(funcs: &[(&str, RustFunction)]) -> Value {
+    let mut lib = Table::new(0, funcs.len());
+    for (name, f) in funcs {
+        lib.set(Value::from(*name), Value::RustFunction(*f));
+    }
+    return Value::Table(Rc::new(RefCell::new(lib)));
+}
+
+/** 读取并解析Lua源文件 , 代码块名是"@文件名" ; 失败时返回错误信息 */
+pub fn load_file(state: &ExeState, filename: &str) -> Result<Value, String> {
+    let src = fs::read(filename).map_err(|e| format!("cannot open {}: {}", filename, io::os_message(&e)))?;
+    return load_chunk(state, skip_header(&src), &format!("@{filename}"), None);
+}
+
+/** 源文件开头的BOM和第一行的#注释(比如#!/usr/bin/lua)不是代码 , 跳过它们 */
+fn skip_header(src: &[u8]) -> &[u8] {
+    let mut src = src.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(src);
+    if src.starts_with(b"#") {
+        /* 保留换行 , 行号不变 */
+        src = &src[src.iter().position(|&c| c == b'\n').unwrap_or(src.len())..];
+    }
+    return src;
+}
+
+/** 检查代码块是否符合mode("b"、"t"或者"bt") , 不支持二进制代码块 */
+fn check_mode(src: &[u8], mode: &str) -> Result<(), String> {
+    let binary = src.starts_with(b"\x1bLua");
+    return match (binary, mode.contains('b'), mode.contains('t')) {
+        (true, false, _) => Err(format!("attempt to load a binary chunk (mode is '{mode}')")),
+        (false, _, false) => Err(format!("attempt to load a text chunk (mode is '{mode}')")),
+        (true, true, _) => Err(String::from("binary chunks are not supported")),
+        _ => Ok(()),
+    };
+}
+
+/** 解析源代码 , 返回主代码块的闭包 : env为None时_ENV是全局变量表 ; 语法错误时返回错误信息 */
+fn load_chunk(state: &ExeState, src: &[u8], chunkname: &str, env: Option<Value>) -> Result<Value, String> {
+    let proto = Rc::new(ParseProto::load_chunk(src, chunkname).map_err(|e| e.to_string())?);
+    return match env {
+        Some(env) => Ok(state.load_with_env(proto, env)),
+        None => Ok(state.load(proto)),
+    };
+}
+
+/** 基础库 : 注册到全局变量 */
+pub fn open_base(state: &mut ExeState) {
+    let funcs: [(&str, RustFunction); 22] = [
+        ("print", lib_print),
+        ("type", lib_type),
+        ("tostring", lib_tostring),
+        ("tonumber", lib_tonumber),
+        ("assert", lib_assert),
+        ("error", lib_error),
+        ("pcall", lib_pcall),
+        ("xpcall", lib_xpcall),
+        ("select", lib_select),
+        ("rawget", lib_rawget),
+        ("rawset", lib_rawset),
+        ("rawequal", lib_rawequal),
+        ("rawlen", lib_rawlen),
+        ("getmetatable", lib_getmetatable),
+        ("setmetatable", lib_setmetatable),
+        ("collectgarbage", lib_collectgarbage),
+        ("next", lib_next),
+        ("pairs", lib_pairs),
+        ("ipairs", lib_ipairs),
+        ("load", lib_load),
+        ("loadfile", lib_loadfile),
+        ("dofile", lib_dofile),
+    ];
+    for (name, f) in funcs {
+        state.set_global(name, Value::RustFunction(f));
+    }
+    state.set_global("_G", Value::Table(state.globals.clone()));
+    state.set_global("_VERSION", "Lua 5.4");
+}
+
+pub fn lib_print(state: &mut ExeState) -> Result<i32, LuaError> {
+    /* 参数从 func_index + 1 一直到栈顶,多个参数之间用\t分隔 ; 每个参数都经过tostring */
+    /* 直接输出字符串的字节 , 经过io.stdout , 宿主可以重定向 */
+    let mut buf = Vec::new();
+    for i in 1..=state.get_top() {
+        if i > 1 {
+            buf.push(b'\t');
+        }
+        let v = state.get(i);
+        let s = state.tostring(&v)?;
+        buf.extend_from_slice(<&[u8]>::from(&s));
+    }
+    buf.push(b'\n');
+    io::write_stdout(state, &buf)?;
+    return Ok(0); /* 返回0表示不返回任何数据 */
+}
+
+/** type(v) : 类型名 */
+fn lib_type(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.check_any(1)?;
+    state.push(v.ty());
+    return Ok(1);
+}
+
+/** tostring(v) */
+fn lib_tostring(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.check_any(1)?;
+    let s = state.tostring(&v)?;
+    state.push(s);
+    return Ok(1);
+}
+
+/** tonumber(v [, base]) : 不能转换时返回nil */
+fn lib_tonumber(state: &mut ExeState) -> Result<i32, LuaError> {
+    if state.get_top() < 2 || matches!(state.get(2), Value::Nil) {
+        let v = state.check_any(1)?;
+        state.push(arith::to_number(&v).unwrap_or(Value::Nil));
+        return Ok(1);
+    }
+    /* 指定进制时只接受字符串形式的整数 */
+    let base = state.check_integer(2)?;
+    let s = state.get(1);
+    if !s.is_str() {
+        return Err(state.type_error(1, "string"));
+    }
+    if !(2..=36).contains(&base) {
+        return Err(state.arg_error(2, "base out of range"));
+    }
+    state.push(str_to_int_base(<&[u8]>::from(&s), base as u32).map_or(Value::Nil, Value::Integer));
+    return Ok(1);
+}
+
+/** 指定进制的整数 : 允许前后空白和负号,溢出时回绕 */
+fn str_to_int_base(s: &[u8], base: u32) -> Option<i64> {
+    let s = s.trim_ascii();
+    let (neg, s) = match s.first() {
+        Some(b'-') => (true, &s[1..]),
+        _ => (false, s),
+    };
+    if s.is_empty() {
+        return None;
+    }
+    let mut n: i64 = 0;
+    for c in s {
+        let d = (*c as char).to_digit(base)?;
+        n = n.wrapping_mul(base as i64).wrapping_add(d as i64);
+    }
+    return Some(if neg { n.wrapping_neg() } else { n });
+}
+
+/** assert(v [, message, ...]) : v为真时返回全部参数 */
+fn lib_assert(state: &mut ExeState) -> Result<i32, LuaError> {
+    if state.check_any(1)?.truthy() {
+        return Ok(state.get_top() as i32);
+    }
+    if state.get_top() >= 2 {
+        return Err(LuaError::new(state.get(2)));
+    }
+    return Err(state.error("assertion failed!"));
+}
+
+/** error(message [, level]) : 字符串错误按level加上位置信息 */
+fn lib_error(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.get(1);
+    let level = if matches!(state.get(2), Value::Nil) { 1 } else { state.check_integer(2)? };
+    if let Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_) = v {
+        if level > 0 {
+            let mut msg = state.location(level as usize).into_bytes();
+            msg.extend_from_slice(<&[u8]>::from(&v));
+            return Err(LuaError::new(msg));
+        }
+    }
+    return Err(LuaError::new(v));
+}
+
+/** pcall(f, ...) : 成功时返回true和f的全部返回值,出错时返回false和错误 */
+fn lib_pcall(state: &mut ExeState) -> Result<i32, LuaError> {
+    state.check_any(1)?;
+    let func = state.func_index + 1;
+    let nargs = state.get_top() - 1;
+    return state.pcall_k(func, nargs, Value::Nil, Box::new(move |state, r| finish_pcall(state, func, r)));
+}
+
+/** xpcall(f, msgh, ...) : 出错时先在出错的位置调用msgh,返回msgh的结果 */
+fn lib_xpcall(state: &mut ExeState) -> Result<i32, LuaError> {
+    state.check_any(2)?;
+    let func = state.func_index + 1;
+    let handler = state.stack.remove(func + 1);
+    let nargs = state.get_top() - 1;
+    return state.pcall_k(func, nargs, handler, Box::new(move |state, r| finish_pcall(state, func, r)));
+}
+
+/** pcall/xpcall的后续 : 被调用的函数中yield过时,协程恢复之后在这里完成 */
+fn finish_pcall(state: &mut ExeState, func: usize, r: Result<(), LuaError>) -> Result<i32, LuaError> {
+    match r {
+        Ok(()) => {
+            state.stack.insert(func, Value::Boolean(true));
+        }
+        Err(e) => {
+            state.push(false);
+            state.push(e.value);
+        }
+    }
+    return Ok((state.stack.len() - func) as i32);
+}
+
+/** select(n, ...) : 第n个之后的参数 ; select('#', ...) : 参数个数 */
+fn lib_select(state: &mut ExeState) -> Result<i32, LuaError> {
+    let count = state.get_top() as i64 - 1;
+    let n = state.get(1);
+    if n.is_str() && <&[u8]>::from(&n) == b"#" {
+        state.push(count);
+        return Ok(1);
+    }
+    let n = state.check_integer(1)?;
+    let n = if n < 0 { count + n } else { n - 1 };
+    if n < 0 {
+        return Err(state.arg_error(1, "index out of range"));
+    }
+    return Ok((count - n).max(0) as i32);
+}
+
+/** rawget(t, k) */
+fn lib_rawget(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.check_table(1)?;
+    let key = state.check_any(2)?;
+    let v = t.borrow().get(&key);
+    state.push(v);
+    return Ok(1);
+}
+
+/** rawset(t, k, v) : 返回t */
+fn lib_rawset(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.check_table(1)?;
+    let key = state.check_any(2)?;
+    let value = state.check_any(3)?;
+    state.raw_set(&t, key, value)?;
+    state.push(Value::Table(t));
+    return Ok(1);
+}
+
+/** rawequal(a, b) */
+fn lib_rawequal(state: &mut ExeState) -> Result<i32, LuaError> {
+    let a = state.check_any(1)?;
+    let b = state.check_any(2)?;
+    state.push(arith::equal(&a, &b));
+    return Ok(1);
+}
+
+/** rawlen(v) : table或者字符串的长度 */
+fn lib_rawlen(state: &mut ExeState) -> Result<i32, LuaError> {
+    let len = match state.get(1) {
+        Value::Table(t) => t.borrow().len(),
+        v if v.is_str() => <&[u8]>::from(&v).len() as i64,
+        _ => {
+            return Err(state.arg_error(1, "table or string expected"));
+        }
+    };
+    state.push(len);
+    return Ok(1);
+}
+
+/** getmetatable(v) : 元表有__metatable字段时返回该字段 */
+fn lib_getmetatable(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.check_any(1)?;
+    let mt = match state.get_metatable(&v) {
+        Some(mt) => {
+            let protected = mt.borrow().get(&Value::from("__metatable"));
+            if let Value::Nil = protected { Value::Table(mt) } else { protected }
+        }
+        None => Value::Nil,
+    };
+    state.push(mt);
+    return Ok(1);
+}
+
+/** setmetatable(t, mt) : 返回t , 元表被__metatable保护时不能修改 */
+fn lib_setmetatable(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.check_table(1)?;
+    let mt = match state.get(2) {
+        Value::Nil => None,
+        Value::Table(mt) => Some(mt),
+        _ => {
+            return Err(state.type_error(2, "nil or table"));
+        }
+    };
+    if let Some(old) = &t.borrow().metatable {
+        if !matches!(old.borrow().get(&Value::from("__metatable")), Value::Nil) {
+            return Err(state.error("cannot change a protected metatable"));
+        }
+    }
+    t.borrow_mut().metatable = mt;
+    state.push(Value::Table(t));
+    return Ok(1);
+}
+
+/** collectgarbage : 内存由引用计数管理,只保留接口 */
+fn lib_collectgarbage(state: &mut ExeState) -> Result<i32, LuaError> {
+    state.push(0i64);
+    return Ok(1);
+}
+
+/** next(t [, k]) : k之后的下一个键值对 , 遍历结束时返回nil */
+fn lib_next(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.check_table(1)?;
+    let next = t.borrow().next(&state.get(2));
+    return match next {
+        Ok(Some((k, v))) => {
+            state.push(k);
+            state.push(v);
+            Ok(2)
+        }
+        Ok(None) => {
+            state.push(Value::Nil);
+            Ok(1)
+        }
+        Err(msg) => Err(state.error(msg)),
+    };
+}
+
+/** pairs(t) : 有__pairs元方法时返回它的前3个返回值 , 否则返回 next, t, nil */
+fn lib_pairs(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.check_any(1)?;
+    let h = state.metamethod(&t, "__pairs");
+    if let Value::Nil = h {
+        state.push(Value::RustFunction(lib_next));
+        state.push(t);
+        state.push(Value::Nil);
+        return Ok(3);
+    }
+    let func = state.stack.len();
+    state.push(h);
+    state.push(t);
+    /* __pairs中可以yield : 恢复之后它的3个返回值就是pairs的返回值 */
+    return state.call_k(func, 1, Some(3), Box::new(|_, _| Ok(3)));
+}
+
+/** ipairs(t) : 返回 迭代函数, t, 0 , 从1开始遍历到第一个nil为止 */
+fn lib_ipairs(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.check_any(1)?;
+    state.push(Value::RustFunction(ipairs_aux));
+    state.push(t);
+    state.push(0i64);
+    return Ok(3);
+}
+
+/** ipairs的迭代函数 : 读取t[i+1]时遵循__index */
+fn ipairs_aux(state: &mut ExeState) -> Result<i32, LuaError> {
+    let i = state.check_integer(2)?.wrapping_add(1);
+    let v = state.index(&state.get(1), &Value::Integer(i))?;
+    if let Value::Nil = v {
+        state.push(Value::Nil);
+        return Ok(1);
+    }
+    state.push(i);
+    state.push(v);
+    return Ok(2);
+}
+
+/** load(chunk [, chunkname [, mode [, env]]]) : chunk是字符串 , 或者每次调用返回一段源代码的函数(返回nil或空串结束) ;
+    env给出时(即使是nil)作为代码块的_ENV , 否则是全局变量表 ; 失败时返回nil和错误信息
+ */
+fn lib_load(state: &mut ExeState) -> Result<i32, LuaError> {
+    let chunk = state.get(1);
+    let (src, default_name) = if chunk.is_str() {
+        let src = <&[u8]>::from(&chunk).to_vec();
+        let name = String::from_utf8_lossy(&src).into_owned();
+        (src, name)
+    } else if chunk.is_function() {
+        let mut src = Vec::new();
+        loop {
+            let func = state.stack.len();
+            state.push(chunk.clone());
+            if let Err(e) = state.pcall(func, 0, Value::Nil) {
+                if e.is_interrupt() {
+                    return Err(e);
+                }
+                state.push(Value::Nil);
+                state.push(e.value);
+                return Ok(2);
+            }
+            let piece = state.stack.drain(func..).next().unwrap_or(Value::Nil);
+            match piece {
+                Value::Nil => break,
+                v if v.is_str() => {
+                    if <&[u8]>::from(&v).is_empty() {
+                        break;
+                    }
+                    src.extend_from_slice(<&[u8]>::from(&v));
+                }
+                _ => {
+                    state.push(Value::Nil);
+                    state.push("reader function must return a string");
+                    return Ok(2);
+                }
+            }
+        }
+        (src, String::from("=(load)"))
+    } else {
+        return Err(state.type_error(1, "function"));
+    };
+    let chunkname = match state.get(2) {
+        Value::Nil => default_name,
+        _ => String::from(&state.check_string(2)?),
+    };
+    let mode = match state.get(3) {
+        Value::Nil => String::from("bt"),
+        _ => String::from(&state.check_string(3)?),
+    };
+
+    let env = if state.get_top() >= 4 { Some(state.get(4)) } else { None };
+    let r = check_mode(&src, &mode).and_then(|_| load_chunk(state, &src, &chunkname, env));
+    return push_loaded(state, r);
+}
+
+/** load和loadfile的返回值 : 成功时是主代码块的闭包 , 失败时是nil和错误信息 */
+fn push_loaded(state: &mut ExeState, r: Result<Value, String>) -> Result<i32, LuaError> {
+    match r {
+        Ok(f) => {
+            state.push(f);
+            return Ok(1);
+        }
+        Err(msg) => {
+            state.push(Value::Nil);
+            state.push(msg);
+            return Ok(2);
+        }
+    }
+}
+
+/** 读取源文件 , 没有文件名时读取标准输入 ; 返回源代码和代码块名 */
+fn read_source(filename: &Value) -> Result<(Vec<u8>, String), String> {
+    if let Value::Nil = filename {
+        let mut src = Vec::new();
+        std::io::stdin().read_to_end(&mut src).map_err(|e| format!("cannot read stdin: {}", io::os_message(&e)))?;
+        return Ok((src, String::from("=stdin")));
+    }
+    let filename = String::from(filename);
+    let src = fs::read(&filename).map_err(|e| format!("cannot open {}: {}", filename, io::os_message(&e)))?;
+    return Ok((src, format!("@{filename}")));
+}
+
+/** loadfile([filename [, mode [, env]]]) : 和load一样 , 只是代码来自文件(默认是标准输入) */
+fn lib_loadfile(state: &mut ExeState) -> Result<i32, LuaError> {
+    let filename = match state.get(1) {
+        Value::Nil => Value::Nil,
+        _ => state.check_string(1)?,
+    };
+    let mode = match state.get(2) {
+        Value::Nil => String::from("bt"),
+        _ => String::from(&state.check_string(2)?),
+    };
+    let env = if state.get_top() >= 3 { Some(state.get(3)) } else { None };
+    let r = read_source(&filename).and_then(|(src, chunkname)| {
+        let src = skip_header(&src);
+        check_mode(src, &mode)?;
+        return load_chunk(state, src, &chunkname, env);
+    });
+    return push_loaded(state, r);
+}
+
+/** dofile([filename]) : 执行文件(默认是标准输入) , 返回代码块的全部返回值 ; 错误直接抛出 */
+fn lib_dofile(state: &mut ExeState) -> Result<i32, LuaError> {
+    let filename = match state.get(1) {
+        Value::Nil => Value::Nil,
+        _ => state.check_string(1)?,
+    };
+    let f = read_source(&filename)
+        .and_then(|(src, chunkname)| load_chunk(state, skip_header(&src), &chunkname, None))
+        .map_err(LuaError::new)?;
+    let func = state.stack.len();
+    state.push(f);
+    return state.call_k(func, 0, None, Box::new(move |state, r| {
+        r?;
+        return Ok((state.stack.len() - func) as i32);
+    }));
 }
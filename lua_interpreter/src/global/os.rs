@@ -0,0 +1,541 @@
+use std::{
+    rc::Rc,
+    cell::RefCell,
+    env,
+    fs,
+    io::{ self, Write },
+    path::PathBuf,
+    time::{ Instant, SystemTime, UNIX_EPOCH },
+};
+
+use crate::{ vm::ExeState, interface::{ Value, LuaError, RustFunction, arith, table::Table } };
+
+/* ### os库
+    没有依赖libc , 日期的换算自己实现 : 本地时区从TZif文件(TZ环境变量或者/etc/localtime)读取 ,
+    读不到时按UTC处理 ; 最后一个转换点之后(tzdata中一般是2037年)沿用最后的偏移 , 不解析POSIX规则 ;
+    os.date的格式和C语言locale下的strftime一致
+ */
+
+/** os.date支持的转换 : 单个字符的 , 以及E和O修饰的 */
+const OPTIONS_1: &[u8] = b"aAbBcCdDeFgGhHIjmMnprRStTuUVwWxXyYzZ%";
+const OPTIONS_2: &[&[u8]] = &[
+    b"Ec", b"EC", b"Ex", b"EX", b"Ey", b"EY",
+    b"Od", b"Oe", b"OH", b"OI", b"Om", b"OM", b"OS", b"Ou", b"OU", b"OV", b"Ow", b"OW", b"Oy",
+];
+
+const WEEKDAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+/** os库 : 注册到全局变量os */
+pub fn open_os(state: &mut ExeState) {
+    let zone = Rc::new(TimeZone::load());
+    let start = Instant::now();
+    let mut lib = Table::new(0, 11);
+    let z = zone.clone();
+    lib.set(Value::from("time"), Value::closure(move |state| os_time(state, &z)));
+    lib.set(Value::from("date"), Value::closure(move |state| os_date(state, &zone)));
+    lib.set(Value::from("clock"), Value::closure(move |state| os_clock(state, start)));
+    let funcs: [(&str, RustFunction); 6] = [
+        ("difftime", os_difftime),
+        ("getenv", os_getenv),
+        ("remove", os_remove),
+        ("rename", os_rename),
+        ("tmpname", os_tmpname),
+        ("exit", os_exit),
+    ];
+    for (name, f) in funcs {
+        lib.set(Value::from(name), Value::RustFunction(f));
+    }
+    state.set_global("os", Value::Table(Rc::new(RefCell::new(lib))));
+}
+
+/** 沙箱 : 宿主在执行脚本之前删除os库中的部分函数 , 比如exit、remove、rename */
+pub fn disable(state: &mut ExeState, names: &[&str]) {
+    if let Value::Table(os) = state.get_global("os") {
+        let mut os = os.borrow_mut();
+        for name in names {
+            os.set(Value::from(*name), Value::Nil);
+        }
+    }
+}
+
+/** 在临时目录中创建一个新文件 , 返回它的路径 */
+pub fn create_temp() -> io::Result<(PathBuf, fs::File)> {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+    let mut seed = nanos ^ ((std::process::id() as u64) << 32);
+    for _ in 0..100 {
+        let mut name = String::from("lua_");
+        for _ in 0..6 {
+            /* 线性同余 , 只是为了得到不同的名字 */
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            name.push(CHARS[(seed >> 33) as usize % CHARS.len()] as char);
+        }
+        let path = env::temp_dir().join(name);
+        match fs::OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+            Ok(f) => {
+                return Ok((path, f));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+            Err(e) => {
+                return Err(e);
+            }
+        }
+    }
+    return Err(io::ErrorKind::AlreadyExists.into());
+}
+
+/** ## 时区
+    TZif文件中的转换点和每一段的偏移
+ */
+struct TimeZone {
+    transitions: Vec<(i64, usize)> /* (UTC时间, 类型) , 按时间递增 */,
+    types: Vec<ZoneType>,
+}
+
+struct ZoneType {
+    offset: i64 /* 相对UTC的秒数 , 东边为正 */,
+    isdst: bool,
+    abbr: String,
+}
+
+impl TimeZone {
+    fn utc() -> Self {
+        return TimeZone { transitions: Vec::new(), types: vec![ZoneType { offset: 0, isdst: false, abbr: "UTC".into() }] };
+    }
+
+    /** TZ为空表示UTC , 以'/'开头是文件路径 , 否则是zoneinfo中的名字 ; 没有设置TZ时使用/etc/localtime */
+    fn load() -> Self {
+        let path = match env::var("TZ") {
+            Ok(tz) => {
+                let tz = tz.strip_prefix(':').unwrap_or(&tz).to_string();
+                if tz.is_empty() {
+                    return Self::utc();
+                }
+                if tz.starts_with('/') { PathBuf::from(tz) } else { PathBuf::from("/usr/share/zoneinfo").join(tz) }
+            }
+            Err(_) => PathBuf::from("/etc/localtime"),
+        };
+        return fs::read(path).ok().and_then(|data| Self::parse(&data)).unwrap_or_else(Self::utc);
+    }
+
+    /** 解析TZif : 版本2以上跳过32位的数据 , 使用64位的 */
+    fn parse(data: &[u8]) -> Option<Self> {
+        let counts = |d: &[u8]| -> Option<[usize; 6]> {
+            if d.get(..4)? != b"TZif" {
+                return None;
+            }
+            let mut c = [0; 6];
+            for (i, n) in c.iter_mut().enumerate() {
+                let b = d.get(20 + i * 4..24 + i * 4)?;
+                *n = u32::from_be_bytes(b.try_into().ok()?) as usize;
+            }
+            return Some(c);
+        };
+        let body_len = |c: &[usize; 6], tsize: usize| c[3] * (tsize + 1) + c[4] * 6 + c[5] + c[2] * (tsize + 4) + c[1] + c[0];
+
+        let mut c = counts(data)?;
+        let mut d = data;
+        let mut tsize = 4;
+        if data[4] >= b'2' {
+            d = data.get(44 + body_len(&c, 4)..)?;
+            c = counts(d)?;
+            tsize = 8;
+        }
+        let [_, _, _, timecnt, typecnt, charcnt] = c;
+        let body = d.get(44..44 + body_len(&c, tsize))?;
+        let (times, rest) = body.split_at(timecnt * tsize);
+        let (indices, rest) = rest.split_at(timecnt);
+        let (infos, rest) = rest.split_at(typecnt * 6);
+        let chars = &rest[..charcnt];
+
+        let mut transitions = Vec::with_capacity(timecnt);
+        for (i, t) in times.chunks(tsize).enumerate() {
+            let t = if tsize == 8 {
+                i64::from_be_bytes(t.try_into().ok()?)
+            } else {
+                i32::from_be_bytes(t.try_into().ok()?) as i64
+            };
+            let ty = indices[i] as usize;
+            if ty >= typecnt {
+                return None;
+            }
+            transitions.push((t, ty));
+        }
+        let mut types = Vec::with_capacity(typecnt);
+        for info in infos.chunks(6) {
+            let offset = i32::from_be_bytes(info[..4].try_into().ok()?) as i64;
+            let abbr = chars.get(info[5] as usize..).unwrap_or(&[]);
+            let end = abbr.iter().position(|&c| c == 0).unwrap_or(abbr.len());
+            types.push(ZoneType { offset, isdst: info[4] != 0, abbr: String::from_utf8_lossy(&abbr[..end]).into_owned() });
+        }
+        if types.is_empty() {
+            return None;
+        }
+        return Some(TimeZone { transitions, types });
+    }
+
+    /** UTC时间t所在的时段 */
+    fn lookup(&self, t: i64) -> &ZoneType {
+        let i = self.transitions.partition_point(|&(start, _)| start <= t);
+        if i == 0 {
+            /* 第一个转换点之前 : 使用第一个非夏令时的类型 */
+            return self.types.iter().find(|ty| !ty.isdst).unwrap_or(&self.types[0]);
+        }
+        return &self.types[self.transitions[i - 1].1];
+    }
+}
+
+/** ## 日期
+    对应C语言的struct tm , 但是year和month都是真实的值
+ */
+struct Date {
+    year: i64,
+    month: i64 /* 1-12 */,
+    day: i64,
+    hour: i64,
+    min: i64,
+    sec: i64,
+    wday: i64 /* 0-6 , 星期天是0 */,
+    yday: i64 /* 0-365 */,
+    isdst: bool,
+    offset: i64,
+    zone: String,
+}
+
+/** 1970-01-01到指定日期的天数 */
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    return era * 146097 + doe - 719468;
+}
+
+/** days_from_civil的逆运算 : 返回(年, 月, 日) */
+fn civil_from_days(z: i64) -> (i64, i64, i64) {
+    let z = z + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    return (yoe + era * 400 + if m <= 2 { 1 } else { 0 }, m, d);
+}
+
+/** 时间t加上偏移之后的日期 , 年份超出C语言int的范围时为None */
+fn make_date(t: i64, ty: &ZoneType) -> Option<Date> {
+    let local = t.checked_add(ty.offset)?;
+    let days = local.div_euclid(86400);
+    let secs = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    if year - 1900 > i32::MAX as i64 || year - 1900 < i32::MIN as i64 {
+        return None;
+    }
+    return Some(Date {
+        year,
+        month,
+        day,
+        hour: secs / 3600,
+        min: secs / 60 % 60,
+        sec: secs % 60,
+        wday: (days + 4).rem_euclid(7),
+        yday: days - days_from_civil(year, 1, 1),
+        isdst: ty.isdst,
+        offset: ty.offset,
+        zone: ty.abbr.clone(),
+    });
+}
+
+/** 本地时间对应的UTC时间 , 各个字段可以超出正常范围(相当于mktime) */
+fn make_time(zone: &TimeZone, year: i64, month: i64, day: i64, hour: i64, min: i64, sec: i64) -> Option<i64> {
+    let m = month - 1;
+    let year = year.checked_add(m.div_euclid(12))?;
+    if year.unsigned_abs() > 1 << 40 {
+        return None;
+    }
+    let days = days_from_civil(year, m.rem_euclid(12) + 1, 1).checked_add(day - 1)?;
+    let local = days
+        .checked_mul(86400)?
+        .checked_add(hour.checked_mul(3600)?)?
+        .checked_add(min.checked_mul(60)?)?
+        .checked_add(sec)?;
+    /* 先按本地时间猜测偏移 , 再用猜到的时间修正一次 */
+    let t = local - zone.lookup(local).offset;
+    return Some(local - zone.lookup(t).offset);
+}
+
+/** 读取日期table中的整数字段 , 减去delta之后要在C语言int的范围内 */
+fn get_field(state: &mut ExeState, t: &Value, key: &str, default: Option<i64>, delta: i64) -> Result<i64, LuaError> {
+    let v = state.index(t, &Value::from(key))?;
+    match arith::to_integer(&v) {
+        Some(n) => {
+            let ok = if n >= 0 { n - delta <= i32::MAX as i64 } else { i32::MIN as i64 + delta <= n };
+            if !ok {
+                return Err(state.error(format!("field '{}' is out-of-bound", key)));
+            }
+            return Ok(n);
+        }
+        None if !matches!(v, Value::Nil) => {
+            return Err(state.error(format!("field '{}' is not an integer", key)));
+        }
+        None => {
+            return default.ok_or_else(|| state.error(format!("field '{}' missing in date table", key)));
+        }
+    }
+}
+
+/** 把日期的所有字段写到table中 */
+fn set_all_fields(state: &mut ExeState, t: &Value, d: &Date) -> Result<(), LuaError> {
+    let fields = [
+        ("year", d.year),
+        ("month", d.month),
+        ("day", d.day),
+        ("hour", d.hour),
+        ("min", d.min),
+        ("sec", d.sec),
+        ("yday", d.yday + 1),
+        ("wday", d.wday + 1),
+    ];
+    for (key, n) in fields {
+        state.set_index(t, Value::from(key), Value::Integer(n))?;
+    }
+    state.set_index(t, Value::from("isdst"), Value::Boolean(d.isdst))?;
+    return Ok(());
+}
+
+fn now() -> i64 {
+    return match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+}
+
+/** os.time([t]) : 没有参数时返回当前时间 ; 否则按本地时间换算 , 并且把规范化之后的字段写回t */
+fn os_time(state: &mut ExeState, zone: &TimeZone) -> Result<i32, LuaError> {
+    if let Value::Nil = state.get(1) {
+        state.push(now());
+        return Ok(1);
+    }
+    state.check_table(1)?;
+    let t = state.get(1);
+    let year = get_field(state, &t, "year", None, 1900)?;
+    let month = get_field(state, &t, "month", None, 1)?;
+    let day = get_field(state, &t, "day", None, 0)?;
+    let hour = get_field(state, &t, "hour", Some(12), 0)?;
+    let min = get_field(state, &t, "min", Some(0), 0)?;
+    let sec = get_field(state, &t, "sec", Some(0), 0)?;
+    let time = make_time(zone, year, month, day, hour, min, sec);
+    let Some((time, date)) = time.and_then(|time| Some((time, make_date(time, zone.lookup(time))?))) else {
+        return Err(state.error("time result cannot be represented in this installation"));
+    };
+    set_all_fields(state, &t, &date)?;
+    state.push(time);
+    return Ok(1);
+}
+
+/** os.date([format [, time]]) : '!'开头表示UTC , "*t"返回table , 否则按strftime格式化 */
+fn os_date(state: &mut ExeState, zone: &TimeZone) -> Result<i32, LuaError> {
+    let format = match state.get(1) {
+        Value::Nil => Value::from("%c"),
+        _ => state.check_string(1)?,
+    };
+    let time = match state.get(2) {
+        Value::Nil => now(),
+        _ => state.check_integer(2)?,
+    };
+    let mut s = <&[u8]>::from(&format);
+    let date = match s.strip_prefix(b"!") {
+        Some(rest) => {
+            s = rest;
+            make_date(time, &ZoneType { offset: 0, isdst: false, abbr: "GMT".into() })
+        }
+        None => make_date(time, zone.lookup(time)),
+    };
+    let Some(date) = date else {
+        return Err(state.error("date result cannot be represented in this installation"));
+    };
+    if s == b"*t" {
+        let t = Value::Table(Rc::new(RefCell::new(Table::new(0, 9))));
+        set_all_fields(state, &t, &date)?;
+        state.push(t);
+        return Ok(1);
+    }
+    let mut buf = Vec::new();
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] != b'%' {
+            buf.push(s[i]);
+            i += 1;
+            continue;
+        }
+        let rest = &s[i + 1..];
+        let conv = if rest.first().is_some_and(|c| OPTIONS_1.contains(c)) {
+            1
+        } else if rest.len() >= 2 && OPTIONS_2.contains(&&rest[..2]) {
+            2
+        } else {
+            let msg = format!("invalid conversion specifier '%{}'", String::from_utf8_lossy(rest));
+            return Err(state.arg_error(1, msg));
+        };
+        /* E和O修饰符在C语言locale下没有区别 */
+        strftime(&mut buf, rest[conv - 1], &date);
+        i += 1 + conv;
+    }
+    state.push(buf);
+    return Ok(1);
+}
+
+/** ISO 8601的(年, 周) : 每周从星期一开始 , 包含1月4日的那一周是第1周 */
+fn iso_week(d: &Date) -> (i64, i64) {
+    let weeks_in = |y: i64| {
+        let p = |y: i64| (y + y.div_euclid(4) - y.div_euclid(100) + y.div_euclid(400)).rem_euclid(7);
+        if p(y) == 4 || p(y - 1) == 3 { 53 } else { 52 }
+    };
+    let wd = (d.wday + 6) % 7 + 1;
+    let week = (d.yday + 1 - wd + 10) / 7;
+    if week < 1 {
+        return (d.year - 1, weeks_in(d.year - 1));
+    } else if week > weeks_in(d.year) {
+        return (d.year + 1, 1);
+    }
+    return (d.year, week);
+}
+
+/** 一个转换 , C语言locale */
+fn strftime(buf: &mut Vec<u8>, c: u8, d: &Date) {
+    let s = match c {
+        b'a' => WEEKDAYS[d.wday as usize][..3].to_string(),
+        b'A' => WEEKDAYS[d.wday as usize].to_string(),
+        b'b' | b'h' => MONTHS[d.month as usize - 1][..3].to_string(),
+        b'B' => MONTHS[d.month as usize - 1].to_string(),
+        b'c' => {
+            for c in b"a b e H:M:S Y" {
+                if c.is_ascii_alphabetic() { strftime(buf, *c, d) } else { buf.push(*c) }
+            }
+            return;
+        }
+        b'C' => format!("{:02}", d.year.div_euclid(100)),
+        b'd' => format!("{:02}", d.day),
+        b'D' | b'x' => format!("{:02}/{:02}/{:02}", d.month, d.day, d.year.rem_euclid(100)),
+        b'e' => format!("{:2}", d.day),
+        b'F' => format!("{}-{:02}-{:02}", d.year, d.month, d.day),
+        b'g' => format!("{:02}", iso_week(d).0.rem_euclid(100)),
+        b'G' => iso_week(d).0.to_string(),
+        b'H' => format!("{:02}", d.hour),
+        b'I' => format!("{:02}", (d.hour + 11) % 12 + 1),
+        b'j' => format!("{:03}", d.yday + 1),
+        b'm' => format!("{:02}", d.month),
+        b'M' => format!("{:02}", d.min),
+        b'n' => "\n".to_string(),
+        b'p' => (if d.hour < 12 { "AM" } else { "PM" }).to_string(),
+        b'r' => format!("{:02}:{:02}:{:02} {}", (d.hour + 11) % 12 + 1, d.min, d.sec, if d.hour < 12 { "AM" } else { "PM" }),
+        b'R' => format!("{:02}:{:02}", d.hour, d.min),
+        b'S' => format!("{:02}", d.sec),
+        b't' => "\t".to_string(),
+        b'T' | b'X' => format!("{:02}:{:02}:{:02}", d.hour, d.min, d.sec),
+        b'u' => ((d.wday + 6) % 7 + 1).to_string(),
+        b'U' => format!("{:02}", (d.yday + 7 - d.wday) / 7),
+        b'V' => format!("{:02}", iso_week(d).1),
+        b'w' => d.wday.to_string(),
+        b'W' => format!("{:02}", (d.yday + 7 - (d.wday + 6) % 7) / 7),
+        b'y' => format!("{:02}", d.year.rem_euclid(100)),
+        b'Y' => d.year.to_string(),
+        b'z' => {
+            let m = d.offset.abs() / 60;
+            format!("{}{:02}{:02}", if d.offset < 0 { '-' } else { '+' }, m / 60, m % 60)
+        }
+        b'Z' => d.zone.clone(),
+        _ => "%".to_string(),
+    };
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/** os.clock() : 进程使用的CPU时间 , 读不到/proc时用库打开之后经过的时间代替 */
+fn os_clock(state: &mut ExeState, start: Instant) -> Result<i32, LuaError> {
+    let cpu = fs::read_to_string("/proc/self/schedstat")
+        .ok()
+        .and_then(|s| s.split_whitespace().next()?.parse::<u64>().ok())
+        .map(|ns| ns as f64 / 1e9);
+    state.push(cpu.unwrap_or_else(|| start.elapsed().as_secs_f64()));
+    return Ok(1);
+}
+
+/** os.difftime(t2 [, t1]) */
+fn os_difftime(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t2 = state.check_integer(1)?;
+    let t1 = state.opt_integer(2, 0)?;
+    state.push(t2 as f64 - t1 as f64);
+    return Ok(1);
+}
+
+/** os.getenv(name) : 不存在时返回nil */
+fn os_getenv(state: &mut ExeState) -> Result<i32, LuaError> {
+    let name = state.check_string(1)?.to_string();
+    match env::var_os(name) {
+        Some(v) => state.push(v.to_string_lossy().into_owned()),
+        None => state.push(Value::Nil),
+    }
+    return Ok(1);
+}
+
+/** 成功时返回true , 失败时返回nil, "文件名: 错误信息", 错误码 */
+fn file_result(state: &mut ExeState, r: io::Result<()>, fname: &str) -> Result<i32, LuaError> {
+    match r {
+        Ok(()) => {
+            state.push(true);
+            return Ok(1);
+        }
+        Err(e) => {
+            let msg = e.to_string();
+            let msg = msg.find(" (os error").map_or(msg.as_str(), |i| &msg[..i]);
+            state.push(Value::Nil);
+            state.push(format!("{fname}: {msg}"));
+            state.push(e.raw_os_error().unwrap_or(0) as i64);
+            return Ok(3);
+        }
+    }
+}
+
+/** os.remove(filename) : 和C语言的remove一样 , 也可以删除空目录 */
+fn os_remove(state: &mut ExeState) -> Result<i32, LuaError> {
+    let name = state.check_string(1)?.to_string();
+    let r = match fs::symlink_metadata(&name) {
+        Ok(m) if m.is_dir() => fs::remove_dir(&name),
+        _ => fs::remove_file(&name),
+    };
+    return file_result(state, r, &name);
+}
+
+/** os.rename(oldname, newname) */
+fn os_rename(state: &mut ExeState) -> Result<i32, LuaError> {
+    let from = state.check_string(1)?.to_string();
+    let to = state.check_string(2)?.to_string();
+    let r = fs::rename(&from, &to);
+    return file_result(state, r, &from);
+}
+
+/** os.tmpname() : 和mkstemp一样会创建这个文件 */
+fn os_tmpname(state: &mut ExeState) -> Result<i32, LuaError> {
+    let Ok((path, _)) = create_temp() else {
+        return Err(state.error("unable to generate a unique filename"));
+    };
+    state.push(path.to_string_lossy().into_owned());
+    return Ok(1);
+}
+
+/** os.exit([code [, close]]) : true是成功 , false是失败 ; 退出前刷新标准输出 */
+fn os_exit(state: &mut ExeState) -> Result<i32, LuaError> {
+    let code = match state.get(1) {
+        Value::Boolean(b) => if b { 0 } else { 1 },
+        _ => state.opt_integer(1, 0)? as i32,
+    };
+    let _ = io::stdout().flush();
+    std::process::exit(code);
+}
@@ -0,0 +1,352 @@
+use crate::{ vm::ExeState, interface::{ Value, LuaError } };
+
+use super::string::start_pos;
+
+/* ### string.pack/string.unpack/string.packsize
+    按格式把数值编码成二进制字符串 , 和Lua 5.4的实现保持一致 :
+    < > = 设置字节序 , ![n] 设置最大对齐 , b B h H l L j J T i[n] I[n] 整数 ,
+    f d n 浮点数 , c[n] s[n] z 字符串 , x 填充字节 , X 按下一个选项对齐
+ */
+
+/** 整数最多占用的字节数 */
+const MAX_INT_SIZE: usize = 16;
+/** lua_Integer的字节数 */
+const SZINT: usize = 8;
+/** 默认的最大对齐 */
+const MAX_ALIGN: usize = 8;
+/** 选项中数字的上限 , 防止溢出 */
+const MAX_SIZE: usize = i32::MAX as usize;
+
+/** 一个选项的类型 */
+#[derive(PartialEq)]
+enum KOption {
+    Int /* 有符号整数 */,
+    Uint /* 无符号整数 */,
+    Float /* f : float */,
+    Number /* n : lua_Number */,
+    Double /* d : double */,
+    Char /* c : 固定长度的字符串 */,
+    String /* s : 带长度前缀的字符串 */,
+    Zstr /* z : 以0结尾的字符串 */,
+    Padding /* x : 一个填充字节 */,
+    PaddAlign /* X : 按照下一个选项对齐 */,
+    Nop /* 不对应数据的选项 , 比如设置字节序 */,
+}
+
+/** 解析格式的状态 */
+struct Header<'a> {
+    fmt: &'a [u8],
+    pos: usize,
+    little: bool,
+    max_align: usize,
+}
+
+impl<'a> Header<'a> {
+    fn new(fmt: &'a [u8]) -> Self {
+        return Header { fmt, pos: 0, little: cfg!(target_endian = "little"), max_align: 1 };
+    }
+
+    fn done(&self) -> bool {
+        return self.pos >= self.fmt.len();
+    }
+
+    /** 读取可选的数字 , 没有数字时返回默认值 */
+    fn get_num(&mut self, default: usize) -> usize {
+        if !self.fmt.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            return default;
+        }
+        let mut a = 0;
+        while let Some(c) = self.fmt.get(self.pos).filter(|c| c.is_ascii_digit()) {
+            if a > (MAX_SIZE - 9) / 10 {
+                break;
+            }
+            a = a * 10 + (c - b'0') as usize;
+            self.pos += 1;
+        }
+        return a;
+    }
+
+    /** 读取整数的字节数 , 必须在[1, MAX_INT_SIZE]之内 */
+    fn get_num_limit(&mut self, state: &ExeState, default: usize) -> Result<usize, LuaError> {
+        let size = self.get_num(default);
+        if size > MAX_INT_SIZE || size == 0 {
+            return Err(state.error(format!("integral size ({size}) out of limits [1,{MAX_INT_SIZE}]")));
+        }
+        return Ok(size);
+    }
+
+    /** 读取一个选项 , 返回选项类型和占用的字节数 */
+    fn get_option(&mut self, state: &ExeState) -> Result<(KOption, usize), LuaError> {
+        let opt = self.fmt[self.pos];
+        self.pos += 1;
+        return Ok(match opt {
+            b'b' => (KOption::Int, 1),
+            b'B' => (KOption::Uint, 1),
+            b'h' => (KOption::Int, 2),
+            b'H' => (KOption::Uint, 2),
+            b'l' | b'j' => (KOption::Int, 8),
+            b'L' | b'J' | b'T' => (KOption::Uint, 8),
+            b'f' => (KOption::Float, 4),
+            b'n' => (KOption::Number, 8),
+            b'd' => (KOption::Double, 8),
+            b'i' => (KOption::Int, self.get_num_limit(state, 4)?),
+            b'I' => (KOption::Uint, self.get_num_limit(state, 4)?),
+            b's' => (KOption::String, self.get_num_limit(state, 8)?),
+            b'c' => {
+                if !self.fmt.get(self.pos).is_some_and(u8::is_ascii_digit) {
+                    return Err(state.error("missing size for format option 'c'"));
+                }
+                (KOption::Char, self.get_num(0))
+            }
+            b'z' => (KOption::Zstr, 0),
+            b'x' => (KOption::Padding, 1),
+            b'X' => (KOption::PaddAlign, 0),
+            b' ' => (KOption::Nop, 0),
+            b'<' => {
+                self.little = true;
+                (KOption::Nop, 0)
+            }
+            b'>' => {
+                self.little = false;
+                (KOption::Nop, 0)
+            }
+            b'=' => {
+                self.little = cfg!(target_endian = "little");
+                (KOption::Nop, 0)
+            }
+            b'!' => {
+                self.max_align = self.get_num_limit(state, MAX_ALIGN)?;
+                (KOption::Nop, 0)
+            }
+            _ => {
+                return Err(state.error(format!("invalid format option '{}'", opt as char)));
+            }
+        });
+    }
+
+    /** 读取一个选项以及它需要的对齐字节数 , totalsize是当前已经占用的字节数 */
+    fn get_details(&mut self, state: &ExeState, totalsize: usize) -> Result<(KOption, usize, usize), LuaError> {
+        let (opt, size) = self.get_option(state)?;
+        let mut align = size;
+        if opt == KOption::PaddAlign {
+            /* X的对齐来自下一个选项 */
+            if self.done() {
+                return Err(state.arg_error(1, "invalid next option for option 'X'"));
+            }
+            let (next, next_size) = self.get_option(state)?;
+            align = next_size;
+            if next == KOption::Char || align == 0 {
+                return Err(state.arg_error(1, "invalid next option for option 'X'"));
+            }
+        }
+        if align <= 1 || opt == KOption::Char {
+            return Ok((opt, size, 0));
+        }
+        let align = align.min(self.max_align);
+        if !align.is_power_of_two() {
+            return Err(state.arg_error(1, "format asks for alignment not power of 2"));
+        }
+        let ntoalign = (align - (totalsize & (align - 1))) & (align - 1);
+        return Ok((opt, size, ntoalign));
+    }
+}
+
+/** 按字节序写入size字节的整数 , 超过8字节的部分用符号扩展 */
+fn pack_int(buf: &mut Vec<u8>, n: u64, little: bool, size: usize, neg: bool) {
+    let mut bytes: Vec<u8> = (0..size).map(|i| if i < SZINT { (n >> (i * 8)) as u8 } else if neg { 0xff } else { 0 }).collect();
+    if !little {
+        bytes.reverse();
+    }
+    buf.extend_from_slice(&bytes);
+}
+
+/** 按字节序读取size字节的整数 , 超过8字节时多出来的字节必须是符号扩展 */
+fn unpack_int(state: &ExeState, s: &[u8], little: bool, size: usize, signed: bool) -> Result<i64, LuaError> {
+    let byte = |i: usize| if little { s[i] } else { s[size - 1 - i] };
+    let limit = size.min(SZINT);
+    let mut res: u64 = 0;
+    for i in (0..limit).rev() {
+        res = (res << 8) | byte(i) as u64;
+    }
+    if size < SZINT {
+        if signed {
+            let mask = 1u64 << (size * 8 - 1);
+            res = (res ^ mask).wrapping_sub(mask);
+        }
+    } else if size > SZINT {
+        let mask = if !signed || (res as i64) >= 0 { 0 } else { 0xff };
+        if (limit..size).any(|i| byte(i) != mask) {
+            return Err(state.error(format!("{size}-byte integer does not fit into Lua Integer")));
+        }
+    }
+    return Ok(res as i64);
+}
+
+/** 按字节序写入浮点数的字节 */
+fn pack_bytes(buf: &mut Vec<u8>, mut bytes: Vec<u8>, little: bool) {
+    if little != cfg!(target_endian = "little") {
+        bytes.reverse();
+    }
+    buf.extend_from_slice(&bytes);
+}
+
+/** 按字节序读取N字节 , 转换成本机字节序 */
+fn unpack_bytes<const N: usize>(s: &[u8], little: bool) -> [u8; N] {
+    let mut bytes: [u8; N] = s[..N].try_into().unwrap();
+    if little != cfg!(target_endian = "little") {
+        bytes.reverse();
+    }
+    return bytes;
+}
+
+/** string.pack(fmt, v1, v2, ...) */
+pub fn lib_pack(state: &mut ExeState) -> Result<i32, LuaError> {
+    let fmt = state.check_string(1)?;
+    let mut h = Header::new(<&[u8]>::from(&fmt));
+    let mut buf = Vec::new();
+    let mut arg = 1;
+    while !h.done() {
+        let (opt, size, ntoalign) = h.get_details(state, buf.len())?;
+        buf.resize(buf.len() + ntoalign, 0);
+        arg += 1;
+        match opt {
+            KOption::Int => {
+                let n = state.check_integer(arg)?;
+                if size < SZINT {
+                    let lim = 1i64 << (size * 8 - 1);
+                    if !(-lim..lim).contains(&n) {
+                        return Err(state.arg_error(arg, "integer overflow"));
+                    }
+                }
+                pack_int(&mut buf, n as u64, h.little, size, n < 0);
+            }
+            KOption::Uint => {
+                let n = state.check_integer(arg)?;
+                if size < SZINT && (n as u64) >= (1u64 << (size * 8)) {
+                    return Err(state.arg_error(arg, "unsigned overflow"));
+                }
+                pack_int(&mut buf, n as u64, h.little, size, false);
+            }
+            KOption::Float => {
+                let x = state.check_number(arg)? as f32;
+                pack_bytes(&mut buf, x.to_ne_bytes().to_vec(), h.little);
+            }
+            KOption::Number | KOption::Double => {
+                let x = state.check_number(arg)?;
+                pack_bytes(&mut buf, x.to_ne_bytes().to_vec(), h.little);
+            }
+            KOption::Char => {
+                let s = state.check_string(arg)?;
+                let s = <&[u8]>::from(&s);
+                if s.len() > size {
+                    return Err(state.arg_error(arg, "string longer than given size"));
+                }
+                buf.extend_from_slice(s);
+                buf.resize(buf.len() + size - s.len(), 0);
+            }
+            KOption::String => {
+                let s = state.check_string(arg)?;
+                let s = <&[u8]>::from(&s);
+                if size < SZINT && (s.len() as u64) >= (1u64 << (size * 8)) {
+                    return Err(state.arg_error(arg, "string length does not fit in given size"));
+                }
+                pack_int(&mut buf, s.len() as u64, h.little, size, false);
+                buf.extend_from_slice(s);
+            }
+            KOption::Zstr => {
+                let s = state.check_string(arg)?;
+                let s = <&[u8]>::from(&s);
+                if s.contains(&0) {
+                    return Err(state.arg_error(arg, "string contains zeros"));
+                }
+                buf.extend_from_slice(s);
+                buf.push(0);
+            }
+            KOption::Padding | KOption::PaddAlign | KOption::Nop => {
+                if opt == KOption::Padding {
+                    buf.push(0);
+                }
+                arg -= 1;
+            }
+        }
+    }
+    state.push(buf);
+    return Ok(1);
+}
+
+/** string.packsize(fmt) : 格式中不能有变长的选项 */
+pub fn lib_packsize(state: &mut ExeState) -> Result<i32, LuaError> {
+    let fmt = state.check_string(1)?;
+    let mut h = Header::new(<&[u8]>::from(&fmt));
+    let mut totalsize: usize = 0;
+    while !h.done() {
+        let (opt, size, ntoalign) = h.get_details(state, totalsize)?;
+        if opt == KOption::String || opt == KOption::Zstr {
+            return Err(state.arg_error(1, "variable-length format"));
+        }
+        let size = size + ntoalign;
+        if totalsize > MAX_SIZE - size {
+            return Err(state.arg_error(1, "format result too large"));
+        }
+        totalsize += size;
+    }
+    state.push(totalsize as i64);
+    return Ok(1);
+}
+
+/** string.unpack(fmt, s [, pos]) : 返回解码的值以及下一个未读字节的位置 */
+pub fn lib_unpack(state: &mut ExeState) -> Result<i32, LuaError> {
+    let fmt = state.check_string(1)?;
+    let mut h = Header::new(<&[u8]>::from(&fmt));
+    let data = state.check_string(2)?;
+    let data = <&[u8]>::from(&data);
+    let ld = data.len();
+    let mut pos = start_pos(state.opt_integer(3, 1)?, ld) - 1;
+    if pos > ld {
+        return Err(state.arg_error(3, "initial position out of string"));
+    }
+    let mut n = 0;
+    while !h.done() {
+        let (opt, size, ntoalign) = h.get_details(state, pos)?;
+        if ntoalign + size > ld - pos {
+            return Err(state.arg_error(2, "data string too short"));
+        }
+        pos += ntoalign;
+        let s = &data[pos..];
+        let v = match opt {
+            KOption::Int | KOption::Uint =>
+                Value::Integer(unpack_int(state, s, h.little, size, opt == KOption::Int)?),
+            KOption::Float => Value::Float(f32::from_ne_bytes(unpack_bytes(s, h.little)) as f64),
+            KOption::Number | KOption::Double => Value::Float(f64::from_ne_bytes(unpack_bytes(s, h.little))),
+            KOption::Char => Value::from(&s[..size]),
+            KOption::String => {
+                let len = unpack_int(state, s, h.little, size, false)? as u64;
+                if len > (ld - pos - size) as u64 {
+                    return Err(state.arg_error(2, "data string too short"));
+                }
+                let len = len as usize;
+                pos += len;
+                Value::from(&s[size..size + len])
+            }
+            KOption::Zstr => {
+                let len = match s.iter().position(|&c| c == 0) {
+                    Some(len) => len,
+                    None => {
+                        return Err(state.arg_error(2, "unfinished string for format 'z'"));
+                    }
+                };
+                pos += len + 1;
+                Value::from(&s[..len])
+            }
+            KOption::Padding | KOption::PaddAlign | KOption::Nop => {
+                pos += size;
+                continue;
+            }
+        };
+        state.push(v);
+        n += 1;
+        pos += size;
+    }
+    state.push((pos + 1) as i64);
+    return Ok(n + 1);
+}
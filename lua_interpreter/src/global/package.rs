@@ -0,0 +1,230 @@
+use std::{ rc::Rc, cell::RefCell, env, fs::File };
+
+use crate::{ vm::ExeState, interface::{ Value, LuaError, table::Table } };
+
+use super::load_file;
+
+/* ### package库
+    require按package.searchers中的顺序查找模块的加载函数 : 默认是package.preload , 然后是package.path中的Lua文件 ;
+    没有C模块 , 宿主可以用add_searcher加入自己的查找函数 , 比如从内嵌的资源中加载模块
+ */
+
+/** 默认的package.path , 环境变量LUA_PATH_5_4或者LUA_PATH中的";;"也替换成它 */
+const LUA_PATH_DEFAULT: &str = "/usr/local/share/lua/5.4/?.lua;/usr/local/share/lua/5.4/?/init.lua;\
+    /usr/local/lib/lua/5.4/?.lua;/usr/local/lib/lua/5.4/?/init.lua;./?.lua;./?/init.lua";
+
+/** 目录分隔符 , 路径分隔符 , 模板中替换成模块名的标记 , 可执行文件所在目录的标记 , 忽略的标记 */
+const LUA_CONFIG: &str = "/\n;\n?\n!\n-\n";
+
+/** 标准库 , 打开package库时已经注册到全局变量 */
+const STD_LIBS: [&str; 10] = ["_G", "coroutine", "debug", "io", "math", "os", "package", "string", "table", "utf8"];
+
+/** package库 : 注册到全局变量package , 以及全局的require */
+pub fn open_package(state: &mut ExeState) {
+    let package = Rc::new(RefCell::new(Table::new(0, 8)));
+    let loaded = Rc::new(RefCell::new(Table::new(0, STD_LIBS.len())));
+    let preload = Rc::new(RefCell::new(Table::new(0, 0)));
+    let mut searchers = Table::new(2, 0);
+    let p = package.clone();
+    searchers.set_int(1, Value::closure(move |state| searcher_preload(state, &p)));
+    let p = package.clone();
+    searchers.set_int(2, Value::closure(move |state| searcher_lua(state, &p)));
+    {
+        let mut lib = package.borrow_mut();
+        lib.set(Value::from("path"), Value::from(lua_path()));
+        lib.set(Value::from("config"), Value::from(LUA_CONFIG));
+        lib.set(Value::from("loaded"), Value::Table(loaded.clone()));
+        lib.set(Value::from("preload"), Value::Table(preload));
+        lib.set(Value::from("searchers"), Value::Table(Rc::new(RefCell::new(searchers))));
+        lib.set(Value::from("searchpath"), Value::RustFunction(lib_searchpath));
+    }
+    state.set_global("package", Value::Table(package.clone()));
+    for name in STD_LIBS {
+        let lib = state.get_global(name);
+        loaded.borrow_mut().set(Value::from(name), lib);
+    }
+    state.set_global("require", Value::closure(move |state| lib_require(state, &package, &loaded)));
+}
+
+/** 宿主扩展require : 在package.searchers的最后加入查找函数 ;
+    它用模块名调用 , 找到时返回加载函数和传给加载函数的第二个参数 , 找不到时返回说明原因的字符串
+ */
+pub fn add_searcher(state: &mut ExeState, searcher: Value) {
+    if let Value::Table(package) = state.get_global("package") {
+        if let Value::Table(searchers) = package.borrow().get(&Value::from("searchers")) {
+            let mut searchers = searchers.borrow_mut();
+            let n = searchers.len();
+            searchers.set_int(n + 1, searcher);
+        }
+    }
+}
+
+/** 环境变量中的路径 , 其中的";;"替换成默认路径 */
+fn lua_path() -> String {
+    let Some(path) = env::var("LUA_PATH_5_4").or_else(|_| env::var("LUA_PATH")).ok() else {
+        return LUA_PATH_DEFAULT.to_string();
+    };
+    return match path.find(";;") {
+        Some(i) => {
+            let (prefix, suffix) = (&path[..i], &path[i + 2..]);
+            let mut p = String::from(prefix);
+            if !prefix.is_empty() {
+                p.push(';');
+            }
+            p.push_str(LUA_PATH_DEFAULT);
+            if !suffix.is_empty() {
+                p.push(';');
+                p.push_str(suffix);
+            }
+            p
+        }
+        None => path,
+    };
+}
+
+/** 文件存在并且可以读取 */
+fn readable(filename: &str) -> bool {
+    return File::open(filename).and_then(|f| f.metadata()).is_ok_and(|m| !m.is_dir());
+}
+
+/** 在path中查找name : 把name中的sep替换成目录分隔符 , 再依次替换path中每一项的'?' ;
+    找不到时返回尝试过的每个文件组成的错误信息
+ */
+fn search_path(name: &str, path: &str, sep: &str, rep: &str) -> Result<String, String> {
+    let name = if sep.is_empty() { name.to_string() } else { name.replace(sep, rep) };
+    let mut tried = Vec::new();
+    for template in path.split(';').filter(|t| !t.is_empty()) {
+        let filename = template.replace('?', &name);
+        if readable(&filename) {
+            return Ok(filename);
+        }
+        tried.push(format!("no file '{filename}'"));
+    }
+    return Err(tried.join("\n\t"));
+}
+
+/** searchpath(name, path [, sep [, rep]]) : 找到的文件名 , 找不到时返回nil和尝试过的所有文件 */
+fn lib_searchpath(state: &mut ExeState) -> Result<i32, LuaError> {
+    let name = String::from(&state.check_string(1)?);
+    let path = String::from(&state.check_string(2)?);
+    let sep = match state.get(3) {
+        Value::Nil => String::from("."),
+        _ => String::from(&state.check_string(3)?),
+    };
+    let rep = match state.get(4) {
+        Value::Nil => String::from("/"),
+        _ => String::from(&state.check_string(4)?),
+    };
+    match search_path(&name, &path, &sep, &rep) {
+        Ok(filename) => {
+            state.push(filename);
+            return Ok(1);
+        }
+        Err(msg) => {
+            state.push(Value::Nil);
+            state.push(msg);
+            return Ok(2);
+        }
+    }
+}
+
+/** 查找package.preload[name] */
+fn searcher_preload(state: &mut ExeState, package: &Rc<RefCell<Table>>) -> Result<i32, LuaError> {
+    let name = state.check_string(1)?;
+    let loader = match package.borrow().get(&Value::from("preload")) {
+        Value::Table(preload) => preload.borrow().get(&name),
+        _ => Value::Nil,
+    };
+    if let Value::Nil = loader {
+        state.push(format!("no field package.preload['{}']", String::from(&name)));
+        return Ok(1);
+    }
+    state.push(loader);
+    state.push(":preload:");
+    return Ok(2);
+}
+
+/** 在package.path中查找Lua文件 , 加载函数就是文件的主代码块 , 第二个参数是文件名 */
+fn searcher_lua(state: &mut ExeState, package: &Rc<RefCell<Table>>) -> Result<i32, LuaError> {
+    let name = String::from(&state.check_string(1)?);
+    let path = package.borrow().get(&Value::from("path"));
+    if !path.is_str() {
+        return Err(state.error("'package.path' must be a string"));
+    }
+    let filename = match search_path(&name, &String::from(&path), ".", "/") {
+        Ok(filename) => filename,
+        Err(msg) => {
+            state.push(msg);
+            return Ok(1);
+        }
+    };
+    match load_file(state, &filename) {
+        Ok(f) => {
+            state.push(f);
+            state.push(filename);
+            return Ok(2);
+        }
+        Err(msg) => {
+            let msg = format!("error loading module '{name}' from file '{filename}':\n\t{msg}");
+            return Err(state.error(msg));
+        }
+    }
+}
+
+/** require(modname) : 已经加载过时直接返回package.loaded中的值 ;
+    否则依次调用package.searchers找到加载函数 , 用模块名和查找函数给出的数据调用它 ,
+    返回值(为nil时是true)存入package.loaded , 返回它和加载数据
+ */
+fn lib_require(state: &mut ExeState, package: &Rc<RefCell<Table>>, loaded: &Rc<RefCell<Table>>) -> Result<i32, LuaError> {
+    let name = state.check_string(1)?;
+    let module = loaded.borrow().get(&name);
+    if module.truthy() {
+        state.push(module);
+        return Ok(1);
+    }
+
+    /* 依次调用查找函数 */
+    let Value::Table(searchers) = package.borrow().get(&Value::from("searchers")) else {
+        return Err(state.error("'package.searchers' must be a table"));
+    };
+    let mut msg = String::new();
+    let mut i = 1;
+    let (loader, data) = loop {
+        let searcher = searchers.borrow().get_int(i);
+        if let Value::Nil = searcher {
+            return Err(state.error(format!("module '{}' not found:{}", String::from(&name), msg)));
+        }
+        let func = state.stack.len();
+        state.push(searcher);
+        state.push(name.clone());
+        state.call_value(func, 1, Some(2))?;
+        let data = state.stack.pop().unwrap();
+        let loader = state.stack.pop().unwrap();
+        if loader.is_function() {
+            break (loader, data);
+        } else if loader.is_str() || matches!(loader, Value::Integer(_) | Value::Float(_)) {
+            msg.push_str("\n\t");
+            msg.push_str(&loader.to_string());
+        }
+        i += 1;
+    };
+
+    /* 调用加载函数 : 参数是模块名和加载数据 */
+    let func = state.stack.len();
+    state.push(loader);
+    state.push(name.clone());
+    state.push(data.clone());
+    state.call_value(func, 2, Some(1))?;
+    let module = state.stack.pop().unwrap();
+    if !matches!(module, Value::Nil) {
+        loaded.borrow_mut().set(name.clone(), module);
+    }
+    let mut module = loaded.borrow().get(&name);
+    if let Value::Nil = module {
+        module = Value::Boolean(true);
+        loaded.borrow_mut().set(name, module.clone());
+    }
+    state.push(module);
+    state.push(data);
+    return Ok(2);
+}
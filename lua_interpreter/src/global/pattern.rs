@@ -0,0 +1,381 @@
+use crate::interface::Value;
+
+/* ### Lua模式匹配
+    Lua没有使用正则表达式,而是自己定义了一套更轻量的模式(pattern) ,
+    这里参照官方实现(lstrlib.c)的回溯算法 : 每个模式项尝试匹配,失败时回退到上一个可变长度的项
+ */
+
+/** 捕获的最大个数 */
+const MAX_CAPTURES: usize = 32;
+/** 递归匹配的最大深度 , 防止复杂的模式耗尽Rust的栈 */
+const MAX_MATCH_DEPTH: usize = 200;
+/** 捕获还没有遇到右括号 */
+const CAP_UNFINISHED: isize = -1;
+/** 位置捕获 () */
+const CAP_POSITION: isize = -2;
+const L_ESC: u8 = b'%';
+/** 出现这些字符时模式不能按普通字符串查找 */
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+/** 模式中没有特殊字符 */
+pub fn no_specials(pat: &[u8]) -> bool {
+    return !pat.iter().any(|c| SPECIALS.contains(c));
+}
+
+/** 匹配状态 : 源字符串、模式和已经匹配的捕获 */
+pub struct MatchState<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    depth: usize /* 剩余的递归深度 */,
+    level: usize /* 捕获的个数 */,
+    capture: [(usize, isize); MAX_CAPTURES] /* (起始位置, 长度) */,
+}
+
+impl<'a> MatchState<'a> {
+    pub fn new(src: &'a [u8], pat: &'a [u8]) -> Self {
+        return MatchState {
+            src,
+            pat,
+            depth: MAX_MATCH_DEPTH,
+            level: 0,
+            capture: [(0, 0); MAX_CAPTURES],
+        };
+    }
+
+    /** 从源字符串的s位置开始,用模式的p位置开始的部分匹配 , 返回匹配的结束位置 */
+    pub fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        self.level = 0;
+        self.depth = MAX_MATCH_DEPTH;
+        return self.match_at(s, p);
+    }
+
+    fn match_at(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, String> {
+        if self.depth == 0 {
+            return Err(String::from("pattern too complex"));
+        }
+        self.depth -= 1;
+        let (src, pat) = (self.src, self.pat);
+        let r = loop {
+            if p == pat.len() {
+                break Some(s);
+            }
+            match pat[p] {
+                b'(' => {
+                    break if pat.get(p + 1) == Some(&b')') {
+                        self.start_capture(s, p + 2, CAP_POSITION)?
+                    } else {
+                        self.start_capture(s, p + 1, CAP_UNFINISHED)?
+                    };
+                }
+                b')' => {
+                    break self.end_capture(s, p + 1)?;
+                }
+                b'$' if p + 1 == pat.len() => {
+                    break if s == src.len() { Some(s) } else { None };
+                }
+                L_ESC if pat.get(p + 1) == Some(&b'b') => {
+                    match self.match_balance(s, p + 2)? {
+                        Some(e) => {
+                            s = e;
+                            p += 4;
+                            continue;
+                        }
+                        None => {
+                            break None;
+                        }
+                    }
+                }
+                L_ESC if pat.get(p + 1) == Some(&b'f') => {
+                    /* 边界 : 前一个字符不在集合中而当前字符在集合中 */
+                    p += 2;
+                    if pat.get(p) != Some(&b'[') {
+                        return Err(String::from("missing '[' after '%f' in pattern"));
+                    }
+                    let ep = self.class_end(p)?;
+                    let prev = if s == 0 { 0 } else { src[s - 1] };
+                    let cur = src.get(s).copied().unwrap_or(0);
+                    if !self.match_bracket_class(prev, p, ep - 1) && self.match_bracket_class(cur, p, ep - 1) {
+                        p = ep;
+                        continue;
+                    }
+                    break None;
+                }
+                L_ESC if pat.get(p + 1).is_some_and(|c| c.is_ascii_digit()) => {
+                    /* 反向引用 %1-%9 */
+                    match self.match_capture(s, pat[p + 1])? {
+                        Some(e) => {
+                            s = e;
+                            p += 2;
+                            continue;
+                        }
+                        None => {
+                            break None;
+                        }
+                    }
+                }
+                _ => {
+                    let ep = self.class_end(p)?;
+                    let suffix = pat.get(ep).copied();
+                    if !self.single_match(s, p, ep) {
+                        /* 允许0次匹配的后缀 : 跳过这一项 */
+                        if let Some(b'*' | b'?' | b'-') = suffix {
+                            p = ep + 1;
+                            continue;
+                        }
+                        break None;
+                    }
+                    match suffix {
+                        Some(b'?') => {
+                            if let Some(e) = self.match_at(s + 1, ep + 1)? {
+                                break Some(e);
+                            }
+                            p = ep + 1;
+                            continue;
+                        }
+                        Some(b'+') => {
+                            break self.max_expand(s + 1, p, ep)?;
+                        }
+                        Some(b'*') => {
+                            break self.max_expand(s, p, ep)?;
+                        }
+                        Some(b'-') => {
+                            break self.min_expand(s, p, ep)?;
+                        }
+                        _ => {
+                            s += 1;
+                            p = ep;
+                            continue;
+                        }
+                    }
+                }
+            }
+        };
+        self.depth += 1;
+        return Ok(r);
+    }
+
+    /** 一个模式项(单个字符、%类或者[集合])的结束位置 */
+    fn class_end(&self, p: usize) -> Result<usize, String> {
+        let pat = self.pat;
+        let mut p = p;
+        let c = pat[p];
+        p += 1;
+        if c == L_ESC {
+            if p >= pat.len() {
+                return Err(String::from("malformed pattern (ends with '%')"));
+            }
+            return Ok(p + 1);
+        }
+        if c == b'[' {
+            if pat.get(p) == Some(&b'^') {
+                p += 1;
+            }
+            /* 集合的第一个字符可以是']' */
+            loop {
+                if p >= pat.len() {
+                    return Err(String::from("malformed pattern (missing ']')"));
+                }
+                let c = pat[p];
+                p += 1;
+                if c == L_ESC && p < pat.len() {
+                    p += 1; /* 跳过转义 , 比如%] */
+                }
+                if pat.get(p) == Some(&b']') {
+                    break;
+                }
+            }
+            return Ok(p + 1);
+        }
+        return Ok(p);
+    }
+
+    /** 源字符串s位置的字符能否匹配模式项 p..ep */
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        let Some(&c) = self.src.get(s) else {
+            return false;
+        };
+        return match self.pat[p] {
+            b'.' => true,
+            L_ESC => match_class(c, self.pat[p + 1]),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        };
+    }
+
+    /** 字符集合 [...] , p指向'[' , ec指向']' */
+    fn match_bracket_class(&self, c: u8, p: usize, ec: usize) -> bool {
+        let pat = self.pat;
+        let mut p = p + 1;
+        let mut sig = true;
+        if pat[p] == b'^' {
+            sig = false;
+            p += 1;
+        }
+        while p < ec {
+            if pat[p] == L_ESC {
+                p += 1;
+                if match_class(c, pat[p]) {
+                    return sig;
+                }
+            } else if pat[p + 1] == b'-' && p + 2 < ec {
+                if pat[p] <= c && c <= pat[p + 2] {
+                    return sig;
+                }
+                p += 2;
+            } else if pat[p] == c {
+                return sig;
+            }
+            p += 1;
+        }
+        return !sig;
+    }
+
+    /** 贪婪匹配 : 先尽量多地匹配,再逐个回退 */
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, String> {
+        let mut i = 0;
+        while self.single_match(s + i, p, ep) {
+            i += 1;
+        }
+        loop {
+            if let Some(e) = self.match_at(s + i, ep + 1)? {
+                return Ok(Some(e));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    /** 非贪婪匹配 : 尽量少地匹配 */
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> Result<Option<usize>, String> {
+        loop {
+            if let Some(e) = self.match_at(s, ep + 1)? {
+                return Ok(Some(e));
+            }
+            if !self.single_match(s, p, ep) {
+                return Ok(None);
+            }
+            s += 1;
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, what: isize) -> Result<Option<usize>, String> {
+        if self.level >= MAX_CAPTURES {
+            return Err(String::from("too many captures"));
+        }
+        self.capture[self.level] = (s, what);
+        self.level += 1;
+        let r = self.match_at(s, p)?;
+        if r.is_none() {
+            self.level -= 1;
+        }
+        return Ok(r);
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let l = self.capture_to_close()?;
+        self.capture[l].1 = (s - self.capture[l].0) as isize;
+        let r = self.match_at(s, p)?;
+        if r.is_none() {
+            self.capture[l].1 = CAP_UNFINISHED;
+        }
+        return Ok(r);
+    }
+
+    /** 最近一个还没有结束的捕获 */
+    fn capture_to_close(&self) -> Result<usize, String> {
+        return (0..self.level)
+            .rev()
+            .find(|&l| self.capture[l].1 == CAP_UNFINISHED)
+            .ok_or_else(|| String::from("invalid pattern capture"));
+    }
+
+    /** %bxy : 从x开始到配对的y结束 */
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        if p + 1 >= self.pat.len() {
+            return Err(String::from("malformed pattern (missing arguments to '%b')"));
+        }
+        let (b, e) = (self.pat[p], self.pat[p + 1]);
+        if self.src.get(s) != Some(&b) {
+            return Ok(None);
+        }
+        let mut cont = 1;
+        for (i, &c) in self.src.iter().enumerate().skip(s + 1) {
+            if c == e {
+                cont -= 1;
+                if cont == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == b {
+                cont += 1;
+            }
+        }
+        return Ok(None);
+    }
+
+    /** %1-%9 : 和之前的捕获相同的内容 */
+    fn match_capture(&self, s: usize, l: u8) -> Result<Option<usize>, String> {
+        let l = self.check_capture(l)?;
+        let (start, len) = (self.capture[l].0, self.capture[l].1 as usize);
+        if self.src.len() - s >= len && self.src[start..start + len] == self.src[s..s + len] {
+            return Ok(Some(s + len));
+        }
+        return Ok(None);
+    }
+
+    fn check_capture(&self, l: u8) -> Result<usize, String> {
+        let l = (l as usize).wrapping_sub(b'1' as usize);
+        if l >= self.level || self.capture[l].1 == CAP_UNFINISHED {
+            return Err(format!("invalid capture index %{}", l.wrapping_add(1)));
+        }
+        return Ok(l);
+    }
+
+    /** 第i个捕获的值 , 没有捕获时第0个是整个匹配 s..e */
+    pub fn get_capture(&self, i: usize, s: usize, e: usize) -> Result<Value, String> {
+        if i >= self.level {
+            if i != 0 {
+                return Err(format!("invalid capture index %{}", i + 1));
+            }
+            return Ok(self.src[s..e].into());
+        }
+        let (start, len) = self.capture[i];
+        return match len {
+            CAP_UNFINISHED => Err(String::from("unfinished capture")),
+            CAP_POSITION => Ok(Value::Integer((start as i64) + 1)),
+            len => Ok(self.src[start..start + len as usize].into()),
+        };
+    }
+
+    /** 全部捕获的值 , whole为真时没有捕获就返回整个匹配 */
+    pub fn get_captures(&self, s: usize, e: usize, whole: bool) -> Result<Vec<Value>, String> {
+        let n = if self.level == 0 && whole { 1 } else { self.level };
+        return (0..n).map(|i| self.get_capture(i, s, e)).collect();
+    }
+
+    /** 整个匹配的内容 */
+    pub fn whole(&self, s: usize, e: usize) -> &'a [u8] {
+        return &self.src[s..e];
+    }
+}
+
+/** %类 : 大写字母表示取反 , 其他字符表示它自己 */
+fn match_class(c: u8, cl: u8) -> bool {
+    let r = match cl.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => matches!(c, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => {
+            return cl == c;
+        }
+    };
+    return if cl.is_ascii_uppercase() { !r } else { r };
+}
@@ -0,0 +1,396 @@
+use std::{ rc::Rc, cell::RefCell };
+
+use crate::{ vm::ExeState, interface::{ Value, LuaError, RustFunction, table::Table } };
+
+use super::{ pattern::{ self, MatchState }, pack, new_lib };
+
+/** 字符串的最大长度 , 防止string.rep之类的操作耗尽内存 */
+const MAX_STRING_SIZE: usize = i32::MAX as usize;
+
+/** string库 : 注册到全局变量string , 并且作为所有字符串共享的元表的__index */
+pub fn open_string(state: &mut ExeState) {
+    let funcs: [(&str, RustFunction); 16] = [
+        ("len", lib_len),
+        ("sub", lib_sub),
+        ("upper", lib_upper),
+        ("lower", lib_lower),
+        ("rep", lib_rep),
+        ("reverse", lib_reverse),
+        ("byte", lib_byte),
+        ("char", lib_char),
+        ("format", lib_format),
+        ("find", lib_find),
+        ("match", lib_match),
+        ("gmatch", lib_gmatch),
+        ("gsub", lib_gsub),
+        ("pack", pack::lib_pack),
+        ("unpack", pack::lib_unpack),
+        ("packsize", pack::lib_packsize),
+    ];
+    let lib = new_lib(&funcs);
+    let mut meta = Table::new(0, 1);
+    meta.set(Value::from("__index"), lib.clone());
+    state.string_meta = Some(Rc::new(RefCell::new(meta)));
+    state.set_global("string", lib);
+}
+
+/** 字符串的起始位置 : 负数从末尾开始计数 , 结果从1开始 */
+pub fn start_pos(pos: i64, len: usize) -> usize {
+    let len = len as i64;
+    return if pos > 0 {
+        pos as usize
+    } else if pos == 0 || pos < -len {
+        1
+    } else {
+        (len + pos + 1) as usize
+    };
+}
+
+/** 字符串的结束位置 : 负数从末尾开始计数 , 超出长度时截断 */
+fn end_pos(pos: i64, len: usize) -> usize {
+    let len = len as i64;
+    return if pos > len {
+        len as usize
+    } else if pos >= 0 {
+        pos as usize
+    } else if pos < -len {
+        0
+    } else {
+        (len + pos + 1) as usize
+    };
+}
+
+/** string.len(s) */
+fn lib_len(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    state.push(<&[u8]>::from(&s).len() as i64);
+    return Ok(1);
+}
+
+/** string.sub(s, i [, j]) */
+fn lib_sub(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    let s = <&[u8]>::from(&s);
+    let start = start_pos(state.check_integer(2)?, s.len());
+    let end = end_pos(state.opt_integer(3, -1)?, s.len());
+    if start <= end {
+        state.push(&s[start - 1..end]);
+    } else {
+        state.push("");
+    }
+    return Ok(1);
+}
+
+/** string.upper(s) : 只转换ASCII字母 */
+fn lib_upper(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    state.push(<&[u8]>::from(&s).to_ascii_uppercase());
+    return Ok(1);
+}
+
+/** string.lower(s) : 只转换ASCII字母 */
+fn lib_lower(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    state.push(<&[u8]>::from(&s).to_ascii_lowercase());
+    return Ok(1);
+}
+
+/** string.rep(s, n [, sep]) */
+fn lib_rep(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    let n = state.check_integer(2)?;
+    let sep = if matches!(state.get(3), Value::Nil) { Value::from("") } else { state.check_string(3)? };
+    let (s, sep) = (<&[u8]>::from(&s), <&[u8]>::from(&sep));
+    if n <= 0 {
+        state.push("");
+        return Ok(1);
+    }
+    let total = (s.len() + sep.len())
+        .checked_mul(n as usize)
+        .filter(|total| *total <= MAX_STRING_SIZE);
+    let Some(total) = total else {
+        return Err(state.error("resulting string too large"));
+    };
+    let mut buf = Vec::with_capacity(total);
+    for i in 0..n {
+        if i > 0 {
+            buf.extend_from_slice(sep);
+        }
+        buf.extend_from_slice(s);
+    }
+    state.push(buf);
+    return Ok(1);
+}
+
+/** string.reverse(s) */
+fn lib_reverse(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    let mut buf = <&[u8]>::from(&s).to_vec();
+    buf.reverse();
+    state.push(buf);
+    return Ok(1);
+}
+
+/** string.byte(s [, i [, j]]) : s[i..j]每个字节的数值 */
+fn lib_byte(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    let s = <&[u8]>::from(&s);
+    let i = state.opt_integer(2, 1)?;
+    let start = start_pos(i, s.len());
+    let end = end_pos(state.opt_integer(3, start as i64)?, s.len());
+    if start > end {
+        return Ok(0);
+    }
+    if end - start >= (i32::MAX as usize) {
+        return Err(state.error("string slice too long"));
+    }
+    for c in &s[start - 1..end] {
+        state.push(*c as i64);
+    }
+    return Ok((end - start + 1) as i32);
+}
+
+/** string.char(...) : 每个参数是一个字节 */
+fn lib_char(state: &mut ExeState) -> Result<i32, LuaError> {
+    let mut buf = Vec::with_capacity(state.get_top());
+    for i in 1..=state.get_top() {
+        let c = state.check_integer(i)?;
+        if !(0..=255).contains(&c) {
+            return Err(state.arg_error(i, "value out of range"));
+        }
+        buf.push(c as u8);
+    }
+    state.push(buf);
+    return Ok(1);
+}
+
+/** string.format(fmt, ...) */
+fn lib_format(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = super::format::format(state)?;
+    state.push(s);
+    return Ok(1);
+}
+
+/** string.find(s, pattern [, init [, plain]]) */
+fn lib_find(state: &mut ExeState) -> Result<i32, LuaError> {
+    return str_find_aux(state, true);
+}
+
+/** string.match(s, pattern [, init]) */
+fn lib_match(state: &mut ExeState) -> Result<i32, LuaError> {
+    return str_find_aux(state, false);
+}
+
+/** find和match : find返回匹配的起止位置和捕获 , match只返回捕获 */
+fn str_find_aux(state: &mut ExeState, find: bool) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    let p = state.check_string(2)?;
+    let (src, pat) = (<&[u8]>::from(&s), <&[u8]>::from(&p));
+    let init = start_pos(state.opt_integer(3, 1)?, src.len()) - 1;
+    if init > src.len() {
+        state.push(Value::Nil);
+        return Ok(1);
+    }
+    /* 普通字符串查找 */
+    if find && (state.get(4).truthy() || pattern::no_specials(pat)) {
+        let found = if pat.is_empty() {
+            Some(init)
+        } else {
+            src[init..].windows(pat.len()).position(|w| w == pat).map(|i| init + i)
+        };
+        return match found {
+            Some(i) => {
+                state.push((i as i64) + 1);
+                state.push((i + pat.len()) as i64);
+                Ok(2)
+            }
+            None => {
+                state.push(Value::Nil);
+                Ok(1)
+            }
+        };
+    }
+    let (anchor, pat) = match pat.first() {
+        Some(b'^') => (true, &pat[1..]),
+        _ => (false, pat),
+    };
+    let mut ms = MatchState::new(src, pat);
+    let mut s1 = init;
+    loop {
+        let e = ms.do_match(s1, 0).map_err(|msg| state.error(msg))?;
+        if let Some(e) = e {
+            let mut n = 0;
+            if find {
+                state.push((s1 as i64) + 1);
+                state.push(e as i64);
+                n = 2;
+            }
+            let captures = ms.get_captures(s1, e, !find).map_err(|msg| state.error(msg))?;
+            n += captures.len();
+            for c in captures {
+                state.push(c);
+            }
+            return Ok(n as i32);
+        }
+        s1 += 1;
+        if anchor || s1 > src.len() {
+            break;
+        }
+    }
+    state.push(Value::Nil);
+    return Ok(1);
+}
+
+/** string.gmatch(s, pattern [, init]) : 返回迭代函数 , 每次调用返回下一个匹配的捕获 */
+fn lib_gmatch(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    let p = state.check_string(2)?;
+    let len = <&[u8]>::from(&s).len();
+    let init = start_pos(state.opt_integer(3, 1)?, len) - 1;
+    /* 迭代的状态保存在Rust闭包中 : 下次开始查找的位置和上次匹配的结束位置 */
+    let mut pos = init.min(len + 1);
+    let mut last_match = None;
+    state.push(
+        Value::closure(move |state| {
+            let (src, pat) = (<&[u8]>::from(&s), <&[u8]>::from(&p));
+            let mut ms = MatchState::new(src, pat);
+            while pos <= src.len() {
+                let start = pos;
+                pos += 1;
+                let e = ms.do_match(start, 0).map_err(|msg| state.error(msg))?;
+                if let Some(e) = e {
+                    /* 不能在上次匹配的结束位置再次匹配空串 */
+                    if Some(e) != last_match {
+                        pos = e;
+                        last_match = Some(e);
+                        let captures = ms.get_captures(start, e, true).map_err(|msg| state.error(msg))?;
+                        let n = captures.len();
+                        for c in captures {
+                            state.push(c);
+                        }
+                        return Ok(n as i32);
+                    }
+                }
+            }
+            return Ok(0);
+        })
+    );
+    return Ok(1);
+}
+
+/** string.gsub(s, pattern, repl [, n]) : 返回替换后的字符串和替换的次数 */
+fn lib_gsub(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    let p = state.check_string(2)?;
+    let (src, pat) = (<&[u8]>::from(&s), <&[u8]>::from(&p));
+    let repl = state.get(3);
+    match repl {
+        Value::Integer(_) | Value::Float(_) | Value::Table(_) => (),
+        ref r if r.is_str() || r.is_function() => (),
+        _ => {
+            return Err(state.type_error(3, "string/function/table"));
+        }
+    }
+    let max_n = state.opt_integer(4, (src.len() as i64) + 1)?;
+    let (anchor, pat) = match pat.first() {
+        Some(b'^') => (true, &pat[1..]),
+        _ => (false, pat),
+    };
+
+    let mut ms = MatchState::new(src, pat);
+    let mut buf = Vec::with_capacity(src.len());
+    let (mut s1, mut n, mut last_match) = (0, 0, None);
+    while n < max_n {
+        let e = ms.do_match(s1, 0).map_err(|msg| state.error(msg))?;
+        match e {
+            Some(e) if Some(e) != last_match => {
+                n += 1;
+                add_value(state, &ms, &mut buf, s1, e, &repl)?;
+                s1 = e;
+                last_match = Some(e);
+            }
+            _ if s1 < src.len() => {
+                buf.push(src[s1]);
+                s1 += 1;
+            }
+            _ => {
+                break;
+            }
+        }
+        if anchor {
+            break;
+        }
+    }
+    buf.extend_from_slice(&src[s1.min(src.len())..]);
+    state.push(buf);
+    state.push(n);
+    return Ok(2);
+}
+
+/** gsub中一次匹配 s..e 的替换 */
+fn add_value(
+    state: &mut ExeState,
+    ms: &MatchState,
+    buf: &mut Vec<u8>,
+    s: usize,
+    e: usize,
+    repl: &Value
+) -> Result<(), LuaError> {
+    let value = match repl {
+        Value::Table(_) => {
+            let key = ms.get_capture(0, s, e).map_err(|msg| state.error(msg))?;
+            state.index(repl, &key)?
+        }
+        f if f.is_function() => {
+            let args = ms.get_captures(s, e, true).map_err(|msg| state.error(msg))?;
+            state.call_meta(f.clone(), &args)?
+        }
+        _ => {
+            /* 替换字符串 : %0是整个匹配 , %1-%9是捕获 , %%是% */
+            let r = match repl {
+                Value::Integer(_) | Value::Float(_) => repl.to_string().into_bytes(),
+                _ => <&[u8]>::from(repl).to_vec(),
+            };
+            let mut chars = r.iter();
+            while let Some(&c) = chars.next() {
+                if c != b'%' {
+                    buf.push(c);
+                    continue;
+                }
+                match chars.next() {
+                    Some(b'%') => buf.push(b'%'),
+                    Some(&d) if d.is_ascii_digit() => {
+                        let v = if d == b'0' {
+                            Value::from(ms.whole(s, e))
+                        } else {
+                            ms.get_capture((d - b'1') as usize, s, e).map_err(|msg| state.error(msg))?
+                        };
+                        append_value(buf, &v);
+                    }
+                    _ => {
+                        return Err(state.error("invalid use of '%' in replacement string"));
+                    }
+                }
+            }
+            return Ok(());
+        }
+    };
+    match value {
+        /* nil或者false : 保留原来的内容 */
+        Value::Nil | Value::Boolean(false) => buf.extend_from_slice(ms.whole(s, e)),
+        v @ (Value::Integer(_) | Value::Float(_)) => append_value(buf, &v),
+        v if v.is_str() => append_value(buf, &v),
+        v => {
+            return Err(state.error(format!("invalid replacement value (a {})", v.ty())));
+        }
+    }
+    return Ok(());
+}
+
+/** 把字符串或者数字追加到结果中 */
+fn append_value(buf: &mut Vec<u8>, v: &Value) {
+    match v {
+        Value::Integer(_) | Value::Float(_) => buf.extend_from_slice(v.to_string().as_bytes()),
+        v => buf.extend_from_slice(<&[u8]>::from(v)),
+    }
+}
@@ -0,0 +1,362 @@
+use std::{ rc::Rc, cell::RefCell, time::{ SystemTime, UNIX_EPOCH } };
+
+use crate::{ vm::ExeState, interface::{ Value, LuaError, RustFunction, table::Table } };
+
+use super::new_lib;
+
+/* ### table库
+    和Lua 5.4一样 , 所有的读写都经过__index/__newindex , 长度经过__len ,
+    所以带元表的"类table"对象也可以使用
+ */
+
+/** check_tab需要的操作 : 读 , 写 , 取长度 */
+const TAB_R: u8 = 1;
+const TAB_W: u8 = 2;
+const TAB_L: u8 = 4;
+const TAB_RW: u8 = TAB_R | TAB_W;
+
+/** table.unpack最多返回的值的个数 */
+const MAX_UNPACK: i64 = 1000000;
+/** 区间小于这个长度时 , 排序使用中间的元素作为基准 */
+const RANLIMIT: u64 = 100;
+
+/** table库 : 注册到全局变量table */
+pub fn open_table(state: &mut ExeState) {
+    let funcs: [(&str, RustFunction); 7] = [
+        ("insert", lib_insert),
+        ("remove", lib_remove),
+        ("concat", lib_concat),
+        ("unpack", lib_unpack),
+        ("pack", lib_pack),
+        ("move", lib_move),
+        ("sort", lib_sort),
+    ];
+    state.set_global("table", new_lib(&funcs));
+}
+
+/** 第arg个参数必须是table , 或者元表中有需要的元方法 */
+fn check_tab(state: &ExeState, arg: usize, what: u8) -> Result<Value, LuaError> {
+    let v = state.get(arg);
+    if let Value::Table(_) = v {
+        return Ok(v);
+    }
+    if let Some(mt) = state.get_metatable(&v) {
+        let mt = mt.borrow();
+        let has = |event: &str| !matches!(mt.get(&Value::from(event)), Value::Nil);
+        if (what & TAB_R == 0 || has("__index"))
+            && (what & TAB_W == 0 || has("__newindex"))
+            && (what & TAB_L == 0 || has("__len"))
+        {
+            return Ok(v);
+        }
+    }
+    return Err(state.type_error(arg, "table"));
+}
+
+/** 检查参数并取长度 */
+fn aux_getn(state: &mut ExeState, arg: usize, what: u8) -> Result<(Value, i64), LuaError> {
+    let t = check_tab(state, arg, what | TAB_L)?;
+    let n = state.length(&t)?;
+    return Ok((t, n));
+}
+
+fn geti(state: &mut ExeState, t: &Value, i: i64) -> Result<Value, LuaError> {
+    return state.index(t, &Value::Integer(i));
+}
+
+fn seti(state: &mut ExeState, t: &Value, i: i64, v: Value) -> Result<(), LuaError> {
+    return state.set_index(t, Value::Integer(i), v);
+}
+
+/** table.insert(list, [pos,] value) */
+fn lib_insert(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (t, n) = aux_getn(state, 1, TAB_RW)?;
+    let e = n.wrapping_add(1); /* 第一个空位 */
+    let pos = match state.get_top() {
+        2 => e,
+        3 => {
+            let pos = state.check_integer(2)?;
+            /* pos必须在[1, e]之内 */
+            if (pos as u64).wrapping_sub(1) >= e as u64 {
+                return Err(state.arg_error(2, "position out of bounds"));
+            }
+            for i in (pos + 1..=e).rev() {
+                let v = geti(state, &t, i - 1)?;
+                seti(state, &t, i, v)?;
+            }
+            pos
+        }
+        _ => {
+            return Err(state.error("wrong number of arguments to 'insert'"));
+        }
+    };
+    let v = state.get(state.get_top());
+    seti(state, &t, pos, v)?;
+    return Ok(0);
+}
+
+/** table.remove(list [, pos]) : 返回被删除的元素 */
+fn lib_remove(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (t, size) = aux_getn(state, 1, TAB_RW)?;
+    let mut pos = state.opt_integer(2, size)?;
+    /* 指定了pos时必须在[1, size+1]之内 */
+    if pos != size && (pos as u64).wrapping_sub(1) > size as u64 {
+        return Err(state.arg_error(2, "position out of bounds"));
+    }
+    let v = geti(state, &t, pos)?;
+    while pos < size {
+        let next = geti(state, &t, pos + 1)?;
+        seti(state, &t, pos, next)?;
+        pos += 1;
+    }
+    seti(state, &t, pos, Value::Nil)?;
+    state.push(v);
+    return Ok(1);
+}
+
+/** table.concat(list [, sep [, i [, j]]]) : 所有元素写入同一个缓冲区 */
+fn lib_concat(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (t, last) = aux_getn(state, 1, TAB_R)?;
+    let sep = match state.get(2) {
+        Value::Nil => Vec::new(),
+        _ => <&[u8]>::from(&state.check_string(2)?).to_vec(),
+    };
+    let first = state.opt_integer(3, 1)?;
+    let last = state.opt_integer(4, last)?;
+    let mut buf = Vec::new();
+    let mut i = first;
+    while i <= last {
+        match geti(state, &t, i)? {
+            v @ (Value::Integer(_) | Value::Float(_)) => buf.extend_from_slice(v.to_string().as_bytes()),
+            v if v.is_str() => buf.extend_from_slice(<&[u8]>::from(&v)),
+            _ => {
+                return Err(state.error(format!("invalid value (at index {i}) in table for 'concat'")));
+            }
+        }
+        if i == last {
+            break;
+        }
+        buf.extend_from_slice(&sep);
+        i += 1;
+    }
+    state.push(buf);
+    return Ok(1);
+}
+
+/** table.pack(...) : 参数放入新的table , 并且设置n为参数个数 */
+fn lib_pack(state: &mut ExeState) -> Result<i32, LuaError> {
+    let n = state.get_top();
+    let mut t = Table::new(n, 1);
+    for i in 1..=n {
+        t.set(Value::Integer(i as i64), state.get(i));
+    }
+    t.set(Value::from("n"), Value::Integer(n as i64));
+    state.push(Value::Table(Rc::new(RefCell::new(t))));
+    return Ok(1);
+}
+
+/** table.unpack(list [, i [, j]]) */
+fn lib_unpack(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.get(1);
+    let i = state.opt_integer(2, 1)?;
+    let e = match state.get(3) {
+        Value::Nil => state.length(&t)?,
+        _ => state.check_integer(3)?,
+    };
+    if i > e {
+        return Ok(0);
+    }
+    let n = (e as u64).wrapping_sub(i as u64);
+    if n >= MAX_UNPACK as u64 {
+        return Err(state.error("too many results to unpack"));
+    }
+    for k in i..=e {
+        let v = geti(state, &t, k)?;
+        state.push(v);
+    }
+    return Ok(n as i32 + 1);
+}
+
+/** table.move(a1, f, e, t [, a2]) : a2[t..] = a1[f..e] , 返回a2 */
+fn lib_move(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = state.check_integer(2)?;
+    let e = state.check_integer(3)?;
+    let t = state.check_integer(4)?;
+    let tt = if matches!(state.get(5), Value::Nil) { 1 } else { 5 };
+    let src = check_tab(state, 1, TAB_R)?;
+    let dst = check_tab(state, tt, TAB_W)?;
+    if e >= f {
+        if !(f > 0 || e < i64::MAX + f) {
+            return Err(state.arg_error(3, "too many elements to move"));
+        }
+        let n = e - f + 1;
+        if t > i64::MAX - n + 1 {
+            return Err(state.arg_error(4, "destination wrap around"));
+        }
+        /* 目标区间和源区间重叠时从后往前复制 */
+        if t > e || t <= f || (tt != 1 && src != dst) {
+            for i in 0..n {
+                let v = geti(state, &src, f + i)?;
+                seti(state, &dst, t + i, v)?;
+            }
+        } else {
+            for i in (0..n).rev() {
+                let v = geti(state, &src, f + i)?;
+                seti(state, &dst, t + i, v)?;
+            }
+        }
+    }
+    state.push(dst);
+    return Ok(1);
+}
+
+/** table.sort(list [, comp]) */
+fn lib_sort(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (t, n) = aux_getn(state, 1, TAB_RW)?;
+    if n > 1 {
+        if n >= i32::MAX as i64 {
+            return Err(state.arg_error(1, "array too big"));
+        }
+        let comp = state.get(2);
+        if !matches!(comp, Value::Nil) && !comp.is_function() {
+            return Err(state.type_error(2, "function"));
+        }
+        let mut sorter = Sorter { state, t, comp };
+        sorter.auxsort(1, n as u64, 0)?;
+    }
+    return Ok(0);
+}
+
+/* ### 排序
+    和Lua 5.4一样的快速排序 : 三数取中选择基准 , 递归处理较短的区间 ;
+    分区明显不平衡时改用随机的基准 , 保证O(n log n) ;
+    比较函数不一致(比如a<b和b<a同时成立)时会越界 , 这时报错"invalid order function"
+ */
+
+struct Sorter<'a> {
+    state: &'a mut ExeState,
+    t: Value,
+    comp: Value,
+}
+
+impl Sorter<'_> {
+    fn get(&mut self, i: u64) -> Result<Value, LuaError> {
+        return geti(self.state, &self.t, i as i64);
+    }
+
+    fn set(&mut self, i: u64, v: Value) -> Result<(), LuaError> {
+        return seti(self.state, &self.t, i as i64, v);
+    }
+
+    /** a < b : 没有比较函数时使用<运算符 */
+    fn less(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        if let Value::Nil = self.comp {
+            return self.state.less_than(a, b);
+        }
+        return Ok(self.state.call_meta(self.comp.clone(), &[a.clone(), b.clone()])?.truthy());
+    }
+
+    fn invalid(&self) -> LuaError {
+        return self.state.error("invalid order function for sorting");
+    }
+
+    fn auxsort(&mut self, mut lo: u64, mut up: u64, mut rnd: u64) -> Result<(), LuaError> {
+        while lo < up {
+            /* 排序a[lo] , a[p] , a[up] */
+            let (a_lo, a_up) = (self.get(lo)?, self.get(up)?);
+            if self.less(&a_up, &a_lo)? {
+                self.set(lo, a_up)?;
+                self.set(up, a_lo)?;
+            }
+            if up - lo == 1 {
+                break;
+            }
+            let p = if up - lo < RANLIMIT || rnd == 0 { (lo + up) / 2 } else { choose_pivot(lo, up, rnd) };
+            let (a_p, a_lo) = (self.get(p)?, self.get(lo)?);
+            if self.less(&a_p, &a_lo)? {
+                self.set(p, a_lo)?;
+                self.set(lo, a_p)?;
+            } else {
+                let a_up = self.get(up)?;
+                if self.less(&a_up, &a_p)? {
+                    self.set(p, a_up)?;
+                    self.set(up, a_p)?;
+                }
+            }
+            if up - lo == 2 {
+                break;
+            }
+            /* 基准交换到a[up-1] */
+            let pivot = self.get(p)?;
+            let a_up1 = self.get(up - 1)?;
+            self.set(p, a_up1)?;
+            self.set(up - 1, pivot.clone())?;
+            let p = self.partition(lo, up, &pivot)?;
+            /* 递归处理较短的区间 , 较长的区间继续循环 */
+            let n;
+            if p - lo < up - p {
+                self.auxsort(lo, p - 1, rnd)?;
+                n = p - lo;
+                lo = p + 1;
+            } else {
+                self.auxsort(p + 1, up, rnd)?;
+                n = up - p;
+                up = p - 1;
+            }
+            if (up - lo) / 128 > n {
+                rnd = random_pivot();
+            }
+        }
+        return Ok(());
+    }
+
+    /** 分区 : 结束时 a[lo..p-1] <= a[p] == pivot <= a[p+1..up] */
+    fn partition(&mut self, lo: u64, up: u64, pivot: &Value) -> Result<u64, LuaError> {
+        let mut i = lo;
+        let mut j = up - 1;
+        loop {
+            /* a[i] < pivot 时继续向后 */
+            let a_i = loop {
+                i += 1;
+                let a_i = self.get(i)?;
+                if !self.less(&a_i, pivot)? {
+                    break a_i;
+                }
+                if i == up - 1 {
+                    return Err(self.invalid());
+                }
+            };
+            /* pivot < a[j] 时继续向前 */
+            let a_j = loop {
+                j -= 1;
+                let a_j = self.get(j)?;
+                if !self.less(pivot, &a_j)? {
+                    break a_j;
+                }
+                if j < i {
+                    return Err(self.invalid());
+                }
+            };
+            if j < i {
+                /* 基准(a[up-1])和a[i]交换 */
+                self.set(up - 1, a_i)?;
+                self.set(i, pivot.clone())?;
+                return Ok(i);
+            }
+            self.set(i, a_j)?;
+            self.set(j, a_i)?;
+        }
+    }
+}
+
+/** 在区间中间的一半里随机选择基准 */
+fn choose_pivot(lo: u64, up: u64, rnd: u64) -> u64 {
+    let r4 = (up - lo) / 4;
+    return rnd % (r4 * 2) + (lo + r4);
+}
+
+/** 分区不平衡时使用的随机数 */
+fn random_pivot() -> u64 {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+    return time | 1;
+}
@@ -0,0 +1,274 @@
+use crate::{ vm::ExeState, interface::{ Value, LuaError, RustFunction } };
+
+use super::new_lib;
+
+/* ### utf8库
+    直接处理字符串的字节 , 和Lua 5.4一样支持最长6字节的编码(最大0x7FFFFFFF) ;
+    严格模式下只接受合法的Unicode码点 , lax模式下接受到0x7FFFFFFF为止的所有值
+ */
+
+const MAX_UNICODE: u32 = 0x10FFFF;
+const MAX_UTF: u32 = 0x7FFFFFFF;
+const MSG_INVALID: &str = "invalid UTF-8 code";
+
+/** 匹配一个UTF-8字符的模式 */
+const CHARPATTERN: &[u8] = b"[\x00-\x7F\xC2-\xFD][\x80-\xBF]*";
+
+/** utf8库 : 注册到全局变量utf8 */
+pub fn open_utf8(state: &mut ExeState) {
+    let funcs: [(&str, RustFunction); 5] = [
+        ("offset", lib_offset),
+        ("codepoint", lib_codepoint),
+        ("char", lib_char),
+        ("len", lib_len),
+        ("codes", lib_codes),
+    ];
+    let lib = new_lib(&funcs);
+    if let Value::Table(t) = &lib {
+        t.borrow_mut().set(Value::from("charpattern"), Value::from(CHARPATTERN));
+    }
+    state.set_global("utf8", lib);
+}
+
+/** 是否是后续字节(10xxxxxx) , 超出字符串末尾时不是 */
+fn is_cont(s: &[u8], i: usize) -> bool {
+    return s.get(i).is_some_and(|c| c & 0xC0 == 0x80);
+}
+
+/** 负数位置从末尾开始计数 , 太小时为0 */
+fn u_posrelat(pos: i64, len: usize) -> i64 {
+    if pos >= 0 {
+        return pos;
+    } else if pos.unsigned_abs() > len as u64 {
+        return 0;
+    }
+    return len as i64 + pos + 1;
+}
+
+/** 解码从i开始的一个字符 , 返回(码点, 下一个字符的位置) ; 不合法时返回None */
+fn utf8_decode(s: &[u8], i: usize, strict: bool) -> Option<(u32, usize)> {
+    const LIMITS: [u32; 6] = [!0, 0x80, 0x800, 0x10000, 0x200000, 0x4000000];
+    let mut c = s.get(i).copied().unwrap_or(0) as u32;
+    let mut res: u32;
+    let mut count = 0;
+    if c < 0x80 {
+        res = c;
+    } else {
+        res = 0;
+        while c & 0x40 != 0 {
+            count += 1;
+            let cc = s.get(i + count).copied().unwrap_or(0) as u32;
+            if cc & 0xC0 != 0x80 {
+                return None;
+            }
+            res = (res << 6) | (cc & 0x3F);
+            c <<= 1;
+        }
+        if count > 5 {
+            return None;
+        }
+        res |= (c & 0x7F) << (count * 5);
+        if res > MAX_UTF || res < LIMITS[count] {
+            return None;
+        }
+    }
+    if strict && (res > MAX_UNICODE || (0xD800..=0xDFFF).contains(&res)) {
+        return None;
+    }
+    return Some((res, i + count + 1));
+}
+
+/** 编码一个码点 , 最多6字节 */
+fn utf8_encode(buf: &mut Vec<u8>, mut x: u32) {
+    if x < 0x80 {
+        buf.push(x as u8);
+        return;
+    }
+    let mut tail = Vec::new();
+    let mut mfb = 0x3f; /* 第一个字节能容纳的最大值 */
+    loop {
+        tail.push(0x80 | (x & 0x3f) as u8);
+        x >>= 6;
+        mfb >>= 1;
+        if x <= mfb {
+            break;
+        }
+    }
+    buf.push(((!mfb << 1) | x) as u8);
+    buf.extend(tail.iter().rev());
+}
+
+/** utf8.char(...) : 每个参数是一个码点 */
+fn lib_char(state: &mut ExeState) -> Result<i32, LuaError> {
+    let mut buf = Vec::new();
+    for i in 1..=state.get_top() {
+        let code = state.check_integer(i)?;
+        if code as u64 > MAX_UTF as u64 {
+            return Err(state.arg_error(i, "value out of range"));
+        }
+        utf8_encode(&mut buf, code as u32);
+    }
+    state.push(buf);
+    return Ok(1);
+}
+
+/** utf8.len(s [, i [, j [, lax]]]) : 遇到不合法的字节时返回nil和它的位置 */
+fn lib_len(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    let s = <&[u8]>::from(&s);
+    let len = s.len() as i64;
+    let posi = u_posrelat(state.opt_integer(2, 1)?, s.len());
+    let posj = u_posrelat(state.opt_integer(3, -1)?, s.len());
+    let lax = state.get(4).truthy();
+    if !(1 <= posi && posi - 1 <= len) {
+        return Err(state.arg_error(2, "initial position out of bounds"));
+    }
+    if posj > len {
+        return Err(state.arg_error(3, "final position out of bounds"));
+    }
+    let (mut posi, posj) = (posi - 1, posj - 1);
+    let mut n = 0;
+    while posi <= posj {
+        match utf8_decode(s, posi as usize, !lax) {
+            Some((_, next)) => posi = next as i64,
+            None => {
+                state.push(Value::Nil);
+                state.push(posi + 1);
+                return Ok(2);
+            }
+        }
+        n += 1;
+    }
+    state.push(n);
+    return Ok(1);
+}
+
+/** utf8.codepoint(s [, i [, j [, lax]]]) : 返回[i,j]之间所有字符的码点 */
+fn lib_codepoint(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    let s = <&[u8]>::from(&s);
+    let posi = u_posrelat(state.opt_integer(2, 1)?, s.len());
+    let pose = u_posrelat(state.opt_integer(3, posi)?, s.len());
+    let lax = state.get(4).truthy();
+    if posi < 1 {
+        return Err(state.arg_error(2, "out of bounds"));
+    }
+    if pose > s.len() as i64 {
+        return Err(state.arg_error(3, "out of bounds"));
+    }
+    if posi > pose {
+        return Ok(0);
+    }
+    if pose - posi >= i32::MAX as i64 {
+        return Err(state.error("string slice too long"));
+    }
+    let mut n = 0;
+    let mut i = posi as usize - 1;
+    while i < pose as usize {
+        let Some((code, next)) = utf8_decode(s, i, !lax) else {
+            return Err(state.error(MSG_INVALID));
+        };
+        state.push(code as i64);
+        n += 1;
+        i = next;
+    }
+    return Ok(n);
+}
+
+/** utf8.offset(s, n [, i]) : 第n个字符(从位置i开始计数)的字节位置 */
+fn lib_offset(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    let s = <&[u8]>::from(&s);
+    let len = s.len() as i64;
+    let mut n = state.check_integer(2)?;
+    let default = if n >= 0 { 1 } else { len + 1 };
+    let posi = u_posrelat(state.opt_integer(3, default)?, s.len());
+    if !(1 <= posi && posi - 1 <= len) {
+        return Err(state.arg_error(3, "position out of bounds"));
+    }
+    let mut posi = (posi - 1) as usize;
+    if n == 0 {
+        /* 当前字符的开始位置 */
+        while posi > 0 && is_cont(s, posi) {
+            posi -= 1;
+        }
+    } else {
+        if is_cont(s, posi) {
+            return Err(state.error("initial position is a continuation byte"));
+        }
+        if n < 0 {
+            while n < 0 && posi > 0 {
+                posi -= 1;
+                while posi > 0 && is_cont(s, posi) {
+                    posi -= 1;
+                }
+                n += 1;
+            }
+        } else {
+            n -= 1; /* 第1个字符就是当前位置 */
+            while n > 0 && posi < s.len() {
+                posi += 1;
+                while is_cont(s, posi) {
+                    posi += 1;
+                }
+                n -= 1;
+            }
+        }
+    }
+    if n == 0 {
+        state.push(posi as i64 + 1);
+    } else {
+        state.push(Value::Nil);
+    }
+    return Ok(1);
+}
+
+/** utf8.codes的迭代函数 : 控制变量是上一个字符的位置 */
+fn iter_aux(state: &mut ExeState, strict: bool) -> Result<i32, LuaError> {
+    let s = state.check_string(1)?;
+    let s = <&[u8]>::from(&s);
+    let mut n = match state.get(2) {
+        Value::Integer(n) => n as u64,
+        _ => 0,
+    } as usize;
+    if n < s.len() {
+        while is_cont(s, n) {
+            n += 1;
+        }
+    }
+    if n >= s.len() {
+        return Ok(0);
+    }
+    match utf8_decode(s, n, strict) {
+        Some((code, next)) if !is_cont(s, next) => {
+            state.push(n as i64 + 1);
+            state.push(code as i64);
+            return Ok(2);
+        }
+        _ => {
+            return Err(state.error(MSG_INVALID));
+        }
+    }
+}
+
+fn iter_aux_strict(state: &mut ExeState) -> Result<i32, LuaError> {
+    return iter_aux(state, true);
+}
+
+fn iter_aux_lax(state: &mut ExeState) -> Result<i32, LuaError> {
+    return iter_aux(state, false);
+}
+
+/** utf8.codes(s [, lax]) : for p, c in utf8.codes(s) */
+fn lib_codes(state: &mut ExeState) -> Result<i32, LuaError> {
+    let lax = state.get(2).truthy();
+    let s = state.check_string(1)?;
+    if is_cont(<&[u8]>::from(&s), 0) {
+        return Err(state.arg_error(1, MSG_INVALID));
+    }
+    let iter: RustFunction = if lax { iter_aux_lax } else { iter_aux_strict };
+    state.push(Value::RustFunction(iter));
+    state.push(s);
+    state.push(0);
+    return Ok(3);
+}
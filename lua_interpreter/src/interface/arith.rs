@@ -0,0 +1,364 @@
+use std::cmp::Ordering;
+
+use super::{ Value, OpCode };
+
+/* ### 算术/位运算/比较的语义
+    语法分析的常量折叠和虚拟机执行共用这里的规则,保证折叠前后的程序结果完全一致
+ */
+
+/** float -> int : 只有没有小数部分并且在i64范围内的float才能转化 */
+pub fn float_to_int(f: f64) -> Option<i64> {
+    /* -2^63可以精确表示,而2^63已经超出i64范围 */
+    if f.fract() == 0.0 && (-9223372036854775808.0..9223372036854775808.0).contains(&f) {
+        return Some(f as i64);
+    }
+    return None;
+}
+
+/** 数字 , 或者可以转换成数字的字符串(按照数字字面量的语法) : 保留整数和浮点数的类型 */
+pub fn to_number(v: &Value) -> Option<Value> {
+    return match v {
+        Value::Integer(_) | Value::Float(_) => Some(v.clone()),
+        Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_) => str_to_number(<&[u8]>::from(v)),
+        _ => None,
+    };
+}
+
+/** 可以无损转换成整数的数字或者字符串 */
+pub fn to_integer(v: &Value) -> Option<i64> {
+    return match to_number(v)? {
+        Value::Integer(i) => Some(i),
+        Value::Float(f) => float_to_int(f),
+        _ => None,
+    };
+}
+
+/** 位运算的操作数必须能转化成整数 */
+fn to_bit_int(v: &Value) -> Result<i64, String> {
+    return match to_number(v) {
+        Some(Value::Integer(i)) => Ok(i),
+        Some(Value::Float(f)) => float_to_int(f).ok_or(String::from("number has no integer representation")),
+        _ => Err(format!("attempt to perform bitwise operation on a {} value", v.ty())),
+    };
+}
+
+/** 左移 : 位移超过63位结果为0,负数位移表示逻辑右移 */
+pub fn shift_left(x: i64, n: i64) -> i64 {
+    if n <= -64 || n >= 64 {
+        return 0;
+    } else if n < 0 {
+        return ((x as u64) >> -n) as i64;
+    }
+    return ((x as u64) << n) as i64;
+}
+
+/** 整数的向下取整除法 */
+fn int_idiv(a: i64, b: i64) -> Result<i64, String> {
+    if b == 0 {
+        return Err(String::from("attempt to perform 'n//0'"));
+    }
+    let q = a.wrapping_div(b);
+    if a.wrapping_rem(b) != 0 && (a ^ b) < 0 {
+        return Ok(q - 1);
+    }
+    return Ok(q);
+}
+
+/** 整数取模,结果的符号和除数一致 */
+fn int_mod(a: i64, b: i64) -> Result<i64, String> {
+    if b == 0 {
+        return Err(String::from("attempt to perform 'n%0'"));
+    }
+    let m = a.wrapping_rem(b);
+    if m != 0 && (m ^ b) < 0 {
+        return Ok(m + b);
+    }
+    return Ok(m);
+}
+
+/** 浮点数取模,结果的符号和除数一致 */
+fn float_mod(a: f64, b: f64) -> f64 {
+    let m = a % b;
+    if (m > 0.0 && b < 0.0) || (m < 0.0 && b > 0.0) {
+        return m + b;
+    }
+    return m;
+}
+
+/** 二元运算 : Add..Shr 以及 Concat */
+pub fn arith(op: OpCode, a: &Value, b: &Value) -> Result<Value, String> {
+    match op {
+        OpCode::BAnd | OpCode::BOr | OpCode::BXor | OpCode::Shl | OpCode::Shr => {
+            let (x, y) = (to_bit_int(a)?, to_bit_int(b)?);
+            let r = match op {
+                OpCode::BAnd => x & y,
+                OpCode::BOr => x | y,
+                OpCode::BXor => x ^ y,
+                OpCode::Shl => shift_left(x, y),
+                _ => shift_left(x, y.wrapping_neg()),
+            };
+            return Ok(Value::Integer(r));
+        }
+        OpCode::Concat => {
+            return match (a, b) {
+                (
+                    Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_) | Value::Integer(_) | Value::Float(_),
+                    Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_) | Value::Integer(_) | Value::Float(_),
+                ) => {
+                    let mut s = concat_bytes(a);
+                    s.extend_from_slice(&concat_bytes(b));
+                    Ok(s.into())
+                }
+                (Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_) | Value::Integer(_) | Value::Float(_), _) =>
+                    Err(format!("attempt to concatenate a {} value", b.ty())),
+                _ => Err(format!("attempt to concatenate a {} value", a.ty())),
+            };
+        }
+        _ => {}
+    }
+
+    /* 算术运算 : 两个整数保持整数(溢出回绕),否则转成浮点数 ; 除法和乘方总是浮点数 ; 字符串先转换成数字 */
+    let (Some(x), Some(y)) = (to_number(a), to_number(b)) else {
+        let bad = if to_number(a).is_some() { b } else { a };
+        return Err(format!("attempt to perform arithmetic on a {} value", bad.ty()));
+    };
+    return match (&x, &y) {
+        (Value::Integer(x), Value::Integer(y)) =>
+            match op {
+                OpCode::Add => Ok(Value::Integer(x.wrapping_add(*y))),
+                OpCode::Sub => Ok(Value::Integer(x.wrapping_sub(*y))),
+                OpCode::Mul => Ok(Value::Integer(x.wrapping_mul(*y))),
+                OpCode::IDiv => Ok(Value::Integer(int_idiv(*x, *y)?)),
+                OpCode::Mod => Ok(Value::Integer(int_mod(*x, *y)?)),
+                _ => Ok(Value::Float(float_arith(op, *x as f64, *y as f64))),
+            }
+        (Value::Integer(x), Value::Float(y)) => Ok(Value::Float(float_arith(op, *x as f64, *y))),
+        (Value::Float(x), Value::Integer(y)) => Ok(Value::Float(float_arith(op, *x, *y as f64))),
+        (Value::Float(x), Value::Float(y)) => Ok(Value::Float(float_arith(op, *x, *y))),
+        _ => unreachable!(),
+    };
+}
+
+fn float_arith(op: OpCode, x: f64, y: f64) -> f64 {
+    return match op {
+        OpCode::Add => x + y,
+        OpCode::Sub => x - y,
+        OpCode::Mul => x * y,
+        OpCode::Div => x / y,
+        OpCode::IDiv => (x / y).floor(),
+        OpCode::Mod => float_mod(x, y),
+        OpCode::Pow => x.powf(y),
+        _ => panic!("invalid arith op {op:?}"),
+    };
+}
+
+/** 字符串拼接时数字按照print的格式转成字符串 */
+fn concat_bytes(v: &Value) -> Vec<u8> {
+    return match v {
+        Value::Integer(_) | Value::Float(_) => v.to_string().into_bytes(),
+        _ => <&[u8]>::from(v).to_vec(),
+    };
+}
+
+/** 一元运算 : Unm 和 BNot */
+pub fn unary(op: OpCode, a: &Value) -> Result<Value, String> {
+    return match (op, a) {
+        (OpCode::Unm, _) =>
+            match to_number(a) {
+                Some(Value::Integer(i)) => Ok(Value::Integer(i.wrapping_neg())),
+                Some(Value::Float(f)) => Ok(Value::Float(-f)),
+                _ => Err(format!("attempt to perform arithmetic on a {} value", a.ty())),
+            }
+        (OpCode::BNot, _) => Ok(Value::Integer(!to_bit_int(a)?)),
+        _ => panic!("invalid unary op {op:?}"),
+    };
+}
+
+/** 相等比较 : 整数和浮点数之间按数值比较 */
+pub fn equal(a: &Value, b: &Value) -> bool {
+    return match (a, b) {
+        (Value::Integer(x), Value::Float(y)) | (Value::Float(y), Value::Integer(x)) =>
+            int_float_cmp(*x, *y) == Some(Ordering::Equal),
+        _ => a == b,
+    };
+}
+
+/** 整数和浮点数按数学上的值精确比较 , 不经过会丢失精度的int -> float转换 ; 和NaN比较返回None */
+fn int_float_cmp(i: i64, f: f64) -> Option<Ordering> {
+    if f.is_nan() {
+        return None;
+    } else if f >= 9223372036854775808.0 {
+        return Some(Ordering::Less);
+    } else if f < -9223372036854775808.0 {
+        return Some(Ordering::Greater);
+    }
+    /* f在i64范围内 , 它的整数部分可以精确转换成整数 */
+    let floor = f.floor();
+    return match i.cmp(&(floor as i64)) {
+        Ordering::Equal if floor != f => Some(Ordering::Less),
+        o => Some(o),
+    };
+}
+
+/** 小于/小于等于比较 : 只支持数字之间和字符串之间 */
+pub fn compare(op: OpCode, a: &Value, b: &Value) -> Result<bool, String> {
+    let ord = match (a, b) {
+        (Value::Integer(x), Value::Integer(y)) => x.partial_cmp(y),
+        (Value::Integer(x), Value::Float(y)) => int_float_cmp(*x, *y),
+        (Value::Float(x), Value::Integer(y)) => int_float_cmp(*y, *x).map(Ordering::reverse),
+        (Value::Float(x), Value::Float(y)) => x.partial_cmp(y),
+        (
+            Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_),
+            Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_),
+        ) => <&[u8]>::from(a).partial_cmp(<&[u8]>::from(b)),
+        _ if a.ty() == b.ty() => {
+            return Err(format!("attempt to compare two {} values", a.ty()));
+        }
+        _ => {
+            return Err(format!("attempt to compare {} with {}", a.ty(), b.ty()));
+        }
+    };
+    /* NaN参与的比较总是false */
+    return Ok(match ord {
+        Some(o) if op == OpCode::Lt => o.is_lt(),
+        Some(o) => o.is_le(),
+        None => false,
+    });
+}
+
+/** ### 字符串转数字
+    和Lua的数字字面量语法一致 : 允许前后空白和正负号,支持十六进制(包括十六进制浮点数和p指数) ;
+    十进制整数溢出时转成浮点数,十六进制整数溢出时回绕
+ */
+pub fn str_to_number(s: &[u8]) -> Option<Value> {
+    let s = s.trim_ascii();
+    let (neg, s) = match s.first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let v = if s.len() > 1 && s[0] == b'0' && (s[1] == b'x' || s[1] == b'X') {
+        hex_to_number(&s[2..])?
+    } else {
+        dec_to_number(s)?
+    };
+    if !neg {
+        return Some(v);
+    }
+    return match v {
+        Value::Integer(i) => Some(Value::Integer(i.wrapping_neg())),
+        /* -9223372036854775808正好是最小的整数 , 只有它的绝对值溢出了 */
+        Value::Float(_) if s.iter().position(|&c| c != b'0').is_some_and(|i| &s[i..] == b"9223372036854775808") =>
+            Some(Value::Integer(i64::MIN)),
+        Value::Float(f) => Some(Value::Float(-f)),
+        _ => None,
+    };
+}
+
+/** 十进制 : 数字[.数字][e[+-]数字] , 整数和小数部分至少有一个数字 */
+fn dec_to_number(s: &[u8]) -> Option<Value> {
+    let mut i = 0;
+    let mut digits = 0;
+    let mut is_float = false;
+    while i < s.len() && s[i].is_ascii_digit() {
+        i += 1;
+        digits += 1;
+    }
+    if i < s.len() && s[i] == b'.' {
+        is_float = true;
+        i += 1;
+        while i < s.len() && s[i].is_ascii_digit() {
+            i += 1;
+            digits += 1;
+        }
+    }
+    if digits == 0 {
+        return None;
+    }
+    if i < s.len() && (s[i] == b'e' || s[i] == b'E') {
+        is_float = true;
+        i += 1;
+        if i < s.len() && (s[i] == b'+' || s[i] == b'-') {
+            i += 1;
+        }
+        let start = i;
+        while i < s.len() && s[i].is_ascii_digit() {
+            i += 1;
+        }
+        if start == i {
+            return None;
+        }
+    }
+    if i != s.len() {
+        return None;
+    }
+    let text = std::str::from_utf8(s).ok()?;
+    if !is_float {
+        if let Ok(n) = text.parse::<i64>() {
+            return Some(Value::Integer(n));
+        }
+    }
+    return text.parse::<f64>().ok().map(Value::Float);
+}
+
+/** 十六进制 : 没有小数点和指数时是整数 , 否则是浮点数 */
+fn hex_to_number(s: &[u8]) -> Option<Value> {
+    let mut i = 0;
+    let mut digits = 0;
+    let mut int: i64 = 0;
+    let mut mantissa: f64 = 0.0;
+    let mut exp: i64 = 0;
+    let mut is_float = false;
+    while i < s.len() && s[i].is_ascii_hexdigit() {
+        let d = (s[i] as char).to_digit(16).unwrap();
+        int = int.wrapping_mul(16).wrapping_add(d as i64);
+        mantissa = mantissa * 16.0 + (d as f64);
+        i += 1;
+        digits += 1;
+    }
+    if i < s.len() && s[i] == b'.' {
+        is_float = true;
+        i += 1;
+        while i < s.len() && s[i].is_ascii_hexdigit() {
+            mantissa = mantissa * 16.0 + ((s[i] as char).to_digit(16).unwrap() as f64);
+            exp -= 4;
+            i += 1;
+            digits += 1;
+        }
+    }
+    if digits == 0 {
+        return None;
+    }
+    if i < s.len() && (s[i] == b'p' || s[i] == b'P') {
+        is_float = true;
+        i += 1;
+        let neg = match s.get(i) {
+            Some(b'-') => {
+                i += 1;
+                true
+            }
+            Some(b'+') => {
+                i += 1;
+                false
+            }
+            _ => false,
+        };
+        let start = i;
+        let mut e: i64 = 0;
+        while i < s.len() && s[i].is_ascii_digit() {
+            e = e.saturating_mul(10).saturating_add((s[i] - b'0') as i64);
+            i += 1;
+        }
+        if start == i {
+            return None;
+        }
+        exp = exp.saturating_add(if neg { -e } else { e });
+    }
+    if i != s.len() {
+        return None;
+    }
+    if !is_float {
+        return Some(Value::Integer(int));
+    }
+    return Some(Value::Float(mantissa * (2.0f64).powi(exp.clamp(-2000, 2000) as i32)));
+}
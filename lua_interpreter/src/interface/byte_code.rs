@@ -0,0 +1,354 @@
+use std::fmt;
+
+/** ### 32位字节码编码
+    参照Lua 5.4官方实现的指令格式,每条指令都是一个u32,低7位是操作码,其余25位是参数:
+
+    ```text
+            3 3 2 2 2 2 2 2 2 2 2 2 1 1 1 1 1 1 1 1 1 1 0 0 0 0 0 0 0 0 0 0
+            1 0 9 8 7 6 5 4 3 2 1 0 9 8 7 6 5 4 3 2 1 0 9 8 7 6 5 4 3 2 1 0
+    iABC          C(8)     |      B(8)     |k|     A(8)      |   Op(7)     |
+    iABx                Bx(17)               |     A(8)      |   Op(7)     |
+    iAsBx              sBx (signed)(17)      |     A(8)      |   Op(7)     |
+    iAx                           Ax(25)                     |   Op(7)     |
+    isJ                           sJ(25)                     |   Op(7)     |
+    ```
+    有符号参数sBx/sJ使用excess-K表示 : 实际值 = 无符号值 - OFFSET
+ */
+const SIZE_OP: u32 = 7;
+const SIZE_A: u32 = 8;
+const SIZE_B: u32 = 8;
+const SIZE_C: u32 = 8;
+const SIZE_BX: u32 = SIZE_B + SIZE_C + 1;
+const SIZE_AX: u32 = SIZE_BX + SIZE_A;
+
+const POS_A: u32 = SIZE_OP;
+const POS_K: u32 = POS_A + SIZE_A;
+const POS_B: u32 = POS_K + 1;
+const POS_C: u32 = POS_B + SIZE_B;
+const POS_BX: u32 = POS_K;
+const POS_AX: u32 = POS_A;
+
+pub const MAXARG_A: usize = (1 << SIZE_A) - 1;
+pub const MAXARG_B: usize = (1 << SIZE_B) - 1;
+pub const MAXARG_C: usize = (1 << SIZE_C) - 1;
+pub const MAXARG_BX: usize = (1 << SIZE_BX) - 1;
+pub const MAXARG_AX: usize = (1 << SIZE_AX) - 1;
+const OFFSET_SBX: i64 = (MAXARG_BX >> 1) as i64;
+const OFFSET_SJ: i64 = (MAXARG_AX >> 1) as i64;
+
+/** table构造时每攒够这么多个数组元素就用一条SetList写入 */
+pub const FIELDS_PER_FLUSH: usize = 50;
+
+/** 不使用寄存器时的A参数占位,所以可用的栈位置最多到 NO_REG - 1 */
+pub const NO_REG: usize = MAXARG_A;
+
+/** 判断一个整数能否放进sBx参数 */
+pub fn fits_sbx(i: i64) -> bool {
+    return (-OFFSET_SBX..=(MAXARG_BX as i64) - OFFSET_SBX).contains(&i);
+}
+
+/** ### OpCode表示字节码的操作类型
+    注释中 R[x]表示栈上x位置的数据 , K[x]表示常量表中x位置的常量 , UpValue[x]表示第x个Upvalue ,
+    RK(C)表示k标志位为真时取K[C],否则取R[C]
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    GetUpField /* iABC : R[A] := UpValue[B][K[C]] , 全局变量就是对Upvalue _ENV 的索引 */,
+    LoadConst /* iABx : R[A] := K[Bx] */,
+    LoadConstX /* iABx : R[A] := K[后续ExtraArg的Ax] */,
+    LoadNil /* iABC : R[A] := nil */,
+    LoadBool /* iABC : R[A] := (B != 0) */,
+    LoadFalseSkip /* iABC : R[A] := false; pc++ */,
+    LoadInt /* iAsBx : R[A] := sBx */,
+    Call /* iABC : R[A], ... ,R[A+C-2] := R[A](R[A+1], ... ,R[A+B-1]) , B为0时参数一直到栈顶 , C为0时保留全部返回值 */,
+    TailCall /* iABC : return R[A](R[A+1], ... ,R[A+B-1]) , B为0时参数一直到栈顶 ; 后面紧跟Return(A, 0) */,
+    Return /* iABC : return R[A], ... ,R[A+B-2] , B为0时一直到栈顶 */,
+    VarArg /* iABC : R[A], ... ,R[A+C-2] := ... , C为0时载入全部可变参数 */,
+    Closure /* iABx : R[A] := closure(子函数原型[Bx]) */,
+    GetUpval /* iABC : R[A] := UpValue[B] */,
+    SetUpval /* iABC : UpValue[B] := R[A] */,
+    Close /* iABC : 关闭R[A]及之上的局部变量对应的Upvalue和to-be-closed变量 */,
+    Tbc /* iABx : 把R[A]标记为to-be-closed变量 , K[Bx]是变量名,用于报错信息 */,
+    Move /* iABC : R[A] := R[B] */,
+    SetUpFieldConst /* iABC : UpValue[A][K[B]] := K[C] */,
+    SetUpField /* iABC : UpValue[A][K[B]] := R[C] */,
+    NewTable /* iABC : R[A] := {} , 数组部分长度B|Hash部分长度C */,
+    NewTableConst /* iABx : R[A] := K[Bx]的拷贝 , 全部由常量构成的table在编译期构造好 , Bx溢出时由后续的ExtraArg给出 */,
+    SetTable /* iABC : R[A][R[B]] := R[C] */,
+    SetField /* iABC : R[A][K[B]] := R[C] */,
+    SetInt /* iABC : R[A][B] := R[C] */,
+    SetTableConst /* iABC : R[A][R[B]] := K[C] */,
+    SetFieldConst /* iABC : R[A][K[B]] := K[C] */,
+    SetIntConst /* iABC : R[A][B] := K[C] */,
+    SetList /* iABC : R[A][C*50+i] := R[A+i] , 1 <= i <= B , B为0时一直写到栈顶 , k为真时C由后续的ExtraArg给出 */,
+    GetTable /* iABC : R[A] := R[B][R[C]] */,
+    GetField /* iABC : R[A] := R[B][K[C]] */,
+    GetInt /* iABC : R[A] := R[B][C] */,
+    Method /* iABC : R[A+1] := R[B] ; R[A] := R[B][K[C]] , 方法调用 obj:name() 的准备 */,
+    /* 算术/位运算 : iABC , R[A] := R[B] op RK(C) */
+    Add,
+    Sub,
+    Mul,
+    Div,
+    IDiv,
+    Mod,
+    Pow,
+    BAnd,
+    BOr,
+    BXor,
+    Shl,
+    Shr,
+    Concat /* iABC : R[A] := R[B] .. RK(C) */,
+    /* 一元运算 : iABC , R[A] := op R[B] */
+    Unm,
+    BNot,
+    Not,
+    Len,
+    Jmp /* isJ : pc += sJ */,
+    /* 比较 : 比较结果与k不同时跳过下一条指令(也就是Jmp) */
+    Eq /* iABC : if ((R[A] == R[B]) ~= k) then pc++ */,
+    EqConst /* iABC : if ((R[A] == K[B]) ~= k) then pc++ */,
+    Lt /* iABC : if ((R[A] < R[B]) ~= k) then pc++ */,
+    Le /* iABC : if ((R[A] <= R[B]) ~= k) then pc++ */,
+    Test /* iABC : if (not R[A] == k) then pc++ */,
+    TestSet /* iABC : if (not R[B] == k) then pc++ else R[A] := R[B] */,
+    ForPrep /* iABx : 数值for循环的准备,不需要执行时 pc += Bx + 1 */,
+    ForLoop /* iABx : 数值for循环的步进,需要继续时 pc -= Bx */,
+    TForPrep /* iABx : 泛型for循环的准备,R[A+3]是关闭值(to-be-closed) ; pc += Bx */,
+    TForCall /* iABC : R[A+4], ... ,R[A+3+C] := R[A](R[A+1], R[A+2]) */,
+    TForLoop /* iABx : if R[A+4] ~= nil then { R[A+2] := R[A+4] ; pc -= Bx } */,
+    ExtraArg /* iAx : 为前一条指令提供扩展参数 */,
+}
+
+/** 所有OpCode,按照判别值排列,用于u32 -> OpCode的解码 */
+const OPCODES: [OpCode; 62] = [
+    OpCode::GetUpField,
+    OpCode::LoadConst,
+    OpCode::LoadConstX,
+    OpCode::LoadNil,
+    OpCode::LoadBool,
+    OpCode::LoadFalseSkip,
+    OpCode::LoadInt,
+    OpCode::Call,
+    OpCode::TailCall,
+    OpCode::Return,
+    OpCode::VarArg,
+    OpCode::Closure,
+    OpCode::GetUpval,
+    OpCode::SetUpval,
+    OpCode::Close,
+    OpCode::Tbc,
+    OpCode::Move,
+    OpCode::SetUpFieldConst,
+    OpCode::SetUpField,
+    OpCode::NewTable,
+    OpCode::NewTableConst,
+    OpCode::SetTable,
+    OpCode::SetField,
+    OpCode::SetInt,
+    OpCode::SetTableConst,
+    OpCode::SetFieldConst,
+    OpCode::SetIntConst,
+    OpCode::SetList,
+    OpCode::GetTable,
+    OpCode::GetField,
+    OpCode::GetInt,
+    OpCode::Method,
+    OpCode::Add,
+    OpCode::Sub,
+    OpCode::Mul,
+    OpCode::Div,
+    OpCode::IDiv,
+    OpCode::Mod,
+    OpCode::Pow,
+    OpCode::BAnd,
+    OpCode::BOr,
+    OpCode::BXor,
+    OpCode::Shl,
+    OpCode::Shr,
+    OpCode::Concat,
+    OpCode::Unm,
+    OpCode::BNot,
+    OpCode::Not,
+    OpCode::Len,
+    OpCode::Jmp,
+    OpCode::Eq,
+    OpCode::EqConst,
+    OpCode::Lt,
+    OpCode::Le,
+    OpCode::Test,
+    OpCode::TestSet,
+    OpCode::ForPrep,
+    OpCode::ForLoop,
+    OpCode::TForPrep,
+    OpCode::TForCall,
+    OpCode::TForLoop,
+    OpCode::ExtraArg,
+];
+
+/** ### ByteCode表示字节码
+    不同的字节码表示vm在解析的时候会采取不同的方式来进行解释执行
+
+    所谓解释型语言解释的就是字节码,对字节码进行解释,然后执行.
+    每条字节码固定是一个u32,各参数的位宽在构造时检查,超出位宽时返回错误而不是静默截断
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ByteCode(u32);
+
+/** 参数超出位宽时的编译错误 : 寄存器和参数个数用A/B/C , 跳转用sBx/sJ , 常量索引用Ax */
+const REGS_ERROR: &str = "function or expression needs too many registers";
+const JUMP_ERROR: &str = "control structure too long";
+const CONST_ERROR: &str = "too many constants";
+
+/** 检查参数位宽 , 超出时返回错误信息 , 由语法分析报告为编译错误 */
+fn check_arg(v: usize, max: usize, err: &'static str) -> Result<u32, &'static str> {
+    if v > max {
+        return Err(err);
+    }
+    return Ok(v as u32);
+}
+
+impl ByteCode {
+    /** iABC格式 */
+    pub fn abc(op: OpCode, a: usize, b: usize, c: usize) -> Result<Self, &'static str> {
+        return Self::abck(op, a, b, c, false);
+    }
+
+    /** iABC格式,带k标志位 */
+    pub fn abck(op: OpCode, a: usize, b: usize, c: usize, k: bool) -> Result<Self, &'static str> {
+        let a = check_arg(a, MAXARG_A, REGS_ERROR)?;
+        let b = check_arg(b, MAXARG_B, REGS_ERROR)?;
+        let c = check_arg(c, MAXARG_C, REGS_ERROR)?;
+        return Ok(ByteCode(
+            (op as u32) | (a << POS_A) | ((k as u32) << POS_K) | (b << POS_B) | (c << POS_C)
+        ));
+    }
+
+    /** iABx格式 */
+    pub fn abx(op: OpCode, a: usize, bx: usize) -> Result<Self, &'static str> {
+        let a = check_arg(a, MAXARG_A, REGS_ERROR)?;
+        let bx = check_arg(bx, MAXARG_BX, JUMP_ERROR)?;
+        return Ok(ByteCode((op as u32) | (a << POS_A) | (bx << POS_BX)));
+    }
+
+    /** iAsBx格式 */
+    pub fn asbx(op: OpCode, a: usize, sbx: i64) -> Result<Self, &'static str> {
+        if !fits_sbx(sbx) {
+            return Err(JUMP_ERROR);
+        }
+        return Self::abx(op, a, (sbx + OFFSET_SBX) as usize);
+    }
+
+    /** iAx格式 */
+    pub fn ax(op: OpCode, ax: usize) -> Result<Self, &'static str> {
+        let ax = check_arg(ax, MAXARG_AX, CONST_ERROR)?;
+        return Ok(ByteCode((op as u32) | (ax << POS_AX)));
+    }
+
+    /** isJ格式 */
+    pub fn sj(op: OpCode, sj: i64) -> Result<Self, &'static str> {
+        if !(-OFFSET_SJ..=(MAXARG_AX as i64) - OFFSET_SJ).contains(&sj) {
+            return Err(JUMP_ERROR);
+        }
+        return Self::ax(op, (sj + OFFSET_SJ) as usize);
+    }
+
+    pub fn op(self) -> OpCode {
+        return OPCODES[(self.0 & ((1 << SIZE_OP) - 1)) as usize];
+    }
+    pub fn a(self) -> usize {
+        return ((self.0 >> POS_A) as usize) & MAXARG_A;
+    }
+    pub fn b(self) -> usize {
+        return ((self.0 >> POS_B) as usize) & MAXARG_B;
+    }
+    pub fn c(self) -> usize {
+        return ((self.0 >> POS_C) as usize) & MAXARG_C;
+    }
+    pub fn k(self) -> bool {
+        return (self.0 >> POS_K) & 1 == 1;
+    }
+    pub fn bx(self) -> usize {
+        return ((self.0 >> POS_BX) as usize) & MAXARG_BX;
+    }
+    pub fn sbx(self) -> i64 {
+        return (self.bx() as i64) - OFFSET_SBX;
+    }
+    pub fn ax_arg(self) -> usize {
+        return ((self.0 >> POS_AX) as usize) & MAXARG_AX;
+    }
+    pub fn sj_arg(self) -> i64 {
+        return (self.ax_arg() as i64) - OFFSET_SJ;
+    }
+
+    /* 语法分析回填跳转/寄存器时使用的修改操作 */
+    pub fn set_a(&mut self, a: usize) -> Result<(), &'static str> {
+        let a = check_arg(a, MAXARG_A, REGS_ERROR)?;
+        self.0 = (self.0 & !((MAXARG_A as u32) << POS_A)) | (a << POS_A);
+        return Ok(());
+    }
+    pub fn set_k(&mut self, k: bool) {
+        self.0 = (self.0 & !(1 << POS_K)) | ((k as u32) << POS_K);
+    }
+    pub fn set_c(&mut self, c: usize) -> Result<(), &'static str> {
+        let c = check_arg(c, MAXARG_C, REGS_ERROR)?;
+        self.0 = (self.0 & !((MAXARG_C as u32) << POS_C)) | (c << POS_C);
+        return Ok(());
+    }
+    pub fn set_b(&mut self, b: usize) -> Result<(), &'static str> {
+        let b = check_arg(b, MAXARG_B, REGS_ERROR)?;
+        self.0 = (self.0 & !((MAXARG_B as u32) << POS_B)) | (b << POS_B);
+        return Ok(());
+    }
+    pub fn set_bx(&mut self, bx: usize) -> Result<(), &'static str> {
+        let bx = check_arg(bx, MAXARG_BX, JUMP_ERROR)?;
+        self.0 = (self.0 & !((MAXARG_BX as u32) << POS_BX)) | (bx << POS_BX);
+        return Ok(());
+    }
+    pub fn set_sj(&mut self, sj: i64) -> Result<(), &'static str> {
+        *self = Self::sj(self.op(), sj)?;
+        return Ok(());
+    }
+}
+
+/** 按照 luac -l 的风格打印字节码 */
+impl fmt::Debug for ByteCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = self.op();
+        match op {
+            OpCode::LoadConst |
+            OpCode::LoadConstX |
+            OpCode::NewTableConst |
+            OpCode::Closure |
+            OpCode::Tbc |
+            OpCode::ForPrep |
+            OpCode::ForLoop |
+            OpCode::TForPrep |
+            OpCode::TForLoop => write!(f, "{:?}({}, {})", op, self.a(), self.bx()),
+            OpCode::Jmp => write!(f, "{:?}({})", op, self.sj_arg()),
+            OpCode::LoadInt => write!(f, "{:?}({}, {})", op, self.a(), self.sbx()),
+            OpCode::LoadNil | OpCode::LoadFalseSkip | OpCode::Close => write!(f, "{:?}({})", op, self.a()),
+            OpCode::ExtraArg => write!(f, "{:?}({})", op, self.ax_arg()),
+            OpCode::LoadBool |
+            OpCode::TailCall |
+            OpCode::Return |
+            OpCode::GetUpval |
+            OpCode::SetUpval |
+            OpCode::Move |
+            OpCode::Unm |
+            OpCode::BNot |
+            OpCode::Not |
+            OpCode::Len => write!(f, "{:?}({}, {})", op, self.a(), self.b()),
+            OpCode::Test => write!(f, "{:?}({}, {})", op, self.a(), self.k()),
+            OpCode::Eq | OpCode::EqConst | OpCode::Lt | OpCode::Le =>
+                write!(f, "{:?}({}, {}, {})", op, self.a(), self.b(), self.k()),
+            OpCode::TestSet =>
+                write!(f, "{:?}({}, {}, {})", op, self.a(), self.b(), self.k()),
+            OpCode::VarArg | OpCode::TForCall => write!(f, "{:?}({}, {})", op, self.a(), self.c()),
+            OpCode::SetList | OpCode::Call => write!(f, "{:?}({}, {}, {})", op, self.a(), self.b(), self.c()),
+            _ if self.k() => write!(f, "{:?}({}, {}, K{})", op, self.a(), self.b(), self.c()),
+            _ => write!(f, "{:?}({}, {}, {})", op, self.a(), self.b(), self.c()),
+        }
+    }
+}
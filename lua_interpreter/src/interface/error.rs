@@ -0,0 +1,72 @@
+use std::fmt;
+
+use super::Value;
+
+/** ### 运行时错误
+    Lua的错误可以是任意值(error({code=1})),所以错误本身就是一个Value ,
+    字符串错误在抛出时已经带上了位置信息
+ */
+#[derive(Clone, Debug)]
+pub struct LuaError {
+    pub value: Value,
+    pub kind: ErrorKind,
+}
+
+/** 错误的种类 : yield借用错误的传播路径离开Rust函数 , 但是不能被pcall捕获 ;
+    超出运行限制也不能被脚本捕获 , 一直传播到宿主
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    Runtime,
+    Yield /* 协程挂起 , value是传出的值的个数 */,
+    Interrupt /* 超出指令数上限、截止时间或者被宿主中断 */,
+}
+
+impl LuaError {
+    pub fn new(value: impl Into<Value>) -> Self {
+        return LuaError { value: value.into(), kind: ErrorKind::Runtime };
+    }
+
+    /** 挂起协程 : 栈顶的n个值传给resume */
+    pub fn yielding(n: usize) -> Self {
+        return LuaError { value: Value::Integer(n as i64), kind: ErrorKind::Yield };
+    }
+
+    /** 中断执行 : pcall、coroutine.resume等都不捕获 */
+    pub fn interrupt(value: impl Into<Value>) -> Self {
+        return LuaError { value: value.into(), kind: ErrorKind::Interrupt };
+    }
+
+    pub fn is_yield(&self) -> bool {
+        return self.kind == ErrorKind::Yield;
+    }
+
+    pub fn is_interrupt(&self) -> bool {
+        return self.kind == ErrorKind::Interrupt;
+    }
+}
+
+impl From<String> for LuaError {
+    fn from(msg: String) -> Self {
+        return LuaError::new(msg);
+    }
+}
+
+impl From<&str> for LuaError {
+    fn from(msg: &str) -> Self {
+        return LuaError::new(msg.to_string());
+    }
+}
+
+/** 和lua.c一致 : 非字符串(数字也算字符串)的错误对象只显示类型 */
+impl fmt::Display for LuaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match &self.value {
+            v @ (Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_) | Value::Integer(_) | Value::Float(_)) =>
+                write!(f, "{v}"),
+            v => write!(f, "(error object is a {} value)", v.ty()),
+        };
+    }
+}
+
+impl std::error::Error for LuaError {}
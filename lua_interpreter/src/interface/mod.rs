@@ -1,10 +1,43 @@
 pub mod table;
+pub mod byte_code;
+pub mod arith;
+pub mod error;
+pub mod number;
+pub mod userdata;
 
-use std::{ fmt::{ self }, rc::Rc, cell::RefCell, hash::Hash, mem };
+pub use byte_code::{ ByteCode, OpCode };
+pub use error::{ LuaError, ErrorKind };
+
+use std::{ fmt::{ self }, rc::{ Rc, Weak }, cell::RefCell, hash::Hash };
 const SHORT_STR_MAX: usize = 14; // sizeof(一个Value的对齐长度(Value类型的大小是2个字节)) - 1(Enum的tag长度) - 1(用于表示string的len)
 const MID_STR_MAX: usize = 48 - 1; // 48(预估的中等字符串长度,对齐) - 1(用于表示string的len)
 
-use crate::vm;
+use crate::{ vm, parse::FunctionProto };
+
+/** Rust实现的函数 : 参数在栈上,返回值push到栈顶,返回返回值的个数 */
+pub type RustFunction = fn(&mut vm::ExeState) -> Result<i32, LuaError>;
+
+/** Rust闭包 : 捕获的环境就是Upvalue , 调用时会修改环境所以是FnMut ;
+    外面再套一层Box让Rc保持为1个word
+ */
+pub type RustClosure = Rc<RefCell<Box<dyn FnMut(&mut vm::ExeState) -> Result<i32, LuaError>>>>;
+
+/** ### Upvalue
+    外层函数还在执行时,被捕获的局部变量还在栈上,Upvalue只记录栈的绝对位置 ;
+    外层函数返回(或者局部变量离开作用域)时,把栈上的值搬进来,之后所有引用它的闭包共享这一份值 ;
+    每个协程有自己的栈,所以还要记录是哪个线程的栈
+ */
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize, Weak<RefCell<vm::LuaThread>>) /* 所属线程的栈上的绝对位置 */,
+    Closed(Value),
+}
+
+/** Lua闭包 : 函数原型 + 捕获的Upvalue */
+pub struct LuaClosure {
+    pub proto: Rc<FunctionProto>,
+    pub upvalues: RefCell<Vec<Rc<RefCell<Upvalue>>>> /* debug.upvaluejoin可以替换其中的Upvalue */,
+}
 
 /** 用于区分是constant取值操作还是stack取值 */
 pub enum ConstStack {
@@ -12,34 +45,6 @@ pub enum ConstStack {
     Stack(usize),
 }
 
-/** ### ByteCode表示字节码
-    不同的字节码表示vm在解析的时候会采取不同的方式来进行解释执行
-    
-    所谓解释型语言解释的就是字节码,对字节码进行解释,然后执行
- */
-#[derive(Debug)]
-pub enum ByteCode {
-    GetGlobal(u8, u8) /* <获取>装载好的全局变量/函数 : 入栈位置|变量名在constants的index   */,
-    LoadConst(u8, u8) /* <存储>全局变量 : 入栈位置|变量名在constants的index */,
-    LoadNil(u8) /* 存储nil : 入栈位置 */,
-    LoadBool(u8, bool) /* 存储boolean : 入栈位置|bool情况 */,
-    LoadInt(u8, i64) /* 存储int : 入栈位置|int情况 */,
-    Call(u8, u8) /* 函数调用 : 函数在栈的位置|参数个数 */,
-    Move(u8, u8) /* 数据移动,表示数据从调用栈(后)|移向(前)进行替代的行为 
-    局部变量通过栈索引访问，而全局变量要实时查找全局变量表，也 就是Move和GetGlobal这两个字节码的区别 */,
-    SetGlobalConst(u8, u8) /* 设置全局常量 : 常量名|常量位置 */,
-    SetGlobal(u8, u8) /* 设置全局变量 : 常量位置|入栈位置  */,
-    SetGlobalGlobal(u8, u8) /* 设置全局字面量 */,
-    NewTable(u8, u8, u8) /* 新建一个table : 入栈位置|数组部分长度|Hash部分长度  */,
-    SetTable(u8, u8, u8) /* <栈> 获取数据生成table : table入栈位置|key|value  */,
-    SetField(u8, u8, u8) /* <栈> 设置字符串常量 : table入栈位置|key|value  */,
-    SetInt(u8, u8, u8) /* <栈> 设置字符串常量 : table入栈位置|key|value */,
-    SetTableConst(u8, u8, u8) /* <常量表> 获取数据生成table : table入栈位置|key|value  */,
-    SetFieldConst(u8, u8, u8) /* <常量表> 设置字符串常量 : table入栈位置|key|value   */,
-    SetIntConst(u8, u8, u8) /* <常量表> 设置字符串常量 : table入栈位置|key|value */,
-    SetList(u8, u8) /* 把array插入到table上 : table入栈位置|array长度  */,
-}
-
 /** ### Value表示lua支持的值 */
 #[derive(Clone)]
 pub enum Value {
@@ -47,11 +52,64 @@ pub enum Value {
     Boolean(bool) /* Boolean */,
     Integer(i64) /* Integer */,
     Float(f64) /* Float */,
-    Function(fn(&mut vm::ExeState) -> i32) /* Function */,
+    RustFunction(RustFunction) /* Rust函数 */,
+    LuaFunction(Rc<LuaClosure>) /* Lua闭包 */,
+    RustClosure(RustClosure) /* Rust闭包 */,
     ShortStr(u8, [u8; SHORT_STR_MAX]) /* 短长度字符串,长度为 SHORT_STR_MAX */,
     MidStr(Rc<(u8, [u8; MID_STR_MAX])>) /* 中等长度字符串,长度为 MID_STR_MAX */,
     LongStr(Rc<Vec<u8>>) /* 不限制长度字符串 */,
     Table(Rc<RefCell<table::Table>>) /* Table */,
+    UserData(Rc<RefCell<userdata::UserData>>) /* Rust数据 */,
+    Thread(Rc<RefCell<vm::LuaThread>>) /* 协程 */,
+}
+
+impl Value {
+    /** 类型名,用于报错信息 */
+    pub fn ty(&self) -> &'static str {
+        return match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Integer(_) | Value::Float(_) => "number",
+            Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_) => "string",
+            Value::Table(_) => "table",
+            Value::UserData(_) => "userdata",
+            Value::Thread(_) => "thread",
+            Value::RustFunction(_) | Value::LuaFunction(_) | Value::RustClosure(_) => "function",
+        };
+    }
+
+    /** 用Rust闭包创建函数 */
+    pub fn closure(f: impl FnMut(&mut vm::ExeState) -> Result<i32, LuaError> + 'static) -> Self {
+        return Value::RustClosure(Rc::new(RefCell::new(Box::new(f))));
+    }
+
+    /** 是否是函数 */
+    pub fn is_function(&self) -> bool {
+        return matches!(self, Value::RustFunction(_) | Value::LuaFunction(_) | Value::RustClosure(_));
+    }
+
+    /** 真值判断 : 只有nil和false是假 */
+    pub fn truthy(&self) -> bool {
+        return !matches!(self, Value::Nil | Value::Boolean(false));
+    }
+
+    /** 是否是字符串 */
+    pub fn is_str(&self) -> bool {
+        return matches!(self, Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_));
+    }
+
+    /** 引用类型的地址,用于tostring等显示 */
+    pub fn address(&self) -> *const u8 {
+        return match self {
+            Value::Table(t) => Rc::as_ptr(t) as *const u8,
+            Value::LuaFunction(f) => Rc::as_ptr(f) as *const u8,
+            Value::RustFunction(f) => *f as *const u8,
+            Value::RustClosure(c) => Rc::as_ptr(c) as *const u8,
+            Value::UserData(u) => Rc::as_ptr(u) as *const u8,
+            Value::Thread(t) => Rc::as_ptr(t) as *const u8,
+            _ => std::ptr::null(),
+        };
+    }
 }
 
 /* 实现字符串的自动转换 */
@@ -66,6 +124,11 @@ impl From<&[u8]> for Value {
         return vec_to_short_mid_str(value).unwrap_or(Value::LongStr(Rc::new(value.to_vec())));
     }
 }
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        return value.as_bytes().into();
+    }
+}
 impl From<String> for Value {
     fn from(value: String) -> Self {
         /* 先实现Vec<u8>,再实现String就是顺便的事情 */
@@ -100,7 +163,7 @@ impl From<&Value> for String {
             Value::ShortStr(len, buf) => String::from_utf8_lossy(&buf[..*len as usize]).to_string(),
             /* 抽象中的抽象! &s.1[..s.0 as usize] */
             Value::MidStr(s) => String::from_utf8_lossy(&s.1[..s.0 as usize]).to_string(),
-            Value::LongStr(s) => String::from_utf8_lossy(&s).to_string(),
+            Value::LongStr(s) => String::from_utf8_lossy(s).to_string(),
             _ => panic!("不支持的转化类型"),
         };
     }
@@ -110,7 +173,7 @@ impl<'a> From<&'a Value> for &'a str {
         return match value {
             Value::ShortStr(len, buf) => std::str::from_utf8(&buf[..*len as usize]).unwrap(),
             Value::MidStr(s) => std::str::from_utf8(&s.1[..s.0 as usize]).unwrap(),
-            Value::LongStr(s) => std::str::from_utf8(&s).unwrap(),
+            Value::LongStr(s) => std::str::from_utf8(s).unwrap(),
             _ => panic!("不支持的转化类型"),
         };
     }
@@ -133,7 +196,9 @@ impl fmt::Debug for Value {
             Value::Boolean(b) => write!(f, "{b}"),
             Value::Integer(i) => write!(f, "{i}"),
             Value::Float(n) => write!(f, "{n:?}"),
-            Value::Function(_) => write!(f, "function"),
+            Value::RustFunction(_) => write!(f, "builtin"),
+            Value::LuaFunction(_) => write!(f, "function"),
+            Value::RustClosure(_) => write!(f, "closure"),
             Value::ShortStr(len, buf) => {
                 let str = String::from_utf8_lossy(&buf[..*len as usize]).to_string();
                 write!(f, "{str}")
@@ -150,6 +215,8 @@ impl fmt::Debug for Value {
                 let t = t.borrow(); /* borrow获取不可变引用 : 对RefCell 进行解包 */
                 write!(f, "table : len {} - {}", t.array.len(), t.map.len())
             }
+            Value::UserData(_) => write!(f, "userdata"),
+            Value::Thread(_) => write!(f, "thread"),
         }
     }
 }
@@ -160,13 +227,16 @@ impl fmt::Display for Value {
             Value::Nil => write!(f, "nil"),
             Value::Boolean(b) => write!(f, "{b}"),
             Value::Integer(i) => write!(f, "{i}"),
-            Value::Float(n) => write!(f, "{n:?}"),
+            Value::Float(n) => write!(f, "{}", number::float_to_string(*n)),
             Value::ShortStr(len, buf) =>
                 write!(f, "{}", String::from_utf8_lossy(&buf[..*len as usize])),
             Value::MidStr(s) => write!(f, "{}", String::from_utf8_lossy(&s.1[..s.0 as usize])),
-            Value::LongStr(s) => write!(f, "{}", String::from_utf8_lossy(&s)),
-            Value::Table(t) => write!(f, "table: {:?}", Rc::as_ptr(t)),
-            Value::Function(_) => write!(f, "function"),
+            Value::LongStr(s) => write!(f, "{}", String::from_utf8_lossy(s)),
+            Value::Table(_) => write!(f, "table: {:?}", self.address()),
+            Value::UserData(_) => write!(f, "userdata: {:?}", self.address()),
+            Value::Thread(_) => write!(f, "thread: {:?}", self.address()),
+            Value::RustFunction(_) | Value::RustClosure(_) => write!(f, "function: builtin: {:?}", self.address()),
+            Value::LuaFunction(_) => write!(f, "function: {:?}", self.address()),
         }
     }
 }
@@ -178,7 +248,16 @@ impl PartialEq for Value {
             (Self::Boolean(l0), Self::Boolean(r0)) => *l0 == *r0,
             (Self::Integer(l0), Self::Integer(r0)) => *l0 == *r0,
             (Self::Float(l0), Self::Float(r0)) => *l0 == *r0,
-            (Self::Function(l0), Self::Function(r0)) => std::ptr::eq(l0, r0),
+            (Self::RustFunction(l0), Self::RustFunction(r0)) => std::ptr::fn_addr_eq(*l0, *r0),
+            (Self::LuaFunction(l0), Self::LuaFunction(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::RustClosure(l0), Self::RustClosure(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::Table(l0), Self::Table(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::UserData(l0), Self::UserData(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::Thread(l0), Self::Thread(r0)) => Rc::ptr_eq(l0, r0),
+            (
+                Self::ShortStr(..) | Self::MidStr(_) | Self::LongStr(_),
+                Self::ShortStr(..) | Self::MidStr(_) | Self::LongStr(_),
+            ) => <&[u8]>::from(self) == <&[u8]>::from(other),
             _ => false,
         }
     }
@@ -193,11 +272,15 @@ impl Hash for Value {
             Value::Nil => () /* Nil不能作为table key */,
             Value::Boolean(b) => b.hash(state),
             Value::Integer(i) => i.hash(state),
-            Value::Float(f) => unsafe {
+            Value::Float(f) => {
                 /* Rust 中的浮点类型 f32 和 f64 都支持 NaN。 然而由于NaN之间是不相等的,所以不同的NaN获取.hash()值不想等,所以不满足hash()的定义(即相同的数据获取的hash值是相等的),因此不实现.hash() */
-                mem::transmute::<f64, i64>(*f).hash(state) /* 进行不安全的类型转化 */
+                f.to_bits().hash(state) /* 按位转化成u64 */
             }
-            Value::Function(f) => f.hash(state),
+            Value::RustFunction(f) => f.hash(state),
+            Value::LuaFunction(f) => Rc::as_ptr(f).hash(state),
+            Value::RustClosure(c) => Rc::as_ptr(c).hash(state),
+            Value::UserData(u) => Rc::as_ptr(u).hash(state),
+            Value::Thread(t) => Rc::as_ptr(t).hash(state),
             Value::ShortStr(l, b) => b[0..*l as usize].hash(state),
             Value::MidStr(s) => s.1[0..s.0 as usize].hash(state),
             Value::LongStr(v) => v.hash(state),
@@ -291,3 +374,78 @@ pub enum Token {
     Concat /* .. */,
     Dots /* ... */,
 }
+
+/** Token在源代码中的写法 , 用于语法错误信息 */
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Token::Name(name) => name,
+            Token::String(s) => {
+                return write!(f, "\"{}\"", String::from_utf8_lossy(s));
+            }
+            Token::Integer(i) => {
+                return write!(f, "{i}");
+            }
+            Token::Float(n) => {
+                return write!(f, "{}", number::float_to_string(*n));
+            }
+            Token::Eos => "<eof>",
+            Token::And => "and",
+            Token::Break => "break",
+            Token::Do => "do",
+            Token::Else => "else",
+            Token::Elseif => "elseif",
+            Token::End => "end",
+            Token::False => "false",
+            Token::For => "for",
+            Token::Function => "function",
+            Token::Goto => "goto",
+            Token::If => "if",
+            Token::In => "in",
+            Token::Local => "local",
+            Token::Nil => "nil",
+            Token::Not => "not",
+            Token::Or => "or",
+            Token::Repeat => "repeat",
+            Token::Return => "return",
+            Token::Then => "then",
+            Token::True => "true",
+            Token::Until => "until",
+            Token::While => "while",
+            Token::Add => "+",
+            Token::Sub => "-",
+            Token::Mul => "*",
+            Token::Div => "/",
+            Token::Mod => "%",
+            Token::Pow => "^",
+            Token::Len => "#",
+            Token::BitAnd => "&",
+            Token::BitXor => "~",
+            Token::BitOr => "|",
+            Token::ShiftL => "<<",
+            Token::ShiftR => ">>",
+            Token::Idiv => "//",
+            Token::Equal => "==",
+            Token::NotEq => "~=",
+            Token::LesEq => "<=",
+            Token::GreEq => ">=",
+            Token::Less => "<",
+            Token::Greater => ">",
+            Token::Assign => "=",
+            Token::ParL => "(",
+            Token::ParR => ")",
+            Token::CurlyL => "{",
+            Token::CurlyR => "}",
+            Token::SqurL => "[",
+            Token::SqurR => "]",
+            Token::DoubColon => "::",
+            Token::SemiColon => ";",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Concat => "..",
+            Token::Dots => "...",
+        };
+        return write!(f, "{s}");
+    }
+}
@@ -0,0 +1,94 @@
+/* ### 数字转换成字符串
+    和C语言printf的%e/%f/%g/%a输出完全一致 ,
+    Value的Display(也就是tostring)和string.format都使用这里的实现
+ */
+
+/** float转换成字符串 : 和Lua一样使用"%.14g" , 看起来像整数时加上".0" */
+pub fn float_to_string(x: f64) -> String {
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    if !x.is_finite() {
+        return format!("{sign}{}", if x.is_nan() { "nan" } else { "inf" });
+    }
+    let mut s = format!("{sign}{}", fmt_g(x.abs(), 14, false));
+    if s.bytes().all(|c| c == b'-' || c.is_ascii_digit()) {
+        s.push_str(".0");
+    }
+    return s;
+}
+
+/** %f : 非负有限数 */
+pub fn fmt_f(x: f64, precision: usize, alt: bool) -> String {
+    let mut s = format!("{x:.precision$}");
+    if alt && precision == 0 {
+        s.push('.');
+    }
+    return s;
+}
+
+/** %e : 非负有限数 , 指数至少2位并且带符号 */
+pub fn fmt_e(x: f64, precision: usize, alt: bool) -> String {
+    let s = format!("{x:.precision$e}");
+    let (mantissa, exp) = s.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let dot = if alt && precision == 0 { "." } else { "" };
+    let sign = if exp < 0 { '-' } else { '+' };
+    return format!("{mantissa}{dot}e{sign}{:02}", exp.abs());
+}
+
+/** %g : 非负有限数 , 根据指数选择%e或者%f , 非替代形式时去掉末尾的0 */
+pub fn fmt_g(x: f64, precision: usize, alt: bool) -> String {
+    let p = if precision == 0 { 1 } else { precision };
+    /* 按%e舍入之后的指数 */
+    let e = format!("{x:.prec$e}", prec = p - 1);
+    let exp: i32 = e.split_once('e').unwrap().1.parse().unwrap();
+    let mut s = if exp < -4 || exp >= (p as i32) {
+        fmt_e(x, p - 1, alt)
+    } else {
+        fmt_f(x, ((p as i32) - 1 - exp) as usize, alt)
+    };
+    if !alt {
+        /* 去掉小数部分末尾的0 , 以及多余的小数点 */
+        let (num, exp) = match s.find('e') {
+            Some(i) => s.split_at(i),
+            None => (s.as_str(), ""),
+        };
+        let num = if num.contains('.') { num.trim_end_matches('0').trim_end_matches('.') } else { num };
+        s = format!("{num}{exp}");
+    }
+    return s;
+}
+
+/** %a : 非负有限数 , 十六进制的尾数和二进制指数 ; 没有precision时输出精确值 , 否则按偶数舍入 */
+pub fn fmt_a(x: f64, precision: Option<usize>, alt: bool) -> String {
+    let bits = x.to_bits();
+    let biased = ((bits >> 52) & 0x7ff) as i32;
+    let mut mantissa = bits & ((1 << 52) - 1);
+    let (mut lead, exp) = match (biased, mantissa) {
+        (0, 0) => (0u64, 0),
+        (0, _) => (0, -1022) /* 非规格化数 */,
+        _ => (1, biased - 1023),
+    };
+    let digits = match precision {
+        None => {
+            let s = format!("{mantissa:013x}");
+            s.trim_end_matches('0').to_string()
+        }
+        Some(p) if p < 13 => {
+            let shift = (13 - p) * 4;
+            let full = (lead << 52) | mantissa;
+            let rem = full & ((1 << shift) - 1);
+            let half = 1 << (shift - 1);
+            let mut r = full >> shift;
+            if rem > half || (rem == half && r & 1 == 1) {
+                r += 1;
+            }
+            lead = r >> (p * 4);
+            mantissa = r & ((1 << (p * 4)) - 1);
+            if p == 0 { String::new() } else { format!("{mantissa:0p$x}") }
+        }
+        Some(p) => format!("{mantissa:013x}{}", "0".repeat(p - 13)),
+    };
+    let dot = if !digits.is_empty() || alt { "." } else { "" };
+    let sign = if exp < 0 { '-' } else { '+' };
+    return format!("0x{lead}{dot}{digits}p{sign}{}", exp.abs());
+}
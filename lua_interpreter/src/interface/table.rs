@@ -1,28 +1,254 @@
-use std::collections::HashMap;
+use std::{ collections::HashMap, rc::Rc, cell::RefCell };
 
 use crate::exp_desc::ExpDesc;
 
-use super::{ Value, ByteCode };
+use super::{ Value, OpCode, arith::float_to_int };
 
 /** 数据结构:
     对外表现为统一的散列表，其索引可以是数字、字符串、或者除了Nil和Nan以外的其他所有Value类型。但为了性能考虑，对于数字类型又有特殊的处理，即使用数组来存储连续数字索引的项。
  */
+#[derive(Clone)]
 pub struct Table {
     pub array: Vec<Value>,
-    pub map: HashMap<Value, Value>,
+    pub map: OrderedMap,
+    pub metatable: Option<Rc<RefCell<Table>>> /* 元表 */,
 }
 
 impl Table {
     pub fn new(array_len: usize, hm_len: usize) -> Self {
         return Table {
             array: Vec::with_capacity(array_len),
-            map: HashMap::with_capacity(hm_len),
+            map: OrderedMap::with_capacity(hm_len),
+            metatable: None,
         };
     }
+
+    /** t[key] */
+    pub fn get(&self, key: &Value) -> Value {
+        return match key {
+            Value::Integer(i) => self.get_int(*i),
+            /* 有整数值的float作为key时等价于对应的整数 */
+            Value::Float(f) =>
+                match float_to_int(*f) {
+                    Some(i) => self.get_int(i),
+                    None => self.map.get(key).cloned().unwrap_or(Value::Nil),
+                }
+            _ => self.map.get(key).cloned().unwrap_or(Value::Nil),
+        };
+    }
+
+    /** t[i] : 先查数组部分 */
+    pub fn get_int(&self, i: i64) -> Value {
+        if i >= 1 && (i as usize) <= self.array.len() {
+            return self.array[(i as usize) - 1].clone();
+        }
+        return self.map.get(&Value::Integer(i)).cloned().unwrap_or(Value::Nil);
+    }
+
+    /** t[key] = value , 赋值为nil表示删除 */
+    pub fn set(&mut self, key: Value, value: Value) {
+        match key {
+            Value::Integer(i) => self.set_int(i, value),
+            Value::Float(f) =>
+                match float_to_int(f) {
+                    Some(i) => self.set_int(i, value),
+                    None if f.is_nan() => panic!("table index is NaN"),
+                    None => self.set_map(key, value),
+                }
+            Value::Nil => panic!("table index is nil"),
+            _ => self.set_map(key, value),
+        }
+    }
+
+    /** t[i] = value : 紧接数组末尾的key会追加到数组部分,并把散列部分中后续连续的key也迁移过来 */
+    pub fn set_int(&mut self, i: i64, value: Value) {
+        let len = self.array.len();
+        if i >= 1 && (i as usize) <= len {
+            self.array[(i as usize) - 1] = value;
+            /* 数组末尾不保留nil */
+            while let Some(Value::Nil) = self.array.last() {
+                self.array.pop();
+            }
+        } else if i >= 1 && (i as usize) == len + 1 {
+            if let Value::Nil = value {
+                self.map.remove(&Value::Integer(i));
+                return;
+            }
+            self.map.remove(&Value::Integer(i));
+            self.array.push(value);
+            /* 迁移散列部分中的后续key */
+            let mut next = i + 1;
+            while let Some(v) = self.map.remove(&Value::Integer(next)) {
+                self.array.push(v);
+                next += 1;
+            }
+        } else {
+            self.set_map(Value::Integer(i), value);
+        }
+    }
+
+    fn set_map(&mut self, key: Value, value: Value) {
+        if let Value::Nil = value {
+            self.map.remove(&key);
+        } else {
+            self.map.insert(key, value);
+        }
+    }
+
+    /** next(t, key) : key之后的下一个键值对 , 先遍历数组部分再按插入顺序遍历散列部分 ;
+        key不在table中时报错
+     */
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, String> {
+        let key = match key {
+            Value::Float(f) => float_to_int(*f).map_or(key.clone(), Value::Integer),
+            _ => key.clone(),
+        };
+        let ihash = match &key {
+            Value::Nil => {
+                return Ok(self.next_from(0));
+            }
+            Value::Integer(i) if *i >= 1 && (*i as usize) <= self.array.len() => {
+                return Ok(self.next_from(*i as usize));
+            }
+            _ =>
+                match self.map.position(&key) {
+                    Some(pos) => pos + 1,
+                    /* 遍历过程中数组末尾被赋值为nil而缩短了 : 数组部分已经遍历完 */
+                    None if matches!(key, Value::Integer(i) if i >= 1) => 0,
+                    None => {
+                        return Err(String::from("invalid key to 'next'"));
+                    }
+                }
+        };
+        return Ok(self.map.next_from(ihash));
+    }
+
+    /** 从数组部分的第i个位置(从0开始)开始查找下一个非nil的项 */
+    fn next_from(&self, i: usize) -> Option<(Value, Value)> {
+        for (j, v) in self.array.iter().enumerate().skip(i) {
+            if !matches!(v, Value::Nil) {
+                return Some((Value::Integer((j as i64) + 1), v.clone()));
+            }
+        }
+        return self.map.next_from(0);
+    }
+
+    /** 没有任何元素 */
+    pub fn is_empty(&self) -> bool {
+        return self.array.is_empty() && self.map.is_empty();
+    }
+
+    /** #t : 返回一个边界(border) */
+    pub fn len(&self) -> i64 {
+        let mut n = self.array.len() as i64;
+        while self.map.contains_key(&Value::Integer(n + 1)) {
+            n += 1;
+        }
+        return n;
+    }
+}
+
+/** ### 散列部分
+    按插入顺序保存键值对,使得next可以从任意一个key继续遍历 ;
+    删除时只把值置为nil(墓碑),保留key的位置,这样遍历过程中给已有字段赋值(包括赋值为nil)不会打乱顺序 ,
+    插入新key时如果墓碑过多才整理一次(Lua语义中遍历时插入新key本来就是未定义行为)
+ */
+#[derive(Clone, Default)]
+pub struct OrderedMap {
+    index: HashMap<Value, usize> /* key在entries中的位置 */,
+    entries: Vec<(Value, Value)>,
+    dead: usize /* 墓碑的个数 */,
+}
+
+impl OrderedMap {
+    pub fn with_capacity(n: usize) -> Self {
+        return OrderedMap {
+            index: HashMap::with_capacity(n),
+            entries: Vec::with_capacity(n),
+            dead: 0,
+        };
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        return match self.index.get(key) {
+            Some(&i) if !matches!(self.entries[i].1, Value::Nil) => Some(&self.entries[i].1),
+            _ => None,
+        };
+    }
+
+    pub fn contains_key(&self, key: &Value) -> bool {
+        return self.get(key).is_some();
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) {
+        if let Some(&i) = self.index.get(&key) {
+            if let Value::Nil = self.entries[i].1 {
+                self.dead -= 1;
+            }
+            self.entries[i].1 = value;
+            return;
+        }
+        if self.dead > 0 && self.dead * 2 >= self.entries.len() {
+            self.compact();
+        }
+        self.index.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+    }
+
+    /** 删除 : 留下墓碑 */
+    pub fn remove(&mut self, key: &Value) -> Option<Value> {
+        let i = *self.index.get(key)?;
+        let old = std::mem::replace(&mut self.entries[i].1, Value::Nil);
+        if let Value::Nil = old {
+            return None;
+        }
+        self.dead += 1;
+        return Some(old);
+    }
+
+    /** 有效键值对的个数 */
+    pub fn len(&self) -> usize {
+        return self.entries.len() - self.dead;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /** key在遍历顺序中的位置 , 墓碑也有位置 */
+    fn position(&self, key: &Value) -> Option<usize> {
+        return self.index.get(key).copied();
+    }
+
+    /** 从第i个位置开始查找下一个有效的键值对 */
+    fn next_from(&self, i: usize) -> Option<(Value, Value)> {
+        return self.entries[i.min(self.entries.len())..]
+            .iter()
+            .find(|(_, v)| !matches!(v, Value::Nil))
+            .cloned();
+    }
+
+    /** 按顺序遍历有效的键值对 */
+    pub fn iter(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        return self.entries
+            .iter()
+            .filter(|(_, v)| !matches!(v, Value::Nil))
+            .map(|(k, v)| (k, v));
+    }
+
+    /** 清除墓碑 */
+    fn compact(&mut self) {
+        self.entries.retain(|(_, v)| !matches!(v, Value::Nil));
+        self.index.clear();
+        for (i, (k, _)) in self.entries.iter().enumerate() {
+            self.index.insert(k.clone(), i);
+        }
+        self.dead = 0;
+    }
 }
 
 pub enum TableEntry {
-    /* 枚举在rust中可以被看作是一个函数类型 , 因此 ByteCode::SetTable就可以看做是一个函数，其参数类型就是fn(u8,u8,u8)->ByteCode --> 所以可以使用一个 <function sign> 来表示一个 <enum shape> */
-    Map((fn(u8, u8, u8) -> ByteCode, fn(u8, u8, u8) -> ByteCode, usize)),
+    /* (value在栈上时的OpCode , value是常量时的OpCode , key) */
+    Map((OpCode, OpCode, usize)),
     Array(ExpDesc),
 }
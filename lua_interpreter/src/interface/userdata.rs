@@ -0,0 +1,23 @@
+use std::{ any::Any, rc::Rc, cell::RefCell };
+
+use super::table::Table;
+
+/** ### UserData
+    把任意的Rust数据放进Lua , 它的行为(索引、方法、tostring等)全部由元表决定 ;
+    虚拟机没有垃圾回收 , 最后一个引用消失时Rust的Drop就相当于__gc
+ */
+pub struct UserData {
+    pub data: Box<dyn Any>,
+    pub metatable: Option<Rc<RefCell<Table>>>,
+}
+
+impl UserData {
+    pub fn new(data: impl Any, metatable: Option<Rc<RefCell<Table>>>) -> Self {
+        return UserData { data: Box::new(data), metatable };
+    }
+
+    /** 取出指定类型的数据 , 类型不一致时为None */
+    pub fn downcast_mut<T: Any>(&mut self) -> Option<&mut T> {
+        return self.data.downcast_mut::<T>();
+    }
+}
@@ -1,25 +1,41 @@
 use std::{ io::{ Read, Bytes }, mem, iter::Peekable };
 
-use crate::interface::Token;
+use crate::interface::{ Token, LuaError, Value, arith };
 
 /** 词法解析模块 : 将解析到string 转化成相应的Token */
 #[derive(Debug)]
 pub struct Lex<R: Read> {
     input: Peekable<Bytes<R>> /* 将file变成Bytes以满足迭代需要 */,
     ahead: Token /* 后一个字段 */,
+    line: usize /* 当前读到的行号 */,
+    ahead_line: usize /* ahead所在的行号 */,
+    last_line: usize /* 最近一次next()返回的Token所在的行号,用于生成字节码的行号信息 */,
+    chunk: String /* 报错信息中的代码块名 */,
 }
 
 impl<R: Read> Lex<R> {
-    pub fn new(input: R) -> Self {
-        return Lex { input: input.bytes().peekable(), ahead: Token::Eos };
-    } /* new()基于输入文件创建语法分析器 */
+    #[allow(clippy::unbuffered_bytes)] /* 调用方负责传入BufReader */
+    pub fn new(input: R, chunk: String) -> Self {
+        return Lex {
+            input: input.bytes().peekable(),
+            ahead: Token::Eos,
+            line: 1,
+            ahead_line: 1,
+            last_line: 1,
+            chunk,
+        };
+    } /* new()基于输入文件创建语法分析器 , chunk是报错时显示的代码块名 */
 
     /* 返回下一个Token,并且进行移动 */
-    pub fn next(&mut self) -> Token {
+    #[allow(clippy::should_implement_trait)] /* 和peek配对使用,不需要实现Iterator */
+    pub fn next(&mut self) -> Result<Token, LuaError> {
         if self.ahead == Token::Eos {
-            return self.do_next();
+            let token = self.do_next()?;
+            self.last_line = self.line;
+            return Ok(token);
         } else {
-            return mem::replace(&mut self.ahead, Token::Eos);
+            self.last_line = self.ahead_line;
+            return Ok(mem::replace(&mut self.ahead, Token::Eos));
             //mem::replace(&mut self.ahead, Token::Eos)的作用类同于 Option::take() :
             //将 Token::Eos赋值给self.ahead并且返回self.ahead
             //用于处理peek情况下获取的ahead数据作为next()数据,减少循环次数,增强性能
@@ -27,21 +43,45 @@ impl<R: Read> Lex<R> {
     }
 
     /** 返回下一个Token,但是没有移动效果 */
-    pub fn peek(&mut self) -> &Token {
+    pub fn peek(&mut self) -> Result<&Token, LuaError> {
         /* 为什么返回 &Token而不是 Token : 因为Token的所有者还是属于Lex,并不做所有权转移,同时避免使用clone增加性能开销 */
         if self.ahead == Token::Eos {
-            self.ahead = self.do_next();
+            self.ahead = self.do_next()?;
+            self.ahead_line = self.line;
         }
-        return &self.ahead;
+        return Ok(&self.ahead);
+    }
+
+    /** 最近一次next()返回的Token所在的行号 */
+    pub fn line(&self) -> usize {
+        return self.last_line;
+    }
+
+    /** 语法错误 : "代码块名:行号: 错误信息" */
+    pub fn error(&self, msg: &str) -> LuaError {
+        return LuaError::new(format!("{}:{}: {}", self.chunk, self.line, msg));
+    }
+
+    /** 出现在token附近的语法错误 */
+    pub fn error_near(&self, msg: &str, token: &Token) -> LuaError {
+        return self.error(&format!("{} near {}", msg, near(token)));
+    }
+
+    /** 出现在下一个Token附近的语法错误 , 读取下一个Token出错时返回那个错误 */
+    pub fn error_ahead(&mut self, msg: &str) -> LuaError {
+        if let Err(e) = self.peek() {
+            return e;
+        }
+        return self.error(&format!("{} near {}", msg, near(&self.ahead)));
     }
 
     /** do_next()返回下一个Token */
-    fn do_next(&mut self) -> Token {
+    fn do_next(&mut self) -> Result<Token, LuaError> {
         /* 直接读取u8 */
-        if let Some(ch) = self.next_byte() {
+        if let Some(ch) = self.next_byte()? {
             let token = match ch {
                 b'\0' => Token::Eos,
-                b' ' | b'\r' | b'\n' | b'\t' => self.do_next(),
+                b' ' | b'\r' | b'\n' | b'\t' | 0x0b | 0x0c => self.do_next()?,
                 b'+' => Token::Add,
                 b'*' => Token::Mul,
                 b'%' => Token::Mod,
@@ -53,59 +93,76 @@ impl<R: Read> Lex<R> {
                 b')' => Token::ParR,
                 b'{' => Token::CurlyL,
                 b'}' => Token::CurlyR,
-                b'[' => Token::SqurL,
+                b'[' => self.read_square()?,
                 b']' => Token::SqurR,
                 b';' => Token::SemiColon,
                 b',' => Token::Comma,
-                b'/' => self.check_ahead(b'/', Token::Idiv, Token::Div),
-                b'=' => self.check_ahead(b'=', Token::Equal, Token::Assign),
-                b'~' => self.check_ahead(b'=', Token::NotEq, Token::BitXor),
-                b':' => self.check_ahead(b':', Token::DoubColon, Token::Colon),
-                b'<' => self.check_ahead2(b'=', Token::LesEq, b'<', Token::ShiftL, Token::Less),
-                b'>' => self.check_ahead2(b'=', Token::GreEq, b'>', Token::ShiftR, Token::Greater),
-                b'\'' | b'"' => self.read_string(ch),
-                b'A'..=b'Z' | b'a'..=b'z' | b'_' => self.read_name(ch),
-                b'0'..=b'9' => self.read_number(ch),
-                b'.' => self.read_dot(),
-                b'-' => self.read_sub(),
-                _ => panic!("语法出错/不支持语法"),
+                b'/' => self.check_ahead(b'/', Token::Idiv, Token::Div)?,
+                b'=' => self.check_ahead(b'=', Token::Equal, Token::Assign)?,
+                b'~' => self.check_ahead(b'=', Token::NotEq, Token::BitXor)?,
+                b':' => self.check_ahead(b':', Token::DoubColon, Token::Colon)?,
+                b'<' => self.check_ahead2(b'=', Token::LesEq, b'<', Token::ShiftL, Token::Less)?,
+                b'>' => self.check_ahead2(b'=', Token::GreEq, b'>', Token::ShiftR, Token::Greater)?,
+                b'\'' | b'"' => self.read_string(ch)?,
+                b'A'..=b'Z' | b'a'..=b'z' | b'_' => self.read_name(ch)?,
+                b'0'..=b'9' => self.read_number(ch)?,
+                b'.' => self.read_dot()?,
+                b'-' => self.read_sub()?,
+                ch if ch.is_ascii_graphic() => {
+                    return Err(self.error(&format!("unexpected symbol near '{}'", ch as char)));
+                }
+                ch => {
+                    return Err(self.error(&format!("unexpected symbol near '<\\{ch}>'")));
+                }
             };
 
-            return token;
+            return Ok(token);
         } else {
-            Token::Eos
+            return Ok(Token::Eos);
         }
     }
 
     /** 读取一个char : 利用bytes的迭代器特性轻松获取 */
-    fn read_char(&mut self) -> char {
+    fn read_char(&mut self) -> Result<char, LuaError> {
         /* self.input.next() 是消耗型的 */
-        return match self.input.next() {
-            Some(Ok(ch)) => ch as char,
-            Some(_) => panic!("读取到错误字节"),
-            None => '\0',
+        return match self.next_byte()? {
+            Some(ch) => Ok(ch as char),
+            None => Ok('\0'),
         };
     }
 
     /** 读取字符串(单字符串和双字符串) */
-    fn read_string(&mut self, quote: u8) -> Token {
+    fn read_string(&mut self, quote: u8) -> Result<Token, LuaError> {
         let mut s = Vec::new();
         loop {
-            match self.next_byte().expect("string 读取错误") {
-                b'\n' => panic!("string 未完成"),
-                b'\\' => s.push(self.read_escape()),
-                byt if byt == quote => {
+            match self.next_byte()? {
+                None => {
+                    return Err(self.error_near("unfinished string", &Token::Eos));
+                }
+                Some(b'\n') => {
+                    /* 换行已经计入了行号 , 报告字符串所在的行 */
+                    let msg = format!("unfinished string near '{}'", unfinished(quote, &s));
+                    return Err(LuaError::new(format!("{}:{}: {}", self.chunk, self.line - 1, msg)));
+                }
+                Some(b'\\') => {
+                    let byt = self.read_escape(quote, &s)?;
+                    s.push(byt);
+                }
+                Some(byt) if byt == quote => {
                     /* 字符串中止 */ break;
                 }
-                byt => s.push(byt),
+                Some(byt) => s.push(byt),
             }
         }
-        return Token::String(s);
+        return Ok(Token::String(s));
     }
 
-    /** 读取转义字符 */
-    fn read_escape(&mut self) -> u8 {
-        match self.next_byte().expect("转义失败") {
+    /** 读取转义字符 , 出错时给出已经读到的字符串 : quote和s */
+    fn read_escape(&mut self, quote: u8, s: &[u8]) -> Result<u8, LuaError> {
+        let Some(ch) = self.next_byte()? else {
+            return Err(self.error_near("unfinished string", &Token::Eos));
+        };
+        let byt = match ch {
             b'a' => 0x07,
             b'b' => 0x08,
             b'f' => 0x0c,
@@ -116,37 +173,56 @@ impl<R: Read> Lex<R> {
             b'\\' => b'\\',
             b'"' => b'"',
             b'\'' => b'\'',
+            b'\n' => b'\n' /* 反斜杠加换行 : 字符串中的换行 */,
             b'x' => {
                 // format: \xXX
-                let n1 = char::to_digit(self.next_byte().unwrap() as char, 16).unwrap();
-                let n2 = char::to_digit(self.next_byte().unwrap() as char, 16).unwrap();
-                (n1 * 16 + n2) as u8
+                let mut n = 0;
+                let mut text = format!("{}\\x", unfinished(quote, s));
+                for _ in 0..2 {
+                    let ch = self.peek_byte()? as char;
+                    let Some(d) = ch.to_digit(16) else {
+                        return Err(self.error(&format!("hexadecimal digit expected near '{text}{ch}'")));
+                    };
+                    self.next_byte()?;
+                    text.push(ch);
+                    n = n * 16 + d;
+                }
+                n as u8
             }
             ch @ b'0'..=b'9' => {
                 // format: \d[d[d]]
-                let mut n = char::to_digit(ch as char, 10).unwrap(); // TODO no unwrap
-                if let Some(d) = char::to_digit(self.peek_byte() as char, 10) {
-                    self.next_byte();
-                    n = n * 10 + d;
-                    if let Some(d) = char::to_digit(self.peek_byte() as char, 10) {
-                        self.next_byte();
-                        n = n * 10 + d;
+                let mut n = (ch - b'0') as u32;
+                for _ in 0..2 {
+                    match char::to_digit(self.peek_byte()? as char, 10) {
+                        Some(d) => {
+                            self.next_byte()?;
+                            n = n * 10 + d;
+                        }
+                        None => break,
+                    }
+                }
+                match u8::try_from(n) {
+                    Ok(byt) => byt,
+                    Err(_) => {
+                        return Err(self.error(&format!("decimal escape too large near '{}\\{n}'", unfinished(quote, s))));
                     }
                 }
-                u8::try_from(n).expect("decimal escape too large")
             }
-            _ => panic!("未识别的转义字符"),
-        }
+            ch => {
+                return Err(self.error(&format!("invalid escape sequence near '{}\\{}'", unfinished(quote, s), ch as char)));
+            }
+        };
+        return Ok(byt);
     }
 
     /** 读取变量名 和 关键字 必须是char格式数据 */
-    fn read_name(&mut self, first: u8) -> Token {
+    fn read_name(&mut self, first: u8) -> Result<Token, LuaError> {
         let mut s = String::new();
         s.push(first as char); /* 变量名 */
         loop {
-            let ch = self.peek_byte() as char;
-            if ch.is_alphanumeric() || ch == '_' {
-                self.next_byte();
+            let ch = self.peek_byte()? as char;
+            if ch.is_ascii_alphanumeric() || ch == '_' {
+                self.next_byte()?;
                 s.push(ch);
             } else {
                 break;
@@ -154,7 +230,7 @@ impl<R: Read> Lex<R> {
         }
 
         /* 关键字匹配 */
-        match &s as &str {
+        let token = match &s as &str {
             // TODO optimize by hash
             "and" => Token::And,
             "break" => Token::Break,
@@ -179,132 +255,201 @@ impl<R: Read> Lex<R> {
             "until" => Token::Until,
             "while" => Token::While,
             _ => Token::Name(s),
-        }
+        };
+        return Ok(token);
     }
 
-    /** 判断下一个Token是否预期,否则panic */
-    pub fn expect(&mut self, token: Token) {
-        assert_eq!(self.next(), token)
+    /** 判断下一个Token是否预期,否则返回语法错误 */
+    pub fn expect(&mut self, token: Token) -> Result<(), LuaError> {
+        let t = self.next()?;
+        if t != token {
+            return Err(self.error_near(&format!("'{token}' expected"), &t));
+        }
+        return Ok(());
     }
 
-    /** 读取数字 */
-    fn read_number(&mut self, num: u8) -> Token {
-        /* 0开头可能是16进制数字 */
-        if num == b'0' {
-            let second = self.peek_byte();
+    /** 读取数字 : 和Lua官方的read_numeral一样 , 先宽松地读入整个数字 , 再按数字字面量的语法转换 ;
+        字符串转数字(arith::str_to_number)使用同一套语法 , 十进制整数溢出时是浮点数 , 十六进制整数溢出时回绕
+     */
+    fn read_number(&mut self, first: u8) -> Result<Token, LuaError> {
+        let mut text = vec![first];
+        let mut expo = [b'e', b'E'];
+        if first == b'0' {
+            let second = self.peek_byte()?;
             if second == b'x' || second == b'X' {
-                return self.read_heximal();
+                self.next_byte()?;
+                text.push(second);
+                expo = [b'p', b'P'];
             }
         }
-        /* 不是16进制继往下走 */
-        let mut n = (num - b'0') as i64; /* 进行类型转化 */
         loop {
-            let ch = self.peek_byte();
-            if let Some(x) = char::to_digit(ch as char, 10) {
-                self.next_byte(); /* 原来的数乘10再加到个位上 */
-                n = n * 10 + (x as i64);
-            } else if ch == b'.' {
-                return self.read_number_fraction(n); /* 解析小数值 */
-            } else if ch == b'e' || ch == b'E' {
-                return self.read_number_exp(n as f64); /* 解析科学计数法 */
+            let ch = self.peek_byte()?;
+            if expo.contains(&ch) {
+                /* 指数 : 后面可以有正负号 */
+                self.next_byte()?;
+                text.push(ch);
+                let sign = self.peek_byte()?;
+                if sign == b'+' || sign == b'-' {
+                    self.next_byte()?;
+                    text.push(sign);
+                }
+            } else if ch.is_ascii_hexdigit() || ch == b'.' {
+                self.next_byte()?;
+                text.push(ch);
             } else {
-                break; /* 解析结束 */
+                break;
             }
         }
-
-        /* 处理Integer情况 */
-        let fch = self.peek_byte() as char;
-        if fch.is_alphabetic() || fch == '.' {
-            panic!("数字格式不合法!");
+        /* 数字后面紧跟字母时把它也读进来 , 报告数字格式错误 */
+        let ch = self.peek_byte()?;
+        if ch.is_ascii_alphabetic() || ch == b'_' {
+            self.next_byte()?;
+            text.push(ch);
         }
-        return Token::Integer(n);
+        return match arith::str_to_number(&text) {
+            Some(Value::Integer(i)) => Ok(Token::Integer(i)),
+            Some(Value::Float(f)) => Ok(Token::Float(f)),
+            _ => Err(self.error(&format!("malformed number near '{}'", String::from_utf8_lossy(&text)))),
+        };
     }
-    /** 解析小数值 */
-    fn read_number_fraction(&mut self, i: i64) -> Token {
-        /* 先把小数按整数算,最后再除于进的位然后加上原始量 */
-        let mut n: i64 = 0;
-        let mut f: f64 = 1.0;
-        loop {
-            let ch = self.peek_byte() as char;
-            if let Some(d) = char::to_digit(ch, 10) {
-                self.next_byte();
-                n = n * 10 + (d as i64);
-                f = f * 10.0;
-            } else {
-                break;
-            }
+    /** 读取减号 */
+    fn read_sub(&mut self) -> Result<Token, LuaError> {
+        if self.peek_byte()? == b'-' {
+            self.next_byte()?;
+            self.read_comment()?;
+            return self.do_next();
+        } else {
+            return Ok(Token::Sub);
         }
-        return Token::Float((i as f64) + (n as f64) / f);
     }
-    /** 解析16进制数字 */
-    fn read_heximal(&mut self) -> Token {
-        let mut hex_string = String::new();
+    /** 读取注释 : "--[==["开头的是长注释 , 其他的都是到行尾为止的短注释 */
+    fn read_comment(&mut self) -> Result<(), LuaError> {
+        if self.peek_byte()? == b'[' {
+            self.next_byte()?;
+            if let (level, true) = self.skip_sep()? {
+                self.read_long_string(level, "comment")?;
+                return Ok(());
+            }
+        }
+        // line comment
         loop {
-            let ch = self.peek_byte() as char;
-            if let Some(_) = char::to_digit(ch, 10) {
-                hex_string.push(ch);
-                self.next_byte();
-            } else {
+            let ch = self.read_char()?;
+            if ch == '\n' || ch == '\0' {
                 break;
             }
         }
-        return Token::Integer(
-            i64::from_str_radix(hex_string.as_str(), 16).expect("16进制数字解析失败")
-        );
+        return Ok(());
     }
-    /** 解析科学计数法 */
-    fn read_number_exp(&mut self, _f: f64) -> Token {
-        todo!("lex number exp")
+
+    /** 读取左方括号 : "[=*["是长字符串的开始 , 单独的'['是索引 */
+    fn read_square(&mut self) -> Result<Token, LuaError> {
+        return match self.skip_sep()? {
+            (level, true) => Ok(Token::String(self.read_long_string(level, "string")?)),
+            (0, false) => Ok(Token::SqurL),
+            (level, false) => {
+                let msg = format!("invalid long string delimiter near '[{}'", "=".repeat(level));
+                Err(self.error(&msg))
+            }
+        };
     }
-    /** 读取减号 */
-    fn read_sub(&mut self) -> Token {
-        if self.peek_byte() == b'-' {
-            self.next_byte();
-            self.read_comment();
-            return self.next();
-        } else {
-            return Token::Sub;
+
+    /** 已经读过'[' , 跳过后面的'=' : 返回'='的个数 , 以及后面是不是紧跟着第二个'['(是的话一并读掉) */
+    fn skip_sep(&mut self) -> Result<(usize, bool), LuaError> {
+        let mut level = 0;
+        while self.peek_byte()? == b'=' {
+            self.next_byte()?;
+            level += 1;
+        }
+        if self.peek_byte()? == b'[' {
+            self.next_byte()?;
+            return Ok((level, true));
         }
+        return Ok((level, false));
     }
-    fn read_comment(&mut self) {
-        match self.read_char() {
-            '[' => todo!("long comment"),
-            _ => {
-                // line comment
-                loop {
-                    let ch = self.read_char();
-                    if ch == '\n' || ch == '\0' {
-                        break;
+
+    /** 读取level级的长字符串/长注释直到对应的"]=*]" : 紧跟在开头的换行被忽略 , 其中的换行(\r\n , \n\r , \r)都变成\n ;
+        what是报错信息中的"string"或者"comment"
+     */
+    fn read_long_string(&mut self, level: usize, what: &str) -> Result<Vec<u8>, LuaError> {
+        let start = self.line;
+        let mut s = Vec::new();
+        if matches!(self.peek_byte()?, b'\r' | b'\n') {
+            self.read_newline()?;
+        }
+        loop {
+            match self.next_byte()? {
+                None => {
+                    let msg = format!("unfinished long {what} (starting at line {start})");
+                    return Err(self.error_near(&msg, &Token::Eos));
+                }
+                Some(b']') => {
+                    let mut n = 0;
+                    while n < level && self.peek_byte()? == b'=' {
+                        self.next_byte()?;
+                        n += 1;
+                    }
+                    if n == level && self.peek_byte()? == b']' {
+                        self.next_byte()?;
+                        return Ok(s);
                     }
+                    /* 不是结束标记 : ']'和'='都是内容 , 下一个字符可能是新的']'所以不读取 */
+                    s.push(b']');
+                    s.resize(s.len() + n, b'=');
                 }
+                Some(ch @ (b'\r' | b'\n')) => {
+                    self.finish_newline(ch)?;
+                    s.push(b'\n');
+                }
+                Some(byt) => s.push(byt),
             }
         }
     }
+
+    /** 读取一个换行 , 两个字符组成的换行(\r\n或者\n\r)一起读掉 */
+    fn read_newline(&mut self) -> Result<(), LuaError> {
+        if let Some(ch) = self.next_byte()? {
+            self.finish_newline(ch)?;
+        }
+        return Ok(());
+    }
+
+    /** 已经读到换行符ch : 读掉和它组成一个换行的另一个字符 , 单独的\r也算一行 */
+    fn finish_newline(&mut self, ch: u8) -> Result<(), LuaError> {
+        let other = if ch == b'\n' { b'\r' } else { b'\n' };
+        if self.peek_byte()? == other {
+            self.next_byte()?;
+        } else if ch == b'\r' {
+            self.line += 1;
+        }
+        return Ok(());
+    }
     /** 判断下一个char是否达预期,如果是返回long,如果不是返回short,并且不进行步进 */
-    fn check_ahead(&mut self, ahear: u8, long: Token, short: Token) -> Token {
-        if self.peek_byte() == ahear {
-            self.next_byte();
-            long
+    fn check_ahead(&mut self, ahear: u8, long: Token, short: Token) -> Result<Token, LuaError> {
+        if self.peek_byte()? == ahear {
+            self.next_byte()?;
+            return Ok(long);
         } else {
-            short
+            return Ok(short);
         }
     }
     /** 读取句号 */
-    fn read_dot(&mut self) -> Token {
-        match self.read_char() {
-            '.' => {
-                if self.peek_byte() == b'.' {
-                    self.next_byte();
-                    return Token::Dots; /* 三个省略号 */
+    fn read_dot(&mut self) -> Result<Token, LuaError> {
+        /* 只peek不消费,单纯的句号后面的字符属于下一个Token */
+        match self.peek_byte()? {
+            b'.' => {
+                self.next_byte()?;
+                if self.peek_byte()? == b'.' {
+                    self.next_byte()?;
+                    return Ok(Token::Dots); /* 三个省略号 */
                 } else {
-                    return Token::Concat; /* 两个省略号 */
+                    return Ok(Token::Concat); /* 两个省略号 */
                 }
             }
-            '0'..='9' => {
-                return self.read_number_fraction(0); /* 小数点 */
+            b'0'..=b'9' => {
+                return self.read_number(b'.'); /* 小数点开头的数字 */
             }
             _ => {
-                return Token::Dot; /* 单纯句号 */
+                return Ok(Token::Dot); /* 单纯句号 */
             }
         }
     }
@@ -316,45 +461,67 @@ impl<R: Read> Lex<R> {
         ahead2: u8,
         long2: Token,
         short: Token
-    ) -> Token {
-        let ch = self.peek_byte();
+    ) -> Result<Token, LuaError> {
+        let ch = self.peek_byte()?;
         if ch == ahead1 {
-            self.next_byte();
-            long1
+            self.next_byte()?;
+            return Ok(long1);
         } else if ch == ahead2 {
-            self.next_byte();
-            long2
+            self.next_byte()?;
+            return Ok(long2);
         } else {
-            short
+            return Ok(short);
         }
     }
 
-    /** 文件读取回溯,用于read_char消费char的反悔 */
+    // 文件读取回溯,用于read_char消费char的反悔
     // fn putback_char(&mut self) {
     //     self.input.seek(SeekFrom::Current(-1)).unwrap();
     // }
 
-    /**  */
-    fn _peek_char(&mut self) -> char {
+    /** peek look a byte */
+    fn peek_byte(&mut self) -> Result<u8, LuaError> {
         /* self.input.peek() 是非消耗型的 */
         return match self.input.peek() {
-            Some(Ok(c)) => *c as char,
-            Some(_) => panic!("错误的类型引用"),
-            None => '\0',
+            Some(Ok(byt)) => Ok(*byt),
+            Some(Err(_)) => Err(self.read_error()),
+            None => Ok(b'\0'), // good for usage
         };
     }
 
-    /** peek look a byte */
-    fn peek_byte(&mut self) -> u8 {
-        return match self.input.peek() {
-            Some(Ok(byt)) => *byt,
-            Some(_) => panic!("lex peek error"),
-            None => b'\0', // good for usage
+    /** read next byte  in consume */
+    fn next_byte(&mut self) -> Result<Option<u8>, LuaError> {
+        let byt = match self.input.next() {
+            Some(Ok(byt)) => Some(byt),
+            Some(Err(e)) => {
+                return Err(LuaError::new(format!("cannot read {}: {}", self.chunk, e)));
+            }
+            None => None,
         };
+        if byt == Some(b'\n') {
+            self.line += 1;
+        }
+        return Ok(byt);
     }
 
-    /** read next byte  in consume */
-    fn next_byte(&mut self) -> Option<u8> {
-        return self.input.next().and_then(|it| Some(it.unwrap()));
+    /** 读取源代码出错 : 取出peek到的错误 */
+    fn read_error(&mut self) -> LuaError {
+        return match self.input.next() {
+            Some(Err(e)) => LuaError::new(format!("cannot read {}: {}", self.chunk, e)),
+            _ => self.error("read error"),
+        };
     }
 }
+
+/** 错误信息中的Token : 文件结束是<eof> , 其他的加上引号 */
+fn near(token: &Token) -> String {
+    if let Token::Eos = token {
+        return token.to_string();
+    }
+    return format!("'{token}'");
+}
+
+/** 错误信息中没有读完的字符串 : 开始的引号加上已经读到的内容 */
+fn unfinished(quote: u8, s: &[u8]) -> String {
+    return format!("{}{}", quote as char, String::from_utf8_lossy(s));
+}
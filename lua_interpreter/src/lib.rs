@@ -0,0 +1,12 @@
+#![allow(clippy::needless_return)] /* 项目风格 : 函数结尾统一显式return */
+
+/** ### Lua解释器库
+    解析 : parse::ParseProto::load / load_str / load_bytes / load_with -> parse::FunctionProto , 语法错误返回LuaError
+    执行 : vm::ExeState::execute
+ */
+pub mod vm;
+pub mod lex;
+pub mod global;
+pub mod parse;
+pub mod interface;
+pub mod exp_desc;
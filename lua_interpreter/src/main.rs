@@ -1,12 +1,8 @@
-use std::{ env, fs::File, io::BufReader };
+#![allow(clippy::needless_return)] /* 项目风格 : 函数结尾统一显式return */
 
-use crate::parse::ParseProto;
-mod vm;
-mod lex;
-mod global;
-mod parse;
-mod interface;
-mod exp_desc;
+use std::{ env, fs::File, io::{ self, BufReader, Write }, process, rc::Rc };
+
+use lua_interpreter::{ parse::ParseProto, vm::{ self, ExeState }, interface::{ Value, LuaError } };
 
 /** 程序入口,接受一个lua文件地址,然后解释执行 */
 fn main() {
@@ -25,7 +21,41 @@ fn main() {
     4.得出结果
     */
 
-    let file: File = File::open(&args[1]).unwrap(); /* read arg */
-    let proto = ParseProto::load(BufReader::new(file)); /* load file with ParseProto  */
-    vm::ExeState::new().execute(&proto); /* vm execute to result  */
+    let file: File = match File::open(&args[1]) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("lua: cannot open {}: {}", args[1], e);
+            process::exit(1);
+        }
+    }; /* read arg */
+    /* load file with ParseProto , 语法错误和运行时错误一样输出到stderr */
+    let proto = ParseProto::load_chunk(BufReader::new(file), &format!("@{}", args[1]));
+    /* vm execute to result : 没有被捕获的错误输出到stderr */
+    let handler = Value::RustFunction(msg_handler);
+    if let Err(e) = proto.and_then(|proto| vm::ExeState::new().execute_with(Rc::new(proto), handler)) {
+        let _ = io::stdout().flush(); /* exit不会刷新标准输出 */
+        /* 字符串错误直接输出字节 , 不是UTF-8的内容也原样保留 */
+        let msg = if e.value.is_str() { <&[u8]>::from(&e.value).to_vec() } else { e.to_string().into_bytes() };
+        let _ = io::stderr().write_all(&[b"lua: ", &msg[..], b"\n"].concat());
+        process::exit(1);
+    }
+}
+
+/** 和lua.c的msghandler一样 : 在出错的位置给错误信息加上调用链 ; 不是字符串的错误优先使用__tostring的结果 */
+fn msg_handler(state: &mut ExeState) -> Result<i32, LuaError> {
+    let msg = state.get(1);
+    let msg = match msg {
+        Value::Integer(_) | Value::Float(_) => msg.to_string().into_bytes(),
+        msg if msg.is_str() => <&[u8]>::from(&msg).to_vec(),
+        msg if !matches!(state.metamethod(&msg, "__tostring"), Value::Nil) => {
+            let s = state.tostring(&msg)?;
+            state.push(s);
+            return Ok(1);
+        }
+        msg => format!("(error object is a {} value)", msg.ty()).into_bytes(),
+    };
+    let (co, _) = state.running();
+    let tb = state.traceback(&co, 1);
+    state.push([&msg[..], b"\n", tb.as_bytes()].concat());
+    return Ok(1);
 }
@@ -1,420 +1,2349 @@
-use std::io::Read;
+mod reader;
+
+pub use reader::ChunkReader;
+
+use std::{ io::Read, rc::Rc, cell::RefCell, collections::HashMap };
 
 use crate::{
-    interface::{ Value, ByteCode, Token, ConstStack, table::TableEntry },
+    interface::{
+        Value,
+        LuaError,
+        ByteCode,
+        OpCode,
+        Token,
+        ConstStack,
+        arith,
+        table::TableEntry,
+        table::Table,
+        byte_code::{ MAXARG_B, MAXARG_BX, MAXARG_C, NO_REG, FIELDS_PER_FLUSH, fits_sbx },
+    },
     lex::Lex,
     exp_desc::ExpDesc,
 };
 
-/** ### Lua解释器 */
+/** 一元运算的优先级 */
+const UNARY_PRIORITY: i32 = 12;
+/** 单个函数中局部变量的上限 */
+const MAX_LOCALS: usize = 200;
+/** 语句块和表达式嵌套的层数上限 : 递归下降的解析每层都占用Rust的栈 */
+const MAX_SYNTAX_LEVELS: usize = 200;
 
-/** 语法解析模块 : 将Token解析成相应的bytecode */
-pub struct ParseProto<R: Read> {
+/** 常量表的去重key : 和Value的相等比较不同,这里要区分1和1.0,也要区分-0.0和0.0 */
+#[derive(PartialEq, Eq, Hash)]
+enum ConstKey {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Float(u64) /* 按位比较 */,
+    String(Vec<u8>),
+}
+
+impl ConstKey {
+    /** table等不需要去重的常量返回None */
+    fn of(v: &Value) -> Option<ConstKey> {
+        return match v {
+            Value::Nil => Some(ConstKey::Nil),
+            Value::Boolean(b) => Some(ConstKey::Boolean(*b)),
+            Value::Integer(i) => Some(ConstKey::Integer(*i)),
+            Value::Float(f) => Some(ConstKey::Float(f.to_bits())),
+            Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_) =>
+                Some(ConstKey::String(<&[u8]>::from(v).to_vec())),
+            _ => None,
+        };
+    }
+}
+
+/** goto语句或者标签 */
+struct GotoLabel {
+    name: String,
+    line: usize /* 所在的行号 , 用于报错信息 */,
+    icode: usize /* 字节码位置 */,
+    nvar: usize /* 此时局部变量的个数 */,
+    close: bool /* goto跳出了有被捕获的局部变量的语句块,跳转目标处需要关闭Upvalue */,
+}
+
+/** 局部变量的属性 : local name <const> 或者 local name <close> */
+#[derive(Clone, Copy, PartialEq)]
+enum Attrib {
+    Const,
+    Close,
+}
+
+/** 局部变量 */
+struct LocalVar {
+    name: String,
+    captured: bool /* 被内层函数捕获成了Upvalue,离开作用域时需要Close */,
+    attrib: Option<Attrib> /* 有属性的变量都不能赋值 */,
+    value: Option<ExpDesc> /* 初始值是常量的<const>变量 : 编译期常量,引用处直接替换成这个值 */,
+}
+
+/** 局部变量的调试信息 : 在字节码[startpc, endpc)的范围内有效 ;
+    按照定义的顺序排列 , 某条字节码处有效的变量依次对应栈位置0,1,2...
+ */
+#[derive(Debug)]
+pub struct LocVar {
+    pub name: String,
+    pub startpc: usize,
+    pub endpc: usize,
+}
+
+/** Upvalue的来源 : 外层函数的局部变量(栈位置),或者外层函数的Upvalue(索引) */
+#[derive(Debug, Clone, Copy)]
+pub enum UpIndex {
+    Local(usize),
+    Upvalue(usize),
+}
+
+/** ### 语法解析的结果
+    只包含常量表和字节码,和源代码的读取器无关,可以脱离ParseProto单独保存和执行 */
+#[derive(Debug)]
+pub struct FunctionProto {
     pub constants: Vec<Value> /* 常量表 */,
-    pub byte_codes: Vec<ByteCode> /* 字节码表,表示各个模块的调用情况 */,
-    locals: Vec<String> /* 变量表,所有进过 local 定义的变量会在里面 */,
-    lex: Lex<R> /* 词法解析器本器 */,
+    pub byte_codes: Vec<ByteCode> /* 字节码表 */,
+    pub lineinfo: Vec<u32> /* 每条字节码对应的源代码行号 */,
+    pub protos: Vec<Rc<FunctionProto>> /* 内部定义的子函数 */,
+    pub upindexes: Vec<UpIndex> /* 每个Upvalue在外层函数中的来源 */,
+    pub upnames: Vec<String> /* Upvalue的名字 */,
+    pub locvars: Vec<LocVar> /* 局部变量的名字和作用范围 , 用于调试 */,
+    pub nparam: usize /* 固定参数的个数 */,
+    pub is_vararg: bool /* 是否有可变参数 ... */,
+    pub source: Rc<String> /* 代码块名 : "@文件名" , "=名字" 或者源代码本身 */,
+    pub line_defined: usize /* 函数定义所在的行,主代码块为0 */,
+    pub last_line_defined: usize /* 函数结束(end)所在的行,主代码块为0 */,
+}
+
+impl FunctionProto {
+    /** 用于报错信息的代码块名 */
+    pub fn chunk_id(&self) -> String {
+        return chunk_id(&self.source);
+    }
+}
+
+/** 用于报错信息的代码块名,和Lua官方的luaO_chunkid一致 */
+pub fn chunk_id(src: &str) -> String {
+    if let Some(name) = src.strip_prefix('=').or_else(|| src.strip_prefix('@')) {
+        return name.to_string();
+    }
+    /* 源代码本身作为代码块名 : 只取第一行 */
+    let line = src.lines().next().unwrap_or("");
+    if line.len() < src.len() || line.len() > 40 {
+        let end = (0..=line.len().min(40)).rev().find(|i| line.is_char_boundary(*i)).unwrap_or(0);
+        return format!("[string \"{}...\"]", &line[..end]);
+    }
+    return format!("[string \"{line}\"]");
+}
+
+/** 正在解析的一个函数的状态 , 每进入一个函数定义就压入一层 */
+struct FuncState {
+    constants: Vec<Value> /* 常量表 */,
+    const_index: HashMap<ConstKey, usize> /* 常量在常量表中的索引,用于去重 */,
+    byte_codes: Vec<ByteCode> /* 字节码表,表示各个模块的调用情况 */,
+    lineinfo: Vec<u32> /* 字节码对应的行号 */,
+    protos: Vec<Rc<FunctionProto>> /* 子函数 */,
+    upvalues: Vec<(String, UpIndex)> /* Upvalue的名字和来源 */,
+    locals: Vec<LocalVar> /* 变量表,所有进过 local 定义的变量会在里面 */,
+    locvars: Vec<LocVar> /* 所有局部变量的调试信息 , 还在作用域中的变量endpc为usize::MAX */,
+    break_blocks: Vec<Vec<usize>> /* 每层循环中break生成的Jmp位置 */,
+    gotos: Vec<GotoLabel> /* 还没有匹配到标签的goto */,
+    labels: Vec<GotoLabel> /* 当前可见的标签 */,
+    last_target: usize /* 最近一个跳转目标的位置,该位置之前的字节码不能被peephole改写 */,
     sp: usize /* 指向当前栈顶位置 */,
+    nparam: usize,
+    is_vararg: bool,
+    line_defined: usize,
+    last_line_defined: usize,
 }
-impl<R: Read> ParseProto<R> {
-    /** 语法解析 */
-    pub fn load(file: R) -> ParseProto<R> {
-        let mut proto = ParseProto {
+
+impl FuncState {
+    fn new(line_defined: usize) -> Self {
+        return FuncState {
             constants: Vec::new(),
+            const_index: HashMap::new(),
             byte_codes: Vec::new(),
+            lineinfo: Vec::new(),
+            protos: Vec::new(),
+            upvalues: Vec::new(),
             locals: Vec::new(),
-            lex: Lex::new(file),
+            locvars: Vec::new(),
+            break_blocks: Vec::new(),
+            gotos: Vec::new(),
+            labels: Vec::new(),
+            last_target: 0,
             sp: 0,
+            nparam: 0,
+            is_vararg: false,
+            line_defined,
+            last_line_defined: 0,
         };
-        proto.chunk();
-        return proto;
     }
-    /** 执行解析 */
-    pub fn chunk(&mut self) {
+
+    fn into_proto(mut self, source: Rc<String>) -> FunctionProto {
+        let (upnames, upindexes) = self.upvalues.into_iter().unzip();
+        /* 参数和最外层的局部变量一直有效到函数结束 */
+        for var in self.locvars.iter_mut().filter(|v| v.endpc == usize::MAX) {
+            var.endpc = self.byte_codes.len();
+        }
+        return FunctionProto {
+            constants: self.constants,
+            byte_codes: self.byte_codes,
+            lineinfo: self.lineinfo,
+            protos: self.protos,
+            upindexes,
+            upnames,
+            locvars: self.locvars,
+            nparam: self.nparam,
+            is_vararg: self.is_vararg,
+            source,
+            line_defined: self.line_defined,
+            last_line_defined: self.last_line_defined,
+        };
+    }
+}
+
+/** ### Lua解释器
+    语法解析模块 : 将Token解析成相应的bytecode */
+pub struct ParseProto<R: Read> {
+    fs: FuncState /* 当前正在解析的函数 */,
+    enclosing: Vec<FuncState> /* 外层的函数,由内到外依次是栈顶到栈底 */,
+    source: Rc<String> /* 代码块名 */,
+    lex: Lex<R> /* 词法解析器本器 */,
+    level: usize /* 当前语句块和表达式嵌套的层数 */,
+}
+impl<'a> ParseProto<&'a [u8]> {
+    /** 解析内存中的源代码,源代码本身作为代码块名 */
+    pub fn load_bytes(src: &'a [u8]) -> Result<FunctionProto, LuaError> {
+        return Self::load_chunk(src, &String::from_utf8_lossy(src));
+    }
+
+    /** 解析字符串形式的源代码 */
+    pub fn load_str(src: &'a str) -> Result<FunctionProto, LuaError> {
+        return Self::load_chunk(src.as_bytes(), src);
+    }
+}
+
+impl<F: FnMut() -> Option<Vec<u8>>> ParseProto<ChunkReader<F>> {
+    /** 类似lua_load : 通过回调函数一段一段地读取源代码,回调返回None或者空内容表示结束 */
+    pub fn load_with(reader: F) -> Result<FunctionProto, LuaError> {
+        return Self::load_chunk(ChunkReader::new(reader), "=(load)");
+    }
+}
+
+impl<R: Read> ParseProto<R> {
+    /** 语法解析 : 边读取边解析,解析完成后读取器就被释放 */
+    pub fn load(input: R) -> Result<FunctionProto, LuaError> {
+        return Self::load_chunk(input, "=?");
+    }
+
+    /** 指定代码块名的语法解析 , 代码块名用于报错信息 ;
+        语法错误返回"代码块名:行号: 错误信息"形式的字符串错误
+     */
+    pub fn load_chunk(input: R, chunkname: &str) -> Result<FunctionProto, LuaError> {
+        let mut proto = ParseProto {
+            fs: FuncState::new(0),
+            enclosing: Vec::new(),
+            source: Rc::new(chunkname.to_string()),
+            lex: Lex::new(input, chunk_id(chunkname)),
+            level: 0,
+        };
+        proto.chunk()?;
+        return Ok(proto.fs.into_proto(proto.source));
+    }
+
+    /** 执行解析 : 主代码块是一个可变参数的函数 */
+    fn chunk(&mut self) -> Result<(), LuaError> {
+        self.fs.is_vararg = true;
+        /* 主代码块唯一的Upvalue是_ENV , 来源不在任何函数中 , 加载时设置成全局变量表 */
+        self.fs.upvalues.push((String::from("_ENV"), UpIndex::Upvalue(0)));
+        let end_token = self.block_scope()?;
+        self.check_end(end_token, Token::Eos)?;
+        return self.close_function();
+    }
+
+    /** 语句块结束的Token必须是expect */
+    fn check_end(&self, token: Token, expect: Token) -> Result<(), LuaError> {
+        if token != expect {
+            return Err(self.lex.error_near(&format!("'{expect}' expected"), &token));
+        }
+        return Ok(());
+    }
+
+    /** 函数解析结束 : 检查goto,补上最后的Return */
+    fn close_function(&mut self) -> Result<(), LuaError> {
+        if let Some(goto) = self.fs.gotos.first() {
+            let msg = format!("no visible label '{}' for <goto> at line {}", goto.name, goto.line);
+            return Err(self.lex.error(&msg));
+        }
+        let line = self.lex.line();
+        let code = self.check_code(ByteCode::abc(OpCode::Return, 0, 1, 0))?;
+        self.fs.byte_codes.push(code);
+        self.fs.lineinfo.push(line as u32);
+        self.optimize_jumps()?;
+        return Ok(());
+    }
+
+    /** 解析语句块,直到遇到block结束的Token并返回该Token */
+    fn block(&mut self) -> Result<Token, LuaError> {
+        self.enter_level()?;
+        let r = self.statements();
+        self.level -= 1;
+        return r;
+    }
+
+    /** 进入一层语法嵌套 , 和luaE_incCstack一样限制层数 : 层数太多时报语法错误而不是耗尽Rust的栈 */
+    fn enter_level(&mut self) -> Result<(), LuaError> {
+        if self.level >= MAX_SYNTAX_LEVELS {
+            return Err(self.lex.error_ahead("chunk has too many syntax levels"));
+        }
+        self.level += 1;
+        return Ok(());
+    }
+
+    /** 依次解析语句 , 直到遇到block结束的Token */
+    fn statements(&mut self) -> Result<Token, LuaError> {
         loop {
+            self.fs.sp = self.fs.locals.len(); /* 每条语句开始时,栈顶就是局部变量之后的位置 */
             /* 词法解析 */
-            match self.lex.next() {
-                /* Token::name 表示获取到 变量名:可能是局部变量也可能是全局变量;进入下一步判定 */
-                Token::Name(name) => {
-                    if self.lex.peek() == &Token::Assign {
-                        /* 如果下一个Token是等于号 -> 变量赋值 */ self.assignment(name);
+            match self.lex.next()? {
+                Token::SemiColon => (),
+                /* 变量名或者括号开头 : 可能是赋值也可能是函数调用 */
+                t @ (Token::Name(_) | Token::ParL) => self.assign_or_call(t)?,
+                /* 解析local关键字 */
+                Token::Local => {
+                    if self.lex.peek()? == &Token::Function {
+                        self.lex.next()?;
+                        self.local_function()?;
                     } else {
-                        /* 否则就是 ->  函数调用 */ self.function_call(name);
+                        self.local()?;
                     }
                 }
-                /* 解析local关键字 */
-                Token::Local => self.local(),
-                /* 解析结束 */
-                Token::Eos => {
+                Token::Function => self.function_stat()?,
+                Token::If => self.if_stat()?,
+                Token::While => self.while_stat()?,
+                Token::Repeat => self.repeat_stat()?,
+                Token::For => self.for_stat()?,
+                Token::Do => self.do_stat()?,
+                Token::Break => self.break_stat()?,
+                Token::Goto => self.goto_stat()?,
+                Token::DoubColon => self.label_stat()?,
+                /* return只能是语句块的最后一条语句 */
+                Token::Return => {
+                    self.ret_stat()?;
+                    return match self.lex.next()? {
+                        t @ (Token::End | Token::Else | Token::Elseif | Token::Until | Token::Eos) => Ok(t),
+                        t => Err(self.lex.error_near("'<eof>' expected", &t)),
+                    };
+                }
+                /* 语句块结束 */
+                t @ (Token::End | Token::Else | Token::Elseif | Token::Until | Token::Eos) => {
+                    return Ok(t);
+                }
+                t => {
+                    return Err(self.lex.error_near("unexpected symbol", &t));
+                }
+            }
+        }
+    }
+
+    /** 带作用域的语句块 : 结束时清理块内的局部变量和标签,并匹配块内的goto */
+    fn block_scope(&mut self) -> Result<Token, LuaError> {
+        let nvar = self.fs.locals.len();
+        let ilabel = self.fs.labels.len();
+        let igoto = self.fs.gotos.len();
+        let end_token = self.block()?;
+        self.close_block(nvar, ilabel, igoto)?;
+        return Ok(end_token);
+    }
+
+    /** 离开语句块 : 块内有局部变量被捕获时,生成Close把它们搬到Upvalue中 */
+    fn close_block(&mut self, nvar: usize, ilabel: usize, igoto: usize) -> Result<(), LuaError> {
+        let block_end = self.fs.byte_codes.len();
+        let captured = self.fs.locals[nvar..].iter().any(|v| v.captured);
+        let mut i = igoto;
+        while i < self.fs.gotos.len() {
+            let goto = &self.fs.gotos[i];
+            let label = self.fs.labels[ilabel..].iter().find(|l| l.name == goto.name);
+            if let Some(label) = label {
+                /* 位于语句块末尾的标签不受局部变量作用域限制 */
+                if !self.at_block_end(label.icode, block_end) && goto.nvar < label.nvar {
+                    let msg = format!(
+                        "<goto {}> at line {} jumps into the scope of local '{}'",
+                        goto.name,
+                        goto.line,
+                        self.fs.locals[goto.nvar].name
+                    );
+                    return Err(self.lex.error(&msg));
+                }
+                let (icode, target) = (goto.icode, label.icode);
+                self.fix_jump(icode, target)?;
+                self.fs.gotos.remove(i);
+            } else {
+                /* 留给外层语句块匹配,此时已经离开了本块的局部变量 */
+                let goto = &mut self.fs.gotos[i];
+                goto.nvar = goto.nvar.min(nvar);
+                goto.close |= captured;
+                i += 1;
+            }
+        }
+        self.fs.labels.truncate(ilabel);
+        self.remove_locals(nvar);
+        if captured {
+            self.push_code(ByteCode::abc(OpCode::Close, nvar, 0, 0))?;
+        }
+        return Ok(());
+    }
+
+    /** 从from开始的字节码中有没有关闭level及之上的Upvalue , 也就是循环体中有没有被捕获的局部变量 ,
+        有的话循环的出口(break和条件不满足)也需要关闭 */
+    fn closes_upvalue(&self, from: usize, level: usize) -> bool {
+        return self.fs.byte_codes[from..].iter().any(|c| c.op() == OpCode::Close && c.a() >= level);
+    }
+
+    /** 标签后面只有(标签自己生成的)Close时也算在语句块末尾 */
+    fn at_block_end(&self, icode: usize, block_end: usize) -> bool {
+        return icode == block_end ||
+            (icode + 1 == block_end && self.fs.byte_codes[icode].op() == OpCode::Close);
+    }
+
+    /** 赋值语句或者函数调用语句 */
+    fn assign_or_call(&mut self, ahead: Token) -> Result<(), LuaError> {
+        if let Token::Name(name) = &ahead {
+            if self.lex.peek()? == &Token::Assign {
+                self.check_const_name(name)?;
+            }
+        }
+        let (desc, assignable) = self.prefixexp_var(ahead)?;
+        match (self.lex.peek()?, desc) {
+            (Token::Assign, desc) if assignable && is_var(&desc) => {
+                self.lex.next()?;
+                return self.assignment(desc);
+            }
+            /* 函数调用语句 : 不需要返回值,Call的C保持为1 */
+            (_, ExpDesc::Call(_)) => {
+                return Ok(());
+            }
+            _ => {
+                return Err(self.lex.error_ahead("syntax error"));
+            }
+        }
+    }
+
+    /** 添加局部变量 */
+    fn add_local(&mut self, name: String) -> Result<(), LuaError> {
+        if self.fs.locals.len() >= MAX_LOCALS {
+            return Err(self.limit_error(self.enclosing.len(), "local variables", MAX_LOCALS));
+        }
+        let startpc = self.fs.byte_codes.len();
+        self.fs.locvars.push(LocVar { name: name.clone(), startpc, endpc: usize::MAX });
+        self.fs.locals.push(LocalVar { name, captured: false, attrib: None, value: None });
+        return Ok(());
+    }
+
+    /** 离开作用域 , 只保留前nvar个局部变量 : 记录被移除的变量的作用范围到此为止 */
+    fn remove_locals(&mut self, nvar: usize) {
+        let endpc = self.fs.byte_codes.len();
+        let mut n = self.fs.locals.len().saturating_sub(nvar);
+        for var in self.fs.locvars.iter_mut().rev() {
+            if n == 0 {
+                break;
+            }
+            if var.endpc == usize::MAX {
+                var.endpc = endpc;
+                n -= 1;
+            }
+        }
+        self.fs.locals.truncate(nvar);
+    }
+
+    /** 超出限制的错误 : 和Lua官方的信息一致,带上是哪个函数 */
+    fn limit_error(&mut self, level: usize, what: &str, limit: usize) -> LuaError {
+        let line = self.func_state(level).line_defined;
+        let at = if line == 0 { String::from("main function") } else { format!("function at line {line}") };
+        return self.lex.error_ahead(&format!("too many {what} (limit is {limit}) in {at}"));
+    }
+
+    /** function funcname body , funcname : Name {'.' Name} [':' Name] */
+    fn function_stat(&mut self) -> Result<(), LuaError> {
+        let name = self.read_name()?;
+        if !matches!(self.lex.peek()?, Token::Dot | Token::Colon) {
+            self.check_const_name(&name)?;
+        }
+        let mut desc = self.simple_name(name)?;
+        let mut has_self = false;
+        loop {
+            match self.lex.peek()? {
+                Token::Dot => {
+                    self.lex.next()?;
+                    let name = self.read_name()?;
+                    let itable = self.discharge_any(desc)?;
+                    desc = ExpDesc::IndexField(itable, self.add_const(name));
+                }
+                Token::Colon => {
+                    self.lex.next()?;
+                    let name = self.read_name()?;
+                    let itable = self.discharge_any(desc)?;
+                    desc = ExpDesc::IndexField(itable, self.add_const(name));
+                    has_self = true;
+                    break;
+                }
+                _ => break,
+            }
+        }
+        let body = self.function_body(has_self)?;
+        return self.assign(desc, body);
+    }
+
+    /** local function Name body : 先定义局部变量,函数体内就可以递归引用自己 */
+    fn local_function(&mut self) -> Result<(), LuaError> {
+        let name = self.read_name()?;
+        let dst = self.fs.sp;
+        self.add_local(name)?;
+        let body = self.function_body(false)?;
+        return self.discharge(dst, body);
+    }
+
+    /** 函数体 : 参数列表和语句块,解析成子函数原型,返回Closure */
+    fn function_body(&mut self, has_self: bool) -> Result<ExpDesc, LuaError> {
+        let line = self.lex.line();
+        let parent = std::mem::replace(&mut self.fs, FuncState::new(line));
+        self.enclosing.push(parent);
+        if has_self {
+            self.add_local(String::from("self"))?;
+        }
+
+        /* 参数列表 : ( [Name {, Name} [, ...] | ...] ) */
+        self.lex.expect(Token::ParL)?;
+        loop {
+            match self.lex.next()? {
+                Token::Name(name) => {
+                    self.add_local(name)?;
+                    match self.lex.next()? {
+                        Token::Comma => (),
+                        Token::ParR => break,
+                        t => {
+                            return Err(self.lex.error_near("')' expected", &t));
+                        }
+                    }
+                }
+                Token::Dots => {
+                    self.fs.is_vararg = true;
+                    self.lex.expect(Token::ParR)?;
                     break;
                 }
-                /* MayBe is a Table */
-                Token::CurlyL => {
-                    self.table_constructor();
+                Token::ParR => break,
+                t => {
+                    return Err(self.lex.error_near("<name> expected", &t));
+                }
+            }
+        }
+        self.fs.nparam = self.fs.locals.len();
+
+        let end_token = self.block_scope()?;
+        self.check_end(end_token, Token::End)?;
+        self.fs.last_line_defined = self.lex.line();
+        self.close_function()?;
+
+        let parent = self.enclosing.pop().unwrap();
+        let child = std::mem::replace(&mut self.fs, parent);
+        self.fs.protos.push(Rc::new(child.into_proto(self.source.clone())));
+        return Ok(ExpDesc::Closure(self.fs.protos.len() - 1));
+    }
+
+    /** return [explist] [';'] */
+    fn ret_stat(&mut self) -> Result<(), LuaError> {
+        let code = match self.lex.peek()? {
+            Token::SemiColon | Token::End | Token::Else | Token::Elseif | Token::Until | Token::Eos =>
+                ByteCode::abc(OpCode::Return, 0, 1, 0),
+            _ => {
+                let first = self.fs.sp;
+                let desc = self.exp()?;
+                if self.lex.peek()? == &Token::Comma {
+                    self.lex.next()?;
+                    self.discharge(first, desc)?;
+                    let (n, multi) = self.explist_open()?;
+                    ByteCode::abc(OpCode::Return, first, if multi { 0 } else { n + 2 }, 0)
+                } else if let ExpDesc::Call(pc) | ExpDesc::VarArg(pc) = desc {
+                    /* 返回函数调用或者...的全部值 */
+                    self.set_multret(&desc);
+                    let a = self.fs.byte_codes[pc].a();
+                    /* 尾调用 : 离开函数时没有要关闭的to-be-closed变量才可以提前释放当前函数的帧 */
+                    if matches!(desc, ExpDesc::Call(_)) && !self.fs.locals.iter().any(|v| v.attrib == Some(Attrib::Close)) {
+                        let b = self.fs.byte_codes[pc].b();
+                        self.fs.byte_codes[pc] = self.check_code(ByteCode::abc(OpCode::TailCall, a, b, 0))?;
+                    }
+                    ByteCode::abc(OpCode::Return, a, 0, 0)
+                } else {
+                    /* 单个返回值 : 局部变量不需要搬到栈顶 */
+                    let src = self.discharge_any(desc)?;
+                    ByteCode::abc(OpCode::Return, src, 2, 0)
                 }
-                _ => panic!("unexpected token") /* 读取进行报警 */,
             }
+        };
+        if self.lex.peek()? == &Token::SemiColon {
+            self.lex.next()?;
         }
+        self.push_code(code)?;
+        return Ok(());
     }
 
-    /** 创建table : 由于table初始化的步骤不止一步所以返回ExpDesc代表一个需要中间处理的过程(经典包一层) */
-    fn table_constructor(&mut self) -> ExpDesc {
-        let index = self.sp as u8;
-        self.sp += 1; // 更新sp，后续语句如需临时变量，则使用表后面的栈位置
+    /** 创建table : 由于table初始化的步骤不止一步所以返回ExpDesc代表一个需要中间处理的过程(经典包一层)
+        所有的key和value都是常量时,在编译期直接构造好table作为常量,运行时用一条NewTableConst拷贝一份即可
+     */
+    fn table_constructor(&mut self) -> Result<ExpDesc, LuaError> {
+        let index = self.reserve_reg()?; // 更新sp，后续语句如需临时变量，则使用表后面的栈位置
+        let (icode, nconst) = (self.fs.byte_codes.len(), self.fs.constants.len());
         /* Token解析 : 所以只要负责push相应ByteCode即可 */
-        let bidx = self.byte_codes.len();
-        self.byte_codes.push(ByteCode::NewTable(index, 0, 0)); /*长度是随时要变的 */
+        self.push_code(ByteCode::abc(OpCode::NewTable, index, 0, 0))?; /* 长度最后再回填 */
 
-        let mut narray = 0;
-        let mut nmap = 0;
+        /* 到目前为止全部是常量时,按顺序记录每一项 : (key,value) , 数组元素的key为None */
+        let mut template: Option<Vec<(Option<Value>, Value)>> = Some(Vec::new());
+        let (mut narray, mut nmap, mut npending) = (0, 0, 0);
+        /* 函数调用或者...作为数组元素 : 是最后一项时保留全部的值,所以要等确定后面还有没有元素再处理 */
+        let mut open_item: Option<(usize, ExpDesc)> = None;
         loop {
-            let nsp = self.sp;
             /* {    100, 200, 300;  -- list style
                 x="hello", y="world";  -- record style
                 [key]="vvv";  -- general style
             }  */
+            if self.lex.peek()? == &Token::CurlyR {
+                self.lex.next()?;
+                break;
+            }
+            if let Some((isp, desc)) = open_item.take() {
+                self.discharge(isp, desc)?;
+                narray += 1;
+                npending += 1;
+                if npending == FIELDS_PER_FLUSH {
+                    self.set_list(index, npending, narray - npending)?;
+                    npending = 0;
+                    self.fs.sp = index + 1;
+                }
+            }
+            let nsp = self.fs.sp;
 
             /* 处理 Key */
-            let entry = match self.lex.peek() {
-                //Eos
-                Token::CurlyR => {
-                    self.lex.next();
-                    break;
-                }
+            let (entry, const_key) = match self.lex.peek()? {
                 // [key]="value"
                 Token::SqurL => {
-                    self.lex.next(); /* consume */
-                    let desc = self.exp(); /* read exp to desc */
-                    self.lex.expect(Token::SqurR); /* consume ']' */
-                    self.lex.expect(Token::Equal); /* consume '=' */
-
-                    TableEntry::Map(match desc {
-                        ExpDesc::Nil => panic!("nil cannot be table key"),
-                        ExpDesc::Float(f) if f.is_nan() => panic!("nan cannot be table key"),
+                    self.lex.next()?; /* consume */
+                    let desc = self.exp()?; /* read exp to desc */
+                    self.lex.expect(Token::SqurR)?; /* consume ']' */
+                    self.lex.expect(Token::Assign)?; /* consume '=' */
+
+                    /* nil和NaN不能作为key : 不放进模板 , 由运行时的SetTable报错 */
+                    let const_key = match const_value(&desc) {
+                        Some(Value::Nil) => None,
+                        Some(Value::Float(f)) if f.is_nan() => None,
+                        k => k,
+                    };
+                    let entry = TableEntry::Map(match desc {
                         ExpDesc::Integer(i) if u8::try_from(i).is_ok() =>
-                            (ByteCode::SetInt, ByteCode::SetIntConst, i as usize),
-                        ExpDesc::String(_) => todo!(),
-                        ExpDesc::Local(i) => (ByteCode::SetTable, ByteCode::SetTableConst, i),
+                            (OpCode::SetInt, OpCode::SetIntConst, i as usize),
+                        ExpDesc::String(s) => self.field_key(s)?,
                         /* 其他ExpDesc表示为栈顶变量 */
-                        _ =>
-                            (ByteCode::SetTable, ByteCode::SetTableConst, self.discharge_top(desc)),
-                    })
+                        _ => (OpCode::SetTable, OpCode::SetTableConst, self.discharge_any(desc)?),
+                    });
+                    (entry, const_key)
                 }
                 // key=="value" or value
                 Token::Name(_) => {
-                    let name = self.read_name();
-                    if let Token::Equal = self.lex.peek() {
+                    let name = self.read_name()?;
+                    if let Token::Assign = self.lex.peek()? {
                         /* key="value" */
-                        self.lex.next();
-                        /* 只能被解释为Field : 因为 */
+                        self.lex.next()?;
+                        /* 只能被解释为Field : 因为 Name 在这里就是字符串key */
+                        let const_key = Some(Value::from(name.clone()));
+                        (TableEntry::Map(self.field_key(name.into_bytes())?), const_key)
                     } else {
                         /* value  : Array save */
-                        TableEntry::Array(self.exp_with_ahead(Token::Name(name)));
+                        (TableEntry::Array(self.exp_with_ahead(Token::Name(name))?), None)
                     }
-                    todo!()
                 }
-                _ => todo!("完善其他解析"),
+                /* 其他表达式都是数组元素 */
+                _ => (TableEntry::Array(self.exp()?), None),
             };
 
             /* 处理Value */
             match entry {
                 TableEntry::Map((stack, sconst, key)) => {
                     /*  通过判断value是需要栈操作还是常量操作来进行具体ByteCode映射 */
-                    let value = self.exp();
-                    let code = match self.discharge_const(value) {
-                        ConstStack::Const(c) => sconst(index, key as u8, c as u8),
-                        ConstStack::Stack(s) => stack(index, key as u8, s as u8),
+                    let value = self.exp()?;
+                    template = match (template, const_key, const_value(&value)) {
+                        (Some(mut entries), Some(k), Some(v)) => {
+                            entries.push((Some(k), v));
+                            Some(entries)
+                        }
+                        _ => None,
+                    };
+                    let code = match self.discharge_const(value)? {
+                        ConstStack::Const(c) if c <= MAXARG_C =>
+                            ByteCode::abc(sconst, index, key, c),
+                        ConstStack::Const(c) => {
+                            /* 常量索引放不进C参数 : 先载入栈顶再用栈上的value */
+                            let top = self.fs.sp;
+                            self.load_const_index(top, c)?;
+                            ByteCode::abc(stack, index, key, top)
+                        }
+                        ConstStack::Stack(s) => ByteCode::abc(stack, index, key, s),
                     };
-                    self.byte_codes.push(code);
+                    self.push_code(code)?;
+                    self.fs.sp = nsp; /* 释放key和value用到的临时变量 */
                     nmap += 1;
-                    self.sp = nsp; /* sp回溯? */
                 }
                 TableEntry::Array(desc) => {
-                    self.discharge(nsp, desc);
-                    narray += 1;
-                    if narray % 2 == 50 {
-                        /* time to SetList */
-                        self.byte_codes.push(ByteCode::SetList(index, 50));
-                        self.sp = (index as usize) + 1; /* push byte_code then push sp */
+                    template = match (template, const_value(&desc)) {
+                        (Some(mut entries), Some(v)) => {
+                            entries.push((None, v));
+                            Some(entries)
+                        }
+                        _ => None,
+                    };
+                    if let ExpDesc::Call(_) | ExpDesc::VarArg(_) = desc {
+                        open_item = Some((nsp, desc));
+                    } else {
+                        self.discharge(nsp, desc)?;
+                        narray += 1;
+                        npending += 1;
+                        if npending == FIELDS_PER_FLUSH {
+                            /* time to SetList */
+                            self.set_list(index, npending, narray - npending)?;
+                            npending = 0;
+                            self.fs.sp = index + 1; /* push byte_code then push sp */
+                        }
                     }
                 }
             }
-        }
-
-        self.sp = (index as usize) + 1; // 返回前，设置栈顶sp，只保留新建的表，而清理构造过程中可能使用的其他临时变量
-        return ExpDesc::Local(index as usize); // 返回表的类型（栈上临时变量）和栈上的位置
-    }
 
-    /** 函数调用 */
-    fn function_call(&mut self, fn_name: String) {
-        /* 获取函数的偏移量和参数的偏移量 */
-        let ifunc = self.locals.len();
-        let iarg = ifunc + 1;
-        /* 将变量名进行载入 : 函数名本来也算是变量 */
-        let code = self.load_var(ifunc, fn_name);
-        self.byte_codes.push(code);
-        /* 载入函数参数 : 是 LoadConst 还是 Move */
-        match self.lex.next() {
-            Token::ParL => {
-                /* 表达式解析 */
-                self.load_exp();
-                if self.lex.next() != Token::ParR {
-                    panic!("函数缺少 `)`");
+            /* 分隔符 : ',' 或 ';' , 最后一项后面可以有也可以没有 */
+            match self.lex.next()? {
+                Token::Comma | Token::SemiColon => (),
+                Token::CurlyR => break,
+                t => {
+                    return Err(self.lex.error_near("'}' expected", &t));
                 }
             }
-            Token::String(str) => {
-                /* 字符串常量 : 进行直接赋值即可 */
-                let code = self.load_const(iarg as u8, str.into());
-                self.byte_codes.push(code);
+        }
+        if let Some((_, desc)) = open_item {
+            /* B为0 : 一直写到栈顶 */
+            self.set_multret(&desc);
+            self.set_list(index, 0, narray - npending)?;
+        } else if npending > 0 {
+            self.set_list(index, npending, narray - npending)?;
+        }
+
+        match template {
+            Some(entries) if !entries.is_empty() => {
+                /* 丢弃逐项构造的字节码和只被它们用到的常量 */
+                self.truncate_code(icode);
+                self.truncate_consts(nconst);
+                let table = const_table(entries, narray, nmap);
+                let idx = self.add_const(Value::Table(Rc::new(RefCell::new(table))));
+                self.push_abx_ext(OpCode::NewTableConst, index, idx)?;
+            }
+            _ => {
+                self.fs.byte_codes[icode] = self.check_code(ByteCode::abc(
+                    OpCode::NewTable,
+                    index,
+                    narray.min(MAXARG_B),
+                    nmap.min(MAXARG_C)
+                ))?;
             }
-            _ => panic!("不受支持的函数调用形式!"),
         }
-        //Flag 最后加上调用行为
-        self.byte_codes.push(ByteCode::Call(ifunc as u8, 1));
+
+        self.fs.sp = index + 1; // 返回前，设置栈顶sp，只保留新建的表，而清理构造过程中可能使用的其他临时变量
+        return Ok(ExpDesc::Local(index)); // 返回表的类型（栈上临时变量）和栈上的位置
     }
 
-    /** 变量赋值 */
-    fn assignment(&mut self, var: String) {
-        /* 消耗 `=` */
-        if self.lex.next() != Token::Assign {
-            panic!("变量赋值的语法错误!");
+    /** 把栈上table之后的n个值写入数组部分 , before是之前已经写入的个数 */
+    fn set_list(&mut self, table: usize, n: usize, before: usize) -> Result<(), LuaError> {
+        let batch = before / FIELDS_PER_FLUSH;
+        if batch <= MAXARG_C {
+            self.push_code(ByteCode::abc(OpCode::SetList, table, n, batch))?;
+        } else {
+            self.push_code(ByteCode::abck(OpCode::SetList, table, n, 0, true))?;
+            self.push_code(ByteCode::ax(OpCode::ExtraArg, batch))?;
         }
+        return Ok(());
+    }
 
-        /* 先看var(左值)在locals中是否存在 */
-        if let Some(dst) = self.get_local(&var) {
-            /* 如果是 : 代表的是 --> 变量重新赋值操作  --> 将lex.next()的值重新赋值在dst上 */
-            self.load_exp();
-        } else {
-            /* 不在locals就代表可能全局常量中 */
-            /* 先把var(左值)载入consts表 */
-            let dst = self.add_const(var) as u8;
-            /* 解析变量 -> byte_code */
-
-            let code = match self.lex.next() {
-                /* 下面的数据都当成常量  */
-                Token::Nil => ByteCode::SetGlobalConst(dst, self.add_const(None) as u8),
-                Token::True => ByteCode::SetGlobalConst(dst, self.add_const(true) as u8),
-                Token::False => ByteCode::SetGlobalConst(dst, self.add_const(false) as u8),
-                Token::Float(float) => ByteCode::SetGlobalConst(dst, self.add_const(float) as u8),
-                Token::Integer(int) => ByteCode::SetGlobalConst(dst, self.add_const(int) as u8),
-                Token::String(str) => ByteCode::SetGlobalConst(dst, self.add_const(str) as u8),
-                /* 下面的当成变量  */
-                Token::Name(var_name) => if let Some(i) = self.get_local(&var_name) {
-                    // 已有数据 : 重新赋值
-                    ByteCode::SetGlobal(dst, i as u8)
-                } else {
-                    // 未有数据 : 全局赋值
-                    ByteCode::SetGlobalGlobal(dst, self.add_const(var_name) as u8)
-                }
-                /* 语句错误 : */
-                _ => panic!("赋值语法错误 !"),
-            };
-            /* 载入byte_code */
-            self.byte_codes.push(code);
+    /** 字符串key对应的(栈OpCode,常量OpCode,key) : key常量索引放不进B参数时先载入栈顶,再按栈上的key处理 */
+    fn field_key(&mut self, key: Vec<u8>) -> Result<(OpCode, OpCode, usize), LuaError> {
+        let key = self.add_const(key);
+        if key <= MAXARG_B {
+            return Ok((OpCode::SetField, OpCode::SetFieldConst, key));
         }
+        let top = self.reserve_reg()?;
+        self.load_const_index(top, key)?;
+        return Ok((OpCode::SetTable, OpCode::SetTableConst, top));
     }
 
-    /** 解析local关键字 */
-    fn local(&mut self) {
-        /* 先获取变量名 */
-        let var_name = if let Token::Name(local) = self.lex.next() {
-            local
-        } else {
-            panic!("local的语法声明错误: local <var_name> ");
+    /** 函数调用 : 函数放到栈顶,参数依次跟在函数后面 ; 返回的Call默认不保留返回值,作为表达式使用时再回填C */
+    fn function_call(&mut self, func: ExpDesc) -> Result<ExpDesc, LuaError> {
+        let ifunc = match func {
+            /* 局部变量要复制一份 , Call会覆盖函数所在的位置 */
+            ExpDesc::Local(i) if i < self.fs.locals.len() => self.discharge_top(func)?,
+            func => self.discharge_temp(func)?,
         };
+        return self.call_args(ifunc, 0);
+    }
 
-        /* 过渡读取一个等于号 */
-        if let Token::Assign = self.lex.next() {
+    /** 方法调用 obj:name(args) : 函数是obj.name , obj作为第一个参数 */
+    fn method_call(&mut self, obj: ExpDesc) -> Result<ExpDesc, LuaError> {
+        let name = self.read_name()?;
+        let iobj = self.discharge_any(obj)?;
+        /* obj是栈顶的临时变量时 , 函数直接放在它的位置上 */
+        let ifunc = if self.is_top_temp(iobj) { iobj } else { self.fs.sp };
+        let key = self.add_const(name);
+        if key <= MAXARG_C {
+            self.push_code(ByteCode::abc(OpCode::Method, ifunc, iobj, key))?;
         } else {
-            panic!("local的语法声明错误: local <var_name> = ");
+            /* 方法名的常量索引放不进C参数 : 借用参数的位置载入方法名 */
+            self.push_code(ByteCode::abc(OpCode::Move, ifunc + 1, iobj, 0))?;
+            self.load_const_index(ifunc + 2, key)?;
+            self.push_code(ByteCode::abc(OpCode::GetTable, ifunc, ifunc + 1, ifunc + 2))?;
         }
-        self.load_exp(); /* 解析表达式 */
-        self.locals.push(var_name); /* 将locals表进行推进 */
+        self.fs.sp = ifunc + 2;
+        return self.call_args(ifunc, 1);
     }
 
-    /** 解析表达式 : <包含byte_code操作> :: 将下一个表达式数据进行解析 */
-    fn load_exp(&mut self) {
-        let sp = self.sp; /* 获取栈顶 */
-        let desc = self.exp(); /* 转化成ExpDesc  */
-        self.discharge(sp, desc); /* ExpDesc转化并推栈 */
+    /** 解析调用的实参并生成Call : 函数在ifunc位置,前面已经放好了nfixed个参数 */
+    fn call_args(&mut self, ifunc: usize, nfixed: usize) -> Result<ExpDesc, LuaError> {
+        /* 载入函数参数 , B为参数个数+1 , 为0时表示参数一直到栈顶 */
+        let b = match self.lex.next()? {
+            Token::ParL => {
+                if self.lex.peek()? == &Token::ParR {
+                    self.lex.next()?;
+                    nfixed + 1
+                } else {
+                    let (n, multi) = self.explist_open()?;
+                    self.lex.expect(Token::ParR)?;
+                    if multi { 0 } else { nfixed + n + 1 }
+                }
+            }
+            Token::String(str) => {
+                /* 字符串常量 : 进行直接赋值即可 */
+                self.load_const(ifunc + nfixed + 1, str.into())?;
+                nfixed + 2
+            }
+            /* f{...} : table是唯一的参数 */
+            Token::CurlyL => {
+                let table = self.table_constructor()?;
+                self.discharge(ifunc + nfixed + 1, table)?;
+                nfixed + 2
+            }
+            t => {
+                return Err(self.lex.error_near("function arguments expected", &t));
+            }
+        };
+        //Flag 最后加上调用行为
+        self.push_code(ByteCode::abc(OpCode::Call, ifunc, b, 1))?;
+        self.fs.sp = ifunc + 1;
+        return Ok(ExpDesc::Call(self.fs.byte_codes.len() - 1));
     }
 
-    /** 解析行为:载入常量进栈stack */
-    fn load_const(&mut self, index: u8, val: Value) -> ByteCode {
-        return ByteCode::LoadConst(index, self.add_const(val) as u8);
+    /** 表达式列表 : 依次放到栈顶 , 最后一个表达式是函数调用或者...时保留它的全部值
+        @return (确定的值的个数 , 最后一个表达式是否是多返回值)
+     */
+    fn explist_open(&mut self) -> Result<(usize, bool), LuaError> {
+        let mut n = 0;
+        loop {
+            let sp = self.fs.sp;
+            let desc = self.exp()?;
+            if self.lex.peek()? != &Token::Comma {
+                if self.set_multret(&desc) {
+                    return Ok((n, true));
+                }
+                self.discharge(sp, desc)?;
+                return Ok((n + 1, false));
+            }
+            self.lex.next()?;
+            self.discharge(sp, desc)?;
+            n += 1;
+        }
     }
 
-    /** 获取 name 在 local中的偏移量 */
-    fn get_local(&mut self, name: &str) -> Option<usize> {
-        /* 使用rposition的原因是需要使用旧变量覆盖新变量 */
-        return self.locals.iter().rposition(|item| item == name);
+    /** 表达式列表调整成want个值,依次放到栈顶 : 不够的补nil , 多余的丢弃 ,
+        最后一个表达式是函数调用或者...时由它的返回值补齐
+        @return 前want个位置上的常量表达式 , 用于<const>局部变量
+     */
+    fn explist_want(&mut self, want: usize) -> Result<Vec<Option<ExpDesc>>, LuaError> {
+        let base = self.fs.sp;
+        let mut consts = vec![None; want];
+        let mut n = 0;
+        loop {
+            let sp = self.fs.sp;
+            let desc = self.exp()?;
+            let last = self.lex.peek()? != &Token::Comma;
+            if last {
+                if let ExpDesc::Call(pc) | ExpDesc::VarArg(pc) = desc {
+                    let r = self.fs.byte_codes[pc].set_c(want.saturating_sub(n) + 1);
+                    self.check_code(r)?;
+                    break;
+                }
+            }
+            if n < want && const_value(&desc).is_some() {
+                consts[n] = Some(desc.clone());
+            }
+            self.discharge(sp, desc)?;
+            n += 1;
+            if last {
+                for i in n..want {
+                    self.discharge(base + i, ExpDesc::Nil)?;
+                }
+                break;
+            }
+            self.lex.next()?;
+        }
+        self.fs.sp = base + want;
+        return Ok(consts);
     }
 
-    /** 解析变量 : 存在就用Move 不存在就用GetGlobal */
-    fn load_var(&mut self, dst: usize, name: String) -> ByteCode {
-        if let Some(index) = self.get_local(&name) {
-            /* 已存在的变量 */
-            ByteCode::Move(dst as u8, index as u8) /* 将栈上index的数据作为dst的数据 */
-        } else {
-            /* 否则,将数据作为全局常量读取常量 */
-            let idx = self.add_const(name);
-            ByteCode::GetGlobal(dst as u8, idx as u8)
+    /** 函数调用和...保留全部的值 , 返回是否是这两种表达式 */
+    fn set_multret(&mut self, desc: &ExpDesc) -> bool {
+        if let ExpDesc::Call(pc) | ExpDesc::VarArg(pc) = desc {
+            return self.fs.byte_codes[*pc].set_c(0).is_ok(); /* C为0不会超出位宽 */
         }
+        return false;
     }
 
-    /** 载入Value到常量表constants中 , 并返回常量表中的索引 : 对于已有常量返回已有索引 */
-    fn add_const<I: Into<Value>>(&mut self, v: I) -> usize {
-        /* 时间复杂度是O(N^2) --> 后续需要优化为hashMap */
-        let val = v.into();
-        return self.constants
-            .iter()
-            .position(|v| v == &val)
-            .unwrap_or_else(|| {
-                self.constants.push(val);
-                self.constants.len() - 1
-            });
+    /** 变量赋值 : 左值已经解析成ExpDesc */
+    fn assignment(&mut self, var: ExpDesc) -> Result<(), LuaError> {
+        let value = self.exp()?;
+        return self.assign(var, value);
     }
 
-    /** Next Token -> ExpDesc */
-    fn exp(&mut self) -> ExpDesc {
-        let token = self.lex.next();
-        self.exp_with_ahead(token)
+    /** 把value赋值给var */
+    fn assign(&mut self, var: ExpDesc, value: ExpDesc) -> Result<(), LuaError> {
+        self.check_readonly(&var)?;
+        match var {
+            /* 局部变量 : 直接把表达式的值放到变量的栈位置上 */
+            ExpDesc::Local(dst) => self.discharge(dst, value)?,
+            ExpDesc::Upvalue(dst) => {
+                let src = self.discharge_any(value)?;
+                self.push_code(ByteCode::abc(OpCode::SetUpval, src, dst, 0))?;
+            }
+            ExpDesc::IndexUpField(t, key) =>
+                self.assign_table(OpCode::SetUpField, OpCode::SetUpFieldConst, t, key, value)?,
+            ExpDesc::Index(t, key) =>
+                self.assign_table(OpCode::SetTable, OpCode::SetTableConst, t, key, value)?,
+            ExpDesc::IndexField(t, key) if key <= MAXARG_B =>
+                self.assign_table(OpCode::SetField, OpCode::SetFieldConst, t, key, value)?,
+            ExpDesc::IndexField(t, key) => {
+                /* key常量索引放不进B参数 : 先载入栈上 */
+                let ikey = self.reserve_reg()?;
+                self.load_const_index(ikey, key)?;
+                self.assign_table(OpCode::SetTable, OpCode::SetTableConst, t, ikey, value)?;
+            }
+            ExpDesc::IndexInt(t, key) =>
+                self.assign_table(OpCode::SetInt, OpCode::SetIntConst, t, key as usize, value)?,
+            _ => panic!("变量赋值的语法错误!"),
+        }
+        return Ok(());
     }
 
-    /** Any Token -> ExpDesc */
-    fn exp_with_ahead(&mut self, token: Token) -> ExpDesc {
-        return match token {
-            Token::Nil => ExpDesc::Nil,
-            Token::True => ExpDesc::Boolean(true),
-            Token::False => ExpDesc::Boolean(false),
-            Token::Integer(i) => ExpDesc::Integer(i),
-            Token::Float(f) => ExpDesc::Float(f),
-            Token::String(s) => ExpDesc::String(s),
-            Token::Function => todo!("Function"),
-            Token::CurlyL => self.table_constructor(),
-            Token::Sub | Token::Not | Token::BitXor | Token::Len => todo!("unop"),
-            Token::Dots => todo!("dots"),
-            t => self.prefixexp(t) /* Name | SqurL | SqurR */,
+    /** <const>和<close>变量不能赋值 , 包括作为Upvalue被内层函数赋值 */
+    fn check_readonly(&mut self, var: &ExpDesc) -> Result<(), LuaError> {
+        let name = match *var {
+            ExpDesc::Local(i) => self.fs.locals.get(i).filter(|v| v.attrib.is_some()).map(|v| v.name.clone()),
+            ExpDesc::Upvalue(i) => self.readonly_upvalue(self.enclosing.len(), i),
+            _ => None,
         };
+        if let Some(name) = name {
+            return Err(self.lex.error(&format!("attempt to assign to const variable '{name}'")));
+        }
+        return Ok(());
     }
 
-    /* 消除左递归的value解析 */
-    fn prefixexp(&mut self, token: Token) -> ExpDesc {
-        let idx = self.sp;
-        let mut desc_code = match token {
-            Token::Name(name) => self.simple_name(name) /* parse the name */,
-            Token::SqurL => {
-                let desc = self.exp(); /* 这里使用递归调用获取exp */
-                self.lex.expect(Token::SqurR); /* consume ']'  */
-                desc
+    /** 编译期常量被替换成了值 , 赋值之前要按名字检查 */
+    fn check_const_name(&mut self, name: &str) -> Result<(), LuaError> {
+        let konst = match self.fs.locals.iter().rev().find(|v| v.name == name) {
+            Some(var) => var.value.is_some(),
+            None => self.find_const(self.enclosing.len(), name).is_some(),
+        };
+        if konst {
+            return Err(self.lex.error(&format!("attempt to assign to const variable '{name}'")));
+        }
+        return Ok(());
+    }
+
+    /** 第level层函数的第idx个Upvalue最终来自有属性的局部变量时 , 返回它的名字 */
+    fn readonly_upvalue(&mut self, level: usize, idx: usize) -> Option<String> {
+        let up = self.func_state(level).upvalues[idx].1;
+        return match up {
+            _ if level == 0 => None /* 主代码块的_ENV */,
+            UpIndex::Local(i) => {
+                let var = &self.func_state(level - 1).locals[i];
+                var.attrib.map(|_| var.name.clone())
             }
+            UpIndex::Upvalue(i) => self.readonly_upvalue(level - 1, i),
+        };
+    }
 
-            _ => panic!("var 解析失败"),
+    /** 给table的成员赋值 */
+    fn assign_table(&mut self, stack: OpCode, sconst: OpCode, t: usize, key: usize, value: ExpDesc) -> Result<(), LuaError> {
+        let code = match self.discharge_const(value)? {
+            ConstStack::Const(c) if c <= MAXARG_C => ByteCode::abc(sconst, t, key, c),
+            ConstStack::Const(c) => {
+                let top = self.fs.sp;
+                self.load_const_index(top, c)?;
+                ByteCode::abc(stack, t, key, top)
+            }
+            ConstStack::Stack(s) => ByteCode::abc(stack, t, key, s),
         };
-        // [key] = value
-        loop {
-            match self.lex.peek() {
-                Token::SqurL => {
-                    // [ exp ]
-                    self.lex.next();
-                    let itable = self.discharge_if_need(idx, desc_code);
-                    desc_code = match self.exp() {
-                        ExpDesc::String(s) => ExpDesc::IndexField(itable, self.add_const(s)),
-                        ExpDesc::Integer(i) if u8::try_from(i).is_ok() =>
-                            ExpDesc::IndexInt(itable, u8::try_from(i).unwrap()),
-                        key => ExpDesc::Index(itable, self.discharge_top(key)),
-                    };
-                }
-                Token::Dot => {
-                    // .name
-                    self.lex.next();
-                    let name = self.read_name();
-                    let itable = self.discharge_if_need(idx, desc_code);
-                    desc_code = ExpDesc::IndexField(itable, self.add_const(name));
-                }
-                Token::SqurL => {}
+        self.push_code(code)?;
+        return Ok(());
+    }
 
-                _ => {
-                    return desc_code; /* direct return desc */
-                }
+    /** 解析local关键字 : local <name> [<attrib>] {, <name> [<attrib>]} [= <explist>] */
+    fn local(&mut self) -> Result<(), LuaError> {
+        /* 先获取变量名和属性 */
+        let mut vars = Vec::new();
+        loop {
+            let name = self.read_name()?;
+            vars.push((name, self.local_attrib()?));
+            if self.lex.peek()? != &Token::Comma {
+                break;
             }
+            self.lex.next()?;
+        }
+        if vars.iter().filter(|(_, attrib)| *attrib == Some(Attrib::Close)).count() > 1 {
+            return Err(self.lex.error("multiple to-be-closed variables in local list"));
         }
-        todo!()
-    }
 
-    /** String<Local|Global> -> ExpDesc */
-    fn simple_name(&mut self, name: String) -> ExpDesc {
-        /* 判断变量名是局部还是全局变量 */
-        return if let Some(idx) = self.locals.iter().rposition(|v| v == &name) {
-            ExpDesc::Local(idx) /* 栈上的临时变量 */
+        /* 有初始值就解析表达式列表,按变量个数补nil或者截断 , 否则都初始化为nil */
+        let base = self.fs.sp;
+        let consts = if self.lex.peek()? == &Token::Assign {
+            self.lex.next()?;
+            self.explist_want(vars.len())? /* 解析表达式 */
         } else {
-            ExpDesc::Global(self.add_const(name)) /* 全局变量 */
+            for i in 0..vars.len() {
+                self.discharge(base + i, ExpDesc::Nil)?;
+            }
+            vec![None; vars.len()]
         };
-    }
 
-    /** read name  */
-    fn read_name(&mut self) -> String {
-        if let Token::Name(name) = self.lex.next() {
-            return name;
-        } else {
-            panic!("next token isnot Name!")
+        /* 编译期常量也占住栈位置 , 保持局部变量和栈位置一一对应 */
+        for ((name, attrib), konst) in vars.into_iter().zip(consts) {
+            let dst = self.fs.locals.len();
+            self.add_local(name.clone())?; /* 将locals表进行推进 */
+            let var = self.fs.locals.last_mut().unwrap();
+            var.attrib = attrib;
+            if attrib == Some(Attrib::Const) {
+                var.value = konst;
+            }
+            if attrib == Some(Attrib::Close) {
+                /* 离开作用域(包括break,goto,return和出错)时调用__close , 和被捕获的变量一样需要Close */
+                var.captured = true;
+                let iname = self.add_const(name);
+                self.push_abx_ext(OpCode::Tbc, dst, iname)?;
+            }
         }
+        return Ok(());
     }
 
-    /** 将ExpDesc转化成byteCode ,然后推到指定栈dst上 */
-    fn discharge(&mut self, dst: usize, desc: ExpDesc) {
-        /* 将ExpDesc转化成byteCode后 推入当前栈顶 */
-        let code = match desc {
-            ExpDesc::Nil => ByteCode::LoadNil(dst as u8),
-            ExpDesc::Boolean(b) => ByteCode::LoadBool(dst as u8, b),
-            ExpDesc::Integer(i) => ByteCode::LoadInt(dst as u8, i),
-            ExpDesc::Float(f) => self.load_const(dst as u8, Value::Float(f)),
-            ExpDesc::String(s) => self.load_const(dst as u8, s.into()),
-            ExpDesc::Local(l) => {
-                //Local表示数据是从栈上获取的,所以使用Move
-                if dst != l {
-                    ByteCode::Move(dst as u8, l as u8)
-                } else {
-                    return;
-                }
-            }
-            ExpDesc::Global(g) => {
-                //Global表示数据从常量表中获取
-                ByteCode::GetGlobal(dst as u8, g as u8)
-            }
-            _ => panic!("暂时不支持更多ExpDesc"),
+    /** 局部变量的属性 : <const> 或者 <close> */
+    fn local_attrib(&mut self) -> Result<Option<Attrib>, LuaError> {
+        if self.lex.peek()? != &Token::Less {
+            return Ok(None);
+        }
+        self.lex.next()?;
+        let attrib = self.read_name()?;
+        self.lex.expect(Token::Greater)?;
+        return match attrib.as_str() {
+            "const" => Ok(Some(Attrib::Const)),
+            "close" => Ok(Some(Attrib::Close)),
+            _ => Err(self.lex.error(&format!("unknown attribute '{attrib}'"))),
         };
-        self.byte_codes.push(code);
-        self.sp += 1;
     }
 
-    /** 将ExpDesc推到当前栈顶 */
-    fn discharge_top(&mut self, desc: ExpDesc) -> usize {
-        return self.discharge_if_need(self.sp, desc);
+    /** if语句 : 条件为常量的分支在编译期就确定,死分支的字节码直接丢弃 */
+    fn if_stat(&mut self) -> Result<(), LuaError> {
+        let mut jmp_ends = Vec::new();
+        let mut always = false; /* 已经遇到条件恒为真的分支,后面的分支都不会执行 */
+        let mut end_token = Token::Elseif;
+        while end_token == Token::Elseif {
+            let cut = self.fs.byte_codes.len();
+            let cond = self.exp()?;
+            self.lex.expect(Token::Then)?;
+
+            if always {
+                end_token = self.block_scope()?;
+                self.discard_code(cut);
+                continue;
+            }
+            match const_truthy(&cond) {
+                Some(false) => {
+                    end_token = self.block_scope()?;
+                    self.discard_code(cut);
+                }
+                Some(true) => {
+                    end_token = self.block_scope()?;
+                    always = true;
+                }
+                None => {
+                    let false_list = self.test_or_jump(cond)?;
+                    end_token = self.block_scope()?;
+                    if matches!(end_token, Token::Else | Token::Elseif) {
+                        let jmp = self.jump()?;
+                        jmp_ends.push(jmp);
+                    }
+                    self.patch_to_here(false_list)?;
+                }
+            }
+        }
+        if end_token == Token::Else {
+            let cut = self.fs.byte_codes.len();
+            end_token = self.block_scope()?;
+            if always {
+                self.discard_code(cut);
+            }
+        }
+        self.check_end(end_token, Token::End)?;
+        self.patch_to_here(jmp_ends)?;
+        return Ok(());
+    }
+
+    /** while语句 */
+    fn while_stat(&mut self) -> Result<(), LuaError> {
+        let start = self.label();
+        let cond = self.exp()?;
+        self.lex.expect(Token::Do)?;
+
+        let dead = const_truthy(&cond) == Some(false);
+        let false_list = match const_truthy(&cond) {
+            Some(_) => Vec::new(),
+            None => self.test_or_jump(cond)?,
+        };
+
+        self.fs.break_blocks.push(Vec::new());
+        let end_token = self.block_scope()?;
+        self.check_end(end_token, Token::End)?;
+        let jmp = self.jump()?;
+        self.fix_jump(jmp, start)?;
+        let breaks = self.fs.break_blocks.pop().unwrap();
+        if dead {
+            self.discard_code(start);
+        } else {
+            self.patch_to_here(false_list)?;
+            self.patch_to_here(breaks)?;
+            let nvar = self.fs.locals.len();
+            if self.closes_upvalue(start, nvar) {
+                self.push_code(ByteCode::abc(OpCode::Close, nvar, 0, 0))?;
+            }
+        }
+        return Ok(());
+    }
+
+    /** repeat语句 : until的条件中可以访问循环体里的局部变量 */
+    fn repeat_stat(&mut self) -> Result<(), LuaError> {
+        let start = self.label();
+        let nvar = self.fs.locals.len();
+        let ilabel = self.fs.labels.len();
+        let igoto = self.fs.gotos.len();
+
+        self.fs.break_blocks.push(Vec::new());
+        let end_token = self.block()?;
+        self.check_end(end_token, Token::Until)?;
+        let cond = self.exp()?;
+        /* 循环体中有被捕获的局部变量 : 回到开头之前要先关闭,每次循环都是新的变量 */
+        let captured = self.fs.locals[nvar..].iter().any(|v| v.captured);
+        match const_truthy(&cond) {
+            Some(true) => (),
+            Some(false) => {
+                if captured {
+                    self.push_code(ByteCode::abc(OpCode::Close, nvar, 0, 0))?;
+                }
+                let jmp = self.jump()?;
+                self.fix_jump(jmp, start)?;
+            }
+            None if captured => {
+                let false_list = self.test_or_jump(cond)?;
+                let exit = self.jump()?;
+                self.patch_to_here(false_list)?;
+                self.push_code(ByteCode::abc(OpCode::Close, nvar, 0, 0))?;
+                let jmp = self.jump()?;
+                self.fix_jump(jmp, start)?;
+                self.patch_to_here(vec![exit])?;
+            }
+            None => {
+                let false_list = self.test_or_jump(cond)?;
+                self.patch_list(false_list, start)?;
+            }
+        }
+        self.close_block(nvar, ilabel, igoto)?;
+        let breaks = self.fs.break_blocks.pop().unwrap();
+        self.patch_to_here(breaks)?;
+        if self.closes_upvalue(start, nvar) {
+            self.push_code(ByteCode::abc(OpCode::Close, nvar, 0, 0))?;
+        }
+        return Ok(());
+    }
+
+    /** for语句 */
+    fn for_stat(&mut self) -> Result<(), LuaError> {
+        let line = self.lex.line();
+        let name = self.read_name()?;
+        if self.lex.peek()? == &Token::Assign {
+            self.numeric_for(name, line)?;
+        } else {
+            self.generic_for(name, line)?;
+        }
+        return Ok(());
+    }
+
+    /** 数值for循环 : 栈上依次是 初始值|上限(循环次数)|步长|循环变量 */
+    fn numeric_for(&mut self, name: String, line: usize) -> Result<(), LuaError> {
+        self.lex.next()?; /* consume '=' */
+        let base = self.fs.sp;
+        self.load_exp()?;
+        self.lex.expect(Token::Comma)?;
+        self.load_exp()?;
+        if self.lex.peek()? == &Token::Comma {
+            self.lex.next()?;
+            self.load_exp()?;
+        } else {
+            self.discharge(base + 2, ExpDesc::Integer(1))?;
+        }
+        self.lex.expect(Token::Do)?;
+
+        /* 3个内部状态作为匿名局部变量占住栈位置,变量名不合法所以不会和用户变量冲突 */
+        self.add_local(String::from("(for state)"))?;
+        self.add_local(String::from("(for state)"))?;
+        self.add_local(String::from("(for state)"))?;
+
+        let prep = self.fs.byte_codes.len();
+        self.push_code(ByteCode::abx(OpCode::ForPrep, base, 0))?;
+        self.label();
+
+        /* 循环变量和循环体在同一个作用域 , 被捕获时每次循环结束都要关闭 */
+        let (ilabel, igoto) = (self.fs.labels.len(), self.fs.gotos.len());
+        self.add_local(name)?;
+        self.fs.break_blocks.push(Vec::new());
+        let end_token = self.block()?;
+        self.check_end(end_token, Token::End)?;
+        self.close_block(base + 3, ilabel, igoto)?;
+        self.remove_locals(base);
+
+        let iloop = self.fs.byte_codes.len();
+        self.push_code(ByteCode::abx(OpCode::ForLoop, base, iloop - prep))?;
+        self.fix_line(line);
+        /* 一次都不执行时ForPrep跳到ForLoop之后 */
+        let r = self.fs.byte_codes[prep].set_bx(iloop - prep - 1);
+        self.check_code(r)?;
+        self.label();
+
+        let breaks = self.fs.break_blocks.pop().unwrap();
+        self.patch_to_here(breaks)?;
+        if self.closes_upvalue(prep, base) {
+            self.push_code(ByteCode::abc(OpCode::Close, base, 0, 0))?;
+        }
+        return Ok(());
     }
 
-    /** 将ExpDesc推到dst位置上  
-        @return 栈位置 
+    /** 泛型for循环 : 栈上依次是 迭代函数|不可变状态|控制变量|关闭值|循环变量...
+        关闭值是to-be-closed变量,离开循环时(包括break和goto)需要Close
      */
-    fn discharge_if_need(&mut self, dst: usize, desc: ExpDesc) -> usize {
-        //如果位置刚好是ExpDesc::Local(l)的位置说明就是栈顶那就啥都不用做
-        return if let ExpDesc::Local(i) = desc {
-            i
+    fn generic_for(&mut self, name: String, line: usize) -> Result<(), LuaError> {
+        let mut names = vec![name];
+        while self.lex.peek()? == &Token::Comma {
+            self.lex.next()?;
+            names.push(self.read_name()?);
+        }
+        self.lex.expect(Token::In)?;
+
+        let base = self.fs.sp;
+        let (ilabel, igoto) = (self.fs.labels.len(), self.fs.gotos.len());
+        self.explist_want(4)?;
+        self.lex.expect(Token::Do)?;
+
+        self.add_local(String::from("(for state)"))?;
+        self.add_local(String::from("(for state)"))?;
+        self.add_local(String::from("(for state)"))?;
+        self.add_local(String::from("(for state)"))?;
+        self.fs.locals[base + 3].captured = true;
+        self.fs.locals[base + 3].attrib = Some(Attrib::Close);
+
+        let prep = self.fs.byte_codes.len();
+        self.push_code(ByteCode::abx(OpCode::TForPrep, base, 0))?;
+        self.label();
+
+        let (ilabel_body, igoto_body) = (self.fs.labels.len(), self.fs.gotos.len());
+        let nvars = names.len();
+        for name in names {
+            self.add_local(name)?;
+        }
+        self.fs.break_blocks.push(Vec::new());
+        let end_token = self.block()?;
+        self.check_end(end_token, Token::End)?;
+        self.close_block(base + 4, ilabel_body, igoto_body)?;
+
+        let icall = self.label();
+        let r = self.fs.byte_codes[prep].set_bx(icall - prep - 1);
+        self.check_code(r)?;
+        self.push_code(ByteCode::abc(OpCode::TForCall, base, 0, nvars))?;
+        self.fix_line(line);
+        let iloop = self.fs.byte_codes.len();
+        self.push_code(ByteCode::abx(OpCode::TForLoop, base, iloop - prep))?;
+        self.fix_line(line);
+
+        let breaks = self.fs.break_blocks.pop().unwrap();
+        self.patch_to_here(breaks)?;
+        self.close_block(base, ilabel, igoto)?;
+        return Ok(());
+    }
+
+    /** do ... end */
+    fn do_stat(&mut self) -> Result<(), LuaError> {
+        let end_token = self.block_scope()?;
+        self.check_end(end_token, Token::End)?;
+        return Ok(());
+    }
+
+    /** break : 跳出最内层循环 */
+    fn break_stat(&mut self) -> Result<(), LuaError> {
+        if self.fs.break_blocks.is_empty() {
+            return Err(self.lex.error(&format!("break outside a loop at line {}", self.lex.line())));
+        }
+        let jmp = self.jump()?;
+        self.fs.break_blocks.last_mut().unwrap().push(jmp);
+        return Ok(());
+    }
+
+    /** goto : 往回跳的直接确定位置,往前跳的等标签出现 */
+    fn goto_stat(&mut self) -> Result<(), LuaError> {
+        let name = self.read_name()?;
+        if let Some(label) = self.fs.labels.iter().rev().find(|l| l.name == name) {
+            /* 往回跳离开了局部变量的作用域 : 先关闭它们可能的Upvalue */
+            let (target, nvar) = (label.icode, label.nvar);
+            if self.fs.locals.len() > nvar {
+                self.push_code(ByteCode::abc(OpCode::Close, nvar, 0, 0))?;
+            }
+            let jmp = self.jump()?;
+            self.fix_jump(jmp, target)?;
+        } else {
+            let jmp = self.jump()?;
+            let (line, nvar) = (self.lex.line(), self.fs.locals.len());
+            self.fs.gotos.push(GotoLabel { name, line, icode: jmp, nvar, close: false });
+        }
+        return Ok(());
+    }
+
+    /** ::label:: */
+    fn label_stat(&mut self) -> Result<(), LuaError> {
+        let name = self.read_name()?;
+        let line = self.lex.line();
+        self.lex.expect(Token::DoubColon)?;
+        if let Some(label) = self.fs.labels.iter().find(|l| l.name == name) {
+            return Err(self.lex.error(&format!("label '{name}' already defined on line {}", label.line)));
+        }
+        let icode = self.label();
+        let nvar = self.fs.locals.len();
+        /* 跳到这里的goto离开了有被捕获的局部变量的语句块 : 在标签处关闭Upvalue */
+        if self.fs.gotos.iter().any(|g| g.name == name && g.close) {
+            self.push_code(ByteCode::abc(OpCode::Close, nvar, 0, 0))?;
+        }
+        self.fs.labels.push(GotoLabel { name, line, icode, nvar, close: false });
+        return Ok(());
+    }
+
+    /** 解析表达式 : <包含byte_code操作> :: 将下一个表达式数据进行解析 */
+    fn load_exp(&mut self) -> Result<(), LuaError> {
+        let sp = self.fs.sp; /* 获取栈顶 */
+        let desc = self.exp()?; /* 转化成ExpDesc  */
+        self.discharge(sp, desc)?; /* ExpDesc转化并推栈 */
+        return Ok(());
+    }
+
+    /** 解析行为:载入常量进栈stack */
+    fn load_const(&mut self, dst: usize, val: Value) -> Result<(), LuaError> {
+        let idx = self.add_const(val);
+        return self.load_const_index(dst, idx);
+    }
+
+    /** 载入常量表中idx位置的常量 : 索引放不进Bx参数时使用LoadConstX+ExtraArg */
+    fn load_const_index(&mut self, dst: usize, idx: usize) -> Result<(), LuaError> {
+        if idx <= MAXARG_BX {
+            self.push_code(ByteCode::abx(OpCode::LoadConst, dst, idx))?;
         } else {
-            self.discharge(dst, desc);
-            dst
+            self.push_code(ByteCode::abx(OpCode::LoadConstX, dst, 0))?;
+            self.push_code(ByteCode::ax(OpCode::ExtraArg, idx))?;
+        }
+        return Ok(());
+    }
+
+    /** 生成Bx为常量索引的字节码 : 索引放不进Bx参数时Bx置为MAXARG_BX,真实索引由后续的ExtraArg给出 */
+    fn push_abx_ext(&mut self, op: OpCode, a: usize, idx: usize) -> Result<(), LuaError> {
+        if idx < MAXARG_BX {
+            self.push_code(ByteCode::abx(op, a, idx))?;
+        } else {
+            self.push_code(ByteCode::abx(op, a, MAXARG_BX))?;
+            self.push_code(ByteCode::ax(OpCode::ExtraArg, idx))?;
+        }
+        return Ok(());
+    }
+
+    /** 载入Value到常量表constants中 , 并返回常量表中的索引 : 对于已有常量返回已有索引 */
+    fn add_const<I: Into<Value>>(&mut self, v: I) -> usize {
+        let val = v.into();
+        let idx = self.fs.constants.len();
+        match ConstKey::of(&val) {
+            Some(key) => {
+                let idx = *self.fs.const_index.entry(key).or_insert(idx);
+                if idx == self.fs.constants.len() {
+                    self.fs.constants.push(val);
+                }
+                return idx;
+            }
+            None => {
+                self.fs.constants.push(val);
+                return idx;
+            }
+        }
+    }
+
+    /** 丢弃常量表中n之后的常量 */
+    fn truncate_consts(&mut self, n: usize) {
+        for v in self.fs.constants.drain(n..) {
+            if let Some(key) = ConstKey::of(&v) {
+                self.fs.const_index.remove(&key);
+            }
+        }
+    }
+
+    /** Next Token -> ExpDesc */
+    fn exp(&mut self) -> Result<ExpDesc, LuaError> {
+        return self.exp_limit(0);
+    }
+
+    /** 解析优先级高于limit的表达式 */
+    fn exp_limit(&mut self, limit: i32) -> Result<ExpDesc, LuaError> {
+        let token = self.lex.next()?;
+        return self.exp_with_ahead_limit(token, limit);
+    }
+
+    /** Any Token -> ExpDesc */
+    fn exp_with_ahead(&mut self, token: Token) -> Result<ExpDesc, LuaError> {
+        return self.exp_with_ahead_limit(token, 0);
+    }
+
+    /** 按照运算符优先级解析二元运算 : 左结合的运算符左右优先级相等,右结合的左优先级更高 */
+    fn exp_with_ahead_limit(&mut self, token: Token, limit: i32) -> Result<ExpDesc, LuaError> {
+        self.enter_level()?;
+        let r = self.subexp(token, limit);
+        self.level -= 1;
+        return r;
+    }
+
+    /** 一层二元运算表达式 : 一元运算和括号等嵌套的部分递归回exp_with_ahead_limit */
+    fn subexp(&mut self, token: Token, limit: i32) -> Result<ExpDesc, LuaError> {
+        let mut desc = match token {
+            Token::Sub => self.unop(OpCode::Unm)?,
+            Token::Not => self.unop(OpCode::Not)?,
+            Token::BitXor => self.unop(OpCode::BNot)?,
+            Token::Len => self.unop(OpCode::Len)?,
+            t => self.simple_exp(t)?,
+        };
+        loop {
+            let (left_pri, right_pri) = binop_priority(self.lex.peek()?);
+            if left_pri <= limit {
+                return Ok(desc);
+            }
+            let binop = self.lex.next()?;
+
+            /* 左操作数是常量的逻辑运算 : 直接确定结果,被短路的右操作数的字节码直接丢弃 */
+            if matches!(binop, Token::And | Token::Or) && const_truthy(&desc).is_some() {
+                let (cut, nsp) = (self.fs.byte_codes.len(), self.fs.sp);
+                let right = self.exp_limit(right_pri)?;
+                let keep_left = const_truthy(&desc) == Some(binop == Token::Or);
+                if keep_left {
+                    self.discard_code(cut);
+                    self.fs.sp = nsp;
+                } else {
+                    desc = right;
+                }
+                continue;
+            }
+
+            let left = self.preprocess_binop(desc, &binop)?;
+            let right = self.exp_limit(right_pri)?;
+            desc = self.process_binop(binop, left, right)?;
+        }
+    }
+
+    /** 基础表达式 */
+    fn simple_exp(&mut self, token: Token) -> Result<ExpDesc, LuaError> {
+        let desc = match token {
+            Token::Nil => ExpDesc::Nil,
+            Token::True => ExpDesc::Boolean(true),
+            Token::False => ExpDesc::Boolean(false),
+            Token::Integer(i) => ExpDesc::Integer(i),
+            Token::Float(f) => ExpDesc::Float(f),
+            Token::String(s) => ExpDesc::String(s),
+            Token::Function => self.function_body(false)?,
+            Token::CurlyL => self.table_constructor()?,
+            Token::Dots => {
+                if !self.fs.is_vararg {
+                    return Err(self.lex.error_near("cannot use '...' outside a vararg function", &Token::Dots));
+                }
+                let dst = self.fs.sp;
+                self.push_code(ByteCode::abc(OpCode::VarArg, dst, 0, 2))?;
+                self.occupy(dst);
+                ExpDesc::VarArg(self.fs.byte_codes.len() - 1)
+            }
+            t => self.prefixexp(t)? /* Name | ParL */,
+        };
+        return Ok(desc);
+    }
+
+    /** 一元运算 : 常量操作数直接在编译期计算 */
+    fn unop(&mut self, op: OpCode) -> Result<ExpDesc, LuaError> {
+        let desc = self.exp_limit(UNARY_PRIORITY)?;
+        if let Some(folded) = fold_unop(op, &desc) {
+            return Ok(folded);
+        }
+        /* not作用在比较上 : 只需要把比较的条件取反 */
+        if let (OpCode::Not, ExpDesc::Jump(pc)) = (op, &desc) {
+            self.negate_cond(*pc);
+            return Ok(desc);
+        }
+        let operand = self.discharge_any(desc)?;
+        return Ok(ExpDesc::UnaryOp(op, operand));
+    }
+
+    /** 读取二元运算的右操作数前先处理左操作数 */
+    fn preprocess_binop(&mut self, left: ExpDesc, binop: &Token) -> Result<ExpDesc, LuaError> {
+        let desc = match binop {
+            /* 逻辑运算 : 左操作数生成条件跳转 */
+            Token::And => ExpDesc::Test(Box::new(ExpDesc::Nil), Vec::new(), self.test_or_jump(left)?),
+            Token::Or => ExpDesc::Test(Box::new(ExpDesc::Nil), self.test_and_jump(left)?, Vec::new()),
+            /* 常量先保留,等右操作数确定后看能否折叠 */
+            _ if const_value(&left).is_some() => left,
+            _ => ExpDesc::Local(self.discharge_any(left)?),
+        };
+        return Ok(desc);
+    }
+
+    /** 二元运算 */
+    fn process_binop(&mut self, binop: Token, left: ExpDesc, right: ExpDesc) -> Result<ExpDesc, LuaError> {
+        if let Some(folded) = fold_binop(&binop, &left, &right) {
+            return Ok(folded);
+        }
+        let desc = match binop {
+            Token::And | Token::Or => {
+                let ExpDesc::Test(_, mut true_list, mut false_list) = left else {
+                    panic!("invalid logical operand");
+                };
+                match right {
+                    ExpDesc::Test(cond, mut rt, mut rf) => {
+                        true_list.append(&mut rt);
+                        false_list.append(&mut rf);
+                        ExpDesc::Test(cond, true_list, false_list)
+                    }
+                    _ => ExpDesc::Test(Box::new(right), true_list, false_list),
+                }
+            }
+            Token::Add => self.arith_binop(OpCode::Add, left, right)?,
+            Token::Sub => self.arith_binop(OpCode::Sub, left, right)?,
+            Token::Mul => self.arith_binop(OpCode::Mul, left, right)?,
+            Token::Div => self.arith_binop(OpCode::Div, left, right)?,
+            Token::Idiv => self.arith_binop(OpCode::IDiv, left, right)?,
+            Token::Mod => self.arith_binop(OpCode::Mod, left, right)?,
+            Token::Pow => self.arith_binop(OpCode::Pow, left, right)?,
+            Token::BitAnd => self.arith_binop(OpCode::BAnd, left, right)?,
+            Token::BitOr => self.arith_binop(OpCode::BOr, left, right)?,
+            Token::BitXor => self.arith_binop(OpCode::BXor, left, right)?,
+            Token::ShiftL => self.arith_binop(OpCode::Shl, left, right)?,
+            Token::ShiftR => self.arith_binop(OpCode::Shr, left, right)?,
+            Token::Concat => self.arith_binop(OpCode::Concat, left, right)?,
+            Token::Equal => self.compare_binop(OpCode::Eq, true, left, right)?,
+            Token::NotEq => self.compare_binop(OpCode::Eq, false, left, right)?,
+            Token::Less => self.compare_binop(OpCode::Lt, true, left, right)?,
+            Token::LesEq => self.compare_binop(OpCode::Le, true, left, right)?,
+            /* a > b 等价于 b < a */
+            Token::Greater => self.compare_binop(OpCode::Lt, true, right, left)?,
+            Token::GreEq => self.compare_binop(OpCode::Le, true, right, left)?,
+            t => panic!("invalid binop {t:?}"),
+        };
+        return Ok(desc);
+    }
+
+    /** 算术/位运算/拼接 : 右操作数是常量时使用k标志位直接读常量表 */
+    fn arith_binop(&mut self, op: OpCode, left: ExpDesc, right: ExpDesc) -> Result<ExpDesc, LuaError> {
+        if let Some(v) = const_value(&right) {
+            let c = self.add_const(v);
+            if c <= MAXARG_C {
+                let left = self.discharge_any(left)?;
+                return Ok(ExpDesc::BinaryOp(op, left, c, true));
+            }
+        }
+        /* 左操作数是保留下来的常量时还没有占栈位置,要放到右操作数的临时变量之后 */
+        let right = self.discharge_any(right)?;
+        let left = self.discharge_any(left)?;
+        return Ok(ExpDesc::BinaryOp(op, left, right, false));
+    }
+
+    /** 比较运算 : 直接生成比较+Jmp,比较结果为expect时跳转 */
+    fn compare_binop(&mut self, op: OpCode, expect: bool, left: ExpDesc, right: ExpDesc) -> Result<ExpDesc, LuaError> {
+        let nsp = self.fs.sp;
+        let code = match (op, const_value(&right)) {
+            (OpCode::Eq, Some(v)) if self.add_const(v.clone()) <= MAXARG_B => {
+                let ileft = self.discharge_any(left)?;
+                ByteCode::abck(OpCode::EqConst, ileft, self.add_const(v), 0, expect)
+            }
+            _ => {
+                /* 和arith_binop一样 : 保留下来的常量左操作数在右操作数之后载入 , 不然会被右操作数中的跳转跳过 */
+                let iright = self.discharge_any(right)?;
+                let ileft = self.discharge_any(left)?;
+                ByteCode::abck(op, ileft, iright, 0, expect)
+            }
+        };
+        self.push_code(code)?;
+        /* 比较的结果不占栈位置,操作数用到的临时变量都可以释放 */
+        self.fs.sp = self.fs.sp.min(nsp.max(self.fs.locals.len()));
+        return Ok(ExpDesc::Jump(self.jump()?));
+    }
+
+    /* 消除左递归的value解析 */
+    fn prefixexp(&mut self, token: Token) -> Result<ExpDesc, LuaError> {
+        return Ok(self.prefixexp_var(token)?.0);
+    }
+
+    /** prefixexp , 同时返回它能否被赋值 : 括号括起来的表达式不是变量 , 后面再接索引才又是变量 */
+    fn prefixexp_var(&mut self, token: Token) -> Result<(ExpDesc, bool), LuaError> {
+        let mut assignable = token != Token::ParL;
+        let mut desc_code = match token {
+            Token::Name(name) => self.simple_name(name)? /* parse the name */,
+            Token::ParL => {
+                let desc = self.exp()?; /* 这里使用递归调用获取exp */
+                self.lex.expect(Token::ParR)?; /* consume ')'  */
+                /* 括号把多返回值截断成一个值 */
+                match desc {
+                    ExpDesc::Call(_) | ExpDesc::VarArg(_) => ExpDesc::Local(self.discharge_any(desc)?),
+                    desc => desc,
+                }
+            }
+            t => {
+                return Err(self.lex.error_near("unexpected symbol", &t));
+            }
+        };
+        // [key] = value
+        loop {
+            match self.lex.peek()? {
+                Token::SqurL => {
+                    // [ exp ]
+                    self.lex.next()?;
+                    let itable = self.discharge_any(desc_code)?;
+                    desc_code = match self.exp()? {
+                        ExpDesc::String(s) => ExpDesc::IndexField(itable, self.add_const(s)),
+                        ExpDesc::Integer(i) if u8::try_from(i).is_ok() =>
+                            ExpDesc::IndexInt(itable, u8::try_from(i).unwrap()),
+                        key => ExpDesc::Index(itable, self.discharge_any(key)?),
+                    };
+                    self.lex.expect(Token::SqurR)?;
+                    assignable = true;
+                }
+                Token::Dot => {
+                    // .name
+                    self.lex.next()?;
+                    let name = self.read_name()?;
+                    let itable = self.discharge_any(desc_code)?;
+                    desc_code = ExpDesc::IndexField(itable, self.add_const(name));
+                    assignable = true;
+                }
+                /* 函数调用 */
+                Token::ParL | Token::String(_) | Token::CurlyL => {
+                    desc_code = self.function_call(desc_code)?;
+                }
+                Token::Colon => {
+                    self.lex.next()?;
+                    desc_code = self.method_call(desc_code)?;
+                }
+                _ => {
+                    return Ok((desc_code, assignable)); /* direct return desc */
+                }
+            }
+        }
+    }
+
+    /** String<Local|Upvalue|全局变量> -> ExpDesc */
+    fn simple_name(&mut self, name: String) -> Result<ExpDesc, LuaError> {
+        /* 判断变量名是局部变量、Upvalue还是全局变量 */
+        if let Some(idx) = self.fs.locals.iter().rposition(|v| v.name == name) {
+            if let Some(value) = &self.fs.locals[idx].value {
+                return Ok(value.clone()); /* 编译期常量 */
+            }
+            return Ok(ExpDesc::Local(idx)); /* 栈上的临时变量 */
+        }
+        let level = self.enclosing.len();
+        if let Some(value) = self.find_const(level, &name) {
+            return Ok(value);
+        }
+        if let Some(idx) = self.find_upvalue(level, &name)? {
+            return Ok(ExpDesc::Upvalue(idx));
+        }
+        /* 全局变量 : 就是_ENV.name , _ENV是普通的局部变量或者Upvalue(主代码块的第一个Upvalue) , 一定能找到 */
+        let iname = self.add_const(name);
+        let desc = match self.simple_name(String::from("_ENV"))? {
+            ExpDesc::Local(i) => ExpDesc::IndexField(i, iname),
+            ExpDesc::Upvalue(i) if iname <= MAXARG_B => ExpDesc::IndexUpField(i, iname),
+            env => {
+                /* key常量索引放不进B参数 : 先把_ENV载入栈上 */
+                let ienv = self.discharge_any(env)?;
+                ExpDesc::IndexField(ienv, iname)
+            }
+        };
+        return Ok(desc);
+    }
+
+    /** 第level层函数(当前函数是最内层 , 即enclosing.len())中名字为name的Upvalue :
+        依次到外层函数中找局部变量,找到后沿途每一层函数都加上对应的Upvalue */
+    fn find_upvalue(&mut self, level: usize, name: &str) -> Result<Option<usize>, LuaError> {
+        if let Some(idx) = self.func_state(level).upvalues.iter().position(|(n, _)| n == name) {
+            return Ok(Some(idx));
+        }
+        if level == 0 {
+            return Ok(None);
+        }
+        let parent = self.func_state(level - 1);
+        let up = match parent.locals.iter().rposition(|v| v.name == name) {
+            Some(i) => {
+                parent.locals[i].captured = true;
+                UpIndex::Local(i)
+            }
+            None =>
+                match self.find_upvalue(level - 1, name)? {
+                    Some(i) => UpIndex::Upvalue(i),
+                    None => {
+                        return Ok(None);
+                    }
+                }
+        };
+        if self.func_state(level).upvalues.len() > MAXARG_B {
+            return Err(self.limit_error(level, "upvalues", MAXARG_B + 1));
+        }
+        let fs = self.func_state(level);
+        fs.upvalues.push((name.to_string(), up));
+        return Ok(Some(fs.upvalues.len() - 1));
+    }
+
+    /** 外层函数中名字为name的编译期常量 : 内层函数直接使用它的值 , 不需要Upvalue */
+    fn find_const(&mut self, level: usize, name: &str) -> Option<ExpDesc> {
+        for lv in (0..level).rev() {
+            /* 已经是Upvalue的一定不是编译期常量 */
+            if self.func_state(lv + 1).upvalues.iter().any(|(n, _)| n == name) {
+                return None;
+            }
+            let fs = self.func_state(lv);
+            if let Some(var) = fs.locals.iter().rev().find(|v| v.name == name) {
+                return var.value.clone();
+            }
+        }
+        return None;
+    }
+
+    /** 第level层函数的解析状态 */
+    fn func_state(&mut self, level: usize) -> &mut FuncState {
+        if level == self.enclosing.len() {
+            return &mut self.fs;
+        }
+        return &mut self.enclosing[level];
+    }
+
+    /** read name  */
+    fn read_name(&mut self) -> Result<String, LuaError> {
+        match self.lex.next()? {
+            Token::Name(name) => {
+                return Ok(name);
+            }
+            t => {
+                return Err(self.lex.error_near("<name> expected", &t));
+            }
+        }
+    }
+
+    /** 占用栈顶的一个位置作为临时变量 , 栈位置用完时报错 */
+    fn reserve_reg(&mut self) -> Result<usize, LuaError> {
+        let reg = self.fs.sp;
+        if reg >= NO_REG {
+            return Err(self.lex.error_ahead("function or expression needs too many registers"));
+        }
+        self.fs.sp += 1;
+        return Ok(reg);
+    }
+
+    /** 将ExpDesc转化成byteCode ,然后推到指定栈dst上 */
+    fn discharge(&mut self, dst: usize, desc: ExpDesc) -> Result<(), LuaError> {
+        if dst >= NO_REG {
+            return Err(self.lex.error_ahead("function or expression needs too many registers"));
+        }
+        /* 将ExpDesc转化成byteCode后 推入当前栈顶 */
+        let code = match desc {
+            ExpDesc::Nil => ByteCode::abc(OpCode::LoadNil, dst, 0, 0),
+            ExpDesc::Boolean(b) => ByteCode::abc(OpCode::LoadBool, dst, b as usize, 0),
+            /* 放不进sBx的整数只能走常量表 */
+            ExpDesc::Integer(i) if fits_sbx(i) => ByteCode::asbx(OpCode::LoadInt, dst, i),
+            ExpDesc::Integer(i) => {
+                self.load_const(dst, Value::Integer(i))?;
+                self.occupy(dst);
+                return Ok(());
+            }
+            ExpDesc::Float(f) => {
+                self.load_const(dst, Value::Float(f))?;
+                self.occupy(dst);
+                return Ok(());
+            }
+            ExpDesc::String(s) => {
+                self.load_const(dst, s.into())?;
+                self.occupy(dst);
+                return Ok(());
+            }
+            ExpDesc::Local(src) => {
+                //Local表示数据是从栈上获取的,所以使用Move
+                if dst != src && !self.retarget_last(src, dst) {
+                    self.push_code(ByteCode::abc(OpCode::Move, dst, src, 0))?;
+                }
+                self.occupy(dst);
+                return Ok(());
+            }
+            ExpDesc::Index(t, key) => ByteCode::abc(OpCode::GetTable, dst, t, key),
+            ExpDesc::IndexField(t, key) if key <= MAXARG_C =>
+                ByteCode::abc(OpCode::GetField, dst, t, key),
+            ExpDesc::IndexField(t, key) => {
+                /* key常量索引放不进C参数 : 先把key载入栈上 */
+                let ikey = self.fs.sp.max(dst + 1);
+                self.load_const_index(ikey, key)?;
+                ByteCode::abc(OpCode::GetTable, dst, t, ikey)
+            }
+            ExpDesc::IndexInt(t, key) => ByteCode::abc(OpCode::GetInt, dst, t, key as usize),
+            ExpDesc::IndexUpField(t, key) => ByteCode::abc(OpCode::GetUpField, dst, t, key),
+            ExpDesc::UnaryOp(op, operand) => ByteCode::abc(op, dst, operand, 0),
+            ExpDesc::BinaryOp(op, left, right, k) => ByteCode::abck(op, dst, left, right, k),
+            ExpDesc::Jump(_) | ExpDesc::Test(..) => {
+                self.discharge_cond(dst, desc)?;
+                self.occupy(dst);
+                return Ok(());
+            }
+            ExpDesc::Upvalue(idx) => ByteCode::abc(OpCode::GetUpval, dst, idx, 0),
+            ExpDesc::Closure(idx) => {
+                self.push_abx_ext(OpCode::Closure, dst, idx)?;
+                self.occupy(dst);
+                return Ok(());
+            }
+            /* 只保留一个值 : 结果在函数(或者...)的位置上 */
+            ExpDesc::Call(pc) | ExpDesc::VarArg(pc) => {
+                let r = self.fs.byte_codes[pc].set_c(2);
+                self.check_code(r)?;
+                let src = self.fs.byte_codes[pc].a();
+                if dst != src {
+                    self.push_code(ByteCode::abc(OpCode::Move, dst, src, 0))?;
+                }
+                self.occupy(dst);
+                return Ok(());
+            }
+        };
+        self.push_code(code)?;
+        self.occupy(dst);
+        return Ok(());
+    }
+
+    /** 栈位置dst已经被占用 : dst是临时变量时,它之上的临时变量都可以释放了 */
+    fn occupy(&mut self, dst: usize) {
+        if dst >= self.fs.locals.len() {
+            self.fs.sp = dst + 1;
+        }
+    }
+
+    /** 把条件跳转求值到dst上 : 跳转列表中带值的TestSet直接写dst,其他跳转跳到载入true/false的字节码 */
+    fn discharge_cond(&mut self, dst: usize, desc: ExpDesc) -> Result<(), LuaError> {
+        let (is_jump, true_list, false_list) = match desc {
+            ExpDesc::Jump(pc) => (true, vec![pc], Vec::new()),
+            ExpDesc::Test(cond, mut true_list, false_list) =>
+                match *cond {
+                    ExpDesc::Jump(pc) => {
+                        true_list.push(pc);
+                        (true, true_list, false_list)
+                    }
+                    cond => {
+                        self.discharge(dst, cond)?;
+                        (false, true_list, false_list)
+                    }
+                }
+            _ => panic!("not a condition"),
+        };
+
+        let (mut p_false, mut p_true) = (None, None);
+        if self.need_value(&true_list) || self.need_value(&false_list) {
+            /* 表达式本身不是比较时,顺序执行到这里需要跳过下面的true/false */
+            let jmp = if is_jump { None } else { Some(self.jump()?) };
+            p_false = Some(self.fs.byte_codes.len());
+            self.push_code(ByteCode::abc(OpCode::LoadFalseSkip, dst, 0, 0))?;
+            p_true = Some(self.fs.byte_codes.len());
+            self.push_code(ByteCode::abc(OpCode::LoadBool, dst, 1, 0))?;
+            if let Some(jmp) = jmp {
+                self.patch_to_here(vec![jmp])?;
+            }
+        }
+        let end = self.label();
+        self.patch_list_aux(false_list, end, Some(dst), p_false.unwrap_or(end))?;
+        self.patch_list_aux(true_list, end, Some(dst), p_true.unwrap_or(end))?;
+        return Ok(());
+    }
+
+    /** 将ExpDesc放到任意栈位置 : 局部变量不需要移动,其他的放到栈顶
+        @return 栈位置
+     */
+    fn discharge_any(&mut self, desc: ExpDesc) -> Result<usize, LuaError> {
+        match desc {
+            ExpDesc::Local(i) => {
+                return Ok(i);
+            }
+            /* 函数调用的结果本来就在栈顶 */
+            ExpDesc::Call(pc) | ExpDesc::VarArg(pc) => {
+                let r = self.fs.byte_codes[pc].set_c(2);
+                self.check_code(r)?;
+                let a = self.fs.byte_codes[pc].a();
+                self.occupy(a);
+                return Ok(a);
+            }
+            _ => {
+                return self.discharge_top(desc);
+            }
+        }
+    }
+
+    /** 将ExpDesc放到栈顶 : 已经在栈顶的临时变量(比如函数调用的结果)原地使用 */
+    fn discharge_temp(&mut self, desc: ExpDesc) -> Result<usize, LuaError> {
+        let i = self.discharge_any(desc)?;
+        if self.is_top_temp(i) {
+            return Ok(i);
+        }
+        return self.discharge_top(ExpDesc::Local(i));
+    }
+
+    /** 栈位置i是不是栈顶的临时变量 */
+    fn is_top_temp(&self, i: usize) -> bool {
+        return i >= self.fs.locals.len() && i + 1 == self.fs.sp;
+    }
+
+    /** 将ExpDesc放到栈顶 : 局部变量也会复制一份 */
+    fn discharge_top(&mut self, desc: ExpDesc) -> Result<usize, LuaError> {
+        self.free_operands(&desc);
+        let dst = self.fs.sp;
+        self.discharge(dst, desc)?;
+        return Ok(dst);
+    }
+
+    /** 释放栈顶的临时变量i : TestSet执行时已经读过它 , 后面的操作数可以放在同一个位置 */
+    fn free_temp(&mut self, i: usize) {
+        if self.is_top_temp(i) {
+            self.fs.sp = i;
+        }
+    }
+
+    /** 释放表达式的操作数占用的临时变量 , 表达式的结果可以直接覆盖它们 */
+    fn free_operands(&mut self, desc: &ExpDesc) {
+        let nlocal = self.fs.locals.len();
+        let free = |r: usize, sp: usize| if r >= nlocal { sp.min(r) } else { sp };
+        self.fs.sp = match *desc {
+            ExpDesc::UnaryOp(_, r) | ExpDesc::IndexField(r, _) | ExpDesc::IndexInt(r, _) =>
+                free(r, self.fs.sp),
+            ExpDesc::BinaryOp(_, l, r, false) | ExpDesc::Index(l, r) => free(r, free(l, self.fs.sp)),
+            ExpDesc::BinaryOp(_, l, _, true) => free(l, self.fs.sp),
+            _ => self.fs.sp,
         };
     }
 
     /** ExpDesc -> ConStack :: 通过ExpDesc转化成对应的堆栈状态获取 */
-    fn discharge_const(&mut self, desc: ExpDesc) -> ConstStack {
-        return match desc {
-            ExpDesc::Nil => ConstStack::Const(self.add_const(None)),
-            ExpDesc::Boolean(b) => ConstStack::Const(self.add_const(b)),
-            ExpDesc::Integer(i) => ConstStack::Const(self.add_const(i)),
-            ExpDesc::Float(f) => ConstStack::Const(self.add_const(f)),
-            ExpDesc::String(s) => ConstStack::Const(self.add_const(s)),
-            _ => ConstStack::Stack(self.discharge_top(desc)),
+    fn discharge_const(&mut self, desc: ExpDesc) -> Result<ConstStack, LuaError> {
+        return match const_value(&desc) {
+            Some(v) => Ok(ConstStack::Const(self.add_const(v))),
+            None => Ok(ConstStack::Stack(self.discharge_any(desc)?)),
         };
     }
+
+    /** peephole : 上一条字节码把结果写到临时变量src,紧接着又Move到dst时,直接让上一条写到dst */
+    fn retarget_last(&mut self, src: usize, dst: usize) -> bool {
+        let pc = self.fs.byte_codes.len();
+        if src < self.fs.locals.len() || pc == 0 || pc == self.fs.last_target {
+            return false;
+        }
+        let last = self.fs.byte_codes[pc - 1];
+        if last.a() != src {
+            return false;
+        }
+        match last.op() {
+            | OpCode::LoadNil
+            | OpCode::LoadBool
+            | OpCode::LoadInt
+            | OpCode::LoadConst
+            | OpCode::Move
+            | OpCode::GetUpField
+            | OpCode::GetUpval
+            | OpCode::Closure
+            | OpCode::GetTable
+            | OpCode::GetField
+            | OpCode::GetInt
+            | OpCode::NewTable
+            | OpCode::NewTableConst
+            | OpCode::Add
+            | OpCode::Sub
+            | OpCode::Mul
+            | OpCode::Div
+            | OpCode::IDiv
+            | OpCode::Mod
+            | OpCode::Pow
+            | OpCode::BAnd
+            | OpCode::BOr
+            | OpCode::BXor
+            | OpCode::Shl
+            | OpCode::Shr
+            | OpCode::Concat
+            | OpCode::Unm
+            | OpCode::BNot
+            | OpCode::Not
+            | OpCode::Len => {
+                /* dst放不进A参数时不改写 , 之后的Move会报错 */
+                return self.fs.byte_codes[pc - 1].set_a(dst).is_ok();
+            }
+            _ => {
+                return false;
+            }
+        }
+    }
+
+    /** 条件为真时继续执行,返回条件为假时的跳转列表 */
+    fn test_or_jump(&mut self, cond: ExpDesc) -> Result<Vec<usize>, LuaError> {
+        let list = match cond {
+            /* 恒为真 : 不需要判断 ; 恒为假时跳转需要带上常量的值,和普通表达式一样处理 */
+            _ if const_truthy(&cond) == Some(true) => Vec::new(),
+            ExpDesc::Jump(pc) => {
+                self.negate_cond(pc);
+                vec![pc]
+            }
+            ExpDesc::Test(cond, true_list, mut false_list) => {
+                let mut list = self.test_or_jump(*cond)?;
+                false_list.append(&mut list);
+                self.patch_to_here(true_list)?;
+                false_list
+            }
+            _ => {
+                let icond = self.discharge_any(cond)?;
+                self.free_temp(icond);
+                self.push_code(ByteCode::abck(OpCode::TestSet, NO_REG, icond, 0, false))?;
+                vec![self.jump()?]
+            }
+        };
+        return Ok(list);
+    }
+
+    /** 条件为假时继续执行,返回条件为真时的跳转列表 */
+    fn test_and_jump(&mut self, cond: ExpDesc) -> Result<Vec<usize>, LuaError> {
+        let list = match cond {
+            _ if const_truthy(&cond) == Some(false) => Vec::new(),
+            ExpDesc::Jump(pc) => vec![pc],
+            ExpDesc::Test(cond, mut true_list, false_list) => {
+                let mut list = self.test_and_jump(*cond)?;
+                true_list.append(&mut list);
+                self.patch_to_here(false_list)?;
+                true_list
+            }
+            _ => {
+                let icond = self.discharge_any(cond)?;
+                self.free_temp(icond);
+                self.push_code(ByteCode::abck(OpCode::TestSet, NO_REG, icond, 0, true))?;
+                vec![self.jump()?]
+            }
+        };
+        return Ok(list);
+    }
+
+    /** 生成字节码,同时记录行号 */
+    fn push_code(&mut self, code: Result<ByteCode, &'static str>) -> Result<(), LuaError> {
+        let code = self.check_code(code)?;
+        self.fs.byte_codes.push(code);
+        self.fs.lineinfo.push(self.lex.line() as u32);
+        return Ok(());
+    }
+
+    /** 字节码参数超出位宽 : 报告为编译错误 */
+    fn check_code<T>(&mut self, r: Result<T, &'static str>) -> Result<T, LuaError> {
+        return r.map_err(|msg| self.lex.error_ahead(msg));
+    }
+
+    /** 最后一条字节码改记为第line行 : 循环跳回的指令算在for所在的行 */
+    fn fix_line(&mut self, line: usize) {
+        if let Some(l) = self.fs.lineinfo.last_mut() {
+            *l = line as u32;
+        }
+    }
+
+    /** 丢弃n之后的字节码 */
+    fn truncate_code(&mut self, n: usize) {
+        self.fs.byte_codes.truncate(n);
+        self.fs.lineinfo.truncate(n);
+    }
+
+    /** 生成一个待回填的Jmp */
+    fn jump(&mut self) -> Result<usize, LuaError> {
+        self.push_code(ByteCode::sj(OpCode::Jmp, 0))?;
+        return Ok(self.fs.byte_codes.len() - 1);
+    }
+
+    /** 当前位置作为跳转目标 */
+    fn label(&mut self) -> usize {
+        self.fs.last_target = self.fs.byte_codes.len();
+        return self.fs.last_target;
+    }
+
+    /** 回填Jmp的目标位置 */
+    fn fix_jump(&mut self, pc: usize, target: usize) -> Result<(), LuaError> {
+        let r = self.fs.byte_codes[pc].set_sj((target as i64) - (pc as i64) - 1);
+        return self.check_code(r);
+    }
+
+    /** Jmp前面如果是判断指令,那么真正控制跳转的是判断指令 */
+    fn jump_control(&self, pc: usize) -> usize {
+        if pc >= 1 && is_test_op(self.fs.byte_codes[pc - 1].op()) {
+            return pc - 1;
+        }
+        return pc;
+    }
+
+    /** 把条件取反 */
+    fn negate_cond(&mut self, pc: usize) {
+        let ic = self.jump_control(pc);
+        let k = self.fs.byte_codes[ic].k();
+        self.fs.byte_codes[ic].set_k(!k);
+    }
+
+    /** 跳转列表中是否有不带值的跳转 */
+    fn need_value(&self, list: &[usize]) -> bool {
+        return list.iter().any(|pc| self.fs.byte_codes[self.jump_control(*pc)].op() != OpCode::TestSet);
+    }
+
+    /** 回填TestSet的目标栈位置,不需要赋值时退化成Test ; 返回是否是TestSet */
+    fn patch_test_reg(&mut self, pc: usize, reg: Option<usize>) -> Result<bool, LuaError> {
+        let ic = self.jump_control(pc);
+        let code = self.fs.byte_codes[ic];
+        if code.op() != OpCode::TestSet {
+            return Ok(false);
+        }
+        match reg {
+            Some(r) if r != code.b() => {
+                let r = self.fs.byte_codes[ic].set_a(r);
+                self.check_code(r)?;
+            }
+            _ => {
+                self.fs.byte_codes[ic] = self.check_code(ByteCode::abck(OpCode::Test, code.b(), 0, 0, code.k()))?;
+            }
+        }
+        return Ok(true);
+    }
+
+    /** 回填跳转列表 : 带值的跳转到vtarget,其他跳转到dtarget */
+    fn patch_list_aux(&mut self, list: Vec<usize>, vtarget: usize, reg: Option<usize>, dtarget: usize) -> Result<(), LuaError> {
+        for pc in list {
+            if self.patch_test_reg(pc, reg)? {
+                self.fix_jump(pc, vtarget)?;
+            } else {
+                self.fix_jump(pc, dtarget)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /** 回填跳转列表,不需要值 */
+    fn patch_list(&mut self, list: Vec<usize>, target: usize) -> Result<(), LuaError> {
+        return self.patch_list_aux(list, target, None, target);
+    }
+
+    /** 跳转列表跳到当前位置 */
+    fn patch_to_here(&mut self, list: Vec<usize>) -> Result<(), LuaError> {
+        let here = self.label();
+        return self.patch_list(list, here);
+    }
+
+    /** 丢弃cut之后的字节码(死代码),以及指向这些字节码的break和goto */
+    fn discard_code(&mut self, cut: usize) {
+        self.truncate_code(cut);
+        /* 死代码中定义的局部变量不再需要调试信息 */
+        self.fs.locvars.retain(|v| v.endpc == usize::MAX || v.startpc < cut);
+        for var in self.fs.locvars.iter_mut().filter(|v| v.endpc != usize::MAX) {
+            var.endpc = var.endpc.min(cut);
+        }
+        for breaks in self.fs.break_blocks.iter_mut() {
+            breaks.retain(|pc| *pc < cut);
+        }
+        self.fs.gotos.retain(|g| g.icode < cut);
+        self.fs.last_target = cut;
+    }
+
+    /** peephole : 跳转到Jmp的跳转直接跳到最终目标 */
+    fn optimize_jumps(&mut self) -> Result<(), LuaError> {
+        let len = self.fs.byte_codes.len();
+        for pc in 0..len {
+            if self.fs.byte_codes[pc].op() != OpCode::Jmp {
+                continue;
+            }
+            let mut target = ((pc as i64) + 1 + self.fs.byte_codes[pc].sj_arg()) as usize;
+            let mut hops = 0; /* 防止死循环的goto */
+            while target < len && self.fs.byte_codes[target].op() == OpCode::Jmp && hops < 100 {
+                target = ((target as i64) + 1 + self.fs.byte_codes[target].sj_arg()) as usize;
+                hops += 1;
+            }
+            self.fix_jump(pc, target)?;
+        }
+        return Ok(());
+    }
+}
+
+/** 按照运行时执行字节码的顺序构造常量table : 数组元素攒够一批才写入,保证和逐项构造的结果完全一致 */
+fn const_table(entries: Vec<(Option<Value>, Value)>, narray: usize, nmap: usize) -> Table {
+    let mut table = Table::new(narray, nmap);
+    let mut pending = Vec::new();
+    let mut before = 0;
+    let flush = |table: &mut Table, pending: &mut Vec<Value>, before: &mut usize| {
+        for (i, v) in pending.drain(..).enumerate() {
+            table.set_int((*before + i + 1) as i64, v);
+        }
+        *before += FIELDS_PER_FLUSH;
+    };
+    for (key, value) in entries {
+        match key {
+            Some(key) => table.set(key, value),
+            None => {
+                pending.push(value);
+                if pending.len() == FIELDS_PER_FLUSH {
+                    flush(&mut table, &mut pending, &mut before);
+                }
+            }
+        }
+    }
+    flush(&mut table, &mut pending, &mut before);
+    return table;
+}
+
+/** 可以被赋值的表达式 */
+fn is_var(desc: &ExpDesc) -> bool {
+    return matches!(
+        desc,
+        ExpDesc::Local(_)
+            | ExpDesc::Upvalue(_)
+            | ExpDesc::Index(..)
+            | ExpDesc::IndexField(..)
+            | ExpDesc::IndexInt(..)
+            | ExpDesc::IndexUpField(..)
+    );
+}
+
+/** 判断指令 : 后面紧跟Jmp */
+fn is_test_op(op: OpCode) -> bool {
+    return matches!(
+        op,
+        OpCode::Eq | OpCode::EqConst | OpCode::Lt | OpCode::Le | OpCode::Test | OpCode::TestSet
+    );
+}
+
+/** 常量ExpDesc对应的Value */
+fn const_value(desc: &ExpDesc) -> Option<Value> {
+    return match desc {
+        ExpDesc::Nil => Some(Value::Nil),
+        ExpDesc::Boolean(b) => Some(Value::Boolean(*b)),
+        ExpDesc::Integer(i) => Some(Value::Integer(*i)),
+        ExpDesc::Float(f) => Some(Value::Float(*f)),
+        ExpDesc::String(s) => Some(s.clone().into()),
+        _ => None,
+    };
+}
+
+/** 常量的真值,非常量返回None */
+fn const_truthy(desc: &ExpDesc) -> Option<bool> {
+    return const_value(desc).map(|v| v.truthy());
+}
+
+/** 数字常量 */
+fn const_number(desc: &ExpDesc) -> Option<Value> {
+    return match desc {
+        ExpDesc::Integer(i) => Some(Value::Integer(*i)),
+        ExpDesc::Float(f) => Some(Value::Float(*f)),
+        _ => None,
+    };
+}
+
+/** 折叠的结果 : 和Lua官方实现一样不折叠NaN和0.0(避免-0.0的问题) */
+fn folded_number(v: Value) -> Option<ExpDesc> {
+    return match v {
+        Value::Integer(i) => Some(ExpDesc::Integer(i)),
+        Value::Float(f) if f.is_nan() || f == 0.0 => None,
+        Value::Float(f) => Some(ExpDesc::Float(f)),
+        _ => None,
+    };
+}
+
+/** 一元运算的常量折叠 */
+fn fold_unop(op: OpCode, desc: &ExpDesc) -> Option<ExpDesc> {
+    return match op {
+        OpCode::Not => const_truthy(desc).map(|b| ExpDesc::Boolean(!b)),
+        OpCode::Unm | OpCode::BNot => folded_number(arith::unary(op, &const_number(desc)?).ok()?),
+        _ => None,
+    };
+}
+
+/** 二元运算的常量折叠 : 规则和虚拟机执行完全一致,会在运行时报错的运算(比如整数除0)不折叠 */
+fn fold_binop(binop: &Token, left: &ExpDesc, right: &ExpDesc) -> Option<ExpDesc> {
+    let op = match binop {
+        Token::Add => OpCode::Add,
+        Token::Sub => OpCode::Sub,
+        Token::Mul => OpCode::Mul,
+        Token::Div => OpCode::Div,
+        Token::Idiv => OpCode::IDiv,
+        Token::Mod => OpCode::Mod,
+        Token::Pow => OpCode::Pow,
+        Token::BitAnd => OpCode::BAnd,
+        Token::BitOr => OpCode::BOr,
+        Token::BitXor => OpCode::BXor,
+        Token::ShiftL => OpCode::Shl,
+        Token::ShiftR => OpCode::Shr,
+        Token::Equal | Token::NotEq => {
+            let eq = arith::equal(&const_value(left)?, &const_value(right)?);
+            return Some(ExpDesc::Boolean(eq == (binop == &Token::Equal)));
+        }
+        Token::Less | Token::LesEq | Token::Greater | Token::GreEq => {
+            let (l, r) = (const_value(left)?, const_value(right)?);
+            let r = match binop {
+                Token::Less => arith::compare(OpCode::Lt, &l, &r),
+                Token::LesEq => arith::compare(OpCode::Le, &l, &r),
+                Token::Greater => arith::compare(OpCode::Lt, &r, &l),
+                _ => arith::compare(OpCode::Le, &r, &l),
+            };
+            return r.ok().map(ExpDesc::Boolean);
+        }
+        _ => {
+            return None;
+        }
+    };
+    let (l, r) = (const_number(left)?, const_number(right)?);
+    /* 除数为0不折叠 : 整数会报错,浮点数会得到inf/nan */
+    if matches!(op, OpCode::Div | OpCode::IDiv | OpCode::Mod) && arith::equal(&r, &Value::Integer(0)) {
+        return None;
+    }
+    return folded_number(arith::arith(op, &l, &r).ok()?);
+}
+
+/** 二元运算符的(左优先级,右优先级) , 非二元运算符返回(-1,-1) */
+fn binop_priority(binop: &Token) -> (i32, i32) {
+    return match binop {
+        Token::Pow => (14, 13) /* 右结合 */,
+        Token::Mul | Token::Mod | Token::Div | Token::Idiv => (11, 11),
+        Token::Add | Token::Sub => (10, 10),
+        Token::Concat => (9, 8) /* 右结合 */,
+        Token::ShiftL | Token::ShiftR => (7, 7),
+        Token::BitAnd => (6, 6),
+        Token::BitXor => (5, 5),
+        Token::BitOr => (4, 4),
+        Token::Equal | Token::NotEq | Token::Less | Token::Greater | Token::LesEq | Token::GreEq =>
+            (3, 3),
+        Token::And => (2, 2),
+        Token::Or => (1, 1),
+        _ => (-1, -1),
+    };
 }
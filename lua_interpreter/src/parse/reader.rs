@@ -0,0 +1,42 @@
+use std::io::{ self, Read };
+
+/** ### 回调读取器
+    把 `FnMut() -> Option<Vec<u8>>` 形式的回调包装成Read,每次回调返回源代码的一段 ,
+    回调返回None或者空内容表示源代码结束,之后不会再调用回调
+ */
+pub struct ChunkReader<F> {
+    reader: F,
+    piece: Vec<u8> /* 当前正在读取的一段 */,
+    pos: usize /* 当前段中已经读取的位置 */,
+    done: bool,
+}
+
+impl<F: FnMut() -> Option<Vec<u8>>> ChunkReader<F> {
+    pub fn new(reader: F) -> Self {
+        return ChunkReader { reader, piece: Vec::new(), pos: 0, done: false };
+    }
+}
+
+impl<F: FnMut() -> Option<Vec<u8>>> Read for ChunkReader<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        /* 当前段读完了才向回调要下一段 */
+        while self.pos >= self.piece.len() {
+            if self.done {
+                return Ok(0);
+            }
+            match (self.reader)() {
+                Some(piece) if !piece.is_empty() => {
+                    self.piece = piece;
+                    self.pos = 0;
+                }
+                _ => {
+                    self.done = true;
+                }
+            }
+        }
+        let n = buf.len().min(self.piece.len() - self.pos);
+        buf[..n].copy_from_slice(&self.piece[self.pos..self.pos + n]);
+        self.pos += n;
+        return Ok(n);
+    }
+}
@@ -0,0 +1,294 @@
+use std::{ rc::Rc, cell::RefCell, mem };
+
+use crate::interface::{ Value, LuaError, Upvalue };
+
+use super::{ ExeState, CallFrame, MAX_RUST_CALLS };
+
+/** 协程的状态 */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThreadStatus {
+    Suspended /* 还没有开始,或者yield了 */,
+    Running,
+    Normal /* resume了别的协程,等待它yield或者结束 */,
+    Dead /* 执行完毕或者出错 */,
+}
+
+impl ThreadStatus {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ThreadStatus::Suspended => "suspended",
+            ThreadStatus::Running => "running",
+            ThreadStatus::Normal => "normal",
+            ThreadStatus::Dead => "dead",
+        };
+    }
+}
+
+/** ### 线程(协程)
+    每个线程有自己的栈和调用链 : 正在运行的线程的执行状态放在ExeState中 , 其他线程的保存在这里 ,
+    切换线程就是交换这些字段 ; yield像错误一样离开Rust的调用栈 , 调用链上的帧都留在这里 , resume时接着执行
+ */
+pub struct LuaThread {
+    pub(super) stack: Vec<Value>,
+    pub(super) func_index: usize,
+    pub(super) frames: Vec<CallFrame>,
+    pub(super) open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    pub(super) tbc_list: Vec<usize>,
+    pub(super) nny: usize,
+    status: ThreadStatus,
+    pub(super) is_main: bool,
+    error: Option<Value> /* 出错结束时的错误 , close之后清除 */,
+}
+
+impl LuaThread {
+    /** 主线程 : 不能yield */
+    pub(super) fn main() -> Self {
+        let mut t = Self::new(Value::Nil);
+        t.stack.clear();
+        t.status = ThreadStatus::Running;
+        t.is_main = true;
+        return t;
+    }
+
+    /** 新的协程 , 函数放在栈底 */
+    fn new(f: Value) -> Self {
+        return LuaThread {
+            stack: vec![f],
+            func_index: 0,
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+            tbc_list: Vec::new(),
+            nny: 0,
+            status: ThreadStatus::Suspended,
+            is_main: false,
+            error: None,
+        };
+    }
+
+    pub fn status(&self) -> ThreadStatus {
+        return self.status;
+    }
+}
+
+/** 没有执行完的协程被丢弃时 , 还在它栈上的Upvalue要关闭 , 引用它们的闭包还可能在使用 */
+impl Drop for LuaThread {
+    fn drop(&mut self) {
+        for up in self.open_upvalues.drain(..) {
+            let mut up = up.borrow_mut();
+            if let Upvalue::Open(i, _) = *up {
+                *up = Upvalue::Closed(self.stack.get(i).cloned().unwrap_or(Value::Nil));
+            }
+        }
+    }
+}
+
+/** ### 协程相关的API */
+impl ExeState {
+    /** 用函数f创建协程 */
+    pub fn new_thread(&self, f: Value) -> Rc<RefCell<LuaThread>> {
+        return Rc::new(RefCell::new(LuaThread::new(f)));
+    }
+
+    /** 正在运行的线程,以及它是不是主线程 */
+    pub fn running(&self) -> (Rc<RefCell<LuaThread>>, bool) {
+        let is_main = self.current.borrow().is_main;
+        return (self.current.clone(), is_main);
+    }
+
+    /** 线程能否yield : 不是主线程,并且没有在不带后续的Rust调用中 */
+    pub fn is_yieldable(&self, co: &Rc<RefCell<LuaThread>>) -> bool {
+        if Rc::ptr_eq(co, &self.current) {
+            return !co.borrow().is_main && self.nny == 0;
+        }
+        let co = co.borrow();
+        return !co.is_main && co.nny == 0;
+    }
+
+    /** 挂起当前协程 , 栈顶的n个值传给resume : Rust函数用 return state.yield_values(n) 结束 ;
+        协程恢复之后,resume的参数作为这个Rust函数的返回值
+     */
+    pub fn yield_values(&mut self, n: usize) -> Result<i32, LuaError> {
+        if self.current.borrow().is_main {
+            return Err(self.error("attempt to yield from outside a coroutine"));
+        }
+        if self.nny > 0 {
+            return Err(self.error("attempt to yield across a C-call boundary"));
+        }
+        return Err(LuaError::yielding(n));
+    }
+
+    /** 恢复执行协程 , 参数是栈顶的nargs个值 : yield或者结束时,传出的值push到栈上,返回它们的个数 ;
+        协程出错时返回错误 , 协程的调用链保留下来,用于查看出错的位置
+     */
+    pub fn resume(&mut self, co: &Rc<RefCell<LuaThread>>, nargs: usize) -> Result<usize, LuaError> {
+        match co.borrow().status {
+            ThreadStatus::Suspended => (),
+            ThreadStatus::Dead => {
+                return Err(LuaError::new("cannot resume dead coroutine"));
+            }
+            _ => {
+                return Err(LuaError::new("cannot resume non-suspended coroutine"));
+            }
+        }
+        if self.rust_calls >= MAX_RUST_CALLS {
+            return Err(LuaError::new("C stack overflow"));
+        }
+        let args = self.stack.split_off(self.stack.len() - nargs);
+        let prev = self.current.clone();
+        prev.borrow_mut().status = ThreadStatus::Normal;
+        co.borrow_mut().status = ThreadStatus::Running;
+        self.switch(co);
+
+        self.rust_calls += 1;
+        let r = self.resume_run(args);
+        self.rust_calls -= 1;
+        let (status, values, r) = match r {
+            Ok(()) => (ThreadStatus::Dead, mem::take(&mut self.stack), Ok(())),
+            Err(e) if e.is_yield() => {
+                let n = match e.value {
+                    Value::Integer(n) => n as usize,
+                    _ => 0,
+                };
+                let values = self.stack.split_off(self.stack.len() - n);
+                (ThreadStatus::Suspended, values, Ok(()))
+            }
+            Err(e) => (ThreadStatus::Dead, Vec::new(), Err(e)),
+        };
+
+        self.switch(&prev);
+        prev.borrow_mut().status = ThreadStatus::Running;
+        let mut t = co.borrow_mut();
+        t.status = status;
+        if let Err(e) = &r {
+            t.error = Some(e.value.clone());
+        }
+        let n = values.len();
+        self.stack.extend(values);
+        return r.map(|_| n);
+    }
+
+    /** 关闭挂起或者结束的协程 : 关闭还没有关闭的to-be-closed变量 , 然后丢弃整个调用链 ;
+        协程出错结束过,或者__close出错时,返回错误
+     */
+    pub fn close_thread(&mut self, co: &Rc<RefCell<LuaThread>>) -> Result<(), LuaError> {
+        let err = co.borrow_mut().error.take();
+        let prev = self.current.clone();
+        prev.borrow_mut().status = ThreadStatus::Normal;
+        co.borrow_mut().status = ThreadStatus::Running;
+        self.switch(co);
+
+        /* __close中不能yield */
+        self.nny += 1;
+        self.close_upvalues(0);
+        let r = self.close_tbc(0, err.clone().unwrap_or(Value::Nil));
+        self.nny -= 1;
+        self.stack.clear();
+        self.frames.clear();
+        self.tbc_list.clear();
+
+        self.switch(&prev);
+        prev.borrow_mut().status = ThreadStatus::Running;
+        co.borrow_mut().status = ThreadStatus::Dead;
+        return match (r, err) {
+            (Err(e), _) => Err(e),
+            (Ok(()), Some(v)) => Err(LuaError::new(v)),
+            (Ok(()), None) => Ok(()),
+        };
+    }
+
+    /** 切换到线程to : 当前线程的执行状态保存到它自己的LuaThread中 */
+    fn switch(&mut self, to: &Rc<RefCell<LuaThread>>) {
+        let from = self.current.clone();
+        self.swap_thread(&mut from.borrow_mut());
+        self.swap_thread(&mut to.borrow_mut());
+        self.current = to.clone();
+    }
+
+    fn swap_thread(&mut self, t: &mut LuaThread) {
+        mem::swap(&mut self.stack, &mut t.stack);
+        mem::swap(&mut self.func_index, &mut t.func_index);
+        mem::swap(&mut self.frames, &mut t.frames);
+        mem::swap(&mut self.open_upvalues, &mut t.open_upvalues);
+        mem::swap(&mut self.tbc_list, &mut t.tbc_list);
+        mem::swap(&mut self.nny, &mut t.nny);
+    }
+
+    /** 在协程中执行 : 第一次resume时调用栈底的函数 , 之后从yield的位置继续 */
+    fn resume_run(&mut self, args: Vec<Value>) -> Result<(), LuaError> {
+        let n = args.len();
+        self.stack.extend(args);
+        if self.frames.is_empty() {
+            if !self.precall(0, n, None)? {
+                return Ok(());
+            }
+        } else {
+            /* 最上面的帧是yield的Rust函数 , resume的参数作为它的返回值 */
+            let frame = self.frames.pop().unwrap();
+            let start = self.stack.len() - n;
+            self.move_results(frame.base - 1, start, n, frame.nresults);
+            if frame.meta {
+                self.finish_op();
+            }
+        }
+        return self.unroll();
+    }
+
+    /** 执行完调用链 : Lua帧继续执行字节码 , Rust帧(被调用的函数已经返回)由它的后续完成 */
+    fn unroll(&mut self) -> Result<(), LuaError> {
+        loop {
+            let r = match self.frames.last() {
+                None => {
+                    return Ok(());
+                }
+                Some(f) if f.closure.is_some() => {
+                    /* 执行到返回下面的Rust帧为止 */
+                    let stop = self.frames.iter().rposition(|f| f.closure.is_none()).map_or(0, |i| i + 1);
+                    self.run(stop)
+                }
+                Some(_) => self.finish_pending(Ok(())),
+            };
+            match r {
+                Err(e) if e.is_yield() || e.is_interrupt() => {
+                    return Err(e);
+                }
+                Err(e) => self.recover(e)?,
+                Ok(()) => (),
+            }
+        }
+    }
+
+    /** 用后续完成最上面的Rust帧 , 它的返回值和普通的Rust函数一样处理 */
+    fn finish_pending(&mut self, r: Result<(), LuaError>) -> Result<(), LuaError> {
+        let fi = self.frames.len() - 1;
+        let pending = self.frames[fi].k.take().expect("恢复执行的Rust帧必须有后续");
+        let func = self.frames[fi].base - 1;
+        let saved = self.func_index;
+        self.func_index = func;
+        let r = (pending.k)(self, r);
+        self.func_index = saved;
+        let n = r? as usize;
+        let frame = self.frames.pop().unwrap();
+        let start = self.stack.len() - n;
+        self.move_results(func, start, n, frame.nresults);
+        if frame.meta {
+            self.finish_op();
+        }
+        return Ok(());
+    }
+
+    /** 恢复执行之后出错 : 原来的pcall已经不在Rust的调用栈上了 , 找到最近的受保护的后续(pcall_k) ,
+        像pcall一样恢复状态之后用错误完成它 ; 没有时协程出错结束
+     */
+    fn recover(&mut self, e: LuaError) -> Result<(), LuaError> {
+        let found = self.frames.iter().rposition(|f| f.k.as_ref().is_some_and(|p| p.protected.is_some()));
+        let Some(fi) = found else {
+            return Err(e);
+        };
+        let (func, handler) = self.frames[fi].k.as_ref().unwrap().protected.clone().unwrap();
+        let e = self.recover_error(e, func, fi + 1, handler);
+        if e.is_interrupt() {
+            return Err(e);
+        }
+        return self.finish_pending(Err(e));
+    }
+}
@@ -0,0 +1,539 @@
+use std::{ rc::Rc, cell::RefCell };
+
+use crate::{ interface::{ Value, LuaError, OpCode, LuaClosure }, parse::FunctionProto };
+
+use super::{ ExeState, CallFrame, LuaThread, current_line, meta_event };
+
+/** 调用链太长时,回溯信息只显示开头和结尾的层数 */
+const TRACEBACK_HEAD: usize = 10;
+const TRACEBACK_TAIL: usize = 11;
+
+/** 钩子事件 : 进入函数 , 函数返回 , 执行新的一行 , 每执行count条字节码 */
+pub const MASK_CALL: u8 = 1;
+pub const MASK_RET: u8 = 2;
+pub const MASK_LINE: u8 = 4;
+pub const MASK_COUNT: u8 = 8;
+
+/** ### debug.sethook设置的钩子
+    钩子属于整个虚拟机 , 对所有线程生效 ; 钩子函数执行期间不再触发钩子
+ */
+pub(super) struct Hook {
+    pub(super) func: Value,
+    pub(super) mask: u8 /* MASK_XXX的组合 , 为0时没有钩子 */,
+    count: usize /* count事件的间隔 */,
+    left: usize /* 距离下一次count事件还剩的字节码条数 */,
+    pub(super) running: Option<usize> /* 正在执行的钩子函数的帧位置 , 钩子执行时不再触发钩子 */,
+    old_pc: usize /* 上一次检查line事件的字节码位置 */,
+}
+
+impl Hook {
+    pub(super) fn new() -> Self {
+        return Hook { func: Value::Nil, mask: 0, count: 0, left: 0, running: None, old_pc: 0 };
+    }
+}
+
+/** ### 一层函数调用的调试信息 , 对应lua_Debug
+    行号不存在时(Rust函数)为-1
+ */
+pub struct DebugInfo {
+    pub func: Value,
+    pub source: String /* 代码块名 , Rust函数是"=[C]" */,
+    pub short_src: String /* 用于报错信息的代码块名 */,
+    pub what: &'static str /* "Lua" , "C" 或者 "main" */,
+    pub current_line: i64,
+    pub line_defined: i64,
+    pub last_line_defined: i64,
+    pub nups: usize,
+    pub nparams: usize,
+    pub is_vararg: bool,
+    pub name: Option<String> /* 根据调用方的字节码推断出的函数名 */,
+    pub namewhat: &'static str /* "global" , "local" , "method" , "field" , "upvalue" 等 , 不知道时为空 */,
+    pub is_tail_call: bool,
+    pub active_lines: Vec<u32> /* 有字节码的行 */,
+}
+
+impl DebugInfo {
+    /** 函数本身的信息 , 和调用无关 */
+    pub fn of_function(f: &Value) -> Self {
+        let mut info = DebugInfo {
+            func: f.clone(),
+            source: String::from("=[C]"),
+            short_src: String::from("[C]"),
+            what: "C",
+            current_line: -1,
+            line_defined: -1,
+            last_line_defined: -1,
+            nups: 0,
+            nparams: 0,
+            is_vararg: true,
+            name: None,
+            namewhat: "",
+            is_tail_call: false,
+            active_lines: Vec::new(),
+        };
+        if let Value::LuaFunction(closure) = f {
+            let proto = &closure.proto;
+            info.source = proto.source.to_string();
+            info.short_src = proto.chunk_id();
+            info.what = if proto.line_defined == 0 { "main" } else { "Lua" };
+            info.line_defined = proto.line_defined as i64;
+            info.last_line_defined = proto.last_line_defined as i64;
+            info.nups = proto.upindexes.len();
+            info.nparams = proto.nparam;
+            info.is_vararg = proto.is_vararg;
+            info.active_lines = proto.lineinfo.clone();
+            info.active_lines.sort_unstable();
+            info.active_lines.dedup();
+        }
+        return info;
+    }
+}
+
+/** ### 调试相关的API
+    level是调用链上的层数 : 0是栈顶的函数(比如正在执行的debug.getinfo) , 1是调用它的函数 , 以此类推 ;
+    co是要查看的线程 , 可以是正在运行的线程 , 也可以是挂起的协程
+ */
+impl ExeState {
+    /** 在线程co的调用链和栈上执行f : 正在运行的线程的状态在ExeState中 , 其他线程的保存在LuaThread中 */
+    fn with_thread<R>(&self, co: &Rc<RefCell<LuaThread>>, f: impl FnOnce(&[CallFrame], &[Value], bool) -> R) -> R {
+        if Rc::ptr_eq(co, &self.current) {
+            return f(&self.frames, &self.stack, true);
+        }
+        let t = co.borrow();
+        return f(&t.frames, &t.stack, false);
+    }
+
+    /** 第level层函数调用的信息 , 层数超出调用链时返回None */
+    pub fn get_info(&self, co: &Rc<RefCell<LuaThread>>, level: usize) -> Option<DebugInfo> {
+        return self.with_thread(co, |frames, stack, _| {
+            let fi = frames.len().checked_sub(level + 1)?;
+            let frame = &frames[fi];
+            let func = match &frame.closure {
+                Some(closure) => Value::LuaFunction(closure.clone()),
+                None => stack.get(frame.base - 1).cloned().unwrap_or(Value::Nil),
+            };
+            let mut info = DebugInfo::of_function(&func);
+            if frame.closure.is_some() {
+                info.current_line = current_line(frame) as i64;
+            }
+            info.is_tail_call = frame.tail;
+            let name = if frame.hook {
+                Some(("hook", String::from("?")))
+            } else {
+                func_name(frames, fi)
+            };
+            if let Some((namewhat, name)) = name {
+                info.namewhat = namewhat;
+                info.name = Some(name);
+            }
+            return Some(info);
+        });
+    }
+
+    /** 第level层函数的第n个局部变量的名字和值 : n为负数时是第-n个可变参数 ;
+        层数不存在时返回None , 变量不存在时返回Some(None)
+     */
+    pub fn get_local(&self, co: &Rc<RefCell<LuaThread>>, level: usize, n: i64) -> Option<Option<(String, Value)>> {
+        return self.with_thread(co, |frames, stack, _| {
+            let fi = frames.len().checked_sub(level + 1)?;
+            return Some(find_local(frames, stack.len(), fi, n).map(|(name, slot)| {
+                let value = match slot {
+                    LocalSlot::Stack(i) => stack.get(i).cloned().unwrap_or(Value::Nil),
+                    LocalSlot::Vararg(i) => frames[fi].varargs[i].clone(),
+                };
+                (name, value)
+            }));
+        });
+    }
+
+    /** 修改第level层函数的第n个局部变量 , 返回变量名 ; 层数不存在时返回None , 变量不存在时返回Some(None) */
+    pub fn set_local(&mut self, co: &Rc<RefCell<LuaThread>>, level: usize, n: i64, value: Value) -> Option<Option<String>> {
+        let set = |frames: &mut Vec<CallFrame>, stack: &mut Vec<Value>| {
+            let fi = frames.len().checked_sub(level + 1)?;
+            let Some((name, slot)) = find_local(frames, stack.len(), fi, n) else {
+                return Some(None);
+            };
+            match slot {
+                LocalSlot::Stack(i) => stack[i] = value,
+                LocalSlot::Vararg(i) => frames[fi].varargs[i] = value,
+            }
+            return Some(Some(name));
+        };
+        if Rc::ptr_eq(co, &self.current) {
+            return set(&mut self.frames, &mut self.stack);
+        }
+        let mut t = co.borrow_mut();
+        let t = &mut *t;
+        return set(&mut t.frames, &mut t.stack);
+    }
+
+    /** Lua函数f的第n个参数的名字 , Rust函数没有参数名 */
+    pub fn param_name(&self, f: &Value, n: i64) -> Option<String> {
+        let Value::LuaFunction(closure) = f else {
+            return None;
+        };
+        return local_name(&closure.proto, usize::try_from(n).ok()?, 0).map(String::from);
+    }
+
+    /** Lua函数f的第n个Upvalue的名字和值 , 不存在(或者f是Rust函数)时为None */
+    pub fn get_upvalue_of(&self, f: &Value, n: usize) -> Option<(String, Value)> {
+        let Value::LuaFunction(closure) = f else {
+            return None;
+        };
+        let up = closure.upvalues.borrow().get(n.checked_sub(1)?)?.clone();
+        return Some((closure.proto.upnames[n - 1].clone(), self.get_upvalue(&up)));
+    }
+
+    /** 修改Lua函数f的第n个Upvalue , 返回它的名字 */
+    pub fn set_upvalue_of(&mut self, f: &Value, n: usize, value: Value) -> Option<String> {
+        let Value::LuaFunction(closure) = f else {
+            return None;
+        };
+        let up = closure.upvalues.borrow().get(n.checked_sub(1)?)?.clone();
+        self.set_upvalue(&up, value);
+        return Some(closure.proto.upnames[n - 1].clone());
+    }
+
+    /** 设置钩子 : mask为0并且count为0时取消钩子 ; count大于0时打开count事件 */
+    pub fn set_hook(&mut self, func: Value, mut mask: u8, count: usize) {
+        if count > 0 {
+            mask |= MASK_COUNT;
+        }
+        if mask == 0 || matches!(func, Value::Nil) {
+            self.hook.func = Value::Nil;
+            self.hook.mask = 0;
+            self.hook.count = 0;
+            return;
+        }
+        self.hook.func = func;
+        self.hook.mask = mask;
+        self.hook.count = count;
+        self.hook.left = count;
+    }
+
+    /** 当前的钩子函数 , 事件掩码 , count事件的间隔 */
+    pub fn get_hook(&self) -> (Value, u8, usize) {
+        return (self.hook.func.clone(), self.hook.mask, self.hook.count);
+    }
+
+    /** 调用钩子函数 : 参数是事件名 , line事件还有行号 ; 钩子函数执行期间不再触发钩子 */
+    pub(super) fn call_hook(&mut self, event: &str, line: Option<u32>) -> Result<(), LuaError> {
+        if self.hook.running.is_some() {
+            return Ok(());
+        }
+        let mut args = vec![Value::from(event)];
+        if let Some(line) = line {
+            args.push(Value::Integer(line as i64));
+        }
+        self.hook.running = Some(self.frames.len());
+        let r = self.call_meta(self.hook.func.clone(), &args);
+        self.hook.running = None;
+        return r.map(|_| ());
+    }
+
+    /** 执行第fi层帧中pc位置的字节码之前 : 触发count事件和line事件 ;
+        line事件在进入新的一行 , 或者向回跳转(比如循环)时触发
+     */
+    pub(super) fn trace_exec(&mut self, fi: usize, pc: usize) -> Result<(), LuaError> {
+        if self.hook.running.is_some() {
+            return Ok(());
+        }
+        self.frames[fi].pc = pc + 1; /* 钩子中看到的当前行是将要执行的字节码所在的行 */
+        if self.hook.mask & MASK_COUNT != 0 {
+            self.hook.left -= 1;
+            if self.hook.left == 0 {
+                self.hook.left = self.hook.count;
+                self.call_hook("count", None)?;
+            }
+        }
+        if self.hook.mask & MASK_LINE != 0 {
+            let lineinfo = &self.frames[fi].closure.as_ref().unwrap().proto.lineinfo;
+            let old = self.hook.old_pc;
+            let line = lineinfo[pc];
+            let new_line = pc == 0 || pc <= old || lineinfo.get(old) != Some(&line);
+            self.hook.old_pc = pc;
+            if new_line {
+                self.call_hook("line", Some(line))?;
+            }
+        }
+        return Ok(());
+    }
+
+    /** 回到Lua函数的帧之后 : line事件从它正在执行的调用继续比较 */
+    pub(super) fn reset_old_pc(&mut self) {
+        if self.hook.running.is_some() {
+            return; /* 钩子里的调用不影响被跟踪的函数 */
+        }
+        if let Some(frame) = self.frames.last() {
+            if frame.closure.is_some() {
+                self.hook.old_pc = frame.pc.saturating_sub(1);
+            }
+        }
+    }
+
+    /** 线程co从第level层函数开始的调用链 , 和luaL_traceback的格式一样 : 层数太多时只显示开头和结尾的部分 ;
+        尾调用代替了调用方的帧 , 在它下面用"(...tail calls...)"标出 ; 主线程的最底下是宿主程序的调用
+     */
+    pub fn traceback(&self, co: &Rc<RefCell<LuaThread>>, level: usize) -> String {
+        let nframes = self.with_thread(co, |frames, _, _| frames.len());
+        let is_main = co.borrow().is_main;
+        let mut tb = String::from("stack traceback:");
+        let mut level = level;
+        /* 还要显示的开头的层数 */
+        let mut head = if nframes.saturating_sub(level) > TRACEBACK_HEAD + TRACEBACK_TAIL + 1 { Some(TRACEBACK_HEAD) } else { None };
+        while let Some(info) = self.get_info(co, level) {
+            if head == Some(0) {
+                let skip = nframes - level - TRACEBACK_TAIL;
+                tb.push_str(&format!("\n\t...\t(skipping {skip} levels)"));
+                level += skip;
+                head = None;
+                continue;
+            }
+            head = head.map(|n| n - 1);
+            if info.current_line > 0 {
+                tb.push_str(&format!("\n\t{}:{}: in ", info.short_src, info.current_line));
+            } else {
+                tb.push_str(&format!("\n\t{}: in ", info.short_src));
+            }
+            match (self.global_func_name(&info.func), &info.name) {
+                (Some(name), _) => tb.push_str(&format!("function '{name}'")),
+                (None, Some(name)) => tb.push_str(&format!("{} '{}'", info.namewhat, name)),
+                (None, None) if info.what == "main" => tb.push_str("main chunk"),
+                (None, None) if info.what == "Lua" => {
+                    tb.push_str(&format!("function <{}:{}>", info.short_src, info.line_defined));
+                }
+                (None, None) => tb.push('?'),
+            }
+            if info.is_tail_call {
+                tb.push_str("\n\t(...tail calls...)");
+            }
+            level += 1;
+        }
+        if is_main {
+            tb.push_str("\n\t[C]: in ?");
+        }
+        return tb;
+    }
+
+    /** 在package.loaded中查找函数f : 找到时返回"模块名.字段名" , 全局变量省略"_G." */
+    fn global_func_name(&self, f: &Value) -> Option<String> {
+        if !f.is_function() {
+            return None;
+        }
+        let Value::Table(package) = self.get_global("package") else {
+            return None;
+        };
+        let Value::Table(loaded) = package.borrow().get(&Value::from("loaded")) else {
+            return None;
+        };
+        let loaded = loaded.borrow();
+        /* 全局变量优先 */
+        let modules = std::iter::once((Value::from("_G"), loaded.get(&Value::from("_G"))))
+            .chain(loaded.map.iter().map(|(k, v)| (k.clone(), v.clone())));
+        for (modname, module) in modules {
+            let Value::Table(module) = module else {
+                continue;
+            };
+            for (key, v) in module.borrow().map.iter() {
+                if v == f && key.is_str() {
+                    let modname = String::from(&modname);
+                    return Some(if modname == "_G" { key.to_string() } else { format!("{modname}.{key}") });
+                }
+            }
+        }
+        return None;
+    }
+}
+
+/** 局部变量的位置 : 栈上 , 或者帧保存的可变参数中 */
+enum LocalSlot {
+    Stack(usize),
+    Vararg(usize),
+}
+
+/** 第fi层帧的第n个局部变量 : 有名字的变量之外 , 帧的范围内的其他栈位置是临时变量 ; n为负数时是可变参数 */
+fn find_local(frames: &[CallFrame], top: usize, fi: usize, n: i64) -> Option<(String, LocalSlot)> {
+    let frame = &frames[fi];
+    if n < 0 {
+        let i = (-n - 1) as usize;
+        if frame.closure.is_none() || i >= frame.varargs.len() {
+            return None;
+        }
+        return Some((String::from("(vararg)"), LocalSlot::Vararg(i)));
+    }
+    let n = n as usize;
+    if n == 0 {
+        return None;
+    }
+    let name = match &frame.closure {
+        Some(closure) => local_name(&closure.proto, n, frame.pc.saturating_sub(1)),
+        None => None,
+    };
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => {
+            /* 帧的范围 : 到上面一层函数的位置 , 或者栈顶 */
+            let limit = frames.get(fi + 1).map_or(top, |f| f.base - 1);
+            if limit < frame.base + n {
+                return None;
+            }
+            String::from(if frame.closure.is_some() { "(temporary)" } else { "(C temporary)" })
+        }
+    };
+    return Some((name, LocalSlot::Stack(frame.base + n - 1)));
+}
+
+/** 字节码pc处有效的第n个局部变量的名字 */
+fn local_name(proto: &FunctionProto, n: usize, pc: usize) -> Option<&str> {
+    return proto.locvars
+        .iter()
+        .filter(|v| v.startpc <= pc && pc < v.endpc)
+        .nth(n.checked_sub(1)?)
+        .map(|v| v.name.as_str());
+}
+
+/** 第fi层函数的名字 : 从调用方正在执行的字节码推断 ; 尾调用和Rust函数调用的没有名字 */
+fn func_name(frames: &[CallFrame], fi: usize) -> Option<(&'static str, String)> {
+    if fi == 0 || frames[fi].tail {
+        return None;
+    }
+    let caller = &frames[fi - 1];
+    let closure = caller.closure.as_ref()?;
+    return func_name_from_code(closure, caller.pc.checked_sub(1)?);
+}
+
+/** 调用方在pc处的字节码 : 函数调用时是被调用函数的来源 , 其他字节码调用的是元方法 */
+fn func_name_from_code(closure: &LuaClosure, pc: usize) -> Option<(&'static str, String)> {
+    let proto = &closure.proto;
+    let code = proto.byte_codes[pc];
+    let event = match code.op() {
+        OpCode::Call | OpCode::TailCall => {
+            return obj_name(proto, pc, code.a());
+        }
+        OpCode::TForCall => {
+            return Some(("for iterator", String::from("for iterator")));
+        }
+        OpCode::GetUpField | OpCode::GetTable | OpCode::GetField | OpCode::GetInt | OpCode::Method => "index",
+        | OpCode::SetUpField
+        | OpCode::SetUpFieldConst
+        | OpCode::SetTable
+        | OpCode::SetField
+        | OpCode::SetInt
+        | OpCode::SetTableConst
+        | OpCode::SetFieldConst
+        | OpCode::SetIntConst => "newindex",
+        | OpCode::Add
+        | OpCode::Sub
+        | OpCode::Mul
+        | OpCode::Div
+        | OpCode::IDiv
+        | OpCode::Mod
+        | OpCode::Pow
+        | OpCode::BAnd
+        | OpCode::BOr
+        | OpCode::BXor
+        | OpCode::Shl
+        | OpCode::Shr
+        | OpCode::Concat => &meta_event(code.op())[2..],
+        OpCode::Unm => "unm",
+        OpCode::BNot => "bnot",
+        OpCode::Len => "len",
+        OpCode::Eq => "eq",
+        OpCode::Lt => "lt",
+        OpCode::Le => "le",
+        OpCode::Close | OpCode::Return => "close",
+        _ => {
+            return None;
+        }
+    };
+    return Some(("metamethod", String::from(event)));
+}
+
+/** 执行到lastpc时,寄存器reg中的值的来源 : 局部变量 , 全局变量 , 字段 , 方法 , Upvalue 或者字符串常量 */
+fn obj_name(proto: &FunctionProto, lastpc: usize, reg: usize) -> Option<(&'static str, String)> {
+    if let Some(name) = local_name(proto, reg + 1, lastpc) {
+        return Some(("local", name.to_string()));
+    }
+    let pc = find_set_reg(proto, lastpc, reg)?;
+    let code = proto.byte_codes[pc];
+    return match code.op() {
+        OpCode::Move if code.b() < code.a() => obj_name(proto, pc, code.b()),
+        OpCode::GetUpField => {
+            let what = if proto.upnames[code.b()] == "_ENV" { "global" } else { "field" };
+            Some((what, const_name(proto, code.c())))
+        }
+        OpCode::GetField => {
+            let is_env = matches!(obj_name(proto, pc, code.b()), Some(("local" | "upvalue", name)) if name == "_ENV");
+            Some((if is_env { "global" } else { "field" }, const_name(proto, code.c())))
+        }
+        OpCode::GetTable => {
+            let key = match obj_name(proto, pc, code.c()) {
+                Some(("constant", name)) => name,
+                _ => String::from("?"),
+            };
+            Some(("field", key))
+        }
+        OpCode::GetInt => Some(("field", String::from("integer index"))),
+        OpCode::GetUpval => Some(("upvalue", proto.upnames[code.b()].clone())),
+        OpCode::LoadConst if proto.constants[code.bx()].is_str() => {
+            Some(("constant", proto.constants[code.bx()].to_string()))
+        }
+        OpCode::Method => Some(("method", const_name(proto, code.c()))),
+        _ => None,
+    };
+}
+
+/** 常量表中的名字 , 不是字符串时为"?" */
+fn const_name(proto: &FunctionProto, i: usize) -> String {
+    let k = &proto.constants[i];
+    return if k.is_str() { k.to_string() } else { String::from("?") };
+}
+
+/** lastpc之前最后一条修改寄存器reg的字节码 : 这条字节码位于跳转范围内(不一定执行)时为None */
+fn find_set_reg(proto: &FunctionProto, lastpc: usize, reg: usize) -> Option<usize> {
+    let mut setreg = None;
+    let mut jmptarget = 0; /* 跳过的代码的末尾 , 在它之前的修改不一定发生 */
+    for pc in 0..lastpc {
+        let code = proto.byte_codes[pc];
+        let a = code.a();
+        let change = match code.op() {
+            OpCode::Call | OpCode::TailCall | OpCode::VarArg => reg >= a,
+            OpCode::TForCall => reg >= a + 4,
+            OpCode::Method => reg == a || reg == a + 1,
+            OpCode::ForPrep | OpCode::ForLoop => (a..=a + 3).contains(&reg),
+            OpCode::TForLoop => reg == a + 2,
+            OpCode::Jmp => {
+                let target = ((pc as i64) + 1 + code.sj_arg()) as usize;
+                if pc < target && target <= lastpc && target > jmptarget {
+                    jmptarget = target;
+                }
+                false
+            }
+            | OpCode::SetUpval
+            | OpCode::SetUpField
+            | OpCode::SetUpFieldConst
+            | OpCode::SetTable
+            | OpCode::SetField
+            | OpCode::SetInt
+            | OpCode::SetTableConst
+            | OpCode::SetFieldConst
+            | OpCode::SetIntConst
+            | OpCode::SetList
+            | OpCode::Eq
+            | OpCode::EqConst
+            | OpCode::Lt
+            | OpCode::Le
+            | OpCode::Test
+            | OpCode::Return
+            | OpCode::Close
+            | OpCode::Tbc
+            | OpCode::TForPrep
+            | OpCode::ExtraArg => false,
+            _ => reg == a,
+        };
+        if change {
+            setreg = if pc < jmptarget { None } else { Some(pc) };
+        }
+    }
+    return setreg;
+}
@@ -0,0 +1,134 @@
+use std::{ sync::{ Arc, atomic::{ AtomicBool, Ordering } }, time::Instant };
+
+use crate::interface::LuaError;
+
+use super::ExeState;
+
+/** 每执行这么多条字节码检查一次中断标志和截止时间 */
+const CHECK_INTERVAL: u64 = 1000;
+
+/** ### 运行限制
+    执行不受信任的脚本时防止它一直占着线程 : 指令数上限 , 宿主可以从别的线程设置的中断标志 , 截止时间 ;
+    执行循环每条字节码只把left减一 , 用完时才检查 , 超出限制时抛出脚本中不能捕获的错误
+ */
+pub(super) struct Limit {
+    pub(super) left: u64 /* 到下一次检查还能执行的字节码数 */,
+    budget: Option<u64> /* 指令数上限中还没有分给left的部分 , None表示不限制 */,
+    interrupt: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl Limit {
+    pub(super) fn new() -> Self {
+        return Limit { left: CHECK_INTERVAL, budget: None, interrupt: Arc::new(AtomicBool::new(false)), deadline: None };
+    }
+}
+
+impl ExeState {
+    /** 从现在开始最多再执行limit条字节码 , 所有协程一起计算 ; None表示不限制 */
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.limit.budget = limit;
+        self.limit.left = 0; /* 下一条字节码就重新检查 */
+    }
+
+    /** 剩下还能执行的字节码数 , 没有上限时为None */
+    pub fn remaining_instructions(&self) -> Option<u64> {
+        return self.limit.budget.map(|b| b + self.limit.left);
+    }
+
+    /** 中断标志 : 在任意线程中设置为true , 脚本会在下一次检查时停止 ; 宿主要继续使用虚拟机时需要先把它设回false */
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        return self.limit.interrupt.clone();
+    }
+
+    /** 超过deadline之后停止执行 , None表示不限制 */
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.limit.deadline = deadline;
+        self.limit.left = 0;
+    }
+
+    /** left用完时 : 检查所有限制 , 都没有超出时分配下一段字节码数 ; 超出时left保持为0 , 之后每条字节码都会再次报错 */
+    pub(super) fn check_limit(&mut self) -> Result<(), LuaError> {
+        let msg = if self.limit.interrupt.load(Ordering::Relaxed) {
+            "interrupted"
+        } else if self.limit.budget == Some(0) {
+            "instruction limit exceeded"
+        } else if self.limit.deadline.is_some_and(|d| Instant::now() >= d) {
+            "deadline exceeded"
+        } else {
+            let left = self.limit.budget.map_or(CHECK_INTERVAL, |b| b.min(CHECK_INTERVAL));
+            if let Some(b) = &mut self.limit.budget {
+                *b -= left;
+            }
+            self.limit.left = left;
+            return Ok(());
+        };
+        return Err(LuaError::interrupt(format!("{}{msg}", self.location(0))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ rc::Rc, sync::atomic::Ordering, thread, time::{ Duration, Instant } };
+
+    use crate::{ interface::{ ErrorKind, LuaError, Value }, parse::ParseProto, vm::ExeState };
+
+    fn run(state: &mut ExeState, src: &str) -> Result<(), LuaError> {
+        let proto = ParseProto::load_str(src).expect("测试脚本必须能编译");
+        return state.execute(Rc::new(proto));
+    }
+
+    #[test]
+    fn instruction_limit() {
+        let mut state = ExeState::new();
+        state.set_instruction_limit(Some(10_000));
+        let e = run(&mut state, "while true do end").unwrap_err();
+        assert_eq!(e.kind, ErrorKind::Interrupt);
+        assert!(e.to_string().ends_with("instruction limit exceeded"), "{e}");
+        assert_eq!(state.remaining_instructions(), Some(0));
+
+        /* 重新设置上限之后可以继续使用 */
+        state.set_instruction_limit(Some(10_000));
+        run(&mut state, "local s = 0 for i = 1, 100 do s = s + i end").unwrap();
+        assert!(state.remaining_instructions().unwrap() < 10_000);
+    }
+
+    #[test]
+    fn interrupt_from_other_thread() {
+        let mut state = ExeState::new();
+        let flag = state.interrupt_handle();
+        let setter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let e = run(&mut state, "while true do end").unwrap_err();
+        setter.join().unwrap();
+        assert!(e.is_interrupt());
+        assert!(e.to_string().ends_with("interrupted"), "{e}");
+
+        state.interrupt_handle().store(false, Ordering::Relaxed);
+        run(&mut state, "local x = 1").unwrap();
+    }
+
+    #[test]
+    fn past_deadline() {
+        let mut state = ExeState::new();
+        state.set_deadline(Some(Instant::now()));
+        let e = run(&mut state, "while true do end").unwrap_err();
+        assert!(e.is_interrupt());
+        assert!(e.to_string().ends_with("deadline exceeded"), "{e}");
+    }
+
+    #[test]
+    fn pcall_cannot_catch() {
+        /* 直接pcall , 以及pcall恢复协程 , 中断都会穿过去 */
+        for body in ["pcall(function () while true do end end)", "pcall(coroutine.wrap(function () while true do end end))"] {
+            let mut state = ExeState::new();
+            state.set_instruction_limit(Some(10_000));
+            let src = format!("caught = 0 while true do {body} caught = caught + 1 end");
+            let e = run(&mut state, &src).unwrap_err();
+            assert!(e.is_interrupt(), "{e}");
+            assert!(state.get_global("caught") == Value::Integer(0));
+        }
+    }
+}
@@ -1,146 +1,1329 @@
-use std::{ collections::HashMap, cmp::Ordering, io::Read, rc::Rc, cell::RefCell };
-use crate::{ interface::{ Value, ByteCode, table::Table }, global::lib_print, parse::ParseProto };
+mod coroutine;
+mod debug;
+mod limit;
+
+use std::{ rc::Rc, cell::RefCell, collections::HashMap };
+use crate::{
+    interface::{
+        Value,
+        OpCode,
+        LuaError,
+        LuaClosure,
+        Upvalue,
+        arith,
+        table::Table,
+        byte_code::{ MAXARG_BX, FIELDS_PER_FLUSH },
+    },
+    global,
+    parse::{ FunctionProto, UpIndex },
+};
+
+pub use coroutine::{ LuaThread, ThreadStatus };
+pub use debug::{ DebugInfo, MASK_CALL, MASK_RET, MASK_LINE, MASK_COUNT };
+
+/** Lua函数调用的层数上限 */
+const MAX_FRAMES: usize = 200000;
+/** Rust函数再调用Lua函数(比如pcall、元方法)会嵌套执行循环,占用Rust的栈,层数上限 */
+const MAX_RUST_CALLS: usize = 200;
+/** __index/__newindex 链的长度上限 */
+const MAX_META_LOOP: usize = 2000;
+
+/** 一次函数调用 : Lua函数和Rust函数都有 , Rust函数的帧只用于定位调用链和挂起协程 */
+struct CallFrame {
+    closure: Option<Rc<LuaClosure>> /* Lua函数 , Rust函数时为None */,
+    base: usize /* 函数的R[0]在栈上的位置,也就是函数位置+1 */,
+    pc: usize /* 下一条字节码,发生调用或者出错时才需要是最新的 */,
+    varargs: Vec<Value> /* 多出来的实参 */,
+    nresults: Option<usize> /* 调用方期望的返回值个数,None表示全部保留 */,
+    k: Option<Pending> /* Rust函数通过call_k/pcall_k调用时留下的后续 */,
+    tail: bool /* 通过尾调用进入 : 调用方的帧已经被它代替 */,
+    hook: bool /* 调试钩子函数的帧 */,
+    meta: bool /* 执行循环调用的元方法 , 在其中yield过 : 返回时由finish_op完成调用方的字节码 */,
+}
+
+/** ### 后续(continuation)
+    Rust函数调用的Lua函数yield时,Rust函数自己的栈随着yield的传播消失了 ;
+    协程恢复、被调用的函数返回之后,用后续完成Rust函数剩下的工作 ,
+    参数是调用的结果 : 成功时返回值在栈上 , 出错(只有受保护的调用)时是错误
+ */
+pub type Continuation = Box<dyn FnOnce(&mut ExeState, Result<(), LuaError>) -> Result<i32, LuaError>>;
+
+/** 挂在Rust帧上的后续 */
+struct Pending {
+    k: Continuation,
+    protected: Option<(usize, Value)> /* pcall_k : (被调用函数的位置, 错误处理函数) */,
+}
 
 /** ## Lua虚拟机 */
 pub struct ExeState {
-    pub globals: HashMap<String, Value> /* 全局函数表 */,
+    pub globals: Rc<RefCell<Table>> /* 全局变量表 , 也就是主代码块的_ENV , 其中的_G是它自己 */,
     pub stack: Vec<Value> /* 调用栈 */,
-    pub func_index: usize /* 函数调用的位置,实时更新 */,
+    pub func_index: usize /* 正在执行的Rust函数在栈上的位置,实时更新 */,
+    pub string_meta: Option<Rc<RefCell<Table>>> /* 所有字符串共享的元表 */,
+    pub type_metas: HashMap<&'static str, Rc<RefCell<Table>>> /* 其他没有自己元表的类型(数字、函数等)按类型名共享的元表 */,
+    pub stdout: Value /* io库的标准输出文件 , print通过它输出 */,
+    frames: Vec<CallFrame> /* 函数的调用链 */,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>> /* 还指向栈上的Upvalue */,
+    tbc_list: Vec<usize> /* to-be-closed变量在栈上的位置,由低到高 */,
+    nny: usize /* 不能yield的调用(Rust函数不带后续地调用Lua函数)的层数 */,
+    rust_calls: usize /* 嵌套执行循环的层数 */,
+    current: Rc<RefCell<LuaThread>> /* 正在运行的线程 , 它的执行状态在上面的字段中 */,
+    hook: debug::Hook /* debug.sethook设置的钩子 */,
+    limit: limit::Limit /* 指令数上限、中断标志和截止时间 */,
+}
+
+impl Default for ExeState {
+    fn default() -> Self {
+        return Self::new();
+    }
 }
 
 impl ExeState {
     pub fn new() -> Self {
-        /* 提前往堆栈中加入全局的执行函数 */
-        let mut global_var: HashMap<String, Value> = HashMap::new();
-        global_var.insert(String::from("print"), Value::Function(lib_print));
-        return ExeState {
-            globals: global_var /* 全局变量 */,
+        let mut state = ExeState {
+            globals: Rc::new(RefCell::new(Table::new(0, 0))) /* 全局变量 */,
             stack: Vec::new() /* 调用栈 */,
             func_index: 0,
+            string_meta: None,
+            type_metas: HashMap::new(),
+            stdout: Value::Nil,
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+            tbc_list: Vec::new(),
+            nny: 0,
+            rust_calls: 0,
+            current: Rc::new(RefCell::new(LuaThread::main())),
+            hook: debug::Hook::new(),
+            limit: limit::Limit::new(),
+        };
+        /* 提前往全局变量中加入库函数 */
+        global::open_libs(&mut state);
+        return state;
+    }
+
+    /** 虚拟机执行 : 主代码块作为一个没有参数的Lua函数,在保护模式下调用 */
+    pub fn execute(&mut self, proto: Rc<FunctionProto>) -> Result<(), LuaError> {
+        return self.execute_with(proto, Value::Nil);
+    }
+
+    /** 和execute一样 , 出错时先在出错的位置用错误调用handler(比如加上调用链) , 它的返回值作为最终的错误 */
+    pub fn execute_with(&mut self, proto: Rc<FunctionProto>, handler: Value) -> Result<(), LuaError> {
+        let func = self.stack.len();
+        let f = self.load(proto);
+        self.stack.push(f);
+        let r = self.pcall(func, 0, handler);
+        self.stack.truncate(func);
+        return r;
+    }
+
+    /** 解析好的主代码块变成可以调用的Lua函数 , 它的Upvalue _ENV 是全局变量表 */
+    pub fn load(&self, proto: Rc<FunctionProto>) -> Value {
+        return self.load_with_env(proto, Value::Table(self.globals.clone()));
+    }
+
+    /** 指定_ENV加载主代码块 : 第一个Upvalue设置成env , 其他的(如果有)为nil */
+    pub fn load_with_env(&self, proto: Rc<FunctionProto>, env: Value) -> Value {
+        let mut env = Some(env);
+        let upvalues = proto.upindexes
+            .iter()
+            .map(|_| Rc::new(RefCell::new(Upvalue::Closed(env.take().unwrap_or(Value::Nil)))))
+            .collect();
+        return Value::LuaFunction(Rc::new(LuaClosure { proto, upvalues: RefCell::new(upvalues) }));
+    }
+
+    /** 执行字节码,直到调用链回到stop层 */
+    fn run(&mut self, stop: usize) -> Result<(), LuaError> {
+        'frame: loop {
+            let fi = self.frames.len() - 1;
+            let closure = self.frames[fi].closure.clone().expect("执行字节码的帧必须是Lua函数");
+            let proto = &closure.proto;
+            let base = self.frames[fi].base;
+            let mut pc = self.frames[fi].pc;
+            if pc == 0 && self.hook.mask & MASK_CALL != 0 {
+                self.call_hook(if self.frames[fi].tail { "tail call" } else { "call" }, None)?;
+            }
+            loop {
+                if self.hook.mask & (MASK_LINE | MASK_COUNT) != 0 {
+                    self.trace_exec(fi, pc)?;
+                }
+                let code = proto.byte_codes[pc];
+                pc += 1;
+                /* 记录pc : 报错和调用时需要知道当前执行的位置 */
+                self.frames[fi].pc = pc;
+                if self.limit.left == 0 {
+                    self.check_limit()?;
+                }
+                self.limit.left -= 1;
+                let a = base + code.a();
+                /* 解析字节码 */
+                match code.op() {
+                    /* 全局变量 : 对Upvalue _ENV 的索引 */
+                    OpCode::GetUpField => {
+                        let t = self.get_upvalue(&closure.upvalues.borrow()[code.b()]);
+                        let value = self.index(&t, &proto.constants[code.c()])?;
+                        self.set_stack(a, value);
+                    }
+                    /*  函数执行,Call */
+                    OpCode::Call => {
+                        /* 参数之后的栈位置都是临时变量,截掉之后被调函数可以通过栈长度得知参数个数 */
+                        let nargs = if code.b() == 0 { self.stack.len() - a - 1 } else { code.b() - 1 };
+                        let nresults = if code.c() == 0 { None } else { Some(code.c() - 1) };
+                        self.stack.truncate(a + 1 + nargs);
+                        if self.precall(a, nargs, nresults)? {
+                            continue 'frame; /* 进入Lua函数 */
+                        }
+                    }
+                    /* 尾调用 : 被调用的Lua函数代替当前函数的帧,返回值直接交给更外层的调用方 ;
+                       Rust函数和普通调用一样,返回值由后面的Return(A, 0)返回 , 这样在其中yield之后也能正常恢复 */
+                    OpCode::TailCall => {
+                        let nargs = if code.b() == 0 { self.stack.len() - a - 1 } else { code.b() - 1 };
+                        self.stack.truncate(a + 1 + nargs);
+                        let nargs = self.resolve_call(a, nargs)?;
+                        if let Value::LuaFunction(_) = self.stack[a] {
+                            self.close_upvalues(base);
+                            self.stack.drain(base - 1..a);
+                            let frame = self.frames.pop().unwrap();
+                            self.precall(base - 1, nargs, frame.nresults)?;
+                            let last = self.frames.last_mut().unwrap();
+                            (last.tail, last.meta) = (true, frame.meta);
+                            continue 'frame;
+                        }
+                        self.precall(a, nargs, None)?;
+                    }
+                    OpCode::Return => {
+                        let n = if code.b() == 0 { self.stack.len() - a } else { code.b() - 1 };
+                        self.close_upvalues(base);
+                        self.close_tbc(base, Value::Nil)?;
+                        if self.hook.mask & MASK_RET != 0 {
+                            self.call_hook("return", None)?;
+                        }
+                        let frame = self.frames.pop().unwrap();
+                        self.move_results(base - 1, a, n, frame.nresults);
+                        if frame.meta {
+                            self.finish_op();
+                        }
+                        if self.frames.len() <= stop {
+                            return Ok(());
+                        }
+                        self.reset_old_pc();
+                        continue 'frame; /* 回到调用方 */
+                    }
+                    OpCode::VarArg => {
+                        let varargs = &self.frames[fi].varargs;
+                        if code.c() == 0 {
+                            let varargs = varargs.clone();
+                            self.stack.truncate(a);
+                            self.stack.extend(varargs);
+                        } else {
+                            let values: Vec<Value> = (0..code.c() - 1)
+                                .map(|i| varargs.get(i).cloned().unwrap_or(Value::Nil))
+                                .collect();
+                            for (i, v) in values.into_iter().enumerate() {
+                                self.set_stack(a + i, v);
+                            }
+                        }
+                    }
+                    OpCode::Closure => {
+                        let idx = Self::ext_arg(proto, &mut pc, code.bx());
+                        let child = proto.protos[idx].clone();
+                        let upvalues = child.upindexes
+                            .iter()
+                            .map(|up| {
+                                match up {
+                                    UpIndex::Local(i) => self.open_upvalue(base + i),
+                                    UpIndex::Upvalue(i) => closure.upvalues.borrow()[*i].clone(),
+                                }
+                            })
+                            .collect();
+                        let f = Value::LuaFunction(Rc::new(LuaClosure { proto: child, upvalues: RefCell::new(upvalues) }));
+                        self.set_stack(a, f);
+                    }
+                    OpCode::GetUpval => {
+                        let value = self.get_upvalue(&closure.upvalues.borrow()[code.b()]);
+                        self.set_stack(a, value);
+                    }
+                    OpCode::SetUpval => {
+                        let value = self.stack[a].clone();
+                        self.set_upvalue(&closure.upvalues.borrow()[code.b()], value);
+                    }
+                    OpCode::Close => {
+                        self.close_upvalues(a);
+                        self.close_tbc(a, Value::Nil)?;
+                    }
+                    OpCode::Tbc => {
+                        let idx = Self::ext_arg(proto, &mut pc, code.bx());
+                        let name = proto.constants[idx].to_string();
+                        self.mark_tbc(a, &name)?;
+                    }
+                    /* 将常量进行装载 */
+                    OpCode::LoadConst => {
+                        /* 先从常量表中进行复制再入栈 */
+                        let val = proto.constants[code.bx()].clone();
+                        self.set_stack(a, val);
+                    }
+                    /* 常量索引放不进Bx参数时,由紧跟的ExtraArg给出 */
+                    OpCode::LoadConstX => {
+                        let idx = proto.byte_codes[pc].ax_arg();
+                        pc += 1;
+                        let val = proto.constants[idx].clone();
+                        self.set_stack(a, val);
+                    }
+                    /* 将boolean放入全局变量,不需要进经过proto.constants进行中间流转 */
+                    OpCode::LoadBool => {
+                        self.set_stack(a, Value::Boolean(code.b() != 0));
+                    }
+                    /* 载入false并跳过下一条字节码 */
+                    OpCode::LoadFalseSkip => {
+                        self.set_stack(a, Value::Boolean(false));
+                        pc += 1;
+                    }
+                    /*  */
+                    OpCode::LoadNil => {
+                        self.set_stack(a, Value::Nil);
+                    }
+                    OpCode::LoadInt => {
+                        self.set_stack(a, Value::Integer(code.sbx()));
+                    }
+                    /* 将栈上的数据做迁移 */
+                    OpCode::Move => {
+                        let value = self.stack[base + code.b()].clone();
+                        self.set_stack(a, value);
+                    }
+                    /* 设置全局变量 : A是_ENV所在的Upvalue索引 */
+                    OpCode::SetUpField => {
+                        let t = self.get_upvalue(&closure.upvalues.borrow()[code.a()]);
+                        let key = proto.constants[code.b()].clone();
+                        let value = self.stack[base + code.c()].clone();
+                        self.set_index(&t, key, value)?;
+                    }
+                    /* 设置全局变量为常量 : 区别是数据从constants获取 */
+                    OpCode::SetUpFieldConst => {
+                        let t = self.get_upvalue(&closure.upvalues.borrow()[code.a()]);
+                        let key = proto.constants[code.b()].clone();
+                        let value = proto.constants[code.c()].clone();
+                        self.set_index(&t, key, value)?;
+                    }
+                    OpCode::NewTable => {
+                        let table = Value::Table(
+                            Rc::new(RefCell::new(Table::new(code.b(), code.c())))
+                        );
+                        self.set_stack(a, table);
+                    }
+                    /* 拷贝编译期构造好的table,每次执行都得到一个新的table */
+                    OpCode::NewTableConst => {
+                        let idx = Self::ext_arg(proto, &mut pc, code.bx());
+                        let table = match &proto.constants[idx] {
+                            Value::Table(template) => template.borrow().clone(),
+                            _ => panic!("NewTableConst的常量不是table"),
+                        };
+                        self.set_stack(a, Value::Table(Rc::new(RefCell::new(table))));
+                    }
+                    OpCode::SetTable => {
+                        let key = self.stack[base + code.b()].clone();
+                        let value = self.stack[base + code.c()].clone();
+                        self.set_table(a, key, value)?;
+                    }
+                    OpCode::SetField => {
+                        let key = proto.constants[code.b()].clone();
+                        let value = self.stack[base + code.c()].clone();
+                        self.set_table(a, key, value)?;
+                    }
+                    OpCode::SetInt => {
+                        let key = Value::Integer(code.b() as i64);
+                        let value = self.stack[base + code.c()].clone();
+                        self.set_table(a, key, value)?;
+                    }
+                    OpCode::SetTableConst => {
+                        let key = self.stack[base + code.b()].clone();
+                        let value = proto.constants[code.c()].clone();
+                        self.set_table(a, key, value)?;
+                    }
+                    OpCode::SetFieldConst => {
+                        let key = proto.constants[code.b()].clone();
+                        let value = proto.constants[code.c()].clone();
+                        self.set_table(a, key, value)?;
+                    }
+                    OpCode::SetIntConst => {
+                        let key = Value::Integer(code.b() as i64);
+                        let value = proto.constants[code.c()].clone();
+                        self.set_table(a, key, value)?;
+                    }
+                    OpCode::SetList => {
+                        let ivalue = a + 1;
+                        /* 之前已经写入的批次 */
+                        let batch = if code.k() { Self::ext_arg(proto, &mut pc, MAXARG_BX) } else { code.c() };
+                        let start = batch * FIELDS_PER_FLUSH;
+                        /* B为0时一直写到栈顶 */
+                        let n = if code.b() == 0 { self.stack.len() - ivalue } else { code.b() };
+                        let value = self.stack[a].clone();
+                        if let Value::Table(table) = value {
+                            /* 取出  ivalue ~ ivalue + arr_len 的数据并且获得可变引用 */
+                            let mut table = table.borrow_mut();
+                            for (i, v) in self.stack.drain(ivalue..ivalue + n).enumerate() {
+                                table.set_int((start + i + 1) as i64, v);
+                            }
+                        } else {
+                            /* 构造中的table只能被debug.setlocal换掉 */
+                            return Err(self.error("table constructor state is broken"));
+                        }
+                    }
+                    OpCode::GetTable => {
+                        let t = self.stack[base + code.b()].clone();
+                        let key = self.stack[base + code.c()].clone();
+                        let value = self.index(&t, &key)?;
+                        self.set_stack(a, value);
+                    }
+                    OpCode::GetField => {
+                        let t = self.stack[base + code.b()].clone();
+                        let value = self.index(&t, &proto.constants[code.c()])?;
+                        self.set_stack(a, value);
+                    }
+                    OpCode::GetInt => {
+                        let t = self.stack[base + code.b()].clone();
+                        let value = self.index(&t, &Value::Integer(code.c() as i64))?;
+                        self.set_stack(a, value);
+                    }
+                    OpCode::Method => {
+                        let obj = self.stack[base + code.b()].clone();
+                        let value = self.index(&obj, &proto.constants[code.c()])?;
+                        self.set_stack(a + 1, obj);
+                        self.set_stack(a, value);
+                    }
+                    /* 二元运算 : k表示右操作数在常量表中 */
+                    | OpCode::Add
+                    | OpCode::Sub
+                    | OpCode::Mul
+                    | OpCode::Div
+                    | OpCode::IDiv
+                    | OpCode::Mod
+                    | OpCode::Pow
+                    | OpCode::BAnd
+                    | OpCode::BOr
+                    | OpCode::BXor
+                    | OpCode::Shl
+                    | OpCode::Shr
+                    | OpCode::Concat => {
+                        let left = self.stack[base + code.b()].clone();
+                        let right = if code.k() {
+                            proto.constants[code.c()].clone()
+                        } else {
+                            self.stack[base + code.c()].clone()
+                        };
+                        let value = self.arith(code.op(), &left, &right)?;
+                        self.set_stack(a, value);
+                    }
+                    OpCode::Unm | OpCode::BNot => {
+                        let operand = self.stack[base + code.b()].clone();
+                        let value = match arith::unary(code.op(), &operand) {
+                            Ok(v) => v,
+                            Err(msg) => {
+                                let event = if code.op() == OpCode::Unm { "__unm" } else { "__bnot" };
+                                self.call_binary_meta(event, &operand, &operand, msg)?
+                            }
+                        };
+                        self.set_stack(a, value);
+                    }
+                    OpCode::Not => {
+                        let value = Value::Boolean(!self.stack[base + code.b()].truthy());
+                        self.set_stack(a, value);
+                    }
+                    OpCode::Len => {
+                        let operand = self.stack[base + code.b()].clone();
+                        let value = self.len(&operand)?;
+                        self.set_stack(a, value);
+                    }
+                    OpCode::Jmp => {
+                        pc = ((pc as i64) + code.sj_arg()) as usize;
+                    }
+                    /* 判断指令 : 结果和k不一致时跳过后面的Jmp */
+                    OpCode::Eq => {
+                        let (l, r) = (self.stack[a].clone(), self.stack[base + code.b()].clone());
+                        if self.equal(&l, &r)? != code.k() {
+                            pc += 1;
+                        }
+                    }
+                    OpCode::EqConst => {
+                        if arith::equal(&self.stack[a], &proto.constants[code.b()]) != code.k() {
+                            pc += 1;
+                        }
+                    }
+                    OpCode::Lt | OpCode::Le => {
+                        let (l, r) = (self.stack[a].clone(), self.stack[base + code.b()].clone());
+                        if self.compare(code.op(), &l, &r)? != code.k() {
+                            pc += 1;
+                        }
+                    }
+                    OpCode::Test => {
+                        if self.stack[a].truthy() != code.k() {
+                            pc += 1;
+                        }
+                    }
+                    /* 不跳转时直接跳过Jmp,跳转时把值带到A上 */
+                    OpCode::TestSet => {
+                        let value = self.stack[base + code.b()].clone();
+                        if value.truthy() != code.k() {
+                            pc += 1;
+                        } else {
+                            self.set_stack(a, value);
+                        }
+                    }
+                    OpCode::ForPrep => {
+                        if self.for_prep(a)? {
+                            pc += code.bx() + 1; /* 跳过整个循环 */
+                        }
+                    }
+                    OpCode::ForLoop => {
+                        if self.for_loop(a)? {
+                            pc -= code.bx();
+                        }
+                    }
+                    OpCode::TForPrep => {
+                        self.mark_tbc(a + 3, "(for state)")?;
+                        pc += code.bx();
+                    }
+                    /* 迭代函数和两个状态复制到循环变量的位置上调用,返回值就落在循环变量上 */
+                    OpCode::TForCall => {
+                        let (f, s, c) = (self.stack[a].clone(), self.stack[a + 1].clone(), self.stack[a + 2].clone());
+                        self.stack.truncate(a + 4);
+                        self.stack.extend([f, s, c]);
+                        if self.precall(a + 4, 2, Some(code.c()))? {
+                            continue 'frame;
+                        }
+                    }
+                    OpCode::TForLoop => {
+                        if !matches!(self.stack[a + 4], Value::Nil) {
+                            self.stack[a + 2] = self.stack[a + 4].clone();
+                            pc -= code.bx();
+                        }
+                    }
+                    OpCode::ExtraArg => panic!("ExtraArg不能单独执行"),
+                }
+            }
+        }
+    }
+
+    /** 调用栈上func位置的函数,参数跟在函数后面 : Lua函数只压入调用帧,返回true ; Rust函数直接执行完 */
+    fn precall(&mut self, func: usize, nargs: usize, nresults: Option<usize>) -> Result<bool, LuaError> {
+        match self.stack[func].clone() {
+            Value::LuaFunction(closure) => {
+                if self.frames.len() >= MAX_FRAMES {
+                    return Err(self.error("stack overflow"));
+                }
+                let (nparam, is_vararg) = (closure.proto.nparam, closure.proto.is_vararg);
+                let base = func + 1;
+                /* 多出来的实参作为可变参数另外保存,缺少的实参补nil */
+                let varargs = if is_vararg && nargs > nparam {
+                    self.stack.drain(base + nparam..base + nargs).collect()
+                } else {
+                    Vec::new()
+                };
+                self.stack.resize(base + nparam, Value::Nil);
+                let hook = self.hook.running == Some(self.frames.len());
+                self.frames.push(CallFrame { closure: Some(closure), base, pc: 0, varargs, nresults, k: None, tail: false, hook, meta: false });
+                return Ok(true);
+            }
+            f @ (Value::RustFunction(_) | Value::RustClosure(_)) => {
+                if self.frames.len() >= MAX_FRAMES {
+                    return Err(self.error("stack overflow"));
+                }
+                /* 出错或者yield时保留帧 : 出错时用于定位 , 由pcall回退 ; yield时恢复执行需要它 */
+                let hook = self.hook.running == Some(self.frames.len());
+                self.frames.push(CallFrame { closure: None, base: func + 1, pc: 0, varargs: Vec::new(), nresults, k: None, tail: false, hook, meta: false });
+                if self.hook.mask & MASK_CALL != 0 {
+                    self.call_hook("call", None)?;
+                }
+                let saved = self.func_index;
+                self.func_index = func;
+                let r = match f {
+                    Value::RustFunction(f) => f(self),
+                    Value::RustClosure(c) => {
+                        match c.try_borrow_mut() {
+                            Ok(mut c) => c(self),
+                            Err(_) => Err(self.error("attempt to call a running closure")),
+                        }
+                    }
+                    _ => unreachable!(),
+                };
+                self.func_index = saved;
+                let n = r? as usize;
+                if self.hook.mask & MASK_RET != 0 {
+                    self.call_hook("return", None)?;
+                }
+                self.frames.pop();
+                self.reset_old_pc();
+                /* 返回值是栈顶的n个值 */
+                let start = self.stack.len() - n;
+                self.move_results(func, start, n, nresults);
+                return Ok(false);
+            }
+            _ => {
+                let nargs = self.resolve_call(func, nargs)?;
+                return self.precall(func, nargs, nresults);
+            }
+        }
+    }
+
+    /** 不是函数的值通过__call调用 : 元方法作为函数,原来的值作为第一个参数 ; 返回调整后的参数个数 */
+    fn resolve_call(&mut self, func: usize, mut nargs: usize) -> Result<usize, LuaError> {
+        loop {
+            let v = self.stack[func].clone();
+            if v.is_function() {
+                return Ok(nargs);
+            }
+            let h = self.metamethod(&v, "__call");
+            if let Value::Nil = h {
+                return Err(self.error(format!("attempt to call a {} value", v.ty())));
+            }
+            self.stack.insert(func, h);
+            nargs += 1;
+        }
+    }
+
+    /** 把start开始的n个返回值搬到dst上,按照期望的个数截断或者补nil */
+    fn move_results(&mut self, dst: usize, start: usize, n: usize, nresults: Option<usize>) {
+        if dst != start {
+            for i in 0..n {
+                self.stack.swap(dst + i, start + i);
+            }
+        }
+        self.stack.truncate(dst + n);
+        if let Some(want) = nresults {
+            self.stack.resize(dst + want, Value::Nil);
+        }
+    }
+
+    /** 栈位置idx上的局部变量对应的Upvalue,已经有了就共用 */
+    fn open_upvalue(&mut self, idx: usize) -> Rc<RefCell<Upvalue>> {
+        for up in self.open_upvalues.iter() {
+            if let Upvalue::Open(i, _) = *up.borrow() {
+                if i == idx {
+                    return up.clone();
+                }
+            }
+        }
+        let up = Rc::new(RefCell::new(Upvalue::Open(idx, Rc::downgrade(&self.current))));
+        self.open_upvalues.push(up.clone());
+        return up;
+    }
+
+    /** 读取Upvalue : 打开的Upvalue可能在别的(挂起的)线程的栈上 */
+    fn get_upvalue(&self, up: &Rc<RefCell<Upvalue>>) -> Value {
+        return match &*up.borrow() {
+            Upvalue::Open(i, t) if std::ptr::eq(t.as_ptr(), Rc::as_ptr(&self.current)) => self.stack[*i].clone(),
+            Upvalue::Open(i, t) => t.upgrade().map_or(Value::Nil, |t| t.borrow().stack[*i].clone()),
+            Upvalue::Closed(v) => v.clone(),
+        };
+    }
+
+    fn set_upvalue(&mut self, up: &Rc<RefCell<Upvalue>>, value: Value) {
+        match &mut *up.borrow_mut() {
+            Upvalue::Open(i, t) if std::ptr::eq(t.as_ptr(), Rc::as_ptr(&self.current)) => {
+                self.stack[*i] = value;
+            }
+            Upvalue::Open(i, t) => {
+                if let Some(t) = t.upgrade() {
+                    t.borrow_mut().stack[*i] = value;
+                }
+            }
+            Upvalue::Closed(v) => {
+                *v = value;
+            }
+        }
+    }
+
+    /** 关闭栈位置level及之上的Upvalue : 把栈上的值搬进Upvalue */
+    fn close_upvalues(&mut self, level: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|up| {
+            let mut up = up.borrow_mut();
+            if let Upvalue::Open(i, _) = *up {
+                if i >= level {
+                    *up = Upvalue::Closed(stack.get(i).cloned().unwrap_or(Value::Nil));
+                    return false;
+                }
+            }
+            return true;
+        });
+    }
+
+    /** 把栈上idx位置的变量标记为to-be-closed : nil和false不需要关闭,其他值必须有__close元方法 */
+    fn mark_tbc(&mut self, idx: usize, name: &str) -> Result<(), LuaError> {
+        let v = self.stack[idx].clone();
+        if !v.truthy() {
+            return Ok(());
+        }
+        if let Value::Nil = self.metamethod(&v, "__close") {
+            return Err(self.error(format!("variable '{name}' got a non-closable value")));
+        }
+        self.tbc_list.push(idx);
+        return Ok(());
+    }
+
+    /** 按照和标记相反的顺序关闭level及之上的to-be-closed变量 : 调用__close(v, err) ;
+        __close出错时,新的错误代替原来的错误,剩下的变量继续关闭
+     */
+    fn close_tbc(&mut self, level: usize, mut err: Value) -> Result<(), LuaError> {
+        let mut result = Ok(());
+        while let Some(&idx) = self.tbc_list.last() {
+            if idx < level {
+                break;
+            }
+            self.tbc_list.pop();
+            let v = self.stack[idx].clone();
+            let h = self.metamethod(&v, "__close");
+            if let Err(e) = self.call_meta(h, &[v, err.clone()]) {
+                err = e.value.clone();
+                result = Err(e);
+            }
+        }
+        return result;
+    }
+
+    /** 出错后回到nframes层调用、栈顶为top的状态 */
+    fn unwind(&mut self, nframes: usize, top: usize) {
+        self.close_upvalues(top);
+        self.tbc_list.retain(|&i| i < top);
+        self.frames.truncate(nframes);
+        self.stack.truncate(top);
+    }
+
+    /** 数值for循环的准备 : 整数循环提前算出循环次数放在A+1上,返回是否跳过循环 */
+    fn for_prep(&mut self, a: usize) -> Result<bool, LuaError> {
+        if let (Value::Integer(init), Value::Integer(step)) = (&self.stack[a], &self.stack[a + 2]) {
+            let (init, step) = (*init, *step);
+            if step == 0 {
+                return Err(self.error("'for' step is zero"));
+            }
+            let limit = match self.stack[a + 1] {
+                Value::Integer(limit) => limit,
+                Value::Float(f) => {
+                    /* 浮点数的上限 : 按循环方向取整,超出整数范围时截断 */
+                    let f = if step < 0 { f.ceil() } else { f.floor() };
+                    match arith::float_to_int(f) {
+                        Some(limit) => limit,
+                        None if 0.0 < f => {
+                            if step < 0 {
+                                return Ok(true);
+                            }
+                            i64::MAX
+                        }
+                        None => {
+                            if step > 0 {
+                                return Ok(true);
+                            }
+                            i64::MIN
+                        }
+                    }
+                }
+                _ => {
+                    return Err(self.error("'for' limit must be a number"));
+                }
+            };
+            if (step > 0 && init > limit) || (step < 0 && init < limit) {
+                return Ok(true);
+            }
+            /* 循环次数 : 用无符号数避免溢出 */
+            let count = if step > 0 {
+                (limit as u64).wrapping_sub(init as u64) / (step as u64)
+            } else {
+                (init as u64).wrapping_sub(limit as u64) / ((-(step + 1)) as u64 + 1)
+            };
+            self.stack[a + 1] = Value::Integer(count as i64);
+            self.set_stack(a + 3, Value::Integer(init));
+            return Ok(false);
+        }
+
+        let to_float = |v: &Value| match v {
+            Value::Integer(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
         };
+        let init = to_float(&self.stack[a]).ok_or_else(|| self.error("'for' initial value must be a number"))?;
+        let limit = to_float(&self.stack[a + 1]).ok_or_else(|| self.error("'for' limit must be a number"))?;
+        let step = to_float(&self.stack[a + 2]).ok_or_else(|| self.error("'for' step must be a number"))?;
+        if step == 0.0 {
+            return Err(self.error("'for' step is zero"));
+        }
+        if (step > 0.0 && limit < init) || (step < 0.0 && init < limit) {
+            return Ok(true);
+        }
+        self.stack[a] = Value::Float(init);
+        self.stack[a + 1] = Value::Float(limit);
+        self.stack[a + 2] = Value::Float(step);
+        self.set_stack(a + 3, Value::Float(init));
+        return Ok(false);
     }
 
-    /** 虚拟机执行 */
-    pub fn execute<R: Read>(&mut self, proto: &ParseProto<R>) {
-        /* proto.constants作为常量表存储在proto中而不是虚拟机的global中 */
-        /* 虚拟机执行就是解析语法分析产生的字节码 */
-        println!("constants is : {:?}", proto.constants);
-        println!("----and----");
-        println!("bytecodes is : {:?}", proto.byte_codes);
-        println!("------------------------");
-        for code in proto.byte_codes.iter() {
-            /* 解析字节码 */
-            match *code {
-                /* 第一个参数是目标栈索引,第二个参数是全局变量名在全局变量中的索引 */
-                ByteCode::GetGlobal(dst, name) => {
-                    let name: &str = (&proto.constants[name as usize]).into();
-                    let val = self.globals.get(name).unwrap_or(&Value::Nil).clone();
-                    self.set_stack(dst.into(), val);
-                }
-                /*  函数执行,Call */
-                ByteCode::Call(func, _) => {
-                    /* 确定函数位置 */
-                    self.func_index = func as usize;
-                    let func = &self.stack[self.func_index]; /* 获取到全局函数 */
-                    if let Value::Function(f) = func {
-                        f(self);
-                    } else {
-                        panic!("函数执行错误");
-                    }
-                }
-                /* 将常量进行装载 */
-                ByteCode::LoadConst(dst, con) => {
-                    /* 先从常量表中进行复制再入栈 */
-                    let val = proto.constants[con as usize].clone();
-                    self.set_stack(dst, val);
-                }
-                /* 将boolean放入全局变量,不需要进经过proto.constants进行中间流转 */
-                ByteCode::LoadBool(dst, boolean) => {
-                    self.set_stack(dst, Value::Boolean(boolean));
-                }
-                /*  */
-                ByteCode::LoadNil(dst) => {
-                    self.set_stack(dst, Value::Nil);
-                }
-                ByteCode::LoadInt(dst, val) => {
-                    self.set_stack(dst, Value::Integer(val));
-                }
-                /* 将栈上的数据做迁移 */
-                ByteCode::Move(target, src) => {
-                    let index = self.stack[src as usize].clone();
-                    self.set_stack(target, index);
-                }
-                /* 设置全局变量 */
-                ByteCode::SetGlobal(name, src) => {
-                    /* 将Value里面的数据转化成 &str */
-                    let name = (&proto.constants[name as usize]).into();
-                    let value = self.stack[src as usize].clone();
-                    self.globals.insert(name, value);
-                }
-                /* 设置全局常量 : 区别是数据都从constants获取 */
-                ByteCode::SetGlobalConst(name, src) => {
-                    let name = (&proto.constants[name as usize]).into();
-                    let value = proto.constants[src as usize].clone();
-                    self.globals.insert(name, value);
-                }
-                /* 设置全局字面量 :  */
-                ByteCode::SetGlobalGlobal(name, src) => {
-                    let name = (&proto.constants[name as usize]).into();
-                    let src: &str = (&proto.constants[src as usize]).into();
-                    let value = self.globals.get(src).unwrap_or(&Value::Nil).clone();
-                    self.globals.insert(name, value);
-                }
-                ByteCode::NewTable(idx, al, ml) => {
-                    let table = Value::Table(
-                        Rc::new(RefCell::new(Table::new(al as usize, ml as usize)))
-                    );
-                    self.set_stack(idx, table);
-                }
-                ByteCode::SetTable(idx, key, value) => {
-                    let key = self.stack[key as usize].clone();
-                    let value = self.stack[value as usize].clone();
-                    //取出table进行insert
-                    if let Value::Table(table) = &self.stack[idx as usize] {
-                        table.borrow_mut().map.insert(key, value);
-                    } else {
-                        panic!("table in stack is error place");
-                    }
-                }
-                ByteCode::SetField(idx, key, value) => {
-                    let key = proto.constants[key as usize].clone();
-                    let value = self.stack[value as usize].clone();
-                    if let Value::Table(table) = &self.stack[idx as usize] {
-                        table.borrow_mut().map.insert(key, value);
-                    } else {
-                        panic!("table in stack is error place");
-                    }
-                }
-                ByteCode::SetList(idx, arr_len) => {
-                    let ivalue = (idx as usize) + 1;
-                    let value = self.stack[idx as usize].clone();
-                    if let Value::Table(table) = value {
-                        /* 取出  ivalue ~ ivalue + arr_len 的数据并且获得可变引用 */
-                        let values = self.stack.drain(ivalue..ivalue + (arr_len as usize));
-                        table.borrow_mut().array.extend(values);
-                    } else {
-                        panic!("table in stack is error place");
-                    }
-                }
-                _ => panic!("暂时不支持更多字节码"),
-            }
-        }
-    }
-
-    /** ### 入栈操作,进行位置覆盖 : 
-    在 stack的dst位置载入Value */
-    fn set_stack(&mut self, dst: u8, v: Value) {
-        let dst = dst as usize;
-        match dst.cmp(&self.stack.len()) {
-            Ordering::Equal => self.stack.push(v),
-            Ordering::Less => {
-                self.stack[dst] = v;
-            }
-            Ordering::Greater => panic!("栈溢出异常"),
+    /** 数值for循环的一次迭代 : 返回是否继续循环 */
+    fn for_loop(&mut self, a: usize) -> Result<bool, LuaError> {
+        match (&self.stack[a], &self.stack[a + 1], &self.stack[a + 2]) {
+            (Value::Integer(i), Value::Integer(count), Value::Integer(step)) => {
+                if *count as u64 == 0 {
+                    return Ok(false);
+                }
+                let (i, count) = (i.wrapping_add(*step), *count as u64 - 1);
+                self.stack[a] = Value::Integer(i);
+                self.stack[a + 1] = Value::Integer(count as i64);
+                self.stack[a + 3] = Value::Integer(i);
+                return Ok(true);
+            }
+            (Value::Float(i), Value::Float(limit), Value::Float(step)) => {
+                let i = i + step;
+                if (*step > 0.0 && i <= *limit) || (*step < 0.0 && *limit <= i) {
+                    self.stack[a] = Value::Float(i);
+                    self.stack[a + 3] = Value::Float(i);
+                    return Ok(true);
+                }
+                return Ok(false);
+            }
+            /* 循环状态只能被debug.setlocal改掉 , 报错而不是让宿主崩溃 */
+            _ => Err(self.error("'for' state is broken")),
         }
     }
+
+    /** 往栈上idx位置的table写入数据 */
+    fn set_table(&mut self, idx: usize, key: Value, value: Value) -> Result<(), LuaError> {
+        let t = self.stack[idx].clone();
+        return self.set_index(&t, key, value);
+    }
+
+    /** 元方法 : 算术运算 */
+    fn arith(&mut self, op: OpCode, a: &Value, b: &Value) -> Result<Value, LuaError> {
+        return match arith::arith(op, a, b) {
+            Ok(v) => Ok(v),
+            Err(msg) => self.call_binary_meta(meta_event(op), a, b, msg),
+        };
+    }
+
+    /** 依次查找两个操作数的元方法并调用,都没有时报msg错误 */
+    fn call_binary_meta(&mut self, event: &str, a: &Value, b: &Value, msg: String) -> Result<Value, LuaError> {
+        let mut h = self.metamethod(a, event);
+        if let Value::Nil = h {
+            h = self.metamethod(b, event);
+        }
+        if let Value::Nil = h {
+            return Err(self.error(msg));
+        }
+        return self.call_tm(h, &[a.clone(), b.clone()]);
+    }
+
+    /** #v : 字符串的长度 , table优先使用__len */
+    fn len(&mut self, v: &Value) -> Result<Value, LuaError> {
+        if v.is_str() {
+            return Ok(Value::Integer(<&[u8]>::from(v).len() as i64));
+        }
+        let h = self.metamethod(v, "__len");
+        if !matches!(h, Value::Nil) {
+            return self.call_tm(h, &[v.clone(), v.clone()]);
+        }
+        return match v {
+            Value::Table(t) => Ok(Value::Integer(t.borrow().len())),
+            v => Err(self.error(format!("attempt to get length of a {} value", v.ty()))),
+        };
+    }
+
+    /** a == b : 两个不同的table比较时使用__eq */
+    fn equal(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        if arith::equal(a, b) {
+            return Ok(true);
+        }
+        if let (Value::Table(_), Value::Table(_)) = (a, b) {
+            let mut h = self.metamethod(a, "__eq");
+            if let Value::Nil = h {
+                h = self.metamethod(b, "__eq");
+            }
+            if !matches!(h, Value::Nil) {
+                return Ok(self.call_tm(h, &[a.clone(), b.clone()])?.truthy());
+            }
+        }
+        return Ok(false);
+    }
+
+    /** a < b 或者 a <= b : 不是数字或者字符串时使用__lt/__le */
+    fn compare(&mut self, op: OpCode, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        return match arith::compare(op, a, b) {
+            Ok(r) => Ok(r),
+            Err(msg) => {
+                let event = if op == OpCode::Lt { "__lt" } else { "__le" };
+                Ok(self.call_binary_meta(event, a, b, msg)?.truthy())
+            }
+        };
+    }
+
+    /** 读取可能溢出的Bx参数 : 值为MAXARG_BX时真实参数在后续的ExtraArg中 */
+    fn ext_arg(proto: &FunctionProto, pc: &mut usize, bx: usize) -> usize {
+        if bx == MAXARG_BX {
+            let ax = proto.byte_codes[*pc].ax_arg();
+            *pc += 1;
+            return ax;
+        }
+        return bx;
+    }
+
+    /** ### 入栈操作,进行位置覆盖 :
+    在 stack的dst位置载入Value,中间空出的位置填充nil */
+    fn set_stack(&mut self, dst: usize, v: Value) {
+        if dst >= self.stack.len() {
+            self.stack.resize(dst + 1, Value::Nil);
+        }
+        self.stack[dst] = v;
+    }
+}
+
+/** ### 提供给Rust函数(库函数和宿主程序)使用的API
+    参数从1开始编号,第i个参数在栈上 func_index + i 的位置 ; 返回值push到栈顶,函数返回返回值的个数
+ */
+impl ExeState {
+    /** 参数个数 */
+    pub fn get_top(&self) -> usize {
+        return self.stack.len() - self.func_index - 1;
+    }
+
+    /** 第i个参数,不存在时为nil */
+    pub fn get(&self, i: usize) -> Value {
+        return self.stack.get(self.func_index + i).cloned().unwrap_or(Value::Nil);
+    }
+
+    /** push返回值 */
+    pub fn push(&mut self, v: impl Into<Value>) {
+        self.stack.push(v.into());
+    }
+
+    /** 设置全局变量 */
+    pub fn set_global(&mut self, name: &str, v: impl Into<Value>) {
+        self.globals.borrow_mut().set(Value::from(name), v.into());
+    }
+
+    /** 读取全局变量 , 不触发元方法 */
+    pub fn get_global(&self, name: &str) -> Value {
+        return self.globals.borrow().get(&Value::from(name));
+    }
+
+    /** 带位置信息的错误 : 在Rust函数中时,位置是调用它的函数 ; 否则是正在执行的Lua函数 */
+    pub fn error(&self, msg: impl Into<String>) -> LuaError {
+        let level = if self.frames.last().is_some_and(|f| f.closure.is_none()) { 1 } else { 0 };
+        return LuaError::new(format!("{}{}", self.location(level), msg.into()));
+    }
+
+    /** 正在执行的Rust函数的名字 : 在全局变量和全局的库table中查找 , 用于报错信息 */
+    pub fn func_name(&self) -> String {
+        let f = &self.stack[self.func_index];
+        let globals = self.globals.borrow();
+        for (name, v) in globals.map.iter() {
+            if v == f && name.is_str() {
+                return String::from(name);
+            }
+        }
+        for (_, lib) in globals.map.iter() {
+            if let Value::Table(lib) = lib {
+                for (name, v) in lib.borrow().map.iter() {
+                    if v == f && name.is_str() {
+                        return String::from(name);
+                    }
+                }
+            }
+        }
+        return String::from("?");
+    }
+
+    /** 参数错误 : bad argument #i to 'f' (msg) */
+    pub fn arg_error(&self, i: usize, msg: impl Into<String>) -> LuaError {
+        return self.error(format!("bad argument #{} to '{}' ({})", i, self.func_name(), msg.into()));
+    }
+
+    /** 参数类型错误 : 参数不存在时显示no value */
+    pub fn type_error(&self, i: usize, expected: &str) -> LuaError {
+        let got = if i > self.get_top() { "no value" } else { self.get(i).ty() };
+        return self.arg_error(i, format!("{} expected, got {}", expected, got));
+    }
+
+    /** 检查第i个参数存在 */
+    pub fn check_any(&self, i: usize) -> Result<Value, LuaError> {
+        if i > self.get_top() {
+            return Err(self.arg_error(i, "value expected"));
+        }
+        return Ok(self.get(i));
+    }
+
+    /** 检查第i个参数是table */
+    pub fn check_table(&self, i: usize) -> Result<Rc<RefCell<Table>>, LuaError> {
+        return match self.get(i) {
+            Value::Table(t) => Ok(t),
+            _ => Err(self.type_error(i, "table")),
+        };
+    }
+
+    /** 检查第i个参数是字符串 , 数字转换成字符串 */
+    pub fn check_string(&self, i: usize) -> Result<Value, LuaError> {
+        return match self.get(i) {
+            v @ (Value::Integer(_) | Value::Float(_)) => Ok(v.to_string().into()),
+            v if v.is_str() => Ok(v),
+            _ => Err(self.type_error(i, "string")),
+        };
+    }
+
+    /** 可选的整数参数 , 不存在或者为nil时取默认值 */
+    pub fn opt_integer(&self, i: usize, default: i64) -> Result<i64, LuaError> {
+        if let Value::Nil = self.get(i) {
+            return Ok(default);
+        }
+        return self.check_integer(i);
+    }
+
+    /** 检查第i个参数能转化成整数 */
+    pub fn check_integer(&self, i: usize) -> Result<i64, LuaError> {
+        let v = self.get(i);
+        return match arith::to_number(&v) {
+            Some(Value::Integer(n)) => Ok(n),
+            Some(Value::Float(f)) =>
+                arith::float_to_int(f).ok_or_else(|| self.arg_error(i, "number has no integer representation")),
+            _ => Err(self.type_error(i, "number")),
+        };
+    }
+
+    /** 检查第i个参数是数字(或者可以转换成数字的字符串) , 返回浮点数 */
+    pub fn check_number(&self, i: usize) -> Result<f64, LuaError> {
+        return match arith::to_number(&self.get(i)) {
+            Some(Value::Integer(n)) => Ok(n as f64),
+            Some(Value::Float(f)) => Ok(f),
+            _ => Err(self.type_error(i, "number")),
+        };
+    }
+
+    /** 第level层函数当前执行到的位置 "chunkname:line:" : 0是正在执行的函数 , 1是调用它的函数 , 以此类推 ;
+        不存在或者是Rust函数时为空
+     */
+    pub fn location(&self, level: usize) -> String {
+        if level >= self.frames.len() {
+            return String::new();
+        }
+        let frame = &self.frames[self.frames.len() - 1 - level];
+        let Some(closure) = &frame.closure else {
+            return String::new();
+        };
+        return format!("{}:{}: ", closure.proto.chunk_id(), current_line(frame));
+    }
+
+    /** 调用栈上func位置的函数,nargs个参数跟在函数后面 ; 返回值从func位置开始,nresults为None时保留全部 ;
+        被调用的函数不能yield
+     */
+    pub fn call_value(&mut self, func: usize, nargs: usize, nresults: Option<usize>) -> Result<(), LuaError> {
+        return self.call(func, nargs, nresults, false);
+    }
+
+    fn call(&mut self, func: usize, nargs: usize, nresults: Option<usize>, yieldable: bool) -> Result<(), LuaError> {
+        if self.rust_calls >= MAX_RUST_CALLS {
+            return Err(self.error("stack overflow (too many nested calls)"));
+        }
+        self.rust_calls += 1;
+        if !yieldable {
+            self.nny += 1;
+        }
+        let nframes = self.frames.len();
+        let r = match self.precall(func, nargs, nresults) {
+            Ok(true) => self.run(nframes),
+            Ok(false) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if !yieldable {
+            self.nny -= 1;
+        }
+        self.rust_calls -= 1;
+        return r;
+    }
+
+    /** 保护模式调用 : 和call_value一样,出错时恢复调用前的状态 ; handler不为nil时,先在出错的位置用错误调用handler */
+    pub fn pcall(&mut self, func: usize, nargs: usize, handler: Value) -> Result<(), LuaError> {
+        return self.protected_call(func, nargs, handler, false);
+    }
+
+    fn protected_call(&mut self, func: usize, nargs: usize, handler: Value, yieldable: bool) -> Result<(), LuaError> {
+        let (nframes, saved) = (self.frames.len(), self.func_index);
+        let r = self.call(func, nargs, None, yieldable);
+        self.func_index = saved;
+        return match r {
+            Err(e) if !e.is_yield() => Err(self.recover_error(e, func, nframes, handler)),
+            r => r,
+        };
+    }
+
+    /** 受保护的调用出错之后 : 先调用handler , 再关闭func之上的Upvalue和to-be-closed变量 , 回到nframes层调用 */
+    fn recover_error(&mut self, e: LuaError, func: usize, nframes: usize, handler: Value) -> LuaError {
+        /* 中断时不再执行脚本中的handler和__close , 只回到调用前的状态 , 错误继续向外传播 */
+        if e.is_interrupt() {
+            self.unwind(nframes, func);
+            return e;
+        }
+        /* 此时调用链还停在出错的位置 */
+        let e = match handler {
+            Value::Nil => e,
+            h => match self.call_meta(h, &[e.value]) {
+                Ok(v) => LuaError::new(v),
+                Err(e) if e.is_interrupt() => e,
+                Err(_) => LuaError::new("error in error handling"),
+            },
+        };
+        /* 关闭出错时还没有关闭的to-be-closed变量 */
+        self.close_upvalues(func);
+        let e = match self.close_tbc(func, e.value.clone()) {
+            Ok(()) => e,
+            Err(e) => e,
+        };
+        self.unwind(nframes, func);
+        return e;
+    }
+
+    /** 在Rust函数中调用函数 , 被调用的函数可以yield : 没有yield时直接用结果调用k ,
+        yield时k挂在当前Rust函数的帧上,协程恢复之后由它完成当前Rust函数 ; 返回值就是当前Rust函数的返回值
+     */
+    pub fn call_k(&mut self, func: usize, nargs: usize, nresults: Option<usize>, k: Continuation) -> Result<i32, LuaError> {
+        let Some(fi) = self.rust_frame() else {
+            self.call_value(func, nargs, nresults)?;
+            return k(self, Ok(()));
+        };
+        self.frames[fi].k = Some(Pending { k, protected: None });
+        let r = self.call(func, nargs, nresults, true);
+        if let Err(e) = r {
+            if !e.is_yield() {
+                self.frames[fi].k = None;
+            }
+            return Err(e);
+        }
+        let pending = self.frames[fi].k.take().unwrap();
+        return (pending.k)(self, Ok(()));
+    }
+
+    /** 受保护的call_k : 出错时和pcall一样恢复状态 , 然后用错误调用k */
+    pub fn pcall_k(&mut self, func: usize, nargs: usize, handler: Value, k: Continuation) -> Result<i32, LuaError> {
+        let Some(fi) = self.rust_frame() else {
+            let r = self.pcall(func, nargs, handler);
+            if let Err(e) = &r {
+                if e.is_interrupt() {
+                    return r.map(|_| 0);
+                }
+            }
+            return k(self, r);
+        };
+        self.frames[fi].k = Some(Pending { k, protected: Some((func, handler.clone())) });
+        let r = self.protected_call(func, nargs, handler, true);
+        if let Err(e) = &r {
+            if e.is_yield() {
+                return r.map(|_| 0);
+            }
+            if e.is_interrupt() {
+                self.frames[fi].k = None;
+                return r.map(|_| 0);
+            }
+        }
+        let pending = self.frames[fi].k.take().unwrap();
+        return (pending.k)(self, r);
+    }
+
+    /** 正在执行的Rust函数的帧 */
+    fn rust_frame(&self) -> Option<usize> {
+        return match self.frames.last() {
+            Some(f) if f.closure.is_none() => Some(self.frames.len() - 1),
+            _ => None,
+        };
+    }
+
+    /** 调用函数,取第一个返回值 */
+    pub fn call_meta(&mut self, f: Value, args: &[Value]) -> Result<Value, LuaError> {
+        let func = self.stack.len();
+        self.stack.push(f);
+        self.stack.extend_from_slice(args);
+        self.call_value(func, args.len(), Some(1))?;
+        return Ok(self.stack.pop().unwrap());
+    }
+
+    /** 调用元方法,取第一个返回值 : 在执行循环中(最上面是Lua帧)调用时可以yield ,
+        这时被调用的帧记下meta , 协程恢复之后它返回时由finish_op完成当前的字节码 ; Rust函数中调用时和call_meta一样
+     */
+    fn call_tm(&mut self, f: Value, args: &[Value]) -> Result<Value, LuaError> {
+        if self.frames.last().is_none_or(|f| f.closure.is_none()) {
+            return self.call_meta(f, args);
+        }
+        let (func, nframes) = (self.stack.len(), self.frames.len());
+        self.stack.push(f);
+        self.stack.extend_from_slice(args);
+        if let Err(e) = self.call(func, args.len(), Some(1), true) {
+            if e.is_yield() {
+                self.frames[nframes].meta = true;
+            }
+            return Err(e);
+        }
+        return Ok(self.stack.pop().unwrap());
+    }
+
+    /** 元方法中yield之后返回 : 结果在栈顶 , 按照最上面的Lua帧刚执行的字节码完成它 */
+    fn finish_op(&mut self) {
+        let fi = self.frames.len() - 1;
+        let (base, pc) = (self.frames[fi].base, self.frames[fi].pc);
+        let code = self.frames[fi].closure.as_ref().expect("调用元方法的帧必须是Lua函数").proto.byte_codes[pc - 1];
+        let a = base + code.a();
+        let value = self.stack.pop().unwrap();
+        match code.op() {
+            /* 判断指令 : 和执行循环中一样,结果和k不一致时跳过后面的Jmp */
+            OpCode::Eq | OpCode::Lt | OpCode::Le => {
+                if value.truthy() != code.k() {
+                    self.frames[fi].pc += 1;
+                }
+            }
+            OpCode::Method => {
+                let obj = self.stack[base + code.b()].clone();
+                self.set_stack(a + 1, obj);
+                self.set_stack(a, value);
+            }
+            /* __newindex的结果丢弃 */
+            | OpCode::SetUpField
+            | OpCode::SetUpFieldConst
+            | OpCode::SetTable
+            | OpCode::SetField
+            | OpCode::SetInt
+            | OpCode::SetTableConst
+            | OpCode::SetFieldConst
+            | OpCode::SetIntConst => (),
+            _ => self.set_stack(a, value),
+        }
+    }
+
+    /** #v , 支持__len , 结果必须是整数 */
+    pub fn length(&mut self, v: &Value) -> Result<i64, LuaError> {
+        return match self.len(v)? {
+            Value::Integer(n) => Ok(n),
+            Value::Float(f) => arith::float_to_int(f).ok_or_else(|| self.error("object length is not an integer")),
+            _ => Err(self.error("object length is not an integer")),
+        };
+    }
+
+    /** a < b , 支持__lt */
+    pub fn less_than(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        return self.compare(OpCode::Lt, a, b);
+    }
+
+    /** 值的元表 */
+    pub fn get_metatable(&self, v: &Value) -> Option<Rc<RefCell<Table>>> {
+        return match v {
+            Value::Table(t) => t.borrow().metatable.clone(),
+            Value::UserData(u) => u.borrow().metatable.clone(),
+            v if v.is_str() => self.string_meta.clone(),
+            v => self.type_metas.get(v.ty()).cloned(),
+        };
+    }
+
+    /** 值的元表中名为event的元方法,没有时为nil */
+    pub fn metamethod(&self, v: &Value, event: &str) -> Value {
+        return match self.get_metatable(v) {
+            Some(mt) => mt.borrow().get(&Value::from(event)),
+            None => Value::Nil,
+        };
+    }
+
+    /** t[key] , 支持__index */
+    pub fn index(&mut self, t: &Value, key: &Value) -> Result<Value, LuaError> {
+        let mut t = t.clone();
+        for _ in 0..MAX_META_LOOP {
+            let h = self.metamethod(&t, "__index");
+            if let Value::Table(table) = &t {
+                let v = table.borrow().get(key);
+                if !matches!(v, Value::Nil) || matches!(h, Value::Nil) {
+                    return Ok(v);
+                }
+            } else if let Value::Nil = h {
+                return Err(self.error(format!("attempt to index a {} value", t.ty())));
+            }
+            if h.is_function() {
+                return self.call_tm(h, &[t, key.clone()]);
+            }
+            t = h;
+        }
+        return Err(self.error("'__index' chain too long; possibly a loop"));
+    }
+
+    /** t[key] = value , 支持__newindex */
+    pub fn set_index(&mut self, t: &Value, key: Value, value: Value) -> Result<(), LuaError> {
+        let mut t = t.clone();
+        for _ in 0..MAX_META_LOOP {
+            let h = self.metamethod(&t, "__newindex");
+            if let Value::Table(table) = &t {
+                let exists = !matches!(table.borrow().get(&key), Value::Nil);
+                if exists || matches!(h, Value::Nil) {
+                    return self.raw_set(table, key, value);
+                }
+            } else if let Value::Nil = h {
+                return Err(self.error(format!("attempt to index a {} value", t.ty())));
+            }
+            if h.is_function() {
+                self.call_tm(h, &[t, key, value])?;
+                return Ok(());
+            }
+            t = h;
+        }
+        return Err(self.error("'__newindex' chain too long; possibly a loop"));
+    }
+
+    /** 不经过元方法的赋值 , 检查不合法的key */
+    pub fn raw_set(&self, table: &Rc<RefCell<Table>>, key: Value, value: Value) -> Result<(), LuaError> {
+        match key {
+            Value::Nil => {
+                return Err(self.error("table index is nil"));
+            }
+            Value::Float(f) if f.is_nan() => {
+                return Err(self.error("table index is NaN"));
+            }
+            _ => table.borrow_mut().set(key, value),
+        }
+        return Ok(());
+    }
+
+    /** 转换成字符串 , 支持__tostring和__name */
+    pub fn tostring(&mut self, v: &Value) -> Result<Value, LuaError> {
+        let h = self.metamethod(v, "__tostring");
+        if !matches!(h, Value::Nil) {
+            let s = self.call_meta(h, std::slice::from_ref(v))?;
+            return match s {
+                Value::Integer(_) | Value::Float(_) => Ok(s.to_string().into()),
+                s if s.is_str() => Ok(s),
+                _ => Err(self.error("'__tostring' must return a string")),
+            };
+        }
+        /* 字符串原样返回 , 不能经过Display , 否则不是UTF-8的字节会被替换掉 */
+        if v.is_str() {
+            return Ok(v.clone());
+        }
+        if let name @ (Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_)) = self.metamethod(v, "__name") {
+            let mut s = <&[u8]>::from(&name).to_vec();
+            s.extend_from_slice(format!(": {:?}", v.address()).as_bytes());
+            return Ok(s.into());
+        }
+        return Ok(v.to_string().into());
+    }
+}
+
+/** 运算对应的元方法名 */
+fn meta_event(op: OpCode) -> &'static str {
+    return match op {
+        OpCode::Add => "__add",
+        OpCode::Sub => "__sub",
+        OpCode::Mul => "__mul",
+        OpCode::Div => "__div",
+        OpCode::IDiv => "__idiv",
+        OpCode::Mod => "__mod",
+        OpCode::Pow => "__pow",
+        OpCode::BAnd => "__band",
+        OpCode::BOr => "__bor",
+        OpCode::BXor => "__bxor",
+        OpCode::Shl => "__shl",
+        OpCode::Shr => "__shr",
+        OpCode::Concat => "__concat",
+        _ => panic!("no metamethod for {op:?}"),
+    };
+}
+
+/** Lua函数的帧当前执行到的行号 */
+fn current_line(frame: &CallFrame) -> u32 {
+    let Some(closure) = &frame.closure else {
+        return 0;
+    };
+    return closure.proto.lineinfo.get(frame.pc.saturating_sub(1)).copied().unwrap_or(0);
 }